@@ -0,0 +1,670 @@
+//! Typed builders for running simpleaf commands from Rust code.
+//!
+//! Each builder starts from the defaults of the options of the corresponding
+//! command line subcommand, taken from the [`SimpleafConfig`] it is given
+//! (e.g. [`SimpleafConfig::default`] for the built-in defaults, or the
+//! `simpleaf.toml` of a simpleaf home read with [`SimpleafConfig::load`]).
+//! Its setters then set the options directly, without going through a
+//! command line, and it runs the command, returning a structured result
+//! rather than `()`.
+//!
+//! ```no_run
+//! use simpleaf::api::{IndexBuilder, QuantBuilder, Resolution};
+//! use simpleaf::utils::config::SimpleafConfig;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let config = SimpleafConfig::default();
+//! let index = IndexBuilder::new("/path/to/af_home", &config)?
+//!     .fasta("genome.fa")
+//!     .gtf("genes.gtf")
+//!     .rlen(91)
+//!     .output("idx_out")
+//!     .run()?;
+//!
+//! let quant = QuantBuilder::new("/path/to/af_home", &config)?
+//!     .index(index.output_dir.join("index"))
+//!     .chemistry("10xv3")
+//!     .reads1(["r1.fq.gz"])
+//!     .reads2(["r2.fq.gz"])
+//!     .unfiltered_pl()
+//!     .resolution(Resolution::CrLike)
+//!     .output("quant_out")
+//!     .run()?;
+//! println!("count matrix at {}", quant.count_matrix.display());
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::atac::commands::{AtacChemistry, AtacCommand, ProcessOpts};
+use crate::simpleaf_commands::{
+    chemistry, indexing, quant, ChemistryAddOpts, ChemistryRemoveOpts, Commands, IndexOpts,
+    MapQuantOpts, ReferenceType,
+};
+use crate::utils::chem_utils::{get_custom_chem_hm, CustomChemistry};
+use crate::utils::config::SimpleafConfig;
+use crate::utils::constants::CHEMISTRIES_PATH;
+use crate::utils::run_record::{OptionSource, OptionValue};
+
+pub use crate::atac::process::ProcessResult;
+pub use crate::simpleaf_commands::indexing::IndexResult;
+pub use crate::simpleaf_commands::quant::QuantResult;
+pub use crate::utils::prog_utils::StepInfo;
+
+/// The UMI resolution strategies of `simpleaf quant`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    CrLike,
+    CrLikeEm,
+    Parsimony,
+    ParsimonyEm,
+    ParsimonyGene,
+    ParsimonyGeneEm,
+}
+
+impl Resolution {
+    /// The name of the strategy, as given to `--resolution`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::CrLike => "cr-like",
+            Resolution::CrLikeEm => "cr-like-em",
+            Resolution::Parsimony => "parsimony",
+            Resolution::ParsimonyEm => "parsimony-em",
+            Resolution::ParsimonyGene => "parsimony-gene",
+            Resolution::ParsimonyGeneEm => "parsimony-gene-em",
+        }
+    }
+}
+
+fn path_value(p: &Path) -> Value {
+    json!(p.to_string_lossy())
+}
+
+fn paths_value(paths: &[PathBuf]) -> Value {
+    json!(paths
+        .iter()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>())
+}
+
+fn to_paths<I, P>(paths: I) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    paths
+        .into_iter()
+        .map(|p| p.as_ref().to_path_buf())
+        .collect()
+}
+
+/// Checks that exactly one of the `options` (a long name, and whether it is
+/// set) is set.
+fn exactly_one(options: &[(&str, bool)]) -> anyhow::Result<()> {
+    let names = || {
+        options
+            .iter()
+            .map(|(name, _)| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match options.iter().filter(|(_, set)| *set).count() {
+        1 => Ok(()),
+        0 => bail!("one of {} must be set", names()),
+        _ => bail!("only one of {} can be set", names()),
+    }
+}
+
+macro_rules! common_builder_methods {
+    ($section:literal, $variant:pat => $bound:ident) => {
+        /// Starts from the defaults of the options in `config`; the files of
+        /// the runs are looked up in (and written to) the simpleaf home `af_home`.
+        pub fn new<P: AsRef<Path>>(af_home: P, config: &SimpleafConfig) -> anyhow::Result<Self> {
+            let (command, unset) = config.section_defaults($section)?;
+            let opts = match command {
+                $variant => $bound,
+                _ => bail!("the [{}] section does not hold these options", $section),
+            };
+            Ok(Self {
+                af_home: af_home.as_ref().to_path_buf(),
+                opts,
+                unset,
+            })
+        }
+
+        /// Sets the output directory.
+        pub fn output<P: AsRef<Path>>(mut self, output: P) -> Self {
+            self.opts.output = output.as_ref().to_path_buf();
+            self.given("output", path_value(output.as_ref()))
+        }
+
+        /// Sets the number of threads to use.
+        pub fn threads(mut self, threads: u32) -> Self {
+            self.opts.threads = threads;
+            self.given("threads", json!(threads.to_string()))
+        }
+
+        /// Records that the option `name` was set to `value`, for the
+        /// effective options of the run record.
+        fn given(mut self, name: &str, value: Value) -> Self {
+            self.unset.retain(|n| n != name);
+            self.opts.effective_options.insert(
+                name.to_string(),
+                OptionValue {
+                    value,
+                    source: OptionSource::CommandLine,
+                },
+            );
+            self
+        }
+
+        /// Checks that the options that have no default were set.
+        fn check_required(&self) -> anyhow::Result<()> {
+            if !self.unset.is_empty() {
+                bail!(
+                    "the required option(s) {} were not set",
+                    self.unset
+                        .iter()
+                        .map(|n| format!("`{}`", n))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            Ok(())
+        }
+    };
+}
+
+/// Builds an expanded reference and/or index, as `simpleaf index` does.
+#[derive(Clone, Debug)]
+pub struct IndexBuilder {
+    af_home: PathBuf,
+    opts: IndexOpts,
+    unset: Vec<String>,
+}
+
+impl IndexBuilder {
+    common_builder_methods!("index", Commands::Index(opts) => opts);
+
+    /// Sets the reference genome from which to build the expanded reference.
+    pub fn fasta<P: AsRef<Path>>(mut self, fasta: P) -> Self {
+        self.opts.fasta = Some(fasta.as_ref().to_path_buf());
+        self.given("fasta", path_value(fasta.as_ref()))
+    }
+
+    /// Sets the GTF (or, with `gff3_format`, GFF3) annotation of the genome.
+    pub fn gtf<P: AsRef<Path>>(mut self, gtf: P) -> Self {
+        self.opts.gtf = Some(gtf.as_ref().to_path_buf());
+        self.given("gtf", path_value(gtf.as_ref()))
+    }
+
+    /// Sets the type of expanded reference.
+    pub fn ref_type(mut self, ref_type: ReferenceType) -> Self {
+        let name = match ref_type {
+            ReferenceType::SplicedIntronic => "spliced+intronic",
+            ReferenceType::SplicedUnspliced => "spliced+unspliced",
+        };
+        self.opts.ref_type = ref_type;
+        self.given("ref-type", json!(name))
+    }
+
+    /// Sets the read length used to add flanks to the intronic sequences.
+    pub fn rlen(mut self, rlen: i64) -> Self {
+        self.opts.rlen = rlen;
+        self.given("rlen", json!(rlen.to_string()))
+    }
+
+    /// Indexes an existing set of target sequences rather than building an expanded reference.
+    pub fn ref_seq<P: AsRef<Path>>(mut self, ref_seq: P) -> Self {
+        self.opts.ref_seq = Some(ref_seq.as_ref().to_path_buf());
+        self.given("ref-seq", path_value(ref_seq.as_ref()))
+    }
+
+    /// Sets the k-mer length of the index.
+    pub fn kmer_length(mut self, k: u32) -> Self {
+        self.opts.kmer_length = k;
+        self.given("kmer-length", json!(k.to_string()))
+    }
+
+    /// Uses salmon rather than piscem to build the index.
+    pub fn no_piscem(mut self) -> Self {
+        self.opts.use_piscem = false;
+        self.given("no-piscem", json!(true))
+    }
+
+    /// Overwrites the output of a previous run.
+    pub fn overwrite(mut self) -> Self {
+        self.opts.overwrite = true;
+        self.given("overwrite", json!(true))
+    }
+
+    /// Checks the options and returns them.
+    pub fn build_opts(&self) -> anyhow::Result<IndexOpts> {
+        self.check_required()?;
+        exactly_one(&[
+            ("fasta", self.opts.fasta.is_some()),
+            ("ref-seq", self.opts.ref_seq.is_some()),
+            ("probe-csv", self.opts.probe_csv.is_some()),
+            ("feature-csv", self.opts.feature_csv.is_some()),
+        ])?;
+        if self.opts.fasta.is_some() != self.opts.gtf.is_some() {
+            bail!("`fasta` and `gtf` must be set together");
+        }
+        Ok(self.opts.clone())
+    }
+
+    /// Builds the expanded reference and index.
+    pub fn run(&self) -> anyhow::Result<IndexResult> {
+        indexing::build_ref_and_index(&self.af_home, self.build_opts()?)
+    }
+}
+
+/// Maps and quantifies a single sample, as `simpleaf quant` does.
+#[derive(Clone, Debug)]
+pub struct QuantBuilder {
+    af_home: PathBuf,
+    opts: MapQuantOpts,
+    unset: Vec<String>,
+}
+
+impl QuantBuilder {
+    common_builder_methods!("quant", Commands::Quant(opts) => opts);
+
+    /// Sets the index to map against.
+    pub fn index<P: AsRef<Path>>(mut self, index: P) -> Self {
+        self.opts.index = Some(index.as_ref().to_path_buf());
+        self.given("index", path_value(index.as_ref()))
+    }
+
+    /// Sets the read 1 files.
+    pub fn reads1<I, P>(mut self, reads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let reads = to_paths(reads);
+        let value = paths_value(&reads);
+        self.opts.reads1 = Some(reads);
+        self.given("reads1", value)
+    }
+
+    /// Sets the read 2 files.
+    pub fn reads2<I, P>(mut self, reads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let reads = to_paths(reads);
+        let value = paths_value(&reads);
+        self.opts.reads2 = Some(reads);
+        self.given("reads2", value)
+    }
+
+    /// Quantifies an existing mapping directory rather than mapping reads.
+    pub fn map_dir<P: AsRef<Path>>(mut self, map_dir: P) -> Self {
+        self.opts.map_dir = Some(map_dir.as_ref().to_path_buf());
+        self.given("map-dir", path_value(map_dir.as_ref()))
+    }
+
+    /// Sets the chemistry: a registered name, a custom geometry, or `auto`.
+    pub fn chemistry<S: Into<String>>(mut self, chemistry: S) -> Self {
+        self.opts.chemistry = chemistry.into();
+        let value = json!(self.opts.chemistry);
+        self.given("chemistry", value)
+    }
+
+    /// Sets the UMI resolution strategy.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.opts.resolution = resolution.as_str().to_string();
+        self.given("resolution", json!(resolution.as_str()))
+    }
+
+    /// Sets the transcript-to-gene map.
+    pub fn t2g_map<P: AsRef<Path>>(mut self, t2g_map: P) -> Self {
+        self.opts.t2g_map = Some(t2g_map.as_ref().to_path_buf());
+        self.given("t2g-map", path_value(t2g_map.as_ref()))
+    }
+
+    /// Filters cells with the knee method.
+    pub fn knee(mut self) -> Self {
+        self.opts.knee = true;
+        self.given("knee", json!(true))
+    }
+
+    /// Uses the unfiltered permit list of the chemistry.
+    pub fn unfiltered_pl(mut self) -> Self {
+        self.opts.unfiltered_pl = Some(None);
+        self.given("unfiltered-pl", Value::Null)
+    }
+
+    /// Uses the given unfiltered permit list.
+    pub fn unfiltered_pl_file<P: AsRef<Path>>(mut self, pl: P) -> Self {
+        self.opts.unfiltered_pl = Some(Some(pl.as_ref().to_path_buf()));
+        self.given("unfiltered-pl", path_value(pl.as_ref()))
+    }
+
+    /// Uses the given list of cell barcodes as the (filtered) permit list.
+    pub fn explicit_pl<P: AsRef<Path>>(mut self, pl: P) -> Self {
+        self.opts.explicit_pl = Some(pl.as_ref().to_path_buf());
+        self.given("explicit-pl", path_value(pl.as_ref()))
+    }
+
+    /// Writes the count matrix in AnnData (h5ad) format as well.
+    pub fn anndata_out(mut self) -> Self {
+        self.opts.anndata_out = true;
+        self.given("anndata-out", json!(true))
+    }
+
+    /// Skips the stages that completed in a previous run.
+    pub fn resume(mut self) -> Self {
+        self.opts.resume = true;
+        self.given("resume", json!(true))
+    }
+
+    /// Checks the options and returns them.
+    pub fn build_opts(&self) -> anyhow::Result<MapQuantOpts> {
+        self.check_required()?;
+        let opts = &self.opts;
+        exactly_one(&[
+            ("index", opts.index.is_some()),
+            ("map-dir", opts.map_dir.is_some()),
+        ])?;
+        if opts.index.is_some() && (opts.reads1.is_none() || opts.reads2.is_none()) {
+            bail!("`reads1` and `reads2` must be set to map against an index");
+        }
+        if opts.map_dir.is_some() && (opts.reads1.is_some() || opts.reads2.is_some()) {
+            bail!("`reads1` and `reads2` cannot be set along with `map-dir`");
+        }
+        exactly_one(&[
+            ("expect-cells", opts.expect_cells.is_some()),
+            ("explicit-pl", opts.explicit_pl.is_some()),
+            ("forced-cells", opts.forced_cells.is_some()),
+            ("knee", opts.knee),
+            ("unfiltered-pl", opts.unfiltered_pl.is_some()),
+        ])?;
+        Ok(opts.clone())
+    }
+
+    /// Maps and quantifies the sample.
+    pub fn run(&self) -> anyhow::Result<QuantResult> {
+        let mut results = quant::map_and_quant(&self.af_home, self.build_opts()?)?;
+        results
+            .pop()
+            .context("the quantification did not produce any result")
+    }
+}
+
+/// Processes a scATAC-seq sample, as `simpleaf atac process` does.
+#[derive(Clone, Debug)]
+pub struct AtacProcessBuilder {
+    af_home: PathBuf,
+    opts: ProcessOpts,
+    unset: Vec<String>,
+}
+
+impl AtacProcessBuilder {
+    common_builder_methods!("atac.process", Commands::Atac(AtacCommand::Process(opts)) => opts);
+
+    /// Sets the index to map against.
+    pub fn index<P: AsRef<Path>>(mut self, index: P) -> Self {
+        self.opts.index = index.as_ref().to_path_buf();
+        self.given("index", path_value(index.as_ref()))
+    }
+
+    /// Sets the read 1 files of paired-end reads.
+    pub fn reads1<I, P>(mut self, reads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let reads = to_paths(reads);
+        let value = paths_value(&reads);
+        self.opts.reads1 = Some(reads);
+        self.given("reads1", value)
+    }
+
+    /// Sets the read 2 files of paired-end reads.
+    pub fn reads2<I, P>(mut self, reads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let reads = to_paths(reads);
+        let value = paths_value(&reads);
+        self.opts.reads2 = Some(reads);
+        self.given("reads2", value)
+    }
+
+    /// Sets the files of single-end reads.
+    pub fn reads<I, P>(mut self, reads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let reads = to_paths(reads);
+        let value = paths_value(&reads);
+        self.opts.reads = Some(reads);
+        self.given("reads", value)
+    }
+
+    /// Sets the files containing the cell barcodes.
+    pub fn barcode_reads<I, P>(mut self, reads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.opts.barcode_reads = to_paths(reads);
+        let value = paths_value(&self.opts.barcode_reads);
+        self.given("barcode-reads", value)
+    }
+
+    /// Sets the chemistry.
+    pub fn chemistry(mut self, chemistry: AtacChemistry) -> Self {
+        self.opts.chemistry = chemistry;
+        let name = chemistry
+            .to_possible_value()
+            .map(|v| v.get_name().to_string());
+        self.given("chemistry", json!(name))
+    }
+
+    /// Calls peaks with macs3 after generating the BED file.
+    pub fn call_peaks(mut self) -> Self {
+        self.opts.call_peaks = true;
+        self.given("call-peaks", json!(true))
+    }
+
+    /// Checks the options and returns them.
+    pub fn build_opts(&self) -> anyhow::Result<ProcessOpts> {
+        self.check_required()?;
+        let opts = &self.opts;
+        match (&opts.reads1, &opts.reads2, &opts.reads) {
+            (Some(_), Some(_), None) | (None, None, Some(_)) => {}
+            _ => bail!("either both `reads1` and `reads2`, or only `reads`, must be set"),
+        }
+        if opts.barcode_reads.is_empty() {
+            bail!("`barcode-reads` must list at least one file");
+        }
+        Ok(opts.clone())
+    }
+
+    /// Maps the reads and generates the BED file (and, optionally, the peaks).
+    pub fn run(&self) -> anyhow::Result<ProcessResult> {
+        crate::atac::process::process(&self.af_home, &self.build_opts()?)
+    }
+}
+
+/// The chemistry registry (`chemistries.json`) of an `ALEVIN_FRY_HOME` directory.
+#[derive(Clone, Debug)]
+pub struct ChemistryRegistry {
+    af_home: PathBuf,
+}
+
+impl ChemistryRegistry {
+    pub fn new<P: AsRef<Path>>(af_home: P) -> Self {
+        Self {
+            af_home: af_home.as_ref().to_path_buf(),
+        }
+    }
+
+    fn all(&self) -> anyhow::Result<HashMap<String, CustomChemistry>> {
+        get_custom_chem_hm(&self.af_home.join(CHEMISTRIES_PATH))
+    }
+
+    /// Returns the registered chemistry called `name`, if there is one.
+    pub fn get(&self, name: &str) -> anyhow::Result<Option<CustomChemistry>> {
+        Ok(self.all()?.remove(name))
+    }
+
+    /// Returns all of the registered chemistries, sorted by name.
+    pub fn list(&self) -> anyhow::Result<Vec<CustomChemistry>> {
+        let mut chems: Vec<CustomChemistry> = self.all()?.into_values().collect();
+        chems.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(chems)
+    }
+
+    /// Adds (or updates) a chemistry, as `simpleaf chemistry add` does.
+    pub fn add(&self, opts: ChemistryAddOpts) -> anyhow::Result<()> {
+        chemistry::add_chemistry(self.af_home.clone(), opts)
+    }
+
+    /// Removes the chemistries whose names match `name` (a regular expression).
+    pub fn remove(&self, name: &str) -> anyhow::Result<()> {
+        chemistry::remove_chemistry(
+            self.af_home.clone(),
+            ChemistryRemoveOpts {
+                name: name.to_string(),
+                dry_run: false,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::home::{HomeSource, SimpleafHome};
+
+    #[test]
+    fn test_quant_builder() {
+        let config = SimpleafConfig::default();
+        let builder = QuantBuilder::new("af_home", &config)
+            .unwrap()
+            .index("idx")
+            .chemistry("10xv3")
+            .reads1(["a,b/r1.fq"])
+            .reads2(["a,b/r2.fq"])
+            .knee()
+            .resolution(Resolution::CrLikeEm)
+            .t2g_map("t2g.tsv");
+
+        // the output has no default
+        let err = builder.build_opts().unwrap_err();
+        assert!(err.to_string().contains("`output`"), "{}", err);
+
+        let opts = builder.output("out").build_opts().unwrap();
+        // the paths are kept as given, even when they contain a comma
+        assert_eq!(opts.reads1, Some(vec![PathBuf::from("a,b/r1.fq")]));
+        assert_eq!(opts.reads2, Some(vec![PathBuf::from("a,b/r2.fq")]));
+        assert_eq!(opts.resolution, "cr-like-em");
+        assert_eq!(opts.threads, 16);
+        assert!(opts.knee && opts.use_piscem);
+        let options = &opts.effective_options;
+        assert_eq!(options["reads1"].value, json!(["a,b/r1.fq"]));
+        assert_eq!(options["reads1"].source, OptionSource::CommandLine);
+        assert_eq!(options["threads"].source, OptionSource::Default);
+
+        // a second filtering method
+        let builder = QuantBuilder::new("af_home", &config)
+            .unwrap()
+            .map_dir("map")
+            .chemistry("10xv3")
+            .knee()
+            .unfiltered_pl()
+            .resolution(Resolution::CrLike)
+            .output("out");
+        assert!(builder.build_opts().is_err());
+    }
+
+    #[test]
+    fn test_builders_use_the_given_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = SimpleafHome::single_dir(dir.path().to_path_buf(), HomeSource::Cli);
+        let mut config = SimpleafConfig::load(&home).unwrap();
+        config.set("quant.threads", &["4".to_string()]).unwrap();
+        config
+            .set("index.kmer-length", &["23".to_string()])
+            .unwrap();
+
+        let opts = QuantBuilder::new(dir.path(), &config)
+            .unwrap()
+            .map_dir("map")
+            .chemistry("10xv3")
+            .knee()
+            .resolution(Resolution::CrLike)
+            .output("out")
+            .build_opts()
+            .unwrap();
+        assert_eq!(opts.threads, 4);
+        assert_eq!(
+            opts.effective_options["threads"].source,
+            OptionSource::Config
+        );
+
+        let opts = IndexBuilder::new(dir.path(), &config)
+            .unwrap()
+            .ref_seq("txome.fa")
+            .ref_type(ReferenceType::SplicedUnspliced)
+            .output("idx")
+            .build_opts()
+            .unwrap();
+        assert_eq!(opts.kmer_length, 23);
+        assert!(matches!(opts.ref_type, ReferenceType::SplicedUnspliced));
+
+        // the built-in defaults are used without a configuration
+        let opts = IndexBuilder::new(dir.path(), &SimpleafConfig::default())
+            .unwrap()
+            .ref_seq("txome.fa")
+            .output("idx")
+            .build_opts()
+            .unwrap();
+        assert_eq!(opts.kmer_length, 31);
+    }
+
+    #[test]
+    fn test_atac_process_builder() {
+        let config = SimpleafConfig::default();
+        let builder = AtacProcessBuilder::new("af_home", &config)
+            .unwrap()
+            .index("idx")
+            .reads1(["r1.fq"])
+            .reads2(["r2.fq"])
+            .barcode_reads(["bc.fq"])
+            .output("out");
+        let err = builder.build_opts().unwrap_err();
+        assert!(err.to_string().contains("`chemistry`"), "{}", err);
+
+        let opts = builder
+            .clone()
+            .chemistry(AtacChemistry::TenxMulti)
+            .build_opts()
+            .unwrap();
+        assert!(opts.chemistry == AtacChemistry::TenxMulti);
+        assert_eq!(opts.barcode_reads, vec![PathBuf::from("bc.fq")]);
+        assert_eq!(
+            opts.effective_options["chemistry"].value,
+            json!("10x-multi")
+        );
+
+        // single-end reads cannot be given along with paired-end ones
+        assert!(builder
+            .chemistry(AtacChemistry::TenxV2)
+            .reads(["r.fq"])
+            .build_opts()
+            .is_err());
+    }
+}
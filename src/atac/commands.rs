@@ -1,6 +1,7 @@
 use crate::atac::defaults::{AtacIndexParams, DefaultAtacParams};
 use crate::defaults::{DefaultMappingParams, DefaultParams};
 use crate::utils::chem_utils::{ExpectedOri, QueryInRegistry};
+use crate::utils::run_record::EffectiveOptions;
 use clap::{
     builder::{ArgPredicate, PossibleValue},
     Args, Subcommand, ValueEnum,
@@ -222,6 +223,10 @@ pub struct IndexOpts {
     /// path to output directory (will be created if it doesn't exist)
     #[arg(short, long, display_order = 2)]
     pub output: PathBuf,
+    /// The effective value of each option, recorded in the run record (filled in when the
+    /// command line is parsed)
+    #[arg(skip)]
+    pub effective_options: EffectiveOptions,
 }
 
 /// process a scATAC-seq sample by performing
@@ -309,6 +314,11 @@ pub struct ProcessOpts {
     #[arg(long)]
     pub call_peaks: bool,
 
+    /// print the commands that would be run, checking that their inputs exist,
+    /// without running anything
+    #[arg(long)]
+    pub dry_run: bool,
+
     /// The expected orientation of the barcodes in the permit list relative
     /// to the barcodes extracted from the reads. If this is "fw", it is expected
     /// that the sequences will match directly, if "rc" it is expected the reverse
@@ -421,4 +431,8 @@ pub struct ProcessOpts {
     /// The value to be passed to the `macs3` `--extsize` option.
     #[arg(long, help_heading = "Peak Caller Options", default_value_t = 50)]
     pub extsize: usize,
+    /// The effective value of each option, recorded in the run record (filled in when the
+    /// command line is parsed)
+    #[arg(skip)]
+    pub effective_options: EffectiveOptions,
 }
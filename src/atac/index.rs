@@ -1,20 +1,24 @@
 use crate::atac::commands::IndexOpts;
+use crate::utils::constants::LOGS_DIR;
+use crate::utils::executor::Executor;
+use crate::utils::run_record::{write_json_atomic, RunDetails, RunRecord, INDEX_RUN_RECORD_FILE};
 use crate::utils::{
     prog_utils,
-    prog_utils::{CommandVerbosityLevel, ReqProgs},
+    prog_utils::{CommandVerbosityLevel, ReqProgs, StepInfo},
 };
 use anyhow;
-use anyhow::{bail, Context};
+use anyhow::bail;
 use cmd_lib::run_fun;
 use serde_json::{json, Value};
 use std::path::Path;
 use std::time::Instant;
 use tracing::{info, warn};
 
-pub(crate) fn piscem_index(af_home_path: &Path, opts: &IndexOpts) -> anyhow::Result<()> {
+pub fn piscem_index(af_home_path: &Path, opts: &IndexOpts) -> anyhow::Result<()> {
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     let piscem_prog_info = rp
         .piscem
@@ -33,8 +37,7 @@ pub(crate) fn piscem_index(af_home_path: &Path, opts: &IndexOpts) -> anyhow::Res
     let output = opts.output.clone();
     let output_index_dir = output.join("index");
 
-    let mut piscem_index_cmd =
-        std::process::Command::new(format!("{}", piscem_prog_info.exe_path.display()));
+    let mut piscem_index_cmd = executor.command(&piscem_prog_info.exe_path);
 
     run_fun!(mkdir -p $output_index_dir)?;
     let output_index_stem = output_index_dir.join("piscem_idx");
@@ -94,8 +97,13 @@ pub(crate) fn piscem_index(af_home_path: &Path, opts: &IndexOpts) -> anyhow::Res
     info!("piscem build cmd : {}", index_cmd_string);
 
     let index_start = Instant::now();
-    let cres = prog_utils::execute_command(&mut piscem_index_cmd, CommandVerbosityLevel::Quiet)
-        .expect("failed to invoke piscem index command");
+    let cres = prog_utils::execute_logged_command(
+        &mut piscem_index_cmd,
+        CommandVerbosityLevel::global(),
+        &output.join(LOGS_DIR),
+        "piscem_index",
+    )
+    .expect("failed to invoke piscem index command");
     let index_duration = index_start.elapsed();
 
     if !cres.status.success() {
@@ -106,9 +114,6 @@ pub(crate) fn piscem_index(af_home_path: &Path, opts: &IndexOpts) -> anyhow::Res
     let index_json = json!({
             "cmd" : index_cmd_string,
             "index_type" : "piscem",
-            "time_info" : {
-                "index_time" : index_duration.as_secs_f64()
-            },
             "piscem_index_parameters" : {
                 "k" : opts.kmer_length,
                 "m" : opts.minimizer_length,
@@ -117,11 +122,21 @@ pub(crate) fn piscem_index(af_home_path: &Path, opts: &IndexOpts) -> anyhow::Res
                 "ref" : &opts.input
             }
     });
-    std::fs::write(
-        &index_json_file,
-        serde_json::to_string_pretty(&index_json).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", index_json_file.display()))?;
+    write_json_atomic(&index_json_file, &index_json)?;
+
+    let mut run_record = RunRecord::new(RunDetails::Index {
+        index_type: "piscem".to_string(),
+        index_dir: output_index_dir,
+        ref_dir: None,
+        roers_options: None,
+    });
+    run_record.options = opts.effective_options.clone();
+    run_record.steps.push(StepInfo::new(
+        "index",
+        Some(index_cmd_string),
+        index_duration,
+    ));
+    run_record.write(&output.join(INDEX_RUN_RECORD_FILE))?;
 
     Ok(())
 }
@@ -2,14 +2,17 @@ use crate::atac::commands::ProcessOpts;
 use crate::utils::chem_utils::get_single_custom_chem_from_file;
 use crate::utils::chem_utils::ExpectedOri;
 use crate::utils::chem_utils::QueryInRegistry;
-use crate::utils::constants::CHEMISTRIES_PATH;
+use crate::utils::constants::{CHEMISTRIES_PATH, LOGS_DIR};
+use crate::utils::executor::Executor;
+use crate::utils::run_record::{RunDetails, RunRecord, PROCESS_RUN_RECORD_FILE};
 use crate::utils::{
-    prog_utils,
-    prog_utils::{CommandVerbosityLevel, ReqProgs},
+    af_utils, prog_utils,
+    prog_utils::{CommandVerbosityLevel, DryRunPlan, ReqProgs, StepInfo},
+    provenance::Provenance,
 };
 use anyhow;
 use anyhow::{bail, Context};
-use serde_json::{json, Value};
+use serde_json::Value;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
@@ -58,7 +61,12 @@ fn push_advanced_piscem_options(
     Ok(())
 }
 
-fn add_read_args(map_cmd: &mut std::process::Command, opts: &ProcessOpts) -> anyhow::Result<()> {
+/// Adds either the paired-end or single-end read arguments to `map_cmd`, and
+/// returns the read files that were added.
+fn add_read_args(
+    map_cmd: &mut std::process::Command,
+    opts: &ProcessOpts,
+) -> anyhow::Result<Vec<PathBuf>> {
     if let Some(ref reads1) = opts.reads1 {
         let reads2 = opts
             .reads2
@@ -74,10 +82,6 @@ fn add_read_args(map_cmd: &mut std::process::Command, opts: &ProcessOpts) -> any
             );
         }
 
-        prog_utils::check_files_exist(reads1)?;
-        prog_utils::check_files_exist(reads2)?;
-        prog_utils::check_files_exist(barcode_reads)?;
-
         let reads1_str = reads1
             .iter()
             .map(|x| x.to_string_lossy().into_owned())
@@ -98,6 +102,7 @@ fn add_read_args(map_cmd: &mut std::process::Command, opts: &ProcessOpts) -> any
             .collect::<Vec<String>>()
             .join(",");
         map_cmd.arg("--barcode").arg(bc_str);
+        Ok([reads1.as_slice(), reads2, barcode_reads].concat())
     } else {
         let reads = opts.reads.as_ref().expect(
             "since reads1 and reads2 are not provided, the single-end reads must be provided.",
@@ -111,9 +116,6 @@ fn add_read_args(map_cmd: &mut std::process::Command, opts: &ProcessOpts) -> any
             );
         }
 
-        prog_utils::check_files_exist(reads)?;
-        prog_utils::check_files_exist(barcode_reads)?;
-
         let reads_str = reads
             .iter()
             .map(|x| x.to_string_lossy().into_owned())
@@ -127,14 +129,11 @@ fn add_read_args(map_cmd: &mut std::process::Command, opts: &ProcessOpts) -> any
             .collect::<Vec<String>>()
             .join(",");
         map_cmd.arg("--barcode").arg(bc_str);
+        Ok([reads.as_slice(), barcode_reads].concat())
     }
-    Ok(())
 }
 
-pub(crate) fn check_progs<P: AsRef<Path>>(
-    af_home_path: P,
-    opts: &ProcessOpts,
-) -> anyhow::Result<()> {
+pub fn check_progs<P: AsRef<Path>>(af_home_path: P, opts: &ProcessOpts) -> anyhow::Result<()> {
     let af_home_path = af_home_path.as_ref();
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
@@ -186,12 +185,15 @@ pub(crate) fn check_progs<P: AsRef<Path>>(
     Ok(())
 }
 
-// NOTE: we assume that check_progs has already been called and so version constraints have
-// already been checked.
-pub(crate) fn map_reads(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
+/// Builds the piscem mapping command, and returns it along with its input files.
+fn map_cmd(
+    af_home_path: &Path,
+    opts: &ProcessOpts,
+) -> anyhow::Result<(std::process::Command, Vec<PathBuf>)> {
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     let piscem_prog_info = rp
         .piscem
@@ -269,16 +271,14 @@ pub(crate) fn map_reads(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Resu
         }
     }
 
-    let input_files = vec![
+    let mut input_files = vec![
         index_base.with_extension("ctab"),
         index_base.with_extension("refinfo"),
         index_base.with_extension("sshash"),
     ];
-    prog_utils::check_files_exist(&input_files)?;
 
     // using a piscem index
-    let mut piscem_map_cmd =
-        std::process::Command::new(format!("{}", &piscem_prog_info.exe_path.display()));
+    let mut piscem_map_cmd = executor.command(&piscem_prog_info.exe_path);
     let index_path = format!("{}", index_base.display());
     piscem_map_cmd
         .arg("map-sc-atac")
@@ -314,7 +314,7 @@ pub(crate) fn map_reads(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Resu
         .arg(&map_output);
 
     // add either the paired-end or single-end read arguments
-    add_read_args(&mut piscem_map_cmd, opts)?;
+    input_files.extend(add_read_args(&mut piscem_map_cmd, opts)?);
 
     // if the user is requesting a mapping option that required
     // piscem version >= 0.7.0, ensure we have that
@@ -334,14 +334,26 @@ being used by simpleaf"#,
             &piscem_prog_info.version
         );
     }
+    Ok((piscem_map_cmd, input_files))
+}
+
+// NOTE: we assume that check_progs has already been called and so version constraints have
+// already been checked.
+pub fn map_reads(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<StepInfo> {
+    let (mut piscem_map_cmd, input_files) = map_cmd(af_home_path, opts)?;
+    prog_utils::check_files_exist(&input_files)?;
 
     let map_cmd_string = prog_utils::get_cmd_line_string(&piscem_map_cmd);
     info!("map command : {}", map_cmd_string);
 
     let map_start = Instant::now();
-    let map_proc_out =
-        prog_utils::execute_command(&mut piscem_map_cmd, CommandVerbosityLevel::Quiet)
-            .expect("could not execute [atac::map]");
+    let map_proc_out = prog_utils::execute_logged_command(
+        &mut piscem_map_cmd,
+        CommandVerbosityLevel::global(),
+        &opts.output.join(LOGS_DIR),
+        "piscem_map",
+    )
+    .expect("could not execute [atac::map]");
     let map_duration = map_start.elapsed();
 
     if !map_proc_out.status.success() {
@@ -353,37 +365,19 @@ being used by simpleaf"#,
         info!("mapping completed successfully in {:#?}", map_duration);
     }
 
-    let af_process_info_file = opts.output.join("simpleaf_process_log.json");
-    let af_process_info = json!({
-        "time_info" : {
-        "map_time" : map_duration.as_secs_f64(),
-    },
-        "cmd_info" : {
-        "map_cmd" : map_cmd_string,
-    },
-        "map_info" : {
-        "mapper" : "piscem",
-        "map_cmd" : map_cmd_string,
-        "map_outdir": map_output
-    }
-    });
-
-    // write the relevant info about
-    // our run to file.
-    std::fs::write(
-        &af_process_info_file,
-        serde_json::to_string_pretty(&af_process_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", af_process_info_file.display()))?;
-
     info!("successfully mapped reads and generated output RAD file.");
-    Ok(())
+    Ok(StepInfo::new("map", Some(map_cmd_string), map_duration))
 }
 
-fn macs_call_peaks(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
+/// Builds the macs3 peak calling command, and returns it along with its input files.
+fn macs_cmd(
+    af_home_path: &Path,
+    opts: &ProcessOpts,
+) -> anyhow::Result<(std::process::Command, Vec<PathBuf>)> {
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     let macs_prog_info = rp
         .macs
@@ -394,8 +388,7 @@ fn macs_call_peaks(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()
     let bedsuf = if opts.compress { ".bed.gz" } else { ".bed" };
     let bed_input = gpl_dir.join(format!("map{}", bedsuf));
     let peaks_output = gpl_dir.join("macs");
-    let mut macs_cmd =
-        std::process::Command::new(format!("{}", &macs_prog_info.exe_path.display()));
+    let mut macs_cmd = executor.command(&macs_prog_info.exe_path);
     macs_cmd
         .arg("callpeak")
         .arg("-f")
@@ -410,16 +403,25 @@ fn macs_call_peaks(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()
         .arg("-g")
         .arg(opts.gsize.as_arg_str())
         .arg("-t")
-        .arg(bed_input)
+        .arg(&bed_input)
         .arg("-n")
         .arg(peaks_output);
+    Ok((macs_cmd, vec![bed_input]))
+}
 
+fn macs_call_peaks(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<StepInfo> {
+    let (mut macs_cmd, _) = macs_cmd(af_home_path, opts)?;
     let macs_cmd_string = prog_utils::get_cmd_line_string(&macs_cmd);
     info!("macs3 command : {}", macs_cmd_string);
 
     let macs_start = Instant::now();
-    let macs_proc_out = prog_utils::execute_command(&mut macs_cmd, CommandVerbosityLevel::Quiet)
-        .expect("could not execute [atac::macs]");
+    let macs_proc_out = prog_utils::execute_logged_command(
+        &mut macs_cmd,
+        CommandVerbosityLevel::global(),
+        &opts.output.join(LOGS_DIR),
+        "macs3_callpeak",
+    )
+    .expect("could not execute [atac::macs]");
     let macs_duration = macs_start.elapsed();
 
     if !macs_proc_out.status.success() {
@@ -431,47 +433,126 @@ fn macs_call_peaks(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()
         info!("macs completed successfully in {:#?}", macs_duration);
     }
 
-    let af_process_info_file = opts.output.join("simpleaf_process_log.json");
-    let json_file = std::fs::File::open(af_process_info_file.clone())
-        .with_context(|| format!("couldn't open file {}", af_process_info_file.display()))?;
-    let json_reader = BufReader::new(json_file);
-    let mut af_process_info: serde_json::Value = serde_json::from_reader(json_reader)
-        .with_context(|| {
-            format!(
-                "couldn't parse JSON content from {}",
-                af_process_info_file.display()
-            )
-        })?;
-
-    af_process_info["time_info"]["macs_time"] = json!(macs_duration.as_secs_f64());
-    af_process_info["cmd_info"]["macs_cmd"] = json!(macs_cmd_string);
-
-    // write the relevant info about
-    // our run to file.
-    std::fs::write(
-        &af_process_info_file,
-        serde_json::to_string_pretty(&af_process_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", af_process_info_file.display()))?;
-
     info!("successfully called peaks using macs3.");
 
-    Ok(())
+    Ok(StepInfo::new("macs", Some(macs_cmd_string), macs_duration))
 }
 
-pub(crate) fn gen_bed(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
-    af_gpl(af_home_path, opts)?;
-    af_sort(af_home_path, opts)?;
-    macs_call_peaks(af_home_path, opts)?;
-    Ok(())
+pub fn gen_bed(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<Vec<StepInfo>> {
+    let pl_file = resolve_permit_list(af_home_path, opts)?;
+    let mut steps = vec![
+        af_gpl(af_home_path, opts, &pl_file)?,
+        af_sort(af_home_path, opts)?,
+    ];
+    if opts.call_peaks {
+        steps.push(macs_call_peaks(af_home_path, opts)?);
+    }
+    Ok(steps)
 }
 
-// NOTE: we assume that check_progs has already been called and so version constraints have
-// already been checked.
-fn af_sort(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
+/// The outcome of a successful [`process`] run.
+#[derive(Clone, Debug)]
+pub struct ProcessResult {
+    /// The top-level output directory
+    pub output_dir: PathBuf,
+    /// The directory holding the mapping (RAD) output
+    pub map_dir: PathBuf,
+    /// The directory holding the permit list and the BED file
+    pub process_dir: PathBuf,
+    /// The sorted and deduplicated BED file of fragments
+    pub bed_file: PathBuf,
+    /// The peaks called by macs3, if peak calling was requested
+    pub peaks_file: Option<PathBuf>,
+    /// The steps that were run, with their commands and running times
+    pub steps: Vec<StepInfo>,
+}
+
+/// Runs the full ATAC-seq processing pipeline; the reads are mapped, the
+/// barcodes are corrected and the BED file is generated (and, optionally,
+/// peaks are called).
+pub fn process(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<ProcessResult> {
+    // validate versions
+    check_progs(af_home_path, opts)?;
+
+    if opts.dry_run {
+        return plan_process(af_home_path, opts);
+    }
+
+    let process_dir = opts.output.join("af_process");
+    let record_file = opts.output.join(PROCESS_RUN_RECORD_FILE);
+    let mut run_record = RunRecord::new(RunDetails::AtacProcess {
+        mapper: String::from("piscem"),
+        map_dir: opts.output.join("af_map"),
+        process_dir: process_dir.clone(),
+    });
+    run_record.options = opts.effective_options.clone();
+    // record each step as soon as it completes, so that a failed run still
+    // leaves a record of the steps that did complete.
+    let mut record_step = |step: StepInfo| -> anyhow::Result<()> {
+        run_record.steps.push(step);
+        run_record.write(&record_file)
+    };
+
+    // the permit list is resolved (and possibly downloaded) before any work
+    // is done, and recorded in the provenance as is
+    let pl_file = resolve_permit_list(af_home_path, opts)?;
+    // first we map the reads
+    record_step(map_reads(af_home_path, opts)?)?;
+    // then we generate the permit list and sort the file
+    record_step(af_gpl(af_home_path, opts, &pl_file)?)?;
+    record_step(af_sort(af_home_path, opts)?)?;
+    if opts.call_peaks {
+        record_step(macs_call_peaks(af_home_path, opts)?)?;
+    }
+    let steps = run_record.steps;
+
+    let bedsuf = if opts.compress { ".bed.gz" } else { ".bed" };
+    let bed_file = process_dir.join(format!("map{}", bedsuf));
+    let peaks_file = opts
+        .call_peaks
+        .then(|| process_dir.join("macs_peaks.narrowPeak"));
+
+    let v: Value = prog_utils::inspect_af_home(af_home_path)?;
+    let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let chem = af_utils::Chemistry::Atac(opts.chemistry);
+    let mut provenance = Provenance::new("atac process", &rp);
+    provenance.set_chemistry(&chem, af_home_path)?;
+    for (role, reads) in [
+        ("reads1", &opts.reads1),
+        ("reads2", &opts.reads2),
+        ("reads", &opts.reads),
+    ] {
+        if let Some(reads) = reads {
+            provenance.add_inputs(role, reads)?;
+        }
+    }
+    provenance.add_inputs("barcode_reads", &opts.barcode_reads)?;
+    provenance.add_input("permit_list", &pl_file)?;
+    provenance.add_output("bed", &bed_file)?;
+    if let Some(peaks_file) = peaks_file.as_ref().filter(|p| p.exists()) {
+        provenance.add_output("peaks", peaks_file)?;
+    }
+    provenance.write(&opts.output)?;
+
+    Ok(ProcessResult {
+        output_dir: opts.output.clone(),
+        map_dir: opts.output.join("af_map"),
+        bed_file,
+        peaks_file,
+        process_dir,
+        steps,
+    })
+}
+
+/// Builds the alevin-fry sort command, and returns it along with its input files.
+fn sort_cmd(
+    af_home_path: &Path,
+    opts: &ProcessOpts,
+) -> anyhow::Result<(std::process::Command, Vec<PathBuf>)> {
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     let af_prog_info = rp
         .alevin_fry
@@ -480,14 +561,14 @@ fn af_sort(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
 
     let gpl_dir = opts.output.join("af_process");
     let rad_dir = opts.output.join("af_map");
-    let mut af_sort = std::process::Command::new(format!("{}", &af_prog_info.exe_path.display()));
+    let mut af_sort = executor.command(&af_prog_info.exe_path);
     af_sort
         .arg("atac")
         .arg("sort")
         .arg("--input-dir")
-        .arg(gpl_dir)
+        .arg(&gpl_dir)
         .arg("--rad-dir")
-        .arg(rad_dir);
+        .arg(&rad_dir);
 
     // if the user requested more threads than can be used
     let mut threads = opts.threads;
@@ -507,13 +588,73 @@ fn af_sort(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
     if opts.compress {
         af_sort.arg("--compress");
     }
+    Ok((af_sort, vec![gpl_dir, rad_dir]))
+}
 
+/// Prints the plan of the run of [`process`] for `opts` (see `--dry-run`):
+/// the commands that would be run along with the state of their inputs.
+/// Nothing is run or written.
+fn plan_process(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<ProcessResult> {
+    let map_dir = opts.output.join("af_map");
+    let process_dir = opts.output.join("af_process");
+    let bedsuf = if opts.compress { ".bed.gz" } else { ".bed" };
+    let bed_file = process_dir.join(format!("map{}", bedsuf));
+    let peaks_file = opts
+        .call_peaks
+        .then(|| process_dir.join("macs_peaks.narrowPeak"));
+
+    let mut plan = DryRunPlan::new("simpleaf atac process");
+    plan.resolve("chemistry", opts.chemistry.as_str());
+    plan.resolve("output", opts.output.display());
+
+    let (cmd, inputs) = map_cmd(af_home_path, opts)?;
+    plan.add_step("map", Some(&cmd), &inputs, std::slice::from_ref(&map_dir));
+    let pl_file = resolve_permit_list(af_home_path, opts)?;
+    let (cmd, inputs) = gpl_cmd(af_home_path, opts, &pl_file)?;
+    plan.add_step(
+        "gpl",
+        Some(&cmd),
+        &inputs,
+        std::slice::from_ref(&process_dir),
+    );
+    let (cmd, inputs) = sort_cmd(af_home_path, opts)?;
+    plan.add_step("sort", Some(&cmd), &inputs, std::slice::from_ref(&bed_file));
+    if let Some(peaks_file) = &peaks_file {
+        let (cmd, inputs) = macs_cmd(af_home_path, opts)?;
+        plan.add_step(
+            "macs",
+            Some(&cmd),
+            &inputs,
+            std::slice::from_ref(peaks_file),
+        );
+    }
+    plan.finish()?;
+
+    Ok(ProcessResult {
+        output_dir: opts.output.clone(),
+        map_dir,
+        bed_file,
+        peaks_file,
+        process_dir,
+        steps: plan.steps(),
+    })
+}
+
+// NOTE: we assume that check_progs has already been called and so version constraints have
+// already been checked.
+fn af_sort(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<StepInfo> {
+    let (mut af_sort, _) = sort_cmd(af_home_path, opts)?;
     let sort_cmd_string = prog_utils::get_cmd_line_string(&af_sort);
     info!("sort command : {}", sort_cmd_string);
 
     let af_sort_start = Instant::now();
-    let af_sort_proc_out = prog_utils::execute_command(&mut af_sort, CommandVerbosityLevel::Quiet)
-        .expect("could not execute [atac::af_sort]");
+    let af_sort_proc_out = prog_utils::execute_logged_command(
+        &mut af_sort,
+        CommandVerbosityLevel::global(),
+        &opts.output.join(LOGS_DIR),
+        "af_atac_sort",
+    )
+    .expect("could not execute [atac::af_sort]");
     let af_sort_duration = af_sort_start.elapsed();
 
     if !af_sort_proc_out.status.success() {
@@ -525,104 +666,62 @@ fn af_sort(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
         info!("sort completed successfully in {:#?}", af_sort_duration);
     }
 
-    let af_process_info_file = opts.output.join("simpleaf_process_log.json");
-    let json_file = std::fs::File::open(af_process_info_file.clone())
-        .with_context(|| format!("couldn't open file {}", af_process_info_file.display()))?;
-    let json_reader = BufReader::new(json_file);
-    let mut af_process_info: serde_json::Value = serde_json::from_reader(json_reader)
-        .with_context(|| {
-            format!(
-                "couldn't parse JSON content from {}",
-                af_process_info_file.display()
-            )
-        })?;
-
-    af_process_info["time_info"]["sort_time"] = json!(af_sort_duration.as_secs_f64());
-    af_process_info["cmd_info"]["sort_cmd"] = json!(sort_cmd_string);
-
-    // write the relevant info about
-    // our run to file.
-    std::fs::write(
-        &af_process_info_file,
-        serde_json::to_string_pretty(&af_process_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", af_process_info_file.display()))?;
-
     info!("successfully sorted and deduplicated records and created the output BED file.");
-    Ok(())
+    Ok(StepInfo::new(
+        "sort",
+        Some(sort_cmd_string),
+        af_sort_duration,
+    ))
 }
 
-// NOTE: we assume that check_progs has already been called and so version constraints have
-// already been checked.
-fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
+/// The unfiltered permit list to correct the barcodes against: the one passed to
+/// `--unfiltered-pl`, or the one of the chemistry, obtained through
+/// [`af_utils::get_permit_if_absent`].
+fn resolve_permit_list(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<PathBuf> {
+    if let Some(pl_file) = &opts.unfiltered_pl {
+        // the user has explicily passed a file along, so try
+        // to use that
+        if !pl_file.is_file() {
+            bail!(
+                "The provided path {} does not exist as a regular file.",
+                pl_file.display()
+            );
+        }
+        return Ok(pl_file.clone());
+    }
+    // no file is provided, so we will try to get the permit list
+    // automatically for the chemistry
+    let rc = af_utils::Chemistry::Atac(opts.chemistry);
+    match af_utils::get_permit_if_absent(af_home_path, &rc)? {
+        af_utils::PermitListResult::DownloadSuccessful(p)
+        | af_utils::PermitListResult::AlreadyPresent(p) => Ok(p),
+        af_utils::PermitListResult::UnregisteredChemistry => {
+            bail!(
+                "Cannot automatically obtain an unfiltered permit list for an unregistered chemistry : {}.",
+                opts.chemistry.as_str()
+            );
+        }
+    }
+}
+
+/// Builds the alevin-fry generate-permit-list command, correcting the barcodes
+/// against the permit list `pl_file` (see [`resolve_permit_list`]), and returns
+/// it along with its input files.
+fn gpl_cmd(
+    af_home_path: &Path,
+    opts: &ProcessOpts,
+    pl_file: &Path,
+) -> anyhow::Result<(std::process::Command, Vec<PathBuf>)> {
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     let af_prog_info = rp
         .alevin_fry
         .as_ref()
         .expect("alevin-fry program info should be properly set.");
 
-    let filter_meth_opt;
-
-    use crate::utils::af_utils;
-    // based on the filtering method
-    let pl_file = &opts.unfiltered_pl;
-    {
-        // NOTE: unfiltered_pl is of type Option<Option<PathBuf>> so being in here
-        // tells us nothing about the inner option.  We handle that now.
-
-        // if the -u flag is passed and some file is provided, then the inner
-        // Option is Some(PathBuf)
-        if let Some(pl_file) = pl_file {
-            // the user has explicily passed a file along, so try
-            // to use that
-            if pl_file.is_file() {
-                let min_cells = opts.min_reads;
-                filter_meth_opt = Some(af_utils::CellFilterMethod::UnfilteredExternalList(
-                    pl_file.to_string_lossy().into_owned(),
-                    min_cells,
-                ));
-            } else {
-                bail!(
-                    "The provided path {} does not exist as a regular file.",
-                    pl_file.display()
-                );
-            }
-        } else {
-            // here, the -u flag is provided
-            // but no file is provided, then the
-            // inner option is None and we will try to get the permit list automatically if
-            // using 10xv2, 10xv3, or 10x-multi
-
-            // check the chemistry
-            let rc = af_utils::Chemistry::Atac(opts.chemistry);
-            let pl_res = af_utils::get_permit_if_absent(af_home_path, &rc)?;
-            let min_cells = opts.min_reads;
-            match pl_res {
-                af_utils::PermitListResult::DownloadSuccessful(p)
-                | af_utils::PermitListResult::AlreadyPresent(p) => {
-                    filter_meth_opt = Some(af_utils::CellFilterMethod::UnfilteredExternalList(
-                        p.to_string_lossy().into_owned(),
-                        min_cells,
-                    ));
-                }
-                af_utils::PermitListResult::UnregisteredChemistry => {
-                    bail!(
-                        "Cannot automatically obtain an unfiltered permit list for an unregistered chemistry : {}.",
-                        opts.chemistry.as_str()
-                    );
-                }
-            }
-        }
-    }
-    /*else {
-        bail!(
-            "Only the unfiltered permit list option is currently supported in atac-seq processing."
-        );
-    }*/
-
     // see if we need to reverse complement barcodes
     let custom_chem_p = af_home_path.join(CHEMISTRIES_PATH);
     let permit_bc_ori = if let Some(ori) = &opts.permit_barcode_ori {
@@ -668,7 +767,8 @@ fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
     };
 
     let map_file = opts.output.join("af_map");
-    let mut af_gpl = std::process::Command::new(format!("{}", &af_prog_info.exe_path.display()));
+    let mut input_files = vec![map_file.clone()];
+    let mut af_gpl = executor.command(&af_prog_info.exe_path);
     af_gpl
         .arg("atac")
         .arg("generate-permit-list")
@@ -680,17 +780,9 @@ fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
     let out_dir = opts.output.join("af_process");
     af_gpl.arg("--output-dir").arg(out_dir);
 
-    if let Some(fm) = filter_meth_opt {
-        match fm {
-            af_utils::CellFilterMethod::UnfilteredExternalList(p, _mc) => {
-                af_gpl.arg("-u").arg(p);
-                af_gpl.arg("--min-reads").arg(format!("{}", opts.min_reads));
-            }
-            _ => bail!("unsupported filter method in atac-seq process."),
-        }
-    } else {
-        bail!("unsupported filter method in atac-seq process.");
-    }
+    af_gpl.arg("-u").arg(pl_file);
+    input_files.push(pl_file.to_path_buf());
+    af_gpl.arg("--min-reads").arg(format!("{}", opts.min_reads));
 
     // if the user requested more threads than can be used
     let mut threads = opts.threads;
@@ -706,13 +798,24 @@ fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
         }
     }
     af_gpl.arg("--threads").arg(format!("{}", threads));
+    Ok((af_gpl, input_files))
+}
 
+// NOTE: we assume that check_progs has already been called and so version constraints have
+// already been checked.
+fn af_gpl(af_home_path: &Path, opts: &ProcessOpts, pl_file: &Path) -> anyhow::Result<StepInfo> {
+    let (mut af_gpl, _) = gpl_cmd(af_home_path, opts, pl_file)?;
     let gpl_cmd_string = prog_utils::get_cmd_line_string(&af_gpl);
     info!("gpl command : {}", gpl_cmd_string);
 
     let af_gpl_start = Instant::now();
-    let af_gpl_proc_out = prog_utils::execute_command(&mut af_gpl, CommandVerbosityLevel::Quiet)
-        .expect("could not execute [atac::af_gpl]");
+    let af_gpl_proc_out = prog_utils::execute_logged_command(
+        &mut af_gpl,
+        CommandVerbosityLevel::global(),
+        &opts.output.join(LOGS_DIR),
+        "af_atac_generate_permit_list",
+    )
+    .expect("could not execute [atac::af_gpl]");
     let af_gpl_duration = af_gpl_start.elapsed();
 
     if !af_gpl_proc_out.status.success() {
@@ -727,29 +830,6 @@ fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<()> {
         );
     }
 
-    let af_process_info_file = opts.output.join("simpleaf_process_log.json");
-    let json_file = std::fs::File::open(af_process_info_file.clone())
-        .with_context(|| format!("couldn't open file {}", af_process_info_file.display()))?;
-    let json_reader = BufReader::new(json_file);
-    let mut af_process_info: serde_json::Value = serde_json::from_reader(json_reader)
-        .with_context(|| {
-            format!(
-                "couldn't parse JSON content from {}",
-                af_process_info_file.display()
-            )
-        })?;
-
-    af_process_info["time_info"]["gpl_time"] = json!(af_gpl_duration.as_secs_f64());
-    af_process_info["cmd_info"]["gpl_cmd"] = json!(gpl_cmd_string);
-
-    // write the relevant info about
-    // our run to file.
-    std::fs::write(
-        &af_process_info_file,
-        serde_json::to_string_pretty(&af_process_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", af_process_info_file.display()))?;
-
     info!("successfully performed cell barcode detection and correction.");
-    Ok(())
+    Ok(StepInfo::new("gpl", Some(gpl_cmd_string), af_gpl_duration))
 }
@@ -0,0 +1,171 @@
+//! simpleaf as a library.
+//!
+//! Besides the `simpleaf` command line program, this crate exposes the
+//! functionality behind its main commands so that they can be driven from
+//! other Rust programs. The [`api`] module provides typed builders for
+//! building a reference and index, running mapping and quantification and
+//! processing ATAC-seq data, as well as access to the chemistry registry.
+//! Each builder returns a structured result describing the files it
+//! produced and the steps it ran.
+
+use clap::{crate_version, Parser};
+use std::path::PathBuf;
+
+pub mod api;
+pub mod atac;
+pub mod defaults;
+pub mod simpleaf_commands;
+pub mod utils;
+
+use simpleaf_commands::chemistry::{
+    add_chemistry, clean_chemistries, detect_chemistry, fetch_chemistries, infer_permit_list,
+    lookup_chemistry, refresh_chemistries, remove_chemistry, verify_chemistries,
+};
+use simpleaf_commands::*;
+use utils::home::SimpleafHome;
+
+/// simplifying alevin-fry workflows
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+#[command(propagate_version = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Commands,
+
+    /// Stream the output of the external programs (e.g. piscem, salmon and alevin-fry) to the
+    /// log as they run, rather than only reporting it if they fail
+    #[arg(long, global = true)]
+    pub verbose: bool,
+
+    /// Never access the network: fail right away if a resource (e.g. a permit list or the
+    /// workflow registry) is missing. This can also be set with the `SIMPLEAF_OFFLINE`
+    /// environment variable
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// The directory holding the configuration and data of simpleaf (overrides `ALEVIN_FRY_HOME`).
+    /// Without either, the configuration is kept in `$XDG_CONFIG_HOME/simpleaf` and the data in
+    /// `$XDG_DATA_HOME/simpleaf`
+    #[arg(long, global = true, value_name = "DIR")]
+    pub af_home: Option<PathBuf>,
+}
+
+/// Runs the simpleaf `command`, with the simpleaf home `af_home`. This is
+/// what the `simpleaf` program does once it has parsed its command line, and
+/// what a workflow does for each of its simpleaf commands.
+pub fn run_command(command: Commands, af_home: &SimpleafHome) -> anyhow::Result<()> {
+    let af_home_path = af_home.data_dir.as_path();
+    match command {
+        // set the paths where the relevant tools live
+        Commands::SetPaths(sp_opts) => set_paths(af_home_path.to_path_buf(), sp_opts),
+        Commands::SetExecutor(se_opts) => set_executor(af_home_path.to_path_buf(), se_opts),
+        Commands::SetNetwork(sn_opts) => set_network(af_home_path.to_path_buf(), sn_opts),
+        // chemistry related commands
+        Commands::Chemistry(ChemistryCommand::Add(add_opts)) => {
+            add_chemistry(af_home_path.to_path_buf(), add_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Remove(rem_opts)) => {
+            remove_chemistry(af_home_path.to_path_buf(), rem_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Clean(clean_opts)) => {
+            clean_chemistries(af_home_path.to_path_buf(), clean_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Lookup(lookup_opts)) => {
+            lookup_chemistry(af_home_path.to_path_buf(), lookup_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Refresh(refresh_opts)) => {
+            refresh_chemistries(af_home_path.to_path_buf(), refresh_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Fetch(fetch_opts)) => {
+            fetch_chemistries(af_home_path.to_path_buf(), fetch_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Verify(verify_opts)) => {
+            verify_chemistries(af_home_path.to_path_buf(), verify_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Detect(detect_opts)) => {
+            detect_chemistry(af_home_path.to_path_buf(), detect_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::InferPl(infer_opts)) => {
+            infer_permit_list(af_home_path.to_path_buf(), infer_opts)
+        }
+        // Inspect the status of simpleaf
+        Commands::Inspect {} => inspect_simpleaf(crate_version!(), af_home_path.to_path_buf()),
+        // re-refresh the versions information of all of the programs
+        Commands::RefreshProgInfo {} => refresh_prog_info(af_home_path.to_path_buf()),
+
+        // if we are building the reference and indexing
+        Commands::Index(index_opts) => build_ref_and_index(af_home_path, index_opts).map(|_| ()),
+
+        // if we are running mapping and quantification
+        Commands::Quant(map_quant_opts) => map_and_quant(af_home_path, map_quant_opts).map(|_| ()),
+
+        // if we are summarizing the results of a quantification run
+        Commands::Report(report_opts) => generate_report(report_opts),
+
+        // if we are calling the cells of a quantification run
+        Commands::CallCells(call_cells_opts) => call_cells(call_cells_opts),
+
+        // if we are moving the downloaded resources to or from another machine
+        Commands::Bundle(BundleCommand::Export(export_opts)) => {
+            export_bundle(af_home_path, export_opts)
+        }
+        Commands::Bundle(BundleCommand::Import(import_opts)) => {
+            import_bundle(af_home_path, import_opts)
+        }
+
+        // if we are moving the simpleaf home to the XDG base directories
+        Commands::Home(HomeCommand::Migrate(migrate_opts)) => migrate_home(af_home, migrate_opts),
+
+        // if we are reading or changing the defaults set in simpleaf.toml
+        Commands::Config(ConfigCommand::Get(get_opts)) => get_config(af_home, get_opts),
+        Commands::Config(ConfigCommand::Set(set_opts)) => set_config(af_home, set_opts),
+        Commands::Config(ConfigCommand::Unset(unset_opts)) => unset_config(af_home, unset_opts),
+        Commands::Config(ConfigCommand::List(list_opts)) => list_config(af_home, list_opts),
+
+        // if we are inspecting or comparing run records
+        Commands::Log(LogCommand::Show(show_opts)) => show_log(show_opts),
+        Commands::Log(LogCommand::Diff(diff_opts)) => diff_logs(diff_opts),
+
+        // indexing for ATAC-seq data
+        Commands::Atac(AtacCommand::Index(index_opts)) => {
+            atac::index::piscem_index(af_home_path, &index_opts)
+        }
+
+        // processing for ATAC-seq data
+        Commands::Atac(AtacCommand::Process(process_opts)) => {
+            atac::process::process(af_home_path, &process_opts).map(|_| ())
+        }
+
+        Commands::Workflow(workflow_args) => match workflow_args.command {
+            // if we are running or parsing a
+            // workflow file.
+            cmd @ WorkflowCommands::Run { .. } => run_workflow(af_home_path, cmd),
+
+            // if we are generating a workflow
+            // configuration from a workflow template.
+            cmd @ WorkflowCommands::Get { .. } => get_workflow(af_home_path, cmd),
+
+            cmd @ WorkflowCommands::Patch { .. } => patch_manifest_or_template(af_home_path, cmd),
+
+            WorkflowCommands::Validate(source) => validate_workflow(af_home_path, source),
+            WorkflowCommands::Graph {
+                source,
+                format,
+                out_file,
+            } => graph_workflow(af_home_path, source, format, out_file),
+            WorkflowCommands::List {} => list_workflows(af_home_path),
+            WorkflowCommands::Refresh {} => refresh_protocol_estuary(af_home_path),
+            WorkflowCommands::Registry(RegistryCommand::Add(add_opts)) => {
+                add_registry(af_home_path, add_opts)
+            }
+            WorkflowCommands::Registry(RegistryCommand::Remove(rem_opts)) => {
+                remove_registry(af_home_path, rem_opts)
+            }
+            WorkflowCommands::Registry(RegistryCommand::List) => list_registries(af_home_path),
+        },
+    }
+    // success, yay!
+    // we should not need an explicit value here as the
+    // match above is exhaustive, and each command should
+    // return an appropriate `Result`.
+}
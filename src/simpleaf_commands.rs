@@ -7,7 +7,7 @@ pub use self::refresh::refresh_prog_info;
 pub mod chemistry;
 
 pub mod paths;
-pub use self::paths::set_paths;
+pub use self::paths::{set_executor, set_network, set_paths};
 
 pub mod indexing;
 pub use self::indexing::build_ref_and_index;
@@ -15,15 +15,39 @@ pub use self::indexing::build_ref_and_index;
 pub mod quant;
 pub use self::quant::map_and_quant;
 
+pub mod report;
+pub use self::report::generate_report;
+
+pub mod call_cells;
+pub use self::call_cells::call_cells;
+
+pub mod log;
+pub use self::log::{diff_logs, show_log};
+
+pub mod bundle;
+pub use self::bundle::{export_bundle, import_bundle};
+
+pub mod home;
+pub use self::home::migrate_home;
+
+pub mod config;
+pub use self::config::{get_config, list_config, set_config, unset_config};
+
 pub mod workflow;
 pub use self::workflow::{
-    get_workflow, list_workflows, patch_manifest_or_template, refresh_protocol_estuary,
-    run_workflow,
+    add_registry, get_workflow, graph_workflow, list_registries, list_workflows,
+    patch_manifest_or_template, refresh_protocol_estuary, remove_registry, run_workflow,
+    validate_workflow,
 };
 
 pub use crate::atac::commands::AtacCommand;
 pub use crate::defaults::{DefaultMappingParams, DefaultParams};
 
+use crate::utils::chem_detect::DEFAULT_NUM_SAMPLED_READS;
+use crate::utils::executor::ContainerRuntime;
+use crate::utils::run_record::EffectiveOptions;
+use crate::utils::workflow_jobs::WorkflowExecutor;
+use crate::utils::workflow_utils::GraphFormat;
 use clap::{builder::ArgPredicate, ArgAction, ArgGroup, Args, Subcommand};
 use std::path::PathBuf;
 
@@ -59,7 +83,8 @@ fn ref_type_parser(s: &str) -> Result<ReferenceType, String> {
     .args(["index", "map_dir"])
 ))]
 pub struct MapQuantOpts {
-    /// The name of a registered chemistry or a quoted string representing a custom geometry specification.
+    /// The name of a registered chemistry, a quoted string representing a custom geometry
+    /// specification, or `auto` to detect the chemistry from the reads (see `simpleaf chemistry detect`).
     #[arg(short, long)]
     pub chemistry: String,
 
@@ -71,16 +96,18 @@ pub struct MapQuantOpts {
     #[arg(short, long, default_value_t = 16)]
     pub threads: u32,
 
+    /// Resume a previous run in the same output directory, skipping the stages that completed
+    /// and whose inputs and settings have not changed since.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Print the resolved chemistry, permit list and filtering method and the commands that
+    /// would be run, checking that their inputs exist, without running anything.
+    #[arg(long)]
+    pub dry_run: bool,
+
     /// Path to a folder containing the index files
-    #[arg(
-        short = 'i',
-        long = "index",
-        help_heading = "Mapping Options",
-        requires_ifs([
-            (ArgPredicate::IsPresent, "reads1"),
-            (ArgPredicate::IsPresent, "reads2")
-        ])
-    )]
+    #[arg(short = 'i', long = "index", help_heading = "Mapping Options")]
     pub index: Option<PathBuf>,
 
     /// Comma-separated list of paths to read 1 files. The order must match the read 2 files.
@@ -90,6 +117,7 @@ pub struct MapQuantOpts {
         help_heading = "Mapping Options",
         value_delimiter = ',',
         requires = "index",
+        required_unless_present_any = ["map_dir", "sample_sheet"],
         conflicts_with = "map_dir"
     )]
     pub reads1: Option<Vec<PathBuf>>,
@@ -101,10 +129,37 @@ pub struct MapQuantOpts {
         help_heading = "Mapping Options",
         value_delimiter = ',',
         requires = "index",
+        required_unless_present_any = ["map_dir", "sample_sheet"],
         conflicts_with = "map_dir"
     )]
     pub reads2: Option<Vec<PathBuf>>,
 
+    /// Path to a CSV/TSV sample sheet with the columns `sample`, `reads1`, `reads2` and,
+    /// optionally, `chemistry` and `permit_list`. Each sample is processed against the
+    /// provided index and written to `<OUTPUT>/<sample>`.
+    #[arg(
+        long,
+        help_heading = "Mapping Options",
+        requires = "index",
+        conflicts_with_all = ["reads1", "reads2", "map_dir"]
+    )]
+    pub sample_sheet: Option<PathBuf>,
+
+    /// Don't check the read files before mapping. By default, the names and lengths of the first
+    /// reads of each pair of files are checked against each other and the chemistry.
+    #[arg(long, requires = "index", help_heading = "Mapping Options")]
+    pub skip_read_checks: bool,
+
+    /// Before mapping, also read every read file completely to check that it is not truncated
+    /// and that both files of a pair have the same number of reads
+    #[arg(
+        long,
+        requires = "index",
+        conflicts_with = "skip_read_checks",
+        help_heading = "Mapping Options"
+    )]
+    pub check_integrity: bool,
+
     // It's currently very confusing to have both `--foo` and
     // `--no-foo` fields in derive mode with `--foo` as the default.
     // The following hack was taken from: https://jwodder.github.io/kbits/posts/clap-bool-negate
@@ -219,6 +274,17 @@ pub struct MapQuantOpts {
     #[arg(short, long, help_heading = "Permit List Generation Options")]
     pub unfiltered_pl: Option<Option<PathBuf>>,
 
+    /// When `--unfiltered-pl` is given without a file, infer the unfiltered permit list from the
+    /// barcodes of the reads, rather than using the permit list registered for the chemistry (see
+    /// `simpleaf chemistry infer-pl`)
+    #[arg(
+        long,
+        requires = "unfiltered_pl",
+        conflicts_with = "map_dir",
+        help_heading = "Permit List Generation Options"
+    )]
+    pub infer_pl: bool,
+
     /// Use forced number of cells
     #[arg(short, long, help_heading = "Permit List Generation Options")]
     pub forced_cells: Option<usize>,
@@ -256,6 +322,56 @@ pub struct MapQuantOpts {
     /// output.
     #[arg(long, help_heading = "Output Options")]
     pub anndata_out: bool,
+
+    /// Write a QC report (in HTML and JSON format) summarizing the quantification results
+    /// into the output directory.
+    #[arg(long, help_heading = "Output Options")]
+    pub report: bool,
+
+    /// Call the cells of the count matrix by testing the barcodes against the ambient RNA
+    /// profile (see `simpleaf call-cells`, whose defaults are used); requires `--unfiltered-pl`
+    #[arg(long, requires = "unfiltered_pl", help_heading = "Output Options")]
+    pub call_cells: bool,
+    /// The effective value of each option, recorded in the run record (filled in when the
+    /// command line is parsed)
+    #[arg(skip)]
+    pub effective_options: EffectiveOptions,
+}
+
+/// Call the cells of the output of `simpleaf quant` by testing each barcode against the ambient RNA
+/// profile (in the style of EmptyDrops)
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct CallCellsOpts {
+    /// Path to the output directory of a `simpleaf quant` run that used an unfiltered permit list
+    #[arg(short, long)]
+    pub input: PathBuf,
+    /// Barcodes with at most this many UMIs are taken to be empty droplets, making up the ambient
+    /// RNA profile; the other barcodes are tested
+    #[arg(long, default_value_t = 100)]
+    pub lower: u64,
+    /// Number of Monte-Carlo iterations used to compute the p-values
+    #[arg(long, default_value_t = 10_000)]
+    pub niters: usize,
+    /// False discovery rate below which a barcode is called a cell
+    #[arg(long, default_value_t = 0.001)]
+    pub fdr: f64,
+    /// Call the barcodes with at least this many UMIs cells without testing them
+    #[arg(long)]
+    pub retain: Option<u64>,
+    /// Seed of the random number generator
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+    /// Number of threads to use when running
+    #[arg(short, long, default_value_t = 16)]
+    pub threads: u32,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ReportOpts {
+    /// Path to the output directory of a `simpleaf quant` run
+    #[arg(short, long)]
+    pub input: PathBuf,
 }
 
 #[derive(Args, Clone, Debug)]
@@ -416,6 +532,11 @@ pub struct IndexOpts {
     #[arg(long, display_order = 6)]
     pub overwrite: bool,
 
+    /// Print the commands that would be run to build the index, checking that their inputs
+    /// exist, without running anything.
+    #[arg(long, display_order = 7)]
+    pub dry_run: bool,
+
     /// Number of threads to use when running
     #[arg(short, long, default_value_t = 16, display_order = 2)]
     pub threads: u32,
@@ -453,6 +574,10 @@ pub struct IndexOpts {
     #[arg(long, help_heading = "Direct Reference Options", display_order = 7,
     conflicts_with_all = ["dedup", "unspliced", "spliced", "rlen", "gtf", "fasta", "ref_seq", "probe_csv"])]
     pub feature_csv: Option<PathBuf>,
+    /// The effective value of each option, recorded in the run record (filled in when the
+    /// command line is parsed)
+    #[arg(skip)]
+    pub effective_options: EffectiveOptions,
 }
 
 /// Remove chemistries from the local chemistry registry
@@ -488,6 +613,53 @@ pub struct ChemistryCleanOpts {
     pub dry_run: bool,
 }
 
+/// Re-hash the cached permit list files, and report those whose content does not match their hash
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = false)]
+pub struct ChemistryVerifyOpts {
+    /// Remove the corrupt permit list file(s), so that they are downloaded again when needed
+    #[arg(short, long)]
+    pub remove: bool,
+}
+
+/// Rank the registered chemistries by how well they fit the first reads of a sample
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ChemistryDetectOpts {
+    /// Path to a read 1 file of the sample
+    #[arg(short = '1', long = "reads1")]
+    pub reads1: PathBuf,
+    /// Path to the read 2 file matching the read 1 file
+    #[arg(short = '2', long = "reads2")]
+    pub reads2: PathBuf,
+    /// Number of read pairs sampled from the start of the read files
+    #[arg(short, long, default_value_t = DEFAULT_NUM_SAMPLED_READS)]
+    pub num_reads: usize,
+}
+
+/// Infer an unfiltered permit list from the barcodes of the reads of a sample
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ChemistryInferPlOpts {
+    /// The name of a registered chemistry or a quoted string representing a custom geometry
+    /// specification, whose barcode must be at a fixed position in the reads
+    #[arg(short, long)]
+    pub chemistry: String,
+    /// Comma-separated list of paths to read 1 files. The order must match the read 2 files.
+    #[arg(short = '1', long = "reads1", value_delimiter = ',', required = true)]
+    pub reads1: Vec<PathBuf>,
+    /// Comma-separated list of paths to read 2 files. The order must match the read 1 files.
+    #[arg(short = '2', long = "reads2", value_delimiter = ',', required = true)]
+    pub reads2: Vec<PathBuf>,
+    /// Path to the output directory, to which the permit list and its statistics are written
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Minimum number of reads of a barcode for it to be kept in the permit list (not counting the
+    /// reads of the barcodes one substitution away that are merged into it)
+    #[arg(long, default_value_t = 10)]
+    pub min_reads: u64,
+}
+
 /// Look up chemistries in the local registry and print the details
 #[derive(Args, Clone, Debug)]
 #[command(arg_required_else_help = true)]
@@ -559,6 +731,126 @@ pub enum ChemistryCommand {
     Clean(ChemistryCleanOpts),
     Lookup(ChemistryLookupOpts),
     Fetch(ChemistryFetchOpts),
+    Verify(ChemistryVerifyOpts),
+    Detect(ChemistryDetectOpts),
+    InferPl(ChemistryInferPlOpts),
+}
+
+/// Show a run record of `simpleaf index`, `simpleaf quant`, `simpleaf atac index` or `simpleaf atac process`
+#[derive(Args, Clone, Debug)]
+pub struct LogShowOpts {
+    /// Path to a run record (e.g. `simpleaf_quant_log.json`) or to the output directory of a run
+    pub input: PathBuf,
+    /// Print the record as JSON rather than as a summary
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Compare two run records, e.g. to find what changed between two runs
+#[derive(Args, Clone, Debug)]
+pub struct LogDiffOpts {
+    /// Path to the first run record or to the output directory of the first run
+    pub first: PathBuf,
+    /// Path to the second run record or to the output directory of the second run
+    pub second: PathBuf,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum LogCommand {
+    Show(LogShowOpts),
+    Diff(LogDiffOpts),
+}
+
+/// Pack the chemistry registry, the permit lists and the workflow registries into a bundle,
+/// downloading those that are missing first
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct BundleExportOpts {
+    /// path of the bundle (a tar.gz archive) to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+/// Import a bundle written by `simpleaf bundle export`, e.g. on a machine without internet access
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct BundleImportOpts {
+    /// path of the bundle to import
+    #[arg(short, long)]
+    pub input: PathBuf,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum BundleCommand {
+    Export(BundleExportOpts),
+    Import(BundleImportOpts),
+}
+
+/// Move a simpleaf home kept in a single directory (e.g. `ALEVIN_FRY_HOME`) to the XDG base
+/// directories
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = false)]
+pub struct HomeMigrateOpts {
+    /// the directory to migrate [default: the directory given by `--af-home` or `ALEVIN_FRY_HOME`]
+    #[arg(long, value_name = "DIR")]
+    pub from: Option<PathBuf>,
+    /// print what would be moved without moving anything
+    #[arg(short, long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum HomeCommand {
+    Migrate(HomeMigrateOpts),
+}
+
+/// Print the value of an option in `simpleaf.toml`, or its built-in default if it is not set
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ConfigGetOpts {
+    /// the option, as <SECTION>.<OPTION> (e.g. quant.threads or atac.process.min-reads)
+    pub key: String,
+}
+
+/// Set the default of an option of `simpleaf index`, `quant`, `atac index` or `atac process` in `simpleaf.toml`
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ConfigSetOpts {
+    /// the option, as <SECTION>.<OPTION> (e.g. quant.threads or atac.process.min-reads)
+    pub key: String,
+    /// the value of the option (several values for the options taking more than one); flags
+    /// are set to `true` or `false`
+    #[arg(required = true, allow_hyphen_values = true)]
+    pub value: Vec<String>,
+}
+
+/// Remove an option from `simpleaf.toml`, restoring its built-in default
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ConfigUnsetOpts {
+    /// the option, as <SECTION>.<OPTION> (e.g. quant.threads or atac.process.min-reads)
+    pub key: String,
+}
+
+/// List the options set in `simpleaf.toml`
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = false)]
+pub struct ConfigListOpts {
+    /// also list the options that are not set, with their built-in default
+    #[arg(short, long)]
+    pub all: bool,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum ConfigCommand {
+    Get(ConfigGetOpts),
+    Set(ConfigSetOpts),
+    Unset(ConfigUnsetOpts),
+    List(ConfigListOpts),
 }
 
 #[derive(Args, Clone, Debug)]
@@ -578,6 +870,80 @@ pub struct SetPathOpts {
     macs: Option<PathBuf>,
 }
 
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+#[command(group(
+    ArgGroup::new("executor")
+    .required(true)
+    .args(["local", "container", "wrapper"])
+))]
+pub struct SetExecutorOpts {
+    /// run the external programs directly (the default)
+    #[arg(long)]
+    local: bool,
+    /// run the external programs inside this container image
+    #[arg(long, value_name = "IMAGE")]
+    container: Option<String>,
+    /// the container runtime used to run the image
+    #[arg(long, value_enum, default_value_t = ContainerRuntime::Apptainer)]
+    runtime: ContainerRuntime,
+    /// a bind mount for the container, as <HOST_PATH>[:<CONTAINER_PATH>[:<OPTIONS>]]; can be given several times
+    #[arg(long, requires = "container")]
+    bind: Vec<String>,
+    /// an extra argument passed to the container runtime before the image; can be given several times
+    #[arg(long, requires = "container", allow_hyphen_values = true)]
+    runtime_arg: Vec<String>,
+    /// run the external programs through this wrapper command, which is split on whitespace
+    /// (e.g. "srun --cpus-per-task=16")
+    #[arg(long, value_name = "PREFIX")]
+    wrapper: Option<String>,
+}
+
+/// Parses a mirror given as `<FROM>=<TO>`.
+fn parse_mirror(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => Err(format!("`{}` should be given as <FROM>=<TO>", s)),
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = false)]
+pub struct SetNetworkOpts {
+    /// download the resources whose URL starts with FROM from the mirror TO instead (falling back to
+    /// FROM), e.g. https://raw.githubusercontent.com=https://mirror.example.org/github; can be given
+    /// several times
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_mirror)]
+    pub mirror: Vec<(String, String)>,
+    /// remove the mirror of the URL prefix FROM; can be given several times
+    #[arg(long, value_name = "FROM")]
+    pub remove_mirror: Vec<String>,
+    /// the HTTP(S) proxy to download through, e.g. http://proxy.example.org:3128
+    #[arg(long, value_name = "URL", conflicts_with = "clear_proxy")]
+    pub proxy: Option<String>,
+    /// stop using the proxy set with `--proxy` (the `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY`
+    /// environment variables are still followed)
+    #[arg(long)]
+    pub clear_proxy: bool,
+    /// the number of times a download is retried after a transient failure
+    #[arg(long)]
+    pub retries: Option<u32>,
+    /// the delay, in seconds, before the first retry; it is doubled for each following one
+    #[arg(long, value_name = "SECS")]
+    pub retry_backoff: Option<f64>,
+    /// how long, in seconds, to wait for the connection to a server
+    #[arg(long, value_name = "SECS")]
+    pub connect_timeout: Option<u64>,
+    /// how long, in seconds, to wait for a server to answer once connected
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+    /// restore the default settings before applying the other options
+    #[arg(long)]
+    pub reset: bool,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// build the (expanded) reference index
@@ -589,8 +955,29 @@ pub enum Commands {
     Inspect {},
     /// quantify a sample
     Quant(MapQuantOpts),
+    /// generate a QC report for the output of `simpleaf quant`
+    Report(ReportOpts),
+    /// call the cells of the output of `simpleaf quant` by testing the barcodes against the
+    /// ambient RNA profile
+    CallCells(CallCellsOpts),
+    /// inspect or compare the run records of the index, quant, atac index and atac process commands
+    #[command(subcommand)]
+    Log(LogCommand),
+    /// move the resources that simpleaf downloads to machines without internet access
+    #[command(subcommand)]
+    Bundle(BundleCommand),
+    /// manage the directories where simpleaf keeps its configuration and data
+    #[command(subcommand)]
+    Home(HomeCommand),
+    /// get or set the defaults of the options of the index, quant, atac index and atac process commands
+    #[command(subcommand)]
+    Config(ConfigCommand),
     /// set paths to the programs that simpleaf will use
     SetPaths(SetPathOpts),
+    /// set how the external programs are run (directly, in a container or through a wrapper)
+    SetExecutor(SetExecutorOpts),
+    /// set the mirrors, proxy, retries and timeouts used to download resources, or show them
+    SetNetwork(SetNetworkOpts),
     /// refreshes version information associated with programs used by simpleaf
     RefreshProgInfo {},
     /// run a sub-command dealing with atac-seq data
@@ -607,14 +994,111 @@ pub struct WorkflowOpts {
     pub command: WorkflowCommands,
 }
 
+/// The template or manifest of a workflow that is checked or drawn without
+/// being run.
+#[derive(Debug, Args)]
+#[command(group(
+    clap::ArgGroup::new("source")
+    .required(true)
+    .args(&["manifest", "template"]),
+))]
+pub struct WorkflowSourceOpts {
+    /// path to a simpleaf workflow template.
+    #[arg(short, long, display_order = 1)]
+    pub template: Option<PathBuf>,
+
+    /// output directory passed to the template.
+    #[arg(short, long, display_order = 2, conflicts_with = "manifest")]
+    pub output: Option<PathBuf>,
+
+    /// path to an instantiated simpleaf workflow manifest.
+    #[arg(short, long, display_order = 3)]
+    pub manifest: Option<PathBuf>,
+
+    /// comma separated library search paths passing to internal Jsonnet engine as --jpath flags.
+    #[arg(
+        short,
+        long,
+        display_order = 4,
+        value_delimiter = ',',
+        conflicts_with = "manifest",
+        help_heading = "Jsonnet"
+    )]
+    pub jpaths: Option<Vec<PathBuf>>,
+
+    /// comma separated string passing to internal Jsonnet engine as --ext-code flags.
+    #[arg(
+        short,
+        long,
+        display_order = 5,
+        value_delimiter = ',',
+        conflicts_with = "manifest",
+        help_heading = "Jsonnet",
+        hide = true
+    )]
+    pub ext_codes: Option<Vec<String>>,
+}
+
+/// Add a named registry of workflow templates
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+#[command(group(
+    ArgGroup::new("registry_source")
+    .required(true)
+    .multiple(false)
+    .args(["path", "git", "archive"])
+))]
+pub struct RegistryAddOpts {
+    /// name of the registry, used to refer to its workflows as `<name>/<workflow>`
+    #[arg(short, long)]
+    pub name: String,
+    /// a local directory holding the registry, which is used in place
+    #[arg(long)]
+    pub path: Option<PathBuf>,
+    /// the URL of a git repository holding the registry
+    #[arg(long, requires = "rev")]
+    pub git: Option<String>,
+    /// the commit or tag (or branch) of the git repository to pin the registry to
+    #[arg(long, requires = "git", conflicts_with_all = ["path", "archive"])]
+    pub rev: Option<String>,
+    /// the URL of a zip or tar.gz archive of the registry, e.g. the GitHub archive of a tag
+    #[arg(long)]
+    pub archive: Option<String>,
+    /// the blake3 checksum that the archive must match
+    #[arg(long, requires = "archive", conflicts_with_all = ["path", "git"])]
+    pub checksum: Option<String>,
+}
+
+/// Remove a registry of workflow templates
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct RegistryRemoveOpts {
+    /// name of the registry to remove
+    #[arg(short, long)]
+    pub name: String,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum RegistryCommand {
+    Add(RegistryAddOpts),
+    Remove(RegistryRemoveOpts),
+    /// List the registries, along with the revisions of their local copies
+    List,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum WorkflowCommands {
     /// Print a summary of the currently available workflows.
     List {},
 
-    /// Update the local copy of protocol esturary to the latest version.
+    /// Update the local copies of the workflow registries to the latest version.
     Refresh {},
 
+    /// Add, remove or list the registries that workflows are looked up in.
+    #[command(subcommand)]
+    Registry(RegistryCommand),
+
     #[command(arg_required_else_help = true)]
     /// Get the workflow template and related files of a registered workflow.
     Get {
@@ -622,7 +1106,8 @@ pub enum WorkflowCommands {
         #[arg(short, long, requires = "name")]
         output: PathBuf,
 
-        /// name of the queried workflow.
+        /// name of the queried workflow, optionally qualified by its registry
+        /// as `<registry>/<workflow>`.
         #[arg(short, long)]
         name: String,
         // only write the essential information without any instructions
@@ -753,5 +1238,61 @@ pub enum WorkflowCommands {
             help_heading = "Control Flow"
         )]
         skip_step: Option<Vec<u64>>,
+
+        /// the maximum number of commands run at the same time by the local executor.
+        /// A command starts once all of the commands it depends on have succeeded.
+        #[arg(
+            long,
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u32).range(1..),
+            display_order = 10,
+            help_heading = "Control Flow"
+        )]
+        jobs: u32,
+
+        /// how to run the steps: by this process (local), or as jobs submitted to
+        /// SLURM or PBS, each of which waits for the jobs of the commands it depends on.
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = WorkflowExecutor::Local,
+            display_order = 11,
+            help_heading = "Cluster Execution"
+        )]
+        executor: WorkflowExecutor,
+
+        /// only write the job scripts (and a script submitting them) rather than submitting them.
+        #[arg(long, display_order = 12, help_heading = "Cluster Execution")]
+        no_submit: bool,
+
+        /// an extra argument passed to `sbatch` or `qsub` when submitting each job
+        /// (e.g. --submit-arg=--partition=long); can be given several times.
+        #[arg(
+            long,
+            allow_hyphen_values = true,
+            display_order = 13,
+            help_heading = "Cluster Execution"
+        )]
+        submit_arg: Vec<String>,
+    },
+
+    #[command(arg_required_else_help = true)]
+    /// Check a workflow template or manifest without running it: the flags and dependencies of
+    /// the commands, the external programs they run and the input files they read.
+    Validate(WorkflowSourceOpts),
+
+    #[command(arg_required_else_help = true)]
+    /// Print the commands of a workflow and their dependencies as a DOT or Mermaid diagram.
+    Graph {
+        #[command(flatten)]
+        source: WorkflowSourceOpts,
+
+        /// the format of the diagram.
+        #[arg(long, value_enum, default_value_t = GraphFormat::Mermaid, display_order = 6)]
+        format: GraphFormat,
+
+        /// write the diagram to this file rather than to stdout.
+        #[arg(long, display_order = 7)]
+        out_file: Option<PathBuf>,
     },
 }
@@ -0,0 +1,314 @@
+use crate::utils::af_utils::parse_resource_json_file;
+use crate::utils::archive;
+use crate::utils::chem_utils::{LOCAL_PL_PATH_KEY, REMOTE_PL_URL_KEY};
+use crate::utils::constants::{CHEMISTRIES_PATH, CHEMISTRIES_URL};
+use crate::utils::prog_utils;
+use crate::utils::registry::{Registries, Registry, RegistrySource};
+use crate::utils::run_record::write_json_atomic;
+
+use anyhow::{bail, Context};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+use super::{BundleExportOpts, BundleImportOpts};
+
+/// The file describing the content of a bundle, at its root.
+static BUNDLE_INFO_FILE: &str = "bundle.json";
+static PLIST_DIR: &str = "plist";
+static REGISTRIES_DIR: &str = "registries";
+
+/// The content of a bundle, as recorded in its [`BUNDLE_INFO_FILE`]. The
+/// bundle also holds the chemistry registry at its root, the permit lists
+/// under `plist` and the workflow registries under `registries/<name>`.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleInfo {
+    simpleaf_version: String,
+    created_at: String,
+    permit_lists: Vec<String>,
+    registries: Vec<Registry>,
+}
+
+/// Packs the chemistry registry, the permit lists it refers to and the
+/// workflow registries of `af_home_path` into a bundle, downloading those
+/// that are missing first.
+pub fn export_bundle(af_home_path: &Path, export_opts: BundleExportOpts) -> anyhow::Result<()> {
+    let mut entries = Vec::new();
+
+    let chem_path = af_home_path.join(CHEMISTRIES_PATH);
+    let chem_registry = parse_resource_json_file(&chem_path, Some(CHEMISTRIES_URL))
+        .with_context(|| format!("couldn't obtain and/or parse {}", chem_path.display()))?;
+    entries.push((PathBuf::from(CHEMISTRIES_PATH), chem_path));
+
+    // the permit lists are named after their hash, so chemistries may share one
+    let plist_dir = af_home_path.join(PLIST_DIR);
+    let mut permit_lists: Vec<String> = Vec::new();
+    let mut missing = Vec::new();
+    for (chem, entry) in chem_registry.as_object().into_iter().flatten() {
+        let Some(plist) = entry.get(LOCAL_PL_PATH_KEY).and_then(Value::as_str) else {
+            continue;
+        };
+        if permit_lists.iter().any(|p| p == plist) {
+            continue;
+        }
+        let plist_path = plist_dir.join(plist);
+        if !plist_path.is_file() {
+            let fetched = match entry.get(REMOTE_PL_URL_KEY).and_then(Value::as_str) {
+                Some(url) => {
+                    info!("downloading the permit list of {}", chem);
+                    std::fs::create_dir_all(&plist_dir)?;
+                    prog_utils::download_to_file_verify_hash(url, &plist_path, plist)
+                }
+                None => Err(anyhow::anyhow!("no remote url is registered for it")),
+            };
+            if let Err(e) = fetched {
+                warn!("Skipping the permit list of {}: {:#}", chem, e);
+                missing.push(chem.clone());
+                continue;
+            }
+        }
+        entries.push((Path::new(PLIST_DIR).join(plist), plist_path));
+        permit_lists.push(plist.to_string());
+    }
+
+    let mut registries = Registries::load(af_home_path)?;
+    for r in registries.iter() {
+        if let RegistrySource::Local { path } = &r.source {
+            info!(
+                "The registry {} is the local directory {}; it is not bundled.",
+                r.name,
+                path.display()
+            );
+        }
+    }
+    let copies = registries.local_copies()?;
+    for (registry, dir) in &copies {
+        entries.push((Path::new(REGISTRIES_DIR).join(&registry.name), dir.clone()));
+    }
+
+    let bundle_info = BundleInfo {
+        simpleaf_version: clap::crate_version!().to_string(),
+        created_at: chrono::Local::now().to_rfc3339(),
+        permit_lists,
+        registries: copies.into_iter().map(|(r, _)| r).collect(),
+    };
+    let info_dir = tempfile::tempdir()?;
+    let info_path = info_dir.path().join(BUNDLE_INFO_FILE);
+    std::fs::write(&info_path, serde_json::to_string_pretty(&bundle_info)?)?;
+    entries.push((PathBuf::from(BUNDLE_INFO_FILE), info_path));
+
+    archive::write_tar_gz(&entries, &export_opts.output)?;
+    info!(
+        "Wrote the chemistry registry, {} permit list(s) and {} workflow registry(ies) to {}",
+        bundle_info.permit_lists.len(),
+        bundle_info.registries.len(),
+        export_opts.output.display()
+    );
+    if !missing.is_empty() {
+        warn!(
+            "The permit lists of {} could not be obtained, and are not in the bundle.",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Unpacks a bundle written by [`export_bundle`] into `af_home_path`. The
+/// chemistries of the bundle are merged into the chemistry registry (newer
+/// versions replace older ones), the missing permit lists are added, and the
+/// workflow registries of the bundle replace those with the same name.
+pub fn import_bundle(af_home_path: &Path, import_opts: BundleImportOpts) -> anyhow::Result<()> {
+    std::fs::create_dir_all(af_home_path)
+        .with_context(|| format!("could not create {}", af_home_path.display()))?;
+    // extracted within ALEVIN_FRY_HOME, so that everything can be moved
+    // into place
+    let staging = tempfile::Builder::new()
+        .prefix(".simpleaf-bundle-")
+        .tempdir_in(af_home_path)?;
+    archive::extract_archive(&import_opts.input, staging.path())?;
+    let info_path = staging.path().join(BUNDLE_INFO_FILE);
+    if !info_path.is_file() {
+        bail!(
+            "{} is not a bundle made by `simpleaf bundle export`: it has no {}.",
+            import_opts.input.display(),
+            BUNDLE_INFO_FILE
+        );
+    }
+    let bundle_info: BundleInfo = serde_json::from_value(prog_utils::read_json(&info_path)?)
+        .with_context(|| format!("invalid {} in the bundle", BUNDLE_INFO_FILE))?;
+    info!(
+        "importing a bundle made by simpleaf {} on {}",
+        bundle_info.simpleaf_version, bundle_info.created_at
+    );
+    // the names are checked before anything is changed, since they name
+    // files within ALEVIN_FRY_HOME
+    for plist in &bundle_info.permit_lists {
+        let mut components = Path::new(plist).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            bail!("`{}` is not a valid permit list name in the bundle.", plist);
+        }
+    }
+    for registry in &bundle_info.registries {
+        registry
+            .check_importable()
+            .context("invalid workflow registry in the bundle")?;
+    }
+
+    let bundled_chem_path = staging.path().join(CHEMISTRIES_PATH);
+    let chem_path = af_home_path.join(CHEMISTRIES_PATH);
+    if !chem_path.is_file() {
+        std::fs::rename(&bundled_chem_path, &chem_path)
+            .with_context(|| format!("could not write {}", chem_path.display()))?;
+        info!("added the chemistry registry");
+    } else {
+        let mut chem_registry = parse_resource_json_file(&chem_path, None)?;
+        let bundled = parse_resource_json_file(&bundled_chem_path, None)?;
+        let (Some(existing), Some(bundled)) = (chem_registry.as_object_mut(), bundled.as_object())
+        else {
+            bail!("The chemistry registries should hold JSON objects.");
+        };
+        let mut updated = 0;
+        for (k, v) in bundled {
+            let newer = match existing.get(k) {
+                None => true,
+                Some(ev) => chem_version(v)
+                    .zip(chem_version(ev))
+                    .is_some_and(|(new_ver, curr_ver)| new_ver > curr_ver),
+            };
+            if newer {
+                existing.insert(k.clone(), v.clone());
+                updated += 1;
+            }
+        }
+        write_json_atomic(&chem_path, &chem_registry)?;
+        info!("added or updated {} chemistry(ies)", updated);
+    }
+
+    let plist_dir = af_home_path.join(PLIST_DIR);
+    std::fs::create_dir_all(&plist_dir)?;
+    let mut added = 0;
+    for plist in &bundle_info.permit_lists {
+        let dest = plist_dir.join(plist);
+        if !dest.exists() {
+            std::fs::rename(staging.path().join(PLIST_DIR).join(plist), &dest)
+                .with_context(|| format!("could not write {}", dest.display()))?;
+            added += 1;
+        }
+    }
+    info!("added {} permit list(s)", added);
+
+    let mut registries = Registries::load(af_home_path)?;
+    for registry in bundle_info.registries {
+        let name = registry.name.clone();
+        let copy = staging.path().join(REGISTRIES_DIR).join(&name);
+        registries
+            .import(registry, &copy)
+            .with_context(|| format!("could not import the workflow registry {}", name))?;
+        info!("imported the workflow registry {}", name);
+    }
+    Ok(())
+}
+
+fn chem_version(chem: &Value) -> Option<Version> {
+    chem.get("version")
+        .and_then(Value::as_str)
+        .and_then(|v| Version::parse(v).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src_home");
+        let estuary = src.join("protocol-estuary").join("protocol-estuary-main");
+        std::fs::create_dir_all(estuary.join("protocols").join("foo")).unwrap();
+        std::fs::create_dir_all(estuary.join("utils")).unwrap();
+        std::fs::create_dir_all(src.join(PLIST_DIR)).unwrap();
+        std::fs::write(src.join(PLIST_DIR).join("abc"), "AAAC\n").unwrap();
+        let chems = json!({
+            "chem1": { "geometry": "1{b[16]u[12]x:}2{r:}", "version": "0.2.0", "plist_name": "abc" },
+            "chem2": { "geometry": "1{b[16]u[10]x:}2{r:}", "version": "0.1.0" }
+        });
+        write_json_atomic(&src.join(CHEMISTRIES_PATH), &chems).unwrap();
+
+        let bundle = tmp.path().join("bundle.tar.gz");
+        export_bundle(
+            &src,
+            BundleExportOpts {
+                output: bundle.clone(),
+            },
+        )
+        .unwrap();
+
+        // an older chem1 is updated, and the local chem3 is kept
+        let dst = tmp.path().join("dst_home");
+        std::fs::create_dir_all(&dst).unwrap();
+        let local_chems = json!({
+            "chem1": { "geometry": "1{b[16]u[12]x:}2{r:}", "version": "0.1.0", "plist_name": "abc" },
+            "chem3": { "geometry": "1{b[8]u[8]x:}2{r:}", "version": "0.1.0" }
+        });
+        write_json_atomic(&dst.join(CHEMISTRIES_PATH), &local_chems).unwrap();
+        import_bundle(&dst, BundleImportOpts { input: bundle }).unwrap();
+
+        let merged = prog_utils::read_json(&dst.join(CHEMISTRIES_PATH)).unwrap();
+        assert_eq!(merged["chem1"]["version"], "0.2.0");
+        assert!(merged.get("chem2").is_some() && merged.get("chem3").is_some());
+        assert!(dst.join(PLIST_DIR).join("abc").is_file());
+        assert!(dst
+            .join("protocol-estuary")
+            .join("protocol-estuary-main")
+            .join("protocols")
+            .join("foo")
+            .is_dir());
+        // nothing is left over from the import
+        assert_eq!(std::fs::read_dir(&dst).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_import_invalid_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("dst_home");
+        let bundle_with = |permit_lists: Value, registries: Value| {
+            let info_path = tmp.path().join(BUNDLE_INFO_FILE);
+            let info = json!({
+                "simpleaf_version": "0.19.0",
+                "created_at": "2026-01-01T00:00:00+00:00",
+                "permit_lists": permit_lists,
+                "registries": registries,
+            });
+            std::fs::write(&info_path, info.to_string()).unwrap();
+            let bundle = tmp.path().join("bundle.tar.gz");
+            archive::write_tar_gz(&[(PathBuf::from(BUNDLE_INFO_FILE), info_path)], &bundle)
+                .unwrap();
+            import_bundle(&dst, BundleImportOpts { input: bundle })
+        };
+
+        for plist in ["../abc", "/tmp/abc", "a/b", ".."] {
+            assert!(bundle_with(json!([plist]), json!([])).is_err(), "{}", plist);
+        }
+        let archive = json!({ "type": "archive", "url": "https://example.com/r.zip" });
+        for name in ["../up", "a/b", ".hidden", ""] {
+            let mut registry = archive.clone();
+            registry["name"] = json!(name);
+            assert!(
+                bundle_with(json!([]), json!([registry])).is_err(),
+                "{}",
+                name
+            );
+        }
+        let local = json!({ "name": "mine", "type": "local", "path": "/tmp" });
+        assert!(bundle_with(json!([]), json!([local])).is_err());
+        // nothing was written
+        assert!(!dst.join(CHEMISTRIES_PATH).exists());
+        assert!(!dst.join(PLIST_DIR).exists());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::utils::empty_drops::{self, CountMatrix, EmptyDropsParams};
+use crate::utils::prog_utils::{check_files_exist, read_json};
+
+use anyhow::Context;
+use serde_json::Value;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::{info, warn};
+
+use super::CallCellsOpts;
+
+/// The per-barcode results of the cell calling, written next to the count matrix
+pub const CELL_CALLS_TSV: &str = "emptydrops_calls.tsv";
+/// The barcodes called cells, written next to the count matrix
+pub const CELLS_TXT: &str = "emptydrops_cells.txt";
+
+pub fn call_cells(opts: CallCellsOpts) -> anyhow::Result<()> {
+    let params = EmptyDropsParams {
+        lower: opts.lower,
+        niters: opts.niters,
+        fdr: opts.fdr,
+        retain: opts.retain,
+        seed: opts.seed,
+        threads: opts.threads as usize,
+    };
+    write_cell_calls(&opts.input, &params)
+}
+
+/// Calls the cells of the count matrix of the `simpleaf quant` run in
+/// `quant_output` (see [`empty_drops::call_cells`]), and writes the called
+/// cells and the results of every tested barcode next to the matrix.
+pub fn write_cell_calls(quant_output: &Path, params: &EmptyDropsParams) -> anyhow::Result<()> {
+    let af_quant_dir = quant_output.join("af_quant");
+    let alevin_dir = af_quant_dir.join("alevin");
+    let mtx_path = alevin_dir.join("quants_mat.mtx");
+    let rows_path = alevin_dir.join("quants_mat_rows.txt");
+    check_files_exist(&[mtx_path.clone(), rows_path.clone()])?;
+
+    let quant_info = read_json(&af_quant_dir.join("quant.json")).unwrap_or(Value::Null);
+    let usa_mode = quant_info["usa_mode"].as_bool().unwrap_or(false);
+
+    info!("reading count matrix {}", mtx_path.display());
+    let matrix = CountMatrix::read(&mtx_path, &rows_path, usa_mode)?;
+    info!(
+        "testing the barcodes with more than {} UMIs against the ambient RNA profile ({} iterations)",
+        params.lower, params.niters
+    );
+    let calls = empty_drops::call_cells(&matrix, params)?;
+
+    let calls_path = alevin_dir.join(CELL_CALLS_TSV);
+    let mut w = BufWriter::new(
+        std::fs::File::create(&calls_path)
+            .with_context(|| format!("could not create {}", calls_path.display()))?,
+    );
+    writeln!(
+        w,
+        "barcode\ttotal_umis\tlog_prob\tp_value\tfdr\tlimited\tis_cell"
+    )?;
+    let fmt = |v: Option<f64>| v.map_or_else(|| "NA".to_string(), |v| v.to_string());
+    for c in calls.calls.iter() {
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            c.barcode,
+            c.total_umis,
+            fmt(c.log_prob),
+            fmt(c.p_value),
+            fmt(c.fdr),
+            c.limited,
+            c.is_cell
+        )?;
+    }
+    w.flush()?;
+
+    let cells_path = alevin_dir.join(CELLS_TXT);
+    let mut w = BufWriter::new(
+        std::fs::File::create(&cells_path)
+            .with_context(|| format!("could not create {}", cells_path.display()))?,
+    );
+    for c in calls.calls.iter().filter(|c| c.is_cell) {
+        writeln!(w, "{}", c.barcode)?;
+    }
+    w.flush()?;
+
+    let num_limited = calls
+        .calls
+        .iter()
+        .filter(|c| c.limited && !c.is_cell)
+        .count();
+    if num_limited > 0 {
+        warn!(
+            "{} barcodes are not called cells although their p-value is the smallest that {} \
+            iterations can give; more iterations (`--niters`) may call them cells.",
+            num_limited, params.niters
+        );
+    }
+    info!(
+        "called {} cells ({} tested, {} retained without testing; the ambient profile holds {} \
+        UMIs from {} barcodes); wrote {} and {}",
+        calls.num_cells,
+        calls.num_tested,
+        calls.num_retained,
+        calls.ambient_umis,
+        calls.num_ambient_barcodes,
+        cells_path.display(),
+        calls_path.display()
+    );
+    Ok(())
+}
@@ -1,8 +1,10 @@
+use crate::utils::chem_detect;
 use crate::utils::chem_utils::{
     custom_chem_hm_into_json, get_custom_chem_hm, get_single_custom_chem_from_file,
     CustomChemistry, CustomChemistryMap, ExpectedOri, LOCAL_PL_PATH_KEY, REMOTE_PL_URL_KEY,
 };
 use crate::utils::constants::*;
+use crate::utils::permit_list;
 use crate::utils::prog_utils::{self, download_to_file_compute_hash};
 use crate::utils::{self, af_utils::*};
 use regex::Regex;
@@ -15,6 +17,7 @@ use std::fs;
 use std::io::{Seek, Write};
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use tabled::{settings::Style, Table, Tabled};
 use tracing::{debug, info, warn};
 use utils::prog_utils::read_json_from_remote_url;
 use utils::remote::is_remote_url;
@@ -730,13 +733,10 @@ pub fn fetch_chemistries(
                                     pfile, k, rpath
                                 );
                             } else {
-                                let hash = download_to_file_compute_hash(rpath, &fpath)?;
-                                let expected_hash = pfile.to_string();
-                                let observed_hash = hash.to_string();
-                                if expected_hash != observed_hash {
-                                    warn!("Downloaded the file for chemistry {} from {}, but the observed hash {} was not equal to the expcted hash {}",
-                                    k, rpath, observed_hash, expected_hash);
-                                }
+                                prog_utils::download_to_file_verify_hash(rpath, &fpath, pfile)
+                                    .with_context(|| {
+                                        format!("could not fetch the permit list file for {}", k)
+                                    })?;
                                 info!("Fetched permit list file for {} to {}", k, fpath.display());
                             }
                         } else {
@@ -760,3 +760,203 @@ pub fn fetch_chemistries(
 
     Ok(())
 }
+
+/// Re-hashes the permit list files in `ALEVIN_FRY_HOME/plist`, which are named after the Blake3
+/// hash of their content, and reports those whose content no longer matches their name (removing
+/// them if verify_opts has remove set). Files that are not named after a hash, as well as partial
+/// downloads, are skipped.
+pub fn verify_chemistries(
+    af_home_path: PathBuf,
+    verify_opts: crate::simpleaf_commands::ChemistryVerifyOpts,
+) -> Result<()> {
+    let plist_path = af_home_path.join("plist");
+    if !plist_path.is_dir() {
+        info!(
+            "The permit list cache directory {} does not exist; Nothing to verify.",
+            plist_path.display()
+        );
+        return Ok(());
+    }
+
+    // used to report the chemistries relying on a corrupt file
+    let chem_p = af_home_path.join(CHEMISTRIES_PATH);
+    let chem_hm = if chem_p.is_file() {
+        get_custom_chem_hm(&chem_p)?
+    } else {
+        CustomChemistryMap::new()
+    };
+
+    let mut files = fs::read_dir(&plist_path)?
+        .map(|de| de.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    files.retain(|p| p.is_file());
+    files.sort();
+
+    let mut num_intact = 0;
+    let mut corrupt = Vec::new();
+    for path in files {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        if name.len() != 64 || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+            info!(
+                "Skipping {}, which is not named after the hash of its content.",
+                path.display()
+            );
+            continue;
+        }
+        let hash = prog_utils::hash_file(&path)?.to_string();
+        if hash == name {
+            num_intact += 1;
+            continue;
+        }
+
+        let mut users = chem_hm
+            .iter()
+            .filter(|(_, v)| v.plist_name().as_deref() == Some(name.as_str()))
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<&str>>();
+        users.sort_unstable();
+        let used_by = if users.is_empty() {
+            String::new()
+        } else {
+            format!(" (used by {})", users.join(", "))
+        };
+        warn!(
+            "The permit list file {}{} is corrupt: the hash of its content is {}.",
+            path.display(),
+            used_by,
+            hash
+        );
+        if verify_opts.remove {
+            info!("removing file {}", path.display());
+            fs::remove_file(&path)?;
+        }
+        corrupt.push(path);
+    }
+
+    info!("{} permit list file(s) are intact.", num_intact);
+    if !corrupt.is_empty() {
+        if verify_opts.remove {
+            info!(
+                "Removed {} corrupt permit list file(s); they will be downloaded again when needed, or by `simpleaf chemistry fetch`.",
+                corrupt.len()
+            );
+        } else {
+            bail!(
+                "{} permit list file(s) are corrupt. Run `simpleaf chemistry verify --remove` to remove them, so that they are downloaded again when needed.",
+                corrupt.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct DetectedChemistry {
+    rank: usize,
+    chemistry: String,
+    confidence: String,
+    #[tabled(rename = "reads fitting")]
+    reads_fitting: String,
+    #[tabled(rename = "barcodes in permit list")]
+    barcodes_listed: String,
+}
+
+/// Ranks the registered chemistries by how well they fit the first reads of
+/// the files in `detect_opts`, and reports the one `--chemistry auto` would
+/// choose.
+pub fn detect_chemistry(
+    af_home_path: PathBuf,
+    detect_opts: crate::simpleaf_commands::ChemistryDetectOpts,
+) -> Result<()> {
+    let detection = chem_detect::detect_chemistry(
+        &af_home_path,
+        &detect_opts.reads1,
+        &detect_opts.reads2,
+        detect_opts.num_reads,
+    )?;
+    info!(
+        "Sampled {} read pairs from {} and {}.",
+        detection.num_reads,
+        detect_opts.reads1.display(),
+        detect_opts.reads2.display()
+    );
+
+    let rows = detection
+        .ranking
+        .iter()
+        .enumerate()
+        .map(|(i, c)| DetectedChemistry {
+            rank: i + 1,
+            chemistry: c.name.clone(),
+            confidence: format!("{:.2}", c.confidence),
+            reads_fitting: format!("{:.1}%", 100.0 * c.length_rate),
+            barcodes_listed: match (c.barcode_rate, &c.note) {
+                (Some(r), _) => format!("{:.1}%", 100.0 * r),
+                (None, Some(note)) => format!("- ({})", note),
+                (None, None) => "-".to_string(),
+            },
+        })
+        .collect::<Vec<_>>();
+    println!("{}", Table::new(rows).with(Style::rounded()));
+
+    match detection.choose() {
+        Ok(best) => info!(
+            "The reads fit chemistry {} (geometry {}); `--chemistry auto` would use it.",
+            best.name, best.geometry
+        ),
+        Err(e) => warn!("{:#}", e),
+    }
+    Ok(())
+}
+
+/// Infers an unfiltered permit list from the barcodes of the reads in
+/// `infer_opts`, and writes it to the output directory, from which it can be
+/// registered with `simpleaf chemistry add --local-url`.
+pub fn infer_permit_list(
+    af_home_path: PathBuf,
+    infer_opts: crate::simpleaf_commands::ChemistryInferPlOpts,
+) -> Result<()> {
+    if infer_opts.reads1.len() != infer_opts.reads2.len() {
+        bail!(
+            "{} read1 files and {} read2 files were given; Cannot proceed!",
+            infer_opts.reads1.len(),
+            infer_opts.reads2.len()
+        );
+    }
+    let geometry = match chem_detect::builtin_geometry(&infer_opts.chemistry) {
+        Some(g) => g.to_string(),
+        None if infer_opts.chemistry.contains('{') => {
+            validate_geometry(&infer_opts.chemistry)?;
+            infer_opts.chemistry.clone()
+        }
+        None => {
+            let chem_hm = get_custom_chem_hm(&af_home_path.join(CHEMISTRIES_PATH))?;
+            match chem_hm.get(&infer_opts.chemistry) {
+                Some(chem) => chem.geometry().to_string(),
+                None => bail!(
+                    "{} is neither a registered chemistry nor a geometry.",
+                    infer_opts.chemistry
+                ),
+            }
+        }
+    };
+
+    let (pl_path, _) = permit_list::infer_permit_list(
+        &geometry,
+        &infer_opts.reads1,
+        &infer_opts.reads2,
+        infer_opts.min_reads,
+        &infer_opts.output,
+    )?;
+    let pl_path = pl_path.canonicalize().unwrap_or(pl_path);
+    info!(
+        "The permit list can be registered with `simpleaf chemistry add --name <NAME> --geometry '{}' --expected-ori <ORI> --local-url {}`.",
+        geometry,
+        pl_path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use crate::utils::config::SimpleafConfig;
+use crate::utils::home::SimpleafHome;
+
+use tracing::info;
+
+use super::{ConfigGetOpts, ConfigListOpts, ConfigSetOpts, ConfigUnsetOpts};
+
+/// Prints the value of an option in the `simpleaf.toml` of `af_home`, or its
+/// built-in default if it is not set there.
+pub fn get_config(af_home: &SimpleafHome, get_opts: ConfigGetOpts) -> anyhow::Result<()> {
+    let config = SimpleafConfig::load_lossy(af_home)?;
+    match config.get(&get_opts.key)? {
+        Some(entry) => println!("{}", entry.values.join(" ")),
+        None => {
+            info!(
+                "{} is not set in {}; printing its built-in default.",
+                get_opts.key,
+                af_home.config_path().display()
+            );
+            println!(
+                "{}",
+                SimpleafConfig::default_values(&get_opts.key)?.join(" ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Sets an option in the `simpleaf.toml` of `af_home`.
+pub fn set_config(af_home: &SimpleafHome, set_opts: ConfigSetOpts) -> anyhow::Result<()> {
+    let mut config = SimpleafConfig::load_lossy(af_home)?;
+    config.set(&set_opts.key, &set_opts.value)?;
+    config.save()?;
+    info!(
+        "Set {} to {} in {}.",
+        set_opts.key,
+        set_opts.value.join(" "),
+        af_home.config_path().display()
+    );
+    Ok(())
+}
+
+/// Removes an option from the `simpleaf.toml` of `af_home`.
+pub fn unset_config(af_home: &SimpleafHome, unset_opts: ConfigUnsetOpts) -> anyhow::Result<()> {
+    let mut config = SimpleafConfig::load_lossy(af_home)?;
+    if config.unset(&unset_opts.key)? {
+        config.save()?;
+        info!(
+            "Removed {} from {}.",
+            unset_opts.key,
+            af_home.config_path().display()
+        );
+    } else {
+        info!(
+            "{} is not set in {}; nothing to do.",
+            unset_opts.key,
+            af_home.config_path().display()
+        );
+    }
+    Ok(())
+}
+
+/// Lists the options set in the `simpleaf.toml` of `af_home`, and with
+/// `--all` the built-in defaults of the other ones.
+pub fn list_config(af_home: &SimpleafHome, list_opts: ConfigListOpts) -> anyhow::Result<()> {
+    let config = SimpleafConfig::load_lossy(af_home)?;
+    if list_opts.all {
+        for (key, value, is_set) in config.all_options() {
+            if is_set {
+                println!("{} = {}", key, value);
+            } else {
+                println!("{} = {}  # default", key, value);
+            }
+        }
+        return Ok(());
+    }
+    if config.entries().is_empty() {
+        info!(
+            "No option is set in {}; run `simpleaf config list --all` to see the defaults.",
+            af_home.config_path().display()
+        );
+    }
+    for entry in config.entries() {
+        println!("{} = {}", entry.key(), entry.repr);
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use crate::utils::constants::{SIMPLEAF_CONFIG_PATH, SIMPLEAF_INFO_PATH};
+use crate::utils::home::{HomeSource, SimpleafHome, AF_HOME_VAR};
+use crate::utils::workflow_utils::copy_dir_all;
+
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use super::HomeMigrateOpts;
+
+/// Moves the simpleaf home kept in a single directory (`--from`, or else the
+/// current home if it is given by `--af-home` or `ALEVIN_FRY_HOME`) to the
+/// XDG base directories: `simpleaf_info.json` and `simpleaf.toml` go to the configuration
+/// directory, and everything else to the data directory. Nothing is moved if
+/// any of the destinations already exists.
+pub fn migrate_home(current: &SimpleafHome, migrate_opts: HomeMigrateOpts) -> anyhow::Result<()> {
+    let from = match migrate_opts.from {
+        Some(dir) => dir,
+        None if current.source != HomeSource::Xdg => current.data_dir.clone(),
+        None => bail!(
+            "simpleaf already uses the XDG base directories ({} and {}); please pass the \
+            directory to migrate with `--from`.",
+            current.config_dir.display(),
+            current.data_dir.display()
+        ),
+    };
+    if !from.is_dir() {
+        bail!(
+            "The directory to migrate, {}, does not exist.",
+            from.display()
+        );
+    }
+    let target = SimpleafHome::xdg()?;
+    let from_canon = fs::canonicalize(&from)?;
+    for dir in [&target.config_dir, &target.data_dir] {
+        if fs::canonicalize(dir).is_ok_and(|d| d == from_canon) {
+            bail!(
+                "{} is already one of the XDG base directories of simpleaf.",
+                from.display()
+            );
+        }
+    }
+
+    let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(&from)? {
+        let name = entry?.file_name();
+        let dest_dir = if name == SIMPLEAF_INFO_PATH || name == SIMPLEAF_CONFIG_PATH {
+            &target.config_dir
+        } else {
+            &target.data_dir
+        };
+        moves.push((from.join(&name), dest_dir.join(&name)));
+    }
+    moves.sort();
+    if moves.is_empty() {
+        info!("{} is empty; nothing to migrate.", from.display());
+        return Ok(());
+    }
+
+    let conflicts: Vec<String> = moves
+        .iter()
+        .filter(|(_, dest)| dest.exists())
+        .map(|(_, dest)| dest.display().to_string())
+        .collect();
+    if !conflicts.is_empty() {
+        bail!(
+            "Cannot migrate {}, as the following already exist: {}. Please move or remove them first.",
+            from.display(),
+            conflicts.join(", ")
+        );
+    }
+
+    for (src, dest) in &moves {
+        if migrate_opts.dry_run {
+            info!(
+                "[dry_run] : Would move {} to {}",
+                src.display(),
+                dest.display()
+            );
+            continue;
+        }
+        info!("moving {} to {}", src.display(), dest.display());
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("could not create {}", parent.display()))?;
+        }
+        move_path(src, dest)
+            .with_context(|| format!("could not move {} to {}", src.display(), dest.display()))?;
+    }
+    if migrate_opts.dry_run {
+        return Ok(());
+    }
+
+    // the directory is only removed if nothing else was put in it meanwhile
+    let _ = fs::remove_dir(&from);
+    info!(
+        "Migrated {} to {} (configuration) and {} (data).",
+        from.display(),
+        target.config_dir.display(),
+        target.data_dir.display()
+    );
+    match current.source {
+        HomeSource::Env => warn!(
+            "${} is still set; please unset it (e.g. in your shell profile) so that simpleaf uses \
+            the new directories.",
+            AF_HOME_VAR
+        ),
+        HomeSource::Cli => info!("Run simpleaf without `--af-home` to use the new directories."),
+        HomeSource::Xdg => {}
+    }
+    Ok(())
+}
+
+/// Moves `src` to `dest`, copying it (and then removing it) when they are
+/// on different file systems.
+fn move_path(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    if fs::rename(src, dest).is_ok() {
+        return Ok(());
+    }
+    let copied = if src.is_dir() {
+        copy_dir_all(src, dest)
+    } else {
+        fs::copy(src, dest).map(|_| ()).map_err(anyhow::Error::from)
+    };
+    if let Err(e) = copied {
+        // do not leave a partial copy behind
+        let _ = if dest.is_dir() {
+            fs::remove_dir_all(dest)
+        } else {
+            fs::remove_file(dest)
+        };
+        return Err(e);
+    }
+    if src.is_dir() {
+        fs::remove_dir_all(src)?;
+    } else {
+        fs::remove_file(src)?;
+    }
+    Ok(())
+}
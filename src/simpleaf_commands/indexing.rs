@@ -1,6 +1,10 @@
-use crate::utils::af_utils::create_dir_if_absent;
+use crate::utils::af_utils::{create_dir_if_absent, IndexType};
+use crate::utils::constants::LOGS_DIR;
+use crate::utils::executor::Executor;
 use crate::utils::prog_utils;
-use crate::utils::prog_utils::{CommandVerbosityLevel, ReqProgs};
+use crate::utils::prog_utils::{CommandVerbosityLevel, DryRunPlan, ReqProgs, StepInfo};
+use crate::utils::provenance::Provenance;
+use crate::utils::run_record::{RunDetails, RunRecord, INDEX_RUN_RECORD_FILE};
 
 use anyhow::{anyhow, bail, Context};
 use roers;
@@ -11,7 +15,7 @@ use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
 use super::{IndexOpts, ReferenceType};
@@ -191,9 +195,9 @@ fn parse_csv_record(
     region: Option<&ProbeRegion>,
     has_region: bool,
     seq_id_hs: &mut HashSet<String>,
-    ref_seq_writer: &mut BufWriter<File>,
+    ref_seq_writer: &mut impl Write,
     // id_to_name_writer: &mut BufWriter<File>,
-    t2g_writer: &mut BufWriter<File>,
+    t2g_writer: &mut impl Write,
 ) -> anyhow::Result<()> {
     if !include {
         return Ok(());
@@ -226,13 +230,62 @@ fn parse_csv_record(
     Ok(())
 }
 
-pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Result<()> {
+/// The outcome of a successful [`build_ref_and_index`] run.
+#[derive(Clone, Debug)]
+pub struct IndexResult {
+    /// The top-level output directory
+    pub output_dir: PathBuf,
+    /// The index that was built, and the path to pass to `quant` to use it
+    pub index: IndexType,
+    /// The directory holding the expanded reference, if one was built
+    pub ref_dir: Option<PathBuf>,
+    /// The transcript-to-gene map copied into the index directory, if any
+    pub t2g_file: Option<PathBuf>,
+    /// The gene id to gene name map copied into the index directory, if any
+    pub gene_id_to_name_file: Option<PathBuf>,
+    /// The steps (reference construction and indexing) that were run
+    /// (in a `--dry-run`, the steps that would be run, with a running time of zero)
+    pub steps: Vec<StepInfo>,
+}
+
+/// Finishes the `plan` of a `--dry-run` of [`build_ref_and_index`], and
+/// returns the result that the run would have.
+fn plan_index(
+    mut plan: DryRunPlan,
+    output: PathBuf,
+    index_type: IndexType,
+    ref_seq: &Path,
+    kmer_length: u32,
+    has_t2g: bool,
+    has_gene_id_to_name: bool,
+) -> anyhow::Result<IndexResult> {
+    plan.resolve("index type", index_type.as_str());
+    plan.resolve("reference", ref_seq.display());
+    plan.resolve("k-mer length", kmer_length);
+    plan.resolve("output", output.display());
+    plan.finish()?;
+
+    let output_index_dir = output.join("index");
+    let outref = output.join("ref");
+    Ok(IndexResult {
+        ref_dir: ref_seq.starts_with(&outref).then_some(outref),
+        t2g_file: has_t2g.then(|| output_index_dir.join("t2g_3col.tsv")),
+        gene_id_to_name_file: has_gene_id_to_name
+            .then(|| output_index_dir.join("gene_id_to_name.tsv")),
+        steps: plan.steps(),
+        output_dir: output,
+        index: index_type,
+    })
+}
+
+pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Result<IndexResult> {
     validate_index_type_opts(&opts)?;
     let mut threads = opts.threads;
     let output = opts.output;
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     // Read the JSON contents of the file as an instance of `User`.
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     rp.issue_recommended_version_messages();
     // we are building a custom spliced+intronic reference
@@ -254,7 +307,31 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         }
     });
 
-    create_dir_if_absent(&output)?;
+    // in a dry run, the steps are only planned, and nothing is written
+    let mut plan = opts.dry_run.then(|| DryRunPlan::new("simpleaf index"));
+
+    // record the inputs before they are consumed
+    let mut provenance = Provenance::new("index", &rp);
+    if plan.is_none() {
+        create_dir_if_absent(&output)?;
+
+        for (role, input) in [
+            ("fasta", &opts.fasta),
+            ("gtf", &opts.gtf),
+            ("ref_seq", &opts.ref_seq),
+            ("spliced", &opts.spliced),
+            ("unspliced", &opts.unspliced),
+            ("probe_csv", &opts.probe_csv),
+            ("feature_csv", &opts.feature_csv),
+        ] {
+            if let Some(p) = input {
+                provenance.add_input(role, p)?;
+            }
+        }
+        if let Some(decoy_paths) = &opts.decoy_paths {
+            provenance.add_inputs("decoy", decoy_paths)?;
+        }
+    }
 
     // wow, the compiler is smart enough to
     // figure out that this one need not be
@@ -287,8 +364,6 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
             ReferenceType::SplicedUnspliced => Some(vec![roers::AugType::GeneBody]),
         };
 
-        create_dir_if_absent(&outref)?;
-
         let roers_opts = roers::AugRefOpts {
             // The path to a genome fasta file.
             genome: fasta.clone(),
@@ -322,13 +397,21 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         index_info["args"]["unspliced"] = json!(&opts.unspliced);
         index_info["args"]["dedup"] = json!(opts.dedup);
 
-        prog_utils::check_files_exist(&input_files)?;
+        if let Some(plan) = plan.as_mut() {
+            let mut roers_inputs = input_files;
+            roers_inputs.extend(opts.spliced.clone());
+            roers_inputs.extend(opts.unspliced.clone());
+            plan.add_step("roers", None, &roers_inputs, std::slice::from_ref(&outref));
+        } else {
+            create_dir_if_absent(&outref)?;
+            prog_utils::check_files_exist(&input_files)?;
 
-        info!("preparing to make reference with roers");
+            info!("preparing to make reference with roers");
 
-        let roers_start = Instant::now();
-        roers::make_ref(roers_opts)?;
-        roers_duration = Some(roers_start.elapsed());
+            let roers_start = Instant::now();
+            roers::make_ref(roers_opts)?;
+            roers_duration = Some(roers_start.elapsed());
+        }
 
         min_seq_len = None;
         reference_sequence = Some(ref_file);
@@ -342,8 +425,11 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         reference_sequence = Some(ref_seq.clone());
     } else {
         // now, we have to have a probe csv or feature csv
-        create_dir_if_absent(&outref)?;
-
+        let csv_path = opts
+            .probe_csv
+            .as_ref()
+            .or(opts.feature_csv.as_ref())
+            .cloned();
         let mut csv_reader = if let Some(probe_csv) = &opts.probe_csv {
             index_info["args"]["probe-csv"] = json!(probe_csv);
             let rdr = csv::ReaderBuilder::new()
@@ -378,10 +464,25 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
             outref.join("t2g.tsv")
         };
 
-        // define buffer writers
-        let mut ref_seq_writer = BufWriter::new(File::create(&ref_seq_path)?);
+        // define buffer writers; in a dry run, the records are only
+        // checked (and the minimum sequence length found), not written.
+        let (mut ref_seq_writer, mut t2g_writer): (Box<dyn Write>, Box<dyn Write>) =
+            if let Some(plan) = plan.as_mut() {
+                plan.add_step(
+                    "reference",
+                    None,
+                    &Vec::from_iter(csv_path),
+                    std::slice::from_ref(&outref),
+                );
+                (Box::new(std::io::sink()), Box::new(std::io::sink()))
+            } else {
+                create_dir_if_absent(&outref)?;
+                (
+                    Box::new(BufWriter::new(File::create(&ref_seq_path)?)),
+                    Box::new(BufWriter::new(File::create(&t2g_path)?)),
+                )
+            };
         // let mut id_to_name_writer = BufWriter::new(File::create(&id_to_name_path)?);
-        let mut t2g_writer = BufWriter::new(File::create(&t2g_path)?);
         let mut msl = u32::MAX;
 
         match csv_reader {
@@ -435,18 +536,22 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         // _gene_id_to_name = Some(id_to_name_path);
     }
 
-    std::fs::write(
-        &info_file,
-        serde_json::to_string_pretty(&index_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", info_file.display()))?;
+    if plan.is_none() {
+        std::fs::write(
+            &info_file,
+            serde_json::to_string_pretty(&index_info).unwrap(),
+        )
+        .with_context(|| format!("could not write {}", info_file.display()))?;
+    }
 
     let ref_seq = reference_sequence.with_context(||
                 "Reference sequence should either be generated from --fasta with reftype spliced+intronic / spliced+unspliced or set with --ref-seq",
             )?;
 
-    let input_files = vec![ref_seq.clone()];
-    prog_utils::check_files_exist(&input_files)?;
+    let mut input_files = vec![ref_seq.clone()];
+    if plan.is_none() {
+        prog_utils::check_files_exist(&input_files)?;
+    }
 
     let kmer_length: u32;
     let minimizer_length: u32;
@@ -473,6 +578,9 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
     let output_index_dir = output.join("index");
     let index_duration;
     let index_cmd_string: String;
+    let index_type: IndexType;
+    let mut index_t2g_file: Option<PathBuf> = None;
+    let mut index_gene_id_to_name_file: Option<PathBuf> = None;
 
     if opts.use_piscem {
         // ensure we have piscem
@@ -486,10 +594,11 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
             .as_ref()
             .expect("piscem program info should be properly set.");
 
-        let mut piscem_index_cmd =
-            std::process::Command::new(format!("{}", piscem_prog_info.exe_path.display()));
+        let mut piscem_index_cmd = executor.command(&piscem_prog_info.exe_path);
 
-        create_dir_if_absent(&output_index_dir)?;
+        if plan.is_none() {
+            create_dir_if_absent(&output_index_dir)?;
+        }
         let output_index_stem = output_index_dir.join("piscem_idx");
 
         piscem_index_cmd
@@ -538,6 +647,7 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
                 ">=0.7.0, <1.0.0",
                 &piscem_prog_info.version,
             ) {
+                input_files.extend_from_slice(&decoy_paths);
                 let path_args = decoy_paths
                     .into_iter()
                     .map(|x| x.to_string_lossy().into_owned())
@@ -559,51 +669,69 @@ simpleaf"#,
         // print piscem build command
         index_cmd_string = prog_utils::get_cmd_line_string(&piscem_index_cmd);
         info!("piscem build cmd : {}", index_cmd_string);
-
-        let index_start = Instant::now();
-        let cres = prog_utils::execute_command(&mut piscem_index_cmd, CommandVerbosityLevel::Quiet)
+        index_type = IndexType::Piscem(output_index_stem);
+
+        if let Some(plan) = plan.as_mut() {
+            plan.add_step(
+                "index",
+                Some(&piscem_index_cmd),
+                &input_files,
+                std::slice::from_ref(&output_index_dir),
+            );
+            index_duration = Duration::ZERO;
+        } else {
+            let index_start = Instant::now();
+            let cres = prog_utils::execute_logged_command(
+                &mut piscem_index_cmd,
+                CommandVerbosityLevel::global(),
+                &output.join(LOGS_DIR),
+                "piscem_index",
+            )
             .expect("failed to invoke piscem index command");
-        index_duration = index_start.elapsed();
+            index_duration = index_start.elapsed();
 
-        if !cres.status.success() {
-            bail!("piscem index failed to build succesfully {:?}", cres.status);
-        }
+            if !cres.status.success() {
+                bail!("piscem index failed to build succesfully {:?}", cres.status);
+            }
 
-        // copy over the t2g file to the index
-        let mut t2g_out_path: Option<PathBuf> = None;
-        if let Some(t2g_file) = t2g {
-            let index_t2g_path = output_index_dir.join("t2g_3col.tsv");
-            t2g_out_path = Some(PathBuf::from("t2g_3col.tsv"));
-            std::fs::copy(t2g_file, index_t2g_path)?;
-        }
+            // copy over the t2g file to the index
+            let mut t2g_out_path: Option<PathBuf> = None;
+            if let Some(t2g_file) = &t2g {
+                let index_t2g_path = output_index_dir.join("t2g_3col.tsv");
+                t2g_out_path = Some(PathBuf::from("t2g_3col.tsv"));
+                std::fs::copy(t2g_file, &index_t2g_path)?;
+                index_t2g_file = Some(index_t2g_path);
+            }
 
-        // copy over the gene_id_to_name.tsv file to the index
-        let mut gene_id_to_name_out_path: Option<PathBuf> = None;
-        if let Some(gene_id_to_name_file) = gene_id_to_name {
-            let index_id2name_path = output_index_dir.join("gene_id_to_name.tsv");
-            gene_id_to_name_out_path = Some(PathBuf::from("gene_id_to_name.tsv"));
-            std::fs::copy(gene_id_to_name_file, index_id2name_path)?;
-        }
+            // copy over the gene_id_to_name.tsv file to the index
+            let mut gene_id_to_name_out_path: Option<PathBuf> = None;
+            if let Some(gene_id_to_name_file) = &gene_id_to_name {
+                let index_id2name_path = output_index_dir.join("gene_id_to_name.tsv");
+                gene_id_to_name_out_path = Some(PathBuf::from("gene_id_to_name.tsv"));
+                std::fs::copy(gene_id_to_name_file, &index_id2name_path)?;
+                index_gene_id_to_name_file = Some(index_id2name_path);
+            }
 
-        let index_json_file = output_index_dir.join("simpleaf_index.json");
-        let index_json = json!({
-                "cmd" : index_cmd_string,
-                "index_type" : "piscem",
-                "t2g_file" : t2g_out_path,
-                "gene_id_to_name_file" : gene_id_to_name_out_path,
-                "piscem_index_parameters" : {
-                    "k" : kmer_length,
-                    "m" : minimizer_length,
-                    "overwrite" : opts.overwrite,
-                    "threads" : threads,
-                    "ref" : ref_seq
-                }
-        });
-        std::fs::write(
-            &index_json_file,
-            serde_json::to_string_pretty(&index_json).unwrap(),
-        )
-        .with_context(|| format!("could not write {}", index_json_file.display()))?;
+            let index_json_file = output_index_dir.join("simpleaf_index.json");
+            let index_json = json!({
+                    "cmd" : index_cmd_string,
+                    "index_type" : "piscem",
+                    "t2g_file" : t2g_out_path,
+                    "gene_id_to_name_file" : gene_id_to_name_out_path,
+                    "piscem_index_parameters" : {
+                        "k" : kmer_length,
+                        "m" : minimizer_length,
+                        "overwrite" : opts.overwrite,
+                        "threads" : threads,
+                        "ref" : ref_seq
+                    }
+            });
+            std::fs::write(
+                &index_json_file,
+                serde_json::to_string_pretty(&index_json).unwrap(),
+            )
+            .with_context(|| format!("could not write {}", index_json_file.display()))?;
+        }
     } else {
         // ensure we have piscem
         if rp.salmon.is_none() {
@@ -611,8 +739,7 @@ simpleaf"#,
                             Please either set a path using the `simpleaf set-paths` command, or ensure the `SALMON` environment variable is set properly.");
         }
 
-        let mut salmon_index_cmd =
-            std::process::Command::new(format!("{}", rp.salmon.unwrap().exe_path.display()));
+        let mut salmon_index_cmd = executor.command(rp.salmon.unwrap().exe_path);
 
         salmon_index_cmd
             .arg("index")
@@ -660,82 +787,121 @@ simpleaf"#,
         // print salmon index command
         index_cmd_string = prog_utils::get_cmd_line_string(&salmon_index_cmd);
         info!("salmon index cmd : {}", index_cmd_string);
-
-        let index_start = Instant::now();
-        let cres = prog_utils::execute_command(&mut salmon_index_cmd, CommandVerbosityLevel::Quiet)
+        index_type = IndexType::Salmon(output_index_dir.clone());
+
+        if let Some(plan) = plan.as_mut() {
+            plan.add_step(
+                "index",
+                Some(&salmon_index_cmd),
+                &input_files,
+                std::slice::from_ref(&output_index_dir),
+            );
+            index_duration = Duration::ZERO;
+        } else {
+            let index_start = Instant::now();
+            let cres = prog_utils::execute_logged_command(
+                &mut salmon_index_cmd,
+                CommandVerbosityLevel::global(),
+                &output.join(LOGS_DIR),
+                "salmon_index",
+            )
             .expect("failed to invoke salmon index command");
-        index_duration = index_start.elapsed();
+            index_duration = index_start.elapsed();
 
-        if !cres.status.success() {
-            bail!("salmon index failed to build succesfully {:?}", cres.status);
-        }
+            if !cres.status.success() {
+                bail!("salmon index failed to build succesfully {:?}", cres.status);
+            }
 
-        // copy over the t2g file to the index
-        let mut t2g_out_path: Option<PathBuf> = None;
-        if let Some(t2g_file) = t2g {
-            let index_t2g_path = output_index_dir.join("t2g_3col.tsv");
-            t2g_out_path = Some(PathBuf::from("t2g_3col.tsv"));
-            std::fs::copy(t2g_file, index_t2g_path)?;
-        }
+            // copy over the t2g file to the index
+            let mut t2g_out_path: Option<PathBuf> = None;
+            if let Some(t2g_file) = &t2g {
+                let index_t2g_path = output_index_dir.join("t2g_3col.tsv");
+                t2g_out_path = Some(PathBuf::from("t2g_3col.tsv"));
+                std::fs::copy(t2g_file, &index_t2g_path)?;
+                index_t2g_file = Some(index_t2g_path);
+            }
 
-        // copy over the gene_id_to_name.tsv file to the index
-        let mut gene_id_to_name_out_path: Option<PathBuf> = None;
-        info!("{:?}", gene_id_to_name);
-        if let Some(gene_id_to_name_file) = gene_id_to_name {
-            let index_id2name_path = output_index_dir.join("gene_id_to_name.tsv");
-            gene_id_to_name_out_path = Some(PathBuf::from("gene_id_to_name.tsv"));
-            std::fs::copy(gene_id_to_name_file, index_id2name_path)?;
+            // copy over the gene_id_to_name.tsv file to the index
+            let mut gene_id_to_name_out_path: Option<PathBuf> = None;
+            info!("{:?}", gene_id_to_name);
+            if let Some(gene_id_to_name_file) = &gene_id_to_name {
+                let index_id2name_path = output_index_dir.join("gene_id_to_name.tsv");
+                gene_id_to_name_out_path = Some(PathBuf::from("gene_id_to_name.tsv"));
+                std::fs::copy(gene_id_to_name_file, &index_id2name_path)?;
+                index_gene_id_to_name_file = Some(index_id2name_path);
+            }
+
+            let index_json_file = output_index_dir.join("simpleaf_index.json");
+            let index_json = json!({
+                    "cmd" : index_cmd_string,
+                    "index_type" : "salmon",
+                    "t2g_file" : t2g_out_path,
+                    "gene_id_to_name_file" : gene_id_to_name_out_path,
+                    "salmon_index_parameters" : {
+                        "k" : opts.kmer_length,
+                        "overwrite" : opts.overwrite,
+                        "sparse" : opts.sparse,
+                        "keep_duplicates" : opts.keep_duplicates,
+                        "threads" : threads,
+                        "ref" : ref_seq
+                    }
+            });
+            std::fs::write(
+                &index_json_file,
+                serde_json::to_string_pretty(&index_json).unwrap(),
+            )
+            .with_context(|| format!("could not write {}", index_json_file.display()))?;
         }
+    }
 
-        let index_json_file = output_index_dir.join("simpleaf_index.json");
-        let index_json = json!({
-                "cmd" : index_cmd_string,
-                "index_type" : "salmon",
-                "t2g_file" : t2g_out_path,
-                "gene_id_to_name_file" : gene_id_to_name_out_path,
-                "salmon_index_parameters" : {
-                    "k" : opts.kmer_length,
-                    "overwrite" : opts.overwrite,
-                    "sparse" : opts.sparse,
-                    "keep_duplicates" : opts.keep_duplicates,
-                    "threads" : threads,
-                    "ref" : ref_seq
-                }
-        });
-        std::fs::write(
-            &index_json_file,
-            serde_json::to_string_pretty(&index_json).unwrap(),
-        )
-        .with_context(|| format!("could not write {}", index_json_file.display()))?;
+    if let Some(plan) = plan {
+        return plan_index(
+            plan,
+            output,
+            index_type,
+            &ref_seq,
+            kmer_length,
+            t2g.is_some(),
+            gene_id_to_name.is_some(),
+        );
     }
 
-    let index_log_file = output.join("simpleaf_index_log.json");
-    let index_log_info = if let Some(roers_duration) = roers_duration {
-        // if we ran make-splici
-        json!({
-            "time_info" : {
-                "roers_time" : roers_duration,
-                "index_time" : index_duration
-            },
-            "cmd_info" : {
-                "roers_cmd" : roers_aug_ref_opt,
-                "index_cmd" : index_cmd_string,                    }
-        })
-    } else {
-        // if we indexed provided sequences directly
-        json!({
-            "time_info" : {
-                "index_time" : index_duration
-            },
-            "cmd_info" : {
-                "index_cmd" : index_cmd_string,                    }
-        })
-    };
+    let mut steps = Vec::new();
+    if let Some(roers_duration) = roers_duration {
+        steps.push(StepInfo::new("roers", None, roers_duration));
+    }
+    steps.push(StepInfo::new(
+        "index",
+        Some(index_cmd_string.clone()),
+        index_duration,
+    ));
+
+    let mut run_record = RunRecord::new(RunDetails::Index {
+        index_type: match index_type {
+            IndexType::Salmon(_) => "salmon",
+            _ => "piscem",
+        }
+        .to_string(),
+        index_dir: output_index_dir.clone(),
+        ref_dir: outref.is_dir().then(|| outref.clone()),
+        roers_options: roers_aug_ref_opt.map(|o| json!(o)),
+    });
+    run_record.options = opts.effective_options;
+    run_record.steps = steps.clone();
+    run_record.write(&output.join(INDEX_RUN_RECORD_FILE))?;
 
-    std::fs::write(
-        &index_log_file,
-        serde_json::to_string_pretty(&index_log_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", index_log_file.display()))?;
-    Ok(())
+    if outref.is_dir() {
+        provenance.add_output_dir("ref", &outref)?;
+    }
+    provenance.add_output_dir("index", &output_index_dir)?;
+    provenance.write(&output)?;
+
+    Ok(IndexResult {
+        output_dir: output,
+        index: index_type,
+        ref_dir: outref.is_dir().then_some(outref),
+        t2g_file: index_t2g_file,
+        gene_id_to_name_file: index_gene_id_to_name_file,
+        steps,
+    })
 }
@@ -1,5 +1,6 @@
 use crate::atac::commands::AtacChemistry;
 use crate::utils::constants::CHEMISTRIES_PATH;
+use crate::utils::home::SimpleafHome;
 use crate::utils::{
     af_utils::RnaChemistry,
     chem_utils::{custom_chem_hm_into_json, get_custom_chem_hm},
@@ -27,7 +28,7 @@ pub fn inspect_simpleaf(version: &str, af_home_path: PathBuf) -> Result<()> {
     } else {
         warn!(
             r#"
-            You are missing a "chemistries.json" file from your ALEVIN_FRY_HOME. This 
+            You are missing a "chemistries.json" file from your simpleaf home. This 
             likely means you installed a new version of simpleaf and have not yet run 
             simpleaf chem refresh
             please invoke the `chem refresh` command to obtain the relevant chemistries.json
@@ -46,6 +47,7 @@ pub fn inspect_simpleaf(version: &str, af_home_path: PathBuf) -> Result<()> {
 
     let inspect_v = json!({
         "simpleaf_version" : version,
+        "simpleaf_home" : SimpleafHome::of(&af_home_path),
         "simpleaf_info" : simpleaf_info,
         "custom_chem_info" : chem_info_value,
         "builtin_chemistries" : {
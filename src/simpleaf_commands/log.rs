@@ -0,0 +1,172 @@
+use crate::utils::run_record::{OptionSource, RunDetails, RunRecord};
+
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use super::{LogDiffOpts, LogShowOpts};
+
+/// Prints the run record at `opts.input`, either as a summary or as JSON.
+pub fn show_log(opts: LogShowOpts) -> anyhow::Result<()> {
+    let record = RunRecord::read(&opts.input)?;
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&record)?);
+        return Ok(());
+    }
+
+    println!("command          : {}", record.command());
+    println!("simpleaf version : {}", record.simpleaf_version);
+    println!("schema version   : {}", record.schema_version);
+    match &record.details {
+        RunDetails::Index {
+            index_type,
+            index_dir,
+            ref_dir,
+            ..
+        } => {
+            println!("index type       : {}", index_type);
+            println!("index directory  : {}", index_dir.display());
+            if let Some(ref_dir) = ref_dir {
+                println!("reference dir    : {}", ref_dir.display());
+            }
+        }
+        RunDetails::Quant {
+            mapper,
+            map_dir,
+            quant_dir,
+            resumed_steps,
+            ..
+        } => {
+            println!(
+                "mapper           : {}",
+                mapper.as_deref().unwrap_or("none (existing mapping)")
+            );
+            println!("map directory    : {}", map_dir.display());
+            println!("quant directory  : {}", quant_dir.display());
+            if !resumed_steps.is_empty() {
+                println!("resumed steps    : {}", resumed_steps.join(", "));
+            }
+        }
+        RunDetails::AtacProcess {
+            mapper,
+            map_dir,
+            process_dir,
+        } => {
+            println!("mapper           : {}", mapper);
+            println!("map directory    : {}", map_dir.display());
+            println!("process directory: {}", process_dir.display());
+        }
+    }
+
+    let from_config: Vec<String> = record
+        .options
+        .iter()
+        .filter(|(_, o)| o.source == OptionSource::Config)
+        .map(|(name, o)| format!("{}={}", name, o.value))
+        .collect();
+    if !from_config.is_empty() {
+        println!("simpleaf.toml    : {}", from_config.join(", "));
+    }
+
+    println!("steps:");
+    let name_width = record.steps.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    for step in &record.steps {
+        println!(
+            "  {:<width$}  {:>10.2}s  {}",
+            step.name,
+            step.duration.as_secs_f64(),
+            step.cmd.as_deref().unwrap_or("-"),
+            width = name_width
+        );
+    }
+    Ok(())
+}
+
+/// Converts a run record into a map from the JSON pointer of each of its
+/// (leaf) fields to the value of that field. The steps are keyed by their
+/// name rather than by their position, and the stage checkpoints (whose
+/// fingerprints always differ between runs) are left out.
+fn flatten_record(record: &RunRecord) -> anyhow::Result<BTreeMap<String, Value>> {
+    let mut v = serde_json::to_value(record)?;
+    if let Value::Object(obj) = &mut v {
+        obj.remove("stages");
+        if let Some(Value::Array(steps)) = obj.remove("steps") {
+            let mut by_name = Map::new();
+            for mut step in steps {
+                let name = step["name"].as_str().unwrap_or_default().to_string();
+                if let Value::Object(s) = &mut step {
+                    s.remove("name");
+                }
+                by_name.insert(name, step);
+            }
+            obj.insert("steps".to_string(), Value::Object(by_name));
+        }
+    }
+
+    let mut flat = BTreeMap::new();
+    flatten_value(&v, String::new(), &mut flat);
+    Ok(flat)
+}
+
+fn flatten_value(v: &Value, prefix: String, flat: &mut BTreeMap<String, Value>) {
+    match v {
+        Value::Object(obj) if !obj.is_empty() => {
+            for (k, child) in obj {
+                let k = k.replace('~', "~0").replace('/', "~1");
+                flatten_value(child, format!("{}/{}", prefix, k), flat);
+            }
+        }
+        Value::Array(arr) if !arr.is_empty() => {
+            for (i, child) in arr.iter().enumerate() {
+                flatten_value(child, format!("{}/{}", prefix, i), flat);
+            }
+        }
+        _ => {
+            flat.insert(prefix, v.clone());
+        }
+    }
+}
+
+/// Describes the change of a value from `a` to `b`; running times are
+/// given along with their relative change.
+fn describe_change(key: &str, a: &Value, b: &Value) -> String {
+    if let (true, Some(x), Some(y)) = (key.ends_with("/time_secs"), a.as_f64(), b.as_f64()) {
+        if x > 0.0 {
+            return format!("{:.2}s -> {:.2}s ({:+.1}%)", x, y, (y - x) / x * 100.0);
+        }
+        return format!("{:.2}s -> {:.2}s", x, y);
+    }
+    format!("{} -> {}", a, b)
+}
+
+/// Prints the differences between the run records at `opts.first` and
+/// `opts.second`.
+pub fn diff_logs(opts: LogDiffOpts) -> anyhow::Result<()> {
+    let first = flatten_record(&RunRecord::read(&opts.first)?)?;
+    let second = flatten_record(&RunRecord::read(&opts.second)?)?;
+
+    let mut n_diffs = 0;
+    for (k, a) in &first {
+        match second.get(k) {
+            None => {
+                println!("- {}: {}", k, a);
+                n_diffs += 1;
+            }
+            Some(b) if a != b => {
+                println!("~ {}: {}", k, describe_change(k, a, b));
+                n_diffs += 1;
+            }
+            Some(_) => {}
+        }
+    }
+    for (k, b) in &second {
+        if !first.contains_key(k) {
+            println!("+ {}: {}", k, b);
+            n_diffs += 1;
+        }
+    }
+
+    if n_diffs == 0 {
+        println!("the run records are identical");
+    }
+    Ok(())
+}
@@ -1,32 +1,34 @@
+use crate::utils::executor::Executor;
+use crate::utils::network::NetworkSettings;
 use crate::utils::prog_utils::*;
 
-use anyhow::{bail, Context};
-use serde_json::json;
+use anyhow::bail;
 use std::fs;
 use std::path::PathBuf;
 use tracing::info;
 
-use super::SetPathOpts;
+use super::{SetExecutorOpts, SetNetworkOpts, SetPathOpts};
 
 pub fn set_paths(af_home_path: PathBuf, set_path_args: SetPathOpts) -> anyhow::Result<()> {
-    const AF_HOME: &str = "ALEVIN_FRY_HOME";
     let SetPathOpts {
         salmon,
         piscem,
         alevin_fry,
         macs,
     } = set_path_args;
-    // create AF_HOME if needed
+    // create the simpleaf home if needed
     if !af_home_path.as_path().is_dir() {
         info!(
-            "The {} directory, {}, doesn't exist, creating...",
-            AF_HOME,
+            "The simpleaf home directory, {}, doesn't exist, creating...",
             af_home_path.display()
         );
         fs::create_dir_all(af_home_path.as_path())?;
     }
 
-    let rp = get_required_progs_from_paths(salmon, piscem, alevin_fry, macs)?;
+    // the programs are probed through the configured executor, so that
+    // the paths may refer to programs inside a container image
+    let executor = Executor::from_af_home(&af_home_path)?;
+    let rp = get_required_progs_from_paths(salmon, piscem, alevin_fry, macs, &executor)?;
 
     let have_mapper = rp.salmon.is_some() || rp.piscem.is_some();
     if !have_mapper {
@@ -36,13 +38,97 @@ pub fn set_paths(af_home_path: PathBuf, set_path_args: SetPathOpts) -> anyhow::R
         bail!("Suitable alevin_fry executable not found.");
     }
 
-    let simpleaf_info_file = af_home_path.join("simpleaf_info.json");
-    let simpleaf_info = json!({ "prog_info": rp });
+    update_af_home_info(&af_home_path, "prog_info", serde_json::to_value(&rp)?)
+}
+
+pub fn set_executor(
+    af_home_path: PathBuf,
+    set_executor_args: SetExecutorOpts,
+) -> anyhow::Result<()> {
+    let SetExecutorOpts {
+        local,
+        container,
+        runtime,
+        bind,
+        runtime_arg,
+        wrapper,
+    } = set_executor_args;
+
+    let executor = match (local, container, wrapper) {
+        (true, None, None) => Executor::Local,
+        (false, Some(image), None) => Executor::Container {
+            runtime,
+            image,
+            binds: bind,
+            runtime_args: runtime_arg,
+        },
+        (false, None, Some(w)) => {
+            let prefix: Vec<String> = w.split_whitespace().map(String::from).collect();
+            if prefix.is_empty() {
+                bail!("The wrapper command passed to `--wrapper` is empty.");
+            }
+            Executor::Wrapper { prefix }
+        }
+        _ => bail!("Exactly one of `--local`, `--container` and `--wrapper` must be given."),
+    };
+
+    if !af_home_path.as_path().is_dir() {
+        fs::create_dir_all(af_home_path.as_path())?;
+    }
+    update_af_home_info(&af_home_path, "executor", serde_json::to_value(&executor)?)?;
+    info!("external programs will be run by the {} executor", executor);
+    if inspect_af_home(&af_home_path)?.get("prog_info").is_some() {
+        info!(
+            "please run `simpleaf refresh-prog-info` (or `simpleaf set-paths`, if the programs are \
+             at different paths under the new executor) to check the programs through it."
+        );
+    }
+    Ok(())
+}
+
+pub fn set_network(af_home_path: PathBuf, set_network_args: SetNetworkOpts) -> anyhow::Result<()> {
+    let SetNetworkOpts {
+        mirror,
+        remove_mirror,
+        proxy,
+        clear_proxy,
+        retries,
+        retry_backoff,
+        connect_timeout,
+        timeout,
+        reset,
+    } = set_network_args;
+
+    let mut settings = if reset {
+        NetworkSettings::default()
+    } else {
+        NetworkSettings::from_af_home(&af_home_path)?
+    };
+    for from in remove_mirror {
+        if settings.mirrors.remove(&from).is_none() {
+            bail!("There is no mirror of {}.", from);
+        }
+    }
+    settings.mirrors.extend(mirror);
+    if clear_proxy {
+        settings.proxy = None;
+    }
+    if proxy.is_some() {
+        settings.proxy = proxy;
+    }
+    settings.retries = retries.unwrap_or(settings.retries);
+    settings.retry_backoff_secs = retry_backoff.unwrap_or(settings.retry_backoff_secs);
+    settings.connect_timeout_secs = connect_timeout.unwrap_or(settings.connect_timeout_secs);
+    settings.timeout_secs = timeout.unwrap_or(settings.timeout_secs);
+    settings.check()?;
 
-    std::fs::write(
-        &simpleaf_info_file,
-        serde_json::to_string_pretty(&simpleaf_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", simpleaf_info_file.display()))?;
+    if !af_home_path.as_path().is_dir() {
+        fs::create_dir_all(af_home_path.as_path())?;
+    }
+    update_af_home_info(&af_home_path, "network", serde_json::to_value(&settings)?)?;
+    info!(
+        "the network settings are:\n{}",
+        serde_json::to_string_pretty(&settings)?
+    );
     Ok(())
 }
@@ -1,9 +1,22 @@
 use crate::utils::af_utils::*;
-
+use crate::utils::checkpoint::{stage_fingerprint, stage_time, StageCheckpoints};
+use crate::utils::chem_detect::{
+    self, builtin_geometry, AUTO_CHEMISTRY, DEFAULT_NUM_SAMPLED_READS,
+};
+use crate::utils::empty_drops::EmptyDropsParams;
+use crate::utils::executor::Executor;
+
+use crate::utils::permit_list::{self, INFERRED_PL_DIR, INFERRED_PL_FILE, INFERRED_PL_LOG_FILE};
 use crate::utils::prog_utils;
-use crate::utils::prog_utils::{CommandVerbosityLevel, ReqProgs};
+use crate::utils::prog_utils::{
+    get_generic_buf_reader, CommandVerbosityLevel, DryRunPlan, ReqProgs, StepInfo,
+};
+use crate::utils::provenance::Provenance;
+use crate::utils::read_checks::{self, NUM_CHECKED_READS};
+use crate::utils::run_record::{write_json_atomic, RunDetails, RunRecord, QUANT_RUN_RECORD_FILE};
 
 use anyhow::{bail, Context};
+use serde::Deserialize;
 use serde_json::json;
 use serde_json::Value;
 use std::collections::HashMap;
@@ -12,19 +25,15 @@ use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
+use super::call_cells::write_cell_calls;
+use super::report::write_qc_report;
 use super::MapQuantOpts;
 use crate::utils::chem_utils::ExpectedOri;
-use crate::utils::constants::{CHEMISTRIES_PATH, NUM_SAMPLE_LINES};
+use crate::utils::constants::{CHEMISTRIES_PATH, LOGS_DIR, NUM_SAMPLE_LINES};
 
-fn get_generic_buf_reader(ipath: &PathBuf) -> anyhow::Result<impl BufRead> {
-    let (reader, compression) = niffler::from_path(ipath)
-        .with_context(|| format!("Could not open requsted file {}", ipath.display()))?;
-    match compression {
-        niffler::compression::Format::No => info!("found uncompressed file"),
-        f => info!("found file compressed using {:?}", f),
-    }
-    Ok(BufReader::new(reader))
-}
+/// The file, in the output directory of a sample sheet run, that records the
+/// outcome of every sample.
+static SAMPLE_SHEET_SUMMARY_FILE: &str = "simpleaf_quant_summary.json";
 
 struct CBListInfo {
     pub init_file: PathBuf,
@@ -41,7 +50,8 @@ impl CBListInfo {
         }
     }
     // we iterate the file to see if it only has cb or with affiliated info (by separator \t).
-    fn init(&mut self, pl_file: &PathBuf, output: &PathBuf) -> anyhow::Result<()> {
+    // In a dry run, the first column is not actually written to the final file.
+    fn init(&mut self, pl_file: &Path, output: &PathBuf, dry_run: bool) -> anyhow::Result<()> {
         // open pl_file
         let br = get_generic_buf_reader(pl_file)
             .with_context(|| "failed to successfully open permit-list file.")?;
@@ -58,7 +68,9 @@ impl CBListInfo {
         // if single column, we are good. Otherwise, we need to write the first column to the final file
         let final_file: PathBuf;
         if is_single_column {
-            final_file = pl_file.clone();
+            final_file = pl_file.to_path_buf();
+        } else if dry_run {
+            final_file = output.join("cb_list.txt");
         } else {
             info!("found multiple columns in the barcode list tsv file, use the first column as the barcodes.");
 
@@ -90,7 +102,7 @@ impl CBListInfo {
             }
         }
 
-        self.init_file = pl_file.clone();
+        self.init_file = pl_file.to_path_buf();
         self.final_file = final_file;
         self.is_single_column = is_single_column;
         Ok(())
@@ -235,13 +247,26 @@ fn validate_map_and_quant_opts(opts: &MapQuantOpts) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<()> {
-    validate_map_and_quant_opts(&opts)?;
+/// The information resolved once per `quant` invocation, and shared by
+/// every sample that is mapped and quantified against the same index
+/// (or read from the same mapping directory).
+struct QuantResources {
+    rp: ReqProgs,
+    executor: Executor,
+    index_type: IndexType,
+    t2g_map_file: PathBuf,
+    gene_id_to_name_opt: Option<PathBuf>,
+}
 
+fn resolve_quant_resources(
+    af_home_path: &Path,
+    opts: &MapQuantOpts,
+) -> anyhow::Result<QuantResources> {
     let mut t2g_map = opts.t2g_map.clone();
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = prog_utils::inspect_af_home(af_home_path)?;
     let rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
+    let executor = Executor::from_info(&v)?;
 
     rp.issue_recommended_version_messages();
 
@@ -383,10 +408,509 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
         IndexType::NoIndex => {}
     }
 
+    Ok(QuantResources {
+        rp,
+        executor,
+        index_type,
+        t2g_map_file,
+        gene_id_to_name_opt,
+    })
+}
+
+/// Maps and quantifies the sample described by `opts`, or each of the samples listed
+/// in its sample sheet, and returns one [`QuantResult`] per sample.
+pub fn map_and_quant(
+    af_home_path: &Path,
+    mut opts: MapQuantOpts,
+) -> anyhow::Result<Vec<QuantResult>> {
+    validate_map_and_quant_opts(&opts)?;
+
+    let quant_res = resolve_quant_resources(af_home_path, &opts)?;
+
+    if let Some(sample_sheet) = &opts.sample_sheet {
+        return map_and_quant_sample_sheet(af_home_path, &opts, &quant_res, sample_sheet);
+    }
+
     // the chemistries file
     let custom_chem_p = af_home_path.join(CHEMISTRIES_PATH);
 
-    let chem = Chemistry::from_str(&index_type, &custom_chem_p, &opts.chemistry)?;
+    resolve_auto_chemistry(af_home_path, &mut opts)?;
+    let chem = Chemistry::from_str(&quant_res.index_type, &custom_chem_p, &opts.chemistry)?;
+
+    Ok(vec![map_and_quant_sample(
+        af_home_path,
+        &opts,
+        &quant_res,
+        &chem,
+    )?])
+}
+
+/// The outcome of mapping and quantifying a single sample.
+#[derive(Clone, Debug)]
+pub struct QuantResult {
+    /// The output directory of the sample
+    pub output_dir: PathBuf,
+    /// The directory holding the mapping (RAD) output
+    pub map_dir: PathBuf,
+    /// The directory holding the alevin-fry quantification output
+    pub quant_dir: PathBuf,
+    /// The mapper that was used, or `None` if an existing mapping directory was used
+    pub mapper: Option<String>,
+    /// The count matrix (in MatrixMarket format)
+    pub count_matrix: PathBuf,
+    /// The AnnData (h5ad) count matrix, if one was requested
+    pub anndata_file: Option<PathBuf>,
+    /// The `simpleaf_quant_log.json` file of the run
+    pub log_file: PathBuf,
+    /// The steps of the pipeline, with the commands run and their running times
+    /// (in a `--dry-run`, the steps that would be run, with a running time of zero)
+    pub steps: Vec<StepInfo>,
+    /// The steps that were skipped because they completed in a previous run (see `--resume`)
+    pub resumed_steps: Vec<String>,
+}
+
+/// Replaces `--chemistry auto` in `opts` with the registered chemistry that the
+/// first reads of the sample fit (see [`chem_detect::detect_chemistry`]).
+fn resolve_auto_chemistry(af_home_path: &Path, opts: &mut MapQuantOpts) -> anyhow::Result<()> {
+    if opts.chemistry != AUTO_CHEMISTRY {
+        return Ok(());
+    }
+    if opts.map_dir.is_some() {
+        bail!("`--chemistry auto` detects the chemistry from the reads, so it cannot be used with `--map-dir`; please pass the chemistry explicitly.");
+    }
+    let (reads1, reads2) = sample_reads(opts)?;
+    let detection = chem_detect::detect_chemistry(
+        af_home_path,
+        &reads1[0],
+        &reads2[0],
+        DEFAULT_NUM_SAMPLED_READS,
+    )
+    .with_context(|| format!("could not detect the chemistry of {}", reads1[0].display()))?;
+    let best = detection.choose()?;
+    info!(
+        "detected chemistry {} (confidence {:.2}, {:.1}% of the sampled barcodes in its permit list)",
+        best.name,
+        best.confidence,
+        100.0 * best.barcode_rate.unwrap_or(0.0)
+    );
+    opts.chemistry = best.name.clone();
+    Ok(())
+}
+
+/// The read files of the sample in `opts`, which is mapped against an index.
+fn sample_reads(opts: &MapQuantOpts) -> anyhow::Result<(&Vec<PathBuf>, &Vec<PathBuf>)> {
+    let reads1 = opts
+        .reads1
+        .as_ref()
+        .context("since mapping against an index is requested, read1 files must be provided.")?;
+    let reads2 = opts
+        .reads2
+        .as_ref()
+        .context("since mapping against an index is requested, read2 files must be provided.")?;
+    if reads1.len() != reads2.len() {
+        bail!(
+            "{} read1 files and {} read2 files were given; Cannot proceed!",
+            reads1.len(),
+            reads2.len()
+        );
+    }
+    Ok((reads1, reads2))
+}
+
+/// The files read when mapping `reads1` and `reads2` against `index`.
+fn map_input_files(
+    index_type: &IndexType,
+    index: &Path,
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+) -> Vec<PathBuf> {
+    let mut input_files = match index_type {
+        IndexType::Piscem(index_base) => vec![
+            index_base.with_extension("ctab"),
+            index_base.with_extension("refinfo"),
+            index_base.with_extension("sshash"),
+        ],
+        _ => vec![index.to_path_buf()],
+    };
+    input_files.extend_from_slice(reads1);
+    input_files.extend_from_slice(reads2);
+    input_files
+}
+
+/// Builds the piscem or salmon command that maps `reads1` and `reads2` against
+/// the index of `quant_res`, and returns it along with the name of the mapper
+/// and the transformation of the reads that was started for it (see
+/// [`add_or_transform_fragment_library`]). If `dry_run` is set, no
+/// transformation is started, and the command is only fit to be shown.
+#[allow(clippy::too_many_arguments)]
+fn build_map_cmd(
+    opts: &MapQuantOpts,
+    quant_res: &QuantResources,
+    chem: &Chemistry,
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+    threads: u32,
+    map_output: &Path,
+    dry_run: bool,
+) -> anyhow::Result<(std::process::Command, String, FragmentTransformationType)> {
+    let rp = &quant_res.rp;
+    // check if we can parse the geometry directly, or if we are dealing with a
+    // "complex" geometry.
+    let add_reads = |mapper_type: MapperType, cmd: &mut std::process::Command| {
+        if dry_run {
+            plan_fragment_library(
+                mapper_type,
+                chem.fragment_geometry_str(),
+                reads1,
+                reads2,
+                cmd,
+            )
+            .map(|_| FragmentTransformationType::Identity)
+        } else {
+            add_or_transform_fragment_library(
+                mapper_type,
+                chem.fragment_geometry_str(),
+                reads1,
+                reads2,
+                cmd,
+            )
+        }
+    };
+
+    match &quant_res.index_type {
+        IndexType::Piscem(index_base) => {
+            let piscem_prog_info = rp
+                .piscem
+                .as_ref()
+                .expect("piscem program info should be properly set.");
+
+            // using a piscem index
+            let mut piscem_quant_cmd = quant_res.executor.command(&piscem_prog_info.exe_path);
+            let index_path = format!("{}", index_base.display());
+            piscem_quant_cmd
+                .arg("map-sc")
+                .arg("--index")
+                .arg(index_path);
+
+            // location of output directory, number of threads
+            piscem_quant_cmd
+                .arg("--threads")
+                .arg(format!("{}", threads))
+                .arg("-o")
+                .arg(map_output);
+
+            // if the user is requesting a mapping option that required
+            // piscem version >= 0.7.0, ensure we have that
+            if let Ok(_piscem_ver) = prog_utils::check_version_constraints(
+                "piscem",
+                ">=0.7.0, <1.0.0",
+                &piscem_prog_info.version,
+            ) {
+                push_advanced_piscem_options(&mut piscem_quant_cmd, opts)?;
+            } else {
+                info!(
+                    r#"
+    Simpleaf is currently using piscem version {}, but you must be using version >= 0.7.0 in order to use the 
+    mapping options specific to this, or later versions. If you wish to use these options, please upgrade your 
+    piscem version or, if you believe you have a sufficiently new version installed, update the executable 
+    being used by simpleaf"#,
+                    &piscem_prog_info.version
+                );
+            }
+
+            // we get the final geometry we want to pass to piscem
+            let frag_lib_xform = add_reads(MapperType::Piscem, &mut piscem_quant_cmd)?;
+            Ok((piscem_quant_cmd, String::from("piscem"), frag_lib_xform))
+        }
+        IndexType::Salmon(index_base) => {
+            // using a salmon index
+            let mut salmon_quant_cmd = quant_res.executor.command(
+                &rp.salmon
+                    .as_ref()
+                    .expect("salmon program info should be properly set.")
+                    .exe_path,
+            );
+
+            // set the input index and library type
+            let index_path = format!("{}", index_base.display());
+            salmon_quant_cmd
+                .arg("alevin")
+                .arg("--index")
+                .arg(index_path)
+                .arg("-l")
+                .arg("A");
+
+            let frag_lib_xform = add_reads(MapperType::Salmon, &mut salmon_quant_cmd)?;
+
+            // location of output directory, number of threads
+            salmon_quant_cmd
+                .arg("--threads")
+                .arg(format!("{}", threads))
+                .arg("-o")
+                .arg(map_output);
+
+            // if the user explicitly requested to use selective-alignment
+            // then enable that
+            if opts.use_selective_alignment {
+                salmon_quant_cmd.arg("--rad");
+            } else {
+                // otherwise default to sketch mode
+                salmon_quant_cmd.arg("--sketch");
+            }
+            Ok((salmon_quant_cmd, String::from("salmon"), frag_lib_xform))
+        }
+        IndexType::NoIndex => {
+            bail!(
+                "Cannot perform mapping an quantification without known (piscem or salmon) index!"
+            );
+        }
+    }
+}
+
+/// Waits for the thread transforming the reads of a complex geometry (if any)
+/// to finish, and reports how many of the fragments it could transform.
+fn wait_for_fragment_transformation(
+    frag_lib_xform: FragmentTransformationType,
+) -> anyhow::Result<()> {
+    match frag_lib_xform {
+        FragmentTransformationType::TransformedIntoFifo(xform_data) => {
+            // wait for it to join
+            match xform_data.join_handle.join() {
+                Ok(join_res) => {
+                    let xform_stats = join_res?;
+                    let total = xform_stats.total_fragments;
+                    let failed = xform_stats.failed_parsing;
+                    info!(
+                        "seq_geom_xform : observed {} input fragments. {} ({:.2}%) of them failed to parse and were not transformed",
+                        total, failed, if total > 0 { (failed as f64) / (total as f64) } else { 0_f64 } * 100_f64
+                    );
+                }
+                Err(e) => {
+                    bail!("Thread panicked with {:?}", e);
+                }
+            }
+        }
+        FragmentTransformationType::Identity => {
+            // nothing to do.
+        }
+    }
+    Ok(())
+}
+
+/// Returns a new `Command` running alevin-fry through the configured executor.
+fn alevin_fry_cmd(quant_res: &QuantResources) -> std::process::Command {
+    quant_res.executor.command(
+        &quant_res
+            .rp
+            .alevin_fry
+            .as_ref()
+            .expect("alevin-fry program info should be properly set.")
+            .exe_path,
+    )
+}
+
+/// Builds the `alevin-fry generate-permit-list` command.
+fn build_gpl_cmd(
+    mut alevin_gpl_cmd: std::process::Command,
+    map_output: &Path,
+    ori: &ExpectedOri,
+    threads: u32,
+    filter_meth: &CellFilterMethod,
+    gpl_output: &Path,
+) -> std::process::Command {
+    let gpl_threads = threads.min(8);
+    alevin_gpl_cmd.arg("generate-permit-list");
+    alevin_gpl_cmd.arg("-i").arg(map_output);
+    alevin_gpl_cmd.arg("-d").arg(ori.as_str());
+    alevin_gpl_cmd.arg("-t").arg(format!("{}", gpl_threads));
+
+    // add the filter mode
+    filter_meth.add_to_args(&mut alevin_gpl_cmd);
+
+    alevin_gpl_cmd.arg("-o").arg(gpl_output);
+    alevin_gpl_cmd
+}
+
+/// Builds the `alevin-fry collate` command.
+fn build_collate_cmd(
+    mut alevin_collate_cmd: std::process::Command,
+    gpl_output: &Path,
+    map_output: &Path,
+    threads: u32,
+) -> std::process::Command {
+    alevin_collate_cmd.arg("collate");
+    alevin_collate_cmd.arg("-i").arg(gpl_output);
+    alevin_collate_cmd.arg("-r").arg(map_output);
+    alevin_collate_cmd.arg("-t").arg(format!("{}", threads));
+    alevin_collate_cmd
+}
+
+/// Builds the `alevin-fry quant` command.
+fn build_quant_cmd(
+    mut alevin_quant_cmd: std::process::Command,
+    gpl_output: &Path,
+    threads: u32,
+    t2g_map_file: &Path,
+    resolution: &str,
+) -> std::process::Command {
+    alevin_quant_cmd
+        .arg("quant")
+        .arg("-i")
+        .arg(gpl_output)
+        .arg("-o")
+        .arg(gpl_output);
+    alevin_quant_cmd.arg("-t").arg(format!("{}", threads));
+    alevin_quant_cmd.arg("-m").arg(t2g_map_file);
+    alevin_quant_cmd.arg("-r").arg(resolution);
+    alevin_quant_cmd
+}
+
+/// Prints the plan of the run of [`map_and_quant_sample`] for `opts` (see
+/// `--dry-run`): the resolved settings, and the commands that would be run
+/// along with the state of their inputs. Nothing is run or written.
+fn plan_quant_sample(
+    opts: &MapQuantOpts,
+    quant_res: &QuantResources,
+    chem: &Chemistry,
+    ori: &ExpectedOri,
+    filter_meth: &CellFilterMethod,
+    pl_info: &CBListInfo,
+    threads: u32,
+) -> anyhow::Result<QuantResult> {
+    let mut plan = DryRunPlan::new("simpleaf quant");
+    match &quant_res.index_type {
+        IndexType::Piscem(p) | IndexType::Salmon(p) => plan.resolve(
+            "index",
+            format!("{} ({})", p.display(), quant_res.index_type.as_str()),
+        ),
+        IndexType::NoIndex => plan.resolve("index", "none (existing mapping)"),
+    }
+    if chem.as_str() == chem.fragment_geometry_str() {
+        plan.resolve("chemistry", chem.as_str());
+    } else {
+        plan.resolve(
+            "chemistry",
+            format!("{} ({})", chem.as_str(), chem.fragment_geometry_str()),
+        );
+    }
+    plan.resolve("orientation", ori);
+    if !pl_info.init_file.as_os_str().is_empty() {
+        plan.resolve("permit list", pl_info.init_file.display());
+    }
+    plan.resolve("filter method", filter_meth);
+    plan.resolve("t2g map", quant_res.t2g_map_file.display());
+    plan.resolve("output", opts.output.display());
+
+    let mut mapper = None;
+    let map_output = if let Some(index) = &opts.index {
+        let (reads1, reads2) = sample_reads(opts)?;
+        let map_output = opts.output.join("af_map");
+        if opts.infer_pl && matches!(opts.unfiltered_pl, Some(None)) {
+            let reads: Vec<PathBuf> = reads1.iter().chain(reads2.iter()).cloned().collect();
+            plan.add_step(
+                "infer permit list",
+                None,
+                &reads,
+                &[opts.output.join(INFERRED_PL_DIR)],
+            );
+        }
+        let (map_cmd, sc_mapper, _) = build_map_cmd(
+            opts,
+            quant_res,
+            chem,
+            reads1,
+            reads2,
+            threads,
+            &map_output,
+            true,
+        )?;
+        plan.add_step(
+            "map",
+            Some(&map_cmd),
+            &map_input_files(&quant_res.index_type, index, reads1, reads2),
+            std::slice::from_ref(&map_output),
+        );
+        mapper = Some(sc_mapper);
+        map_output
+    } else {
+        opts.map_dir
+            .clone()
+            .context("map-dir must be provided, since index, read1 and read2 were not.")?
+    };
+
+    let gpl_output = opts.output.join("af_quant");
+    plan.add_step(
+        "gpl",
+        Some(&build_gpl_cmd(
+            alevin_fry_cmd(quant_res),
+            &map_output,
+            ori,
+            threads,
+            filter_meth,
+            &gpl_output,
+        )),
+        std::slice::from_ref(&map_output),
+        std::slice::from_ref(&gpl_output),
+    );
+    plan.add_step(
+        "collate",
+        Some(&build_collate_cmd(
+            alevin_fry_cmd(quant_res),
+            &gpl_output,
+            &map_output,
+            threads,
+        )),
+        &[gpl_output.clone(), map_output.clone()],
+        &[],
+    );
+    plan.add_step(
+        "quant",
+        Some(&build_quant_cmd(
+            alevin_fry_cmd(quant_res),
+            &gpl_output,
+            threads,
+            &quant_res.t2g_map_file,
+            &opts.resolution,
+        )),
+        &[gpl_output.clone(), quant_res.t2g_map_file.clone()],
+        &[gpl_output.join("alevin")],
+    );
+    let anndata_file = opts
+        .anndata_out
+        .then(|| gpl_output.join("alevin").join("quants.h5ad"));
+    if let Some(h5ad) = &anndata_file {
+        plan.add_step(
+            "convert",
+            None,
+            &[gpl_output.join("alevin").join("quants_mat.mtx")],
+            std::slice::from_ref(h5ad),
+        );
+    }
+    plan.finish()?;
+
+    Ok(QuantResult {
+        output_dir: opts.output.clone(),
+        map_dir: map_output,
+        quant_dir: gpl_output.clone(),
+        mapper,
+        count_matrix: gpl_output.join("alevin").join("quants_mat.mtx"),
+        anndata_file,
+        log_file: opts.output.join(QUANT_RUN_RECORD_FILE),
+        steps: plan.steps(),
+        resumed_steps: Vec::new(),
+    })
+}
+
+/// Runs the full map → generate-permit-list → collate → quant pipeline
+/// for the single sample described by `opts`, writing all output (and the
+/// `simpleaf_quant_log.json` file) under `opts.output`.
+fn map_and_quant_sample(
+    af_home_path: &Path,
+    opts: &MapQuantOpts,
+    quant_res: &QuantResources,
+    chem: &Chemistry,
+) -> anyhow::Result<QuantResult> {
+    let rp = &quant_res.rp;
 
     let ori: ExpectedOri;
     // if the user set the orientation, then
@@ -419,7 +943,7 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
             if pl_file.is_file() {
                 // we read the file to see if there is additional columns separated by \t.
                 // unwrap is safe here cuz we defined it above
-                pl_info.init(pl_file, &opts.output)?;
+                pl_info.init(pl_file, &opts.output, opts.dry_run)?;
 
                 let min_cells = opts.min_reads;
                 filter_meth_opt = Some(CellFilterMethod::UnfilteredExternalList(
@@ -432,6 +956,58 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
                     pl_file.display()
                 );
             }
+        } else if opts.infer_pl {
+            // here, the -u flag is provided without a file, and the permit list
+            // is inferred from the barcodes of the reads
+            let pl_dir = opts.output.join(INFERRED_PL_DIR);
+            let pl_file = pl_dir.join(INFERRED_PL_FILE);
+            if opts.dry_run {
+                info!(
+                    "the permit list would be inferred into {}",
+                    pl_file.display()
+                );
+            } else {
+                let (reads1, reads2) = sample_reads(opts)?;
+                let geometry =
+                    builtin_geometry(chem.as_str()).unwrap_or(chem.fragment_geometry_str());
+                // a permit list inferred by a previous run is only reused if it was
+                // inferred with the same settings from the same reads
+                let settings = json!({
+                    "geometry" : geometry,
+                    "min_reads" : opts.min_reads
+                });
+                let reads: Vec<PathBuf> = reads1.iter().chain(reads2.iter()).cloned().collect();
+                let fingerprint = stage_fingerprint(&settings, &reads, None)?;
+                let mut checkpoints =
+                    StageCheckpoints::new(&pl_dir.join(INFERRED_PL_LOG_FILE), opts.resume)?;
+                if checkpoints
+                    .try_skip("infer_pl", &fingerprint, std::slice::from_ref(&pl_file))
+                    .is_none()
+                {
+                    std::fs::create_dir_all(&pl_dir).with_context(|| {
+                        format!("could not create the directory {}", pl_dir.display())
+                    })?;
+                    checkpoints.start("infer_pl", &fingerprint)?;
+                    let infer_start = Instant::now();
+                    permit_list::infer_permit_list(
+                        geometry,
+                        reads1,
+                        reads2,
+                        opts.min_reads as u64,
+                        &pl_dir,
+                    )?;
+                    checkpoints.complete(
+                        "infer_pl",
+                        json!({ "time_secs" : infer_start.elapsed().as_secs_f64() }),
+                    )?;
+                }
+            }
+            pl_info.init_file = pl_file.clone();
+            pl_info.final_file = pl_file;
+            filter_meth_opt = Some(CellFilterMethod::UnfilteredExternalList(
+                pl_info.final_file.to_string_lossy().into_owned(),
+                opts.min_reads,
+            ));
         } else {
             // here, the -u flag is provided
             // but no file is provided, then the
@@ -439,11 +1015,11 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
             // using 10xv2, 10xv3, or 10xv4
 
             // check the chemistry
-            let pl_res = get_permit_if_absent(af_home_path, &chem)?;
+            let pl_res = get_permit_if_absent(af_home_path, chem)?;
             let min_cells = opts.min_reads;
             match pl_res {
                 PermitListResult::DownloadSuccessful(p) | PermitListResult::AlreadyPresent(p) => {
-                    pl_info.init(&p, &opts.output)?;
+                    pl_info.init(&p, &opts.output, opts.dry_run)?;
                     filter_meth_opt = Some(CellFilterMethod::UnfilteredExternalList(
                         pl_info.final_file.to_string_lossy().into_owned(),
                         min_cells,
@@ -451,7 +1027,7 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
                 }
                 PermitListResult::UnregisteredChemistry => {
                     bail!(
-                        "Cannot automatically obtain an unfiltered permit list for an unregistered chemistry : {}.",
+                        "Cannot automatically obtain an unfiltered permit list for an unregistered chemistry : {}. Pass `--infer-pl` to infer one from the barcodes of the reads.",
                         chem.as_str()
                     );
                 }
@@ -459,7 +1035,7 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
         }
     } else {
         if let Some(ref filtered_path) = opts.explicit_pl {
-            pl_info.init(filtered_path, &opts.output)?;
+            pl_info.init(filtered_path, &opts.output, opts.dry_run)?;
             filter_meth_opt = Some(CellFilterMethod::ExplicitList(
                 pl_info.final_file.to_string_lossy().into_owned(),
             ));
@@ -497,6 +1073,21 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
     // here we must be safe to unwrap
     let filter_meth = filter_meth_opt.unwrap();
 
+    if opts.dry_run {
+        return plan_quant_sample(opts, quant_res, chem, &ori, &filter_meth, &pl_info, threads);
+    }
+
+    // make sure the output directory exists, so that the progress of the
+    // individual stages can be recorded in the log as they complete.
+    std::fs::create_dir_all(&opts.output).with_context(|| {
+        format!(
+            "could not create output directory {}",
+            opts.output.display()
+        )
+    })?;
+    let af_quant_info_file = opts.output.join(QUANT_RUN_RECORD_FILE);
+    let mut checkpoints = StageCheckpoints::new(&af_quant_info_file, opts.resume)?;
+
     let sc_mapper: String;
     let map_cmd_string: String;
     let map_output: PathBuf;
@@ -504,390 +1095,802 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
 
     // if we are mapping against an index
     if let Some(index) = opts.index.clone() {
-        let reads1 = opts
-            .reads1
-            .as_ref()
-            .expect("since mapping against an index is requested, read1 files must be provided.");
-        let reads2 = opts
-            .reads2
-            .as_ref()
-            .expect("since mapping against an index is requested, read2 files must be provided.");
-        assert_eq!(
-            reads1.len(),
-            reads2.len(),
-            "{} read1 files and {} read2 files were given; Cannot proceed!",
-            reads1.len(),
-            reads2.len()
-        );
-
-        match index_type {
-            IndexType::Piscem(index_base) => {
-                let piscem_prog_info = rp
-                    .piscem
-                    .as_ref()
-                    .expect("piscem program info should be properly set.");
-
-                // using a piscem index
-                let mut piscem_quant_cmd =
-                    std::process::Command::new(format!("{}", &piscem_prog_info.exe_path.display()));
-                let index_path = format!("{}", index_base.display());
-                piscem_quant_cmd
-                    .arg("map-sc")
-                    .arg("--index")
-                    .arg(index_path);
-
-                // location of output directory, number of threads
-                map_output = opts.output.join("af_map");
-                piscem_quant_cmd
-                    .arg("--threads")
-                    .arg(format!("{}", threads))
-                    .arg("-o")
-                    .arg(&map_output);
-
-                // if the user is requesting a mapping option that required
-                // piscem version >= 0.7.0, ensure we have that
-                if let Ok(_piscem_ver) = prog_utils::check_version_constraints(
-                    "piscem",
-                    ">=0.7.0, <1.0.0",
-                    &piscem_prog_info.version,
-                ) {
-                    push_advanced_piscem_options(&mut piscem_quant_cmd, &opts)?;
-                } else {
-                    info!(
-                        r#"
-Simpleaf is currently using piscem version {}, but you must be using version >= 0.7.0 in order to use the 
-mapping options specific to this, or later versions. If you wish to use these options, please upgrade your 
-piscem version or, if you believe you have a sufficiently new version installed, update the executable 
-being used by simpleaf"#,
-                        &piscem_prog_info.version
-                    );
-                }
-
-                // we get the final geometry we want to pass to piscem
-                // check if we can parse the geometry directly, or if we are dealing with a
-                // "complex" geometry.
-                let frag_lib_xform = add_or_transform_fragment_library(
-                    MapperType::Piscem,
-                    chem.fragment_geometry_str(),
-                    reads1,
-                    reads2,
-                    &mut piscem_quant_cmd,
-                )?;
-
-                map_cmd_string = prog_utils::get_cmd_line_string(&piscem_quant_cmd);
-                info!("piscem map-sc cmd : {}", map_cmd_string);
-                sc_mapper = String::from("piscem");
-
-                let mut input_files = vec![
-                    index_base.with_extension("ctab"),
-                    index_base.with_extension("refinfo"),
-                    index_base.with_extension("sshash"),
-                ];
-                input_files.extend_from_slice(reads1);
-                input_files.extend_from_slice(reads2);
-
-                prog_utils::check_files_exist(&input_files)?;
-
-                let map_start = Instant::now();
-                let cres = prog_utils::execute_command(
-                    &mut piscem_quant_cmd,
-                    CommandVerbosityLevel::Quiet,
-                )
-                .expect("failed to execute piscem [mapping phase]");
-
-                // if we had to filter the reads through a fifo
-                // wait for the thread feeding the fifo to finish
-                match frag_lib_xform {
-                    FragmentTransformationType::TransformedIntoFifo(xform_data) => {
-                        // wait for it to join
-                        match xform_data.join_handle.join() {
-                            Ok(join_res) => {
-                                let xform_stats = join_res?;
-                                let total = xform_stats.total_fragments;
-                                let failed = xform_stats.failed_parsing;
-                                info!(
-                                    "seq_geom_xform : observed {} input fragments. {} ({:.2}%) of them failed to parse and were not transformed",
-                                    total, failed, if total > 0 { (failed as f64) / (total as f64) } else { 0_f64 } * 100_f64
-                                );
-                            }
-                            Err(e) => {
-                                bail!("Thread panicked with {:?}", e);
-                            }
-                        }
-                    }
-                    FragmentTransformationType::Identity => {
-                        // nothing to do.
-                    }
-                }
-
-                map_duration = map_start.elapsed();
-
-                if !cres.status.success() {
-                    bail!("piscem mapping failed with exit status {:?}", cres.status);
+        let (reads1, reads2) = sample_reads(opts)?;
+        map_output = opts.output.join("af_map");
+
+        // the fingerprint of the mapping stage covers everything that determines
+        // the mapping result; the mapping command line itself is not stable, as
+        // it may refer to temporary FIFOs for complex geometries.
+        let map_inputs = map_input_files(&quant_res.index_type, &index, reads1, reads2);
+        prog_utils::check_files_exist(&map_inputs)?;
+
+        let map_settings = json!({
+            "index" : index,
+            "geometry" : chem.fragment_geometry_str(),
+            "use_piscem" : opts.use_piscem,
+            "use_selective_alignment" : opts.use_selective_alignment,
+            "struct_constraints" : opts.struct_constraints,
+            "ignore_ambig_hits" : opts.ignore_ambig_hits,
+            "no_poison" : opts.no_poison,
+            "skipping_strategy" : opts.skipping_strategy,
+            "max_ec_card" : opts.max_ec_card,
+            "max_hit_occ" : opts.max_hit_occ,
+            "max_hit_occ_recover" : opts.max_hit_occ_recover,
+            "max_read_occ" : opts.max_read_occ
+        });
+        let map_fingerprint = stage_fingerprint(&map_settings, &map_inputs, None)?;
+
+        if let Some(prev) =
+            checkpoints.try_skip("map", &map_fingerprint, &[map_output.join("map.rad")])
+        {
+            map_cmd_string = prev["cmd"].as_str().unwrap_or_default().to_string();
+            sc_mapper = prev["mapper"].as_str().unwrap_or_default().to_string();
+            map_duration = stage_time(&prev);
+        } else {
+            if opts.skip_read_checks {
+                info!("skipping the checks of the read files (--skip-read-checks)");
+            } else {
+                let geometry =
+                    builtin_geometry(chem.as_str()).unwrap_or(chem.fragment_geometry_str());
+                read_checks::check_read_files(reads1, reads2, geometry, NUM_CHECKED_READS)
+                .context("the read files failed the checks run before mapping (these checks can be skipped with --skip-read-checks)")?;
+                if opts.check_integrity {
+                    read_checks::check_read_integrity(reads1, reads2, threads as usize)
+                        .context("the read files failed the integrity check (--check-integrity)")?;
                 }
             }
-            IndexType::Salmon(index_base) => {
-                // using a salmon index
-                let mut salmon_quant_cmd = std::process::Command::new(format!(
-                    "{}",
-                    rp.salmon.unwrap().exe_path.display()
-                ));
-
-                // set the input index and library type
-                let index_path = format!("{}", index_base.display());
-                salmon_quant_cmd
-                    .arg("alevin")
-                    .arg("--index")
-                    .arg(index_path)
-                    .arg("-l")
-                    .arg("A");
-
-                // check if we can parse the geometry directly, or if we are dealing with a
-                // "complex" geometry.
-                let frag_lib_xform = add_or_transform_fragment_library(
-                    MapperType::Salmon,
-                    chem.fragment_geometry_str(),
-                    reads1,
-                    reads2,
-                    &mut salmon_quant_cmd,
-                )?;
-
-                // location of output directory, number of threads
-                map_output = opts.output.join("af_map");
-                salmon_quant_cmd
-                    .arg("--threads")
-                    .arg(format!("{}", threads))
-                    .arg("-o")
-                    .arg(&map_output);
-
-                // if the user explicitly requested to use selective-alignment
-                // then enable that
-                if opts.use_selective_alignment {
-                    salmon_quant_cmd.arg("--rad");
-                } else {
-                    // otherwise default to sketch mode
-                    salmon_quant_cmd.arg("--sketch");
-                }
-
-                map_cmd_string = prog_utils::get_cmd_line_string(&salmon_quant_cmd);
-                info!("salmon alevin cmd : {}", map_cmd_string);
-                sc_mapper = String::from("salmon");
-
-                let mut input_files = vec![index];
-                input_files.extend_from_slice(reads1);
-                input_files.extend_from_slice(reads2);
-
-                prog_utils::check_files_exist(&input_files)?;
+            checkpoints.start("map", &map_fingerprint)?;
+
+            let (mut map_cmd, mapper, frag_lib_xform) = build_map_cmd(
+                opts,
+                quant_res,
+                chem,
+                reads1,
+                reads2,
+                threads,
+                &map_output,
+                false,
+            )?;
+            map_cmd_string = prog_utils::get_cmd_line_string(&map_cmd);
+            info!("{} mapping cmd : {}", mapper, map_cmd_string);
+            sc_mapper = mapper;
+
+            let map_start = Instant::now();
+            let cres = prog_utils::execute_logged_command(
+                &mut map_cmd,
+                CommandVerbosityLevel::global(),
+                &opts.output.join(LOGS_DIR),
+                &format!("{}_map", sc_mapper),
+            )
+            .with_context(|| format!("failed to execute {} [mapping phase]", sc_mapper))?;
 
-                let map_start = Instant::now();
-                let cres = prog_utils::execute_command(
-                    &mut salmon_quant_cmd,
-                    CommandVerbosityLevel::Quiet,
-                )
-                .expect("failed to execute salmon [mapping phase]");
-
-                // if we had to filter the reads through a fifo
-                // wait for the thread feeding the fifo to finish
-                match frag_lib_xform {
-                    FragmentTransformationType::TransformedIntoFifo(xform_data) => {
-                        // wait for it to join
-                        match xform_data.join_handle.join() {
-                            Ok(join_res) => {
-                                let xform_stats = join_res?;
-                                let total = xform_stats.total_fragments;
-                                let failed = xform_stats.failed_parsing;
-                                info!(
-                                    "seq_geom_xform : observed {} input fragments. {} ({:.2}%) of them failed to parse and were not transformed",
-                                    total, failed, if total > 0 { (failed as f64) / (total as f64) } else { 0_f64 } * 100_f64
-                                );
-                            }
-                            Err(e) => {
-                                bail!("Thread panicked with {:?}", e);
-                            }
-                        }
-                    }
-                    FragmentTransformationType::Identity => {
-                        // nothing to do.
-                    }
-                }
+            // if we had to filter the reads through a fifo
+            // wait for the thread feeding the fifo to finish
+            wait_for_fragment_transformation(frag_lib_xform)?;
 
-                map_duration = map_start.elapsed();
+            map_duration = map_start.elapsed();
 
-                if !cres.status.success() {
-                    bail!("salmon mapping failed with exit status {:?}", cres.status);
-                }
-            }
-            IndexType::NoIndex => {
-                bail!("Cannot perform mapping an quantification without known (piscem or salmon) index!");
+            if !cres.status.success() {
+                bail!(
+                    "{} mapping failed with exit status {:?}",
+                    sc_mapper,
+                    cres.status
+                );
             }
+            checkpoints.complete(
+                "map",
+                json!({
+                    "cmd" : map_cmd_string,
+                    "mapper" : sc_mapper,
+                    "time_secs" : map_duration.as_secs_f64()
+                }),
+            )?;
         }
     } else {
         map_cmd_string = String::from("");
         sc_mapper = String::from("");
         map_output = opts
             .map_dir
-            .expect("map-dir must be provided, since index, read1 and read2 were not.");
+            .clone()
+            .context("map-dir must be provided, since index, read1 and read2 were not.")?;
         map_duration = Duration::new(0, 0);
     }
 
-    let map_output_string = map_output.display().to_string();
-
-    let alevin_fry = rp.alevin_fry.unwrap().exe_path;
     // alevin-fry generate permit list
-    let mut alevin_gpl_cmd = std::process::Command::new(format!("{}", &alevin_fry.display()));
-
-    let gpl_threads = threads.min(8);
-    alevin_gpl_cmd.arg("generate-permit-list");
-    alevin_gpl_cmd.arg("-i").arg(&map_output);
-    alevin_gpl_cmd.arg("-d").arg(ori.as_str());
-    alevin_gpl_cmd.arg("-t").arg(format!("{}", gpl_threads));
-
-    // add the filter mode
-    filter_meth.add_to_args(&mut alevin_gpl_cmd);
-
     let gpl_output = opts.output.join("af_quant");
-    alevin_gpl_cmd.arg("-o").arg(&gpl_output);
-
-    info!(
-        "alevin-fry generate-permit-list cmd : {}",
-        prog_utils::get_cmd_line_string(&alevin_gpl_cmd)
+    let mut alevin_gpl_cmd = build_gpl_cmd(
+        alevin_fry_cmd(quant_res),
+        &map_output,
+        &ori,
+        threads,
+        &filter_meth,
+        &gpl_output,
     );
+
+    let gpl_cmd_string = prog_utils::get_cmd_line_string(&alevin_gpl_cmd);
+    info!("alevin-fry generate-permit-list cmd : {}", gpl_cmd_string);
     let input_files = vec![map_output.clone()];
     prog_utils::check_files_exist(&input_files)?;
 
-    let gpl_start = Instant::now();
-    let gpl_proc_out =
-        prog_utils::execute_command(&mut alevin_gpl_cmd, CommandVerbosityLevel::Quiet)
-            .expect("could not execute [generate permit list]");
-    let gpl_duration = gpl_start.elapsed();
+    let mut gpl_inputs = vec![map_output.join("map.rad")];
+    if pl_info.init_file.is_file() {
+        gpl_inputs.push(pl_info.init_file.clone());
+    }
+    let map_fingerprint = checkpoints.fingerprint("map").map(str::to_string);
+    let gpl_fingerprint = stage_fingerprint(
+        &json!({ "cmd" : gpl_cmd_string }),
+        &gpl_inputs,
+        map_fingerprint.as_deref(),
+    )?;
 
-    if !gpl_proc_out.status.success() {
-        bail!(
-            "alevin-fry generate-permit-list failed with exit status {:?}",
-            gpl_proc_out.status
-        );
+    let gpl_duration: Duration;
+    if let Some(prev) = checkpoints.try_skip(
+        "gpl",
+        &gpl_fingerprint,
+        &[gpl_output.join("generate_permit_list.json")],
+    ) {
+        gpl_duration = stage_time(&prev);
+    } else {
+        checkpoints.start("gpl", &gpl_fingerprint)?;
+
+        let gpl_start = Instant::now();
+        let gpl_proc_out = prog_utils::execute_logged_command(
+            &mut alevin_gpl_cmd,
+            CommandVerbosityLevel::global(),
+            &opts.output.join(LOGS_DIR),
+            "af_generate_permit_list",
+        )
+        .context("failed to execute alevin-fry [generate permit list]")?;
+        gpl_duration = gpl_start.elapsed();
+
+        if !gpl_proc_out.status.success() {
+            bail!(
+                "alevin-fry generate-permit-list failed with exit status {:?}",
+                gpl_proc_out.status
+            );
+        }
+
+        checkpoints.complete(
+            "gpl",
+            json!({ "cmd" : gpl_cmd_string, "time_secs" : gpl_duration.as_secs_f64() }),
+        )?;
     }
 
     //
     // collate
     //
-    let mut alevin_collate_cmd = std::process::Command::new(format!("{}", &alevin_fry.display()));
+    let mut alevin_collate_cmd =
+        build_collate_cmd(alevin_fry_cmd(quant_res), &gpl_output, &map_output, threads);
 
-    alevin_collate_cmd.arg("collate");
-    alevin_collate_cmd.arg("-i").arg(&gpl_output);
-    alevin_collate_cmd.arg("-r").arg(&map_output);
-    alevin_collate_cmd.arg("-t").arg(format!("{}", threads));
-
-    info!(
-        "alevin-fry collate cmd : {}",
-        prog_utils::get_cmd_line_string(&alevin_collate_cmd)
-    );
-    let input_files = vec![gpl_output.clone(), map_output];
+    let collate_cmd_string = prog_utils::get_cmd_line_string(&alevin_collate_cmd);
+    info!("alevin-fry collate cmd : {}", collate_cmd_string);
+    let input_files = vec![gpl_output.clone(), map_output.clone()];
     prog_utils::check_files_exist(&input_files)?;
 
-    let collate_start = Instant::now();
-    let collate_proc_out =
-        prog_utils::execute_command(&mut alevin_collate_cmd, CommandVerbosityLevel::Quiet)
-            .expect("could not execute [collate]");
-    let collate_duration = collate_start.elapsed();
+    let collate_fingerprint = stage_fingerprint(
+        &json!({ "cmd" : collate_cmd_string }),
+        &[],
+        Some(&gpl_fingerprint),
+    )?;
 
-    if !collate_proc_out.status.success() {
-        bail!(
-            "alevin-fry collate failed with exit status {:?}",
-            collate_proc_out.status
-        );
+    let collate_duration: Duration;
+    if let Some(prev) = checkpoints.try_skip(
+        "collate",
+        &collate_fingerprint,
+        &[gpl_output.join("collate.json")],
+    ) {
+        collate_duration = stage_time(&prev);
+    } else {
+        checkpoints.start("collate", &collate_fingerprint)?;
+
+        let collate_start = Instant::now();
+        let collate_proc_out = prog_utils::execute_logged_command(
+            &mut alevin_collate_cmd,
+            CommandVerbosityLevel::global(),
+            &opts.output.join(LOGS_DIR),
+            "af_collate",
+        )
+        .context("failed to execute alevin-fry [collate]")?;
+        collate_duration = collate_start.elapsed();
+
+        if !collate_proc_out.status.success() {
+            bail!(
+                "alevin-fry collate failed with exit status {:?}",
+                collate_proc_out.status
+            );
+        }
+
+        checkpoints.complete(
+            "collate",
+            json!({ "cmd" : collate_cmd_string, "time_secs" : collate_duration.as_secs_f64() }),
+        )?;
     }
 
     //
     // quant
     //
-    let mut alevin_quant_cmd = std::process::Command::new(format!("{}", &alevin_fry.display()));
-
-    alevin_quant_cmd
-        .arg("quant")
-        .arg("-i")
-        .arg(&gpl_output)
-        .arg("-o")
-        .arg(&gpl_output);
-    alevin_quant_cmd.arg("-t").arg(format!("{}", threads));
-    alevin_quant_cmd.arg("-m").arg(t2g_map_file.clone());
-    alevin_quant_cmd.arg("-r").arg(opts.resolution);
+    let mut alevin_quant_cmd = build_quant_cmd(
+        alevin_fry_cmd(quant_res),
+        &gpl_output,
+        threads,
+        &quant_res.t2g_map_file,
+        &opts.resolution,
+    );
 
     info!("cmd : {:?}", alevin_quant_cmd);
 
-    let input_files = vec![gpl_output.clone(), t2g_map_file];
+    let input_files = vec![gpl_output.clone(), quant_res.t2g_map_file.clone()];
     prog_utils::check_files_exist(&input_files)?;
 
-    let quant_start = Instant::now();
-    let quant_proc_out =
-        prog_utils::execute_command(&mut alevin_quant_cmd, CommandVerbosityLevel::Quiet)
-            .expect("could not execute [quant]");
-    let quant_duration = quant_start.elapsed();
-
-    if !quant_proc_out.status.success() {
-        bail!("quant failed with exit status {:?}", quant_proc_out.status);
+    // the quant stage also covers the post-processing of the count matrix
+    // below, which is why the anndata conversion is part of its fingerprint.
+    let quant_cmd_string = prog_utils::get_cmd_line_string(&alevin_quant_cmd);
+    let quant_fingerprint = stage_fingerprint(
+        &json!({ "cmd" : quant_cmd_string, "anndata_out" : opts.anndata_out }),
+        std::slice::from_ref(&quant_res.t2g_map_file),
+        Some(&collate_fingerprint),
+    )?;
+    let mut quant_outputs = vec![
+        gpl_output.join("quant.json"),
+        gpl_output.join("alevin").join("quants_mat.mtx"),
+    ];
+    if opts.anndata_out {
+        quant_outputs.push(gpl_output.join("alevin").join("quants.h5ad"));
     }
 
-    // If we had a gene_id_to_name.tsv file handy, copy it over into the
-    // quantification directory.
-    if let Some(gene_name_path) = gene_id_to_name_opt {
-        let target_path = gpl_output.join("gene_id_to_name.tsv");
-        match std::fs::copy(&gene_name_path, &target_path) {
-            Ok(_) => {
-                info!("successfully copied the gene_name_to_id.tsv file into the quantification directory.");
-            }
-            Err(err) => {
-                warn!("could not successfully copy gene_id_to_name file from {:?} to {:?} because of {:?}",
-                gene_name_path, target_path, err
-            );
+    let quant_duration: Duration;
+    let mut convert_duration: Option<Duration> = None;
+    if let Some(prev) = checkpoints.try_skip("quant", &quant_fingerprint, &quant_outputs) {
+        quant_duration = stage_time(&prev);
+        if opts.anndata_out {
+            convert_duration = serde_json::from_value(prev["conversion_time"].clone()).ok();
+        }
+        // the barcode affiliate info was already added to the count matrix rows
+        // in the previous run, so we only need to remove the intermediate list.
+        if !pl_info.is_single_column {
+            std::fs::remove_file(&pl_info.final_file)?;
+        }
+    } else {
+        checkpoints.start("quant", &quant_fingerprint)?;
+
+        let quant_start = Instant::now();
+        let quant_proc_out = prog_utils::execute_logged_command(
+            &mut alevin_quant_cmd,
+            CommandVerbosityLevel::global(),
+            &opts.output.join(LOGS_DIR),
+            "af_quant",
+        )
+        .context("failed to execute alevin-fry [quant]")?;
+        quant_duration = quant_start.elapsed();
+
+        if !quant_proc_out.status.success() {
+            bail!("quant failed with exit status {:?}", quant_proc_out.status);
+        }
+
+        // If we had a gene_id_to_name.tsv file handy, copy it over into the
+        // quantification directory.
+        if let Some(gene_name_path) = &quant_res.gene_id_to_name_opt {
+            let target_path = gpl_output.join("gene_id_to_name.tsv");
+            match std::fs::copy(gene_name_path, &target_path) {
+                Ok(_) => {
+                    info!("successfully copied the gene_name_to_id.tsv file into the quantification directory.");
+                }
+                Err(err) => {
+                    warn!("could not successfully copy gene_id_to_name file from {:?} to {:?} because of {:?}",
+                    gene_name_path, target_path, err
+                );
+                }
             }
         }
-    }
 
-    // If a permit/explit list with auxilary info was provided,
-    // we add the auxilary info to the barcodes.tsv file.
-    let quants_mat_rows_p = gpl_output.join("alevin").join("quants_mat_rows.txt");
-    pl_info.update_af_quant_barcodes_tsv(&quants_mat_rows_p)?;
+        // If a permit/explit list with auxilary info was provided,
+        // we add the auxilary info to the barcodes.tsv file.
+        let quants_mat_rows_p = gpl_output.join("alevin").join("quants_mat_rows.txt");
+        pl_info.update_af_quant_barcodes_tsv(&quants_mat_rows_p)?;
 
-    let mut convert_duration = None;
-    if opts.anndata_out {
-        let convert_start = Instant::now();
-        let opath = gpl_output.join("alevin").join("quants.h5ad");
-        af_anndata::convert_csr_to_anndata(&gpl_output, &opath)?;
-        convert_duration = Some(convert_start.elapsed());
-    }
-
-    let af_quant_info_file = opts.output.join("simpleaf_quant_log.json");
-    let mut af_quant_info = json!({
-        "time_info" : {
-        "map_time" : map_duration,
-        "gpl_time" : gpl_duration,
-        "collate_time" : collate_duration,
-        "quant_time" : quant_duration
-    },
-        "cmd_info" : {
-        "map_cmd" : map_cmd_string,
-        "gpl_cmd" : prog_utils::get_cmd_line_string(&alevin_gpl_cmd),
-        "collate_cmd" : prog_utils::get_cmd_line_string(&alevin_collate_cmd),
-        "quant_cmd" : prog_utils::get_cmd_line_string(&alevin_quant_cmd)
-    },
-        "map_info" : {
-        "mapper" : sc_mapper,
-        "map_cmd" : map_cmd_string,
-        "map_outdir": map_output_string
+        if opts.anndata_out {
+            let convert_start = Instant::now();
+            let opath = gpl_output.join("alevin").join("quants.h5ad");
+            af_anndata::convert_csr_to_anndata(&gpl_output, &opath)?;
+            convert_duration = Some(convert_start.elapsed());
+        }
+
+        checkpoints.complete(
+            "quant",
+            json!({
+                "cmd" : quant_cmd_string,
+                "time_secs" : quant_duration.as_secs_f64(),
+                "conversion_time" : convert_duration
+            }),
+        )?;
     }
-    });
 
+    let mut steps = Vec::new();
+    if !sc_mapper.is_empty() {
+        steps.push(StepInfo::new("map", Some(map_cmd_string), map_duration));
+    }
+    steps.push(StepInfo::new("gpl", Some(gpl_cmd_string), gpl_duration));
+    steps.push(StepInfo::new(
+        "collate",
+        Some(collate_cmd_string),
+        collate_duration,
+    ));
+    steps.push(StepInfo::new(
+        "quant",
+        Some(quant_cmd_string),
+        quant_duration,
+    ));
     if let Some(ctime) = convert_duration {
-        af_quant_info["time_info"]["conversion_time"] = json!(ctime);
+        steps.push(StepInfo::new("convert", None, ctime));
     }
 
     // write the relevant info about
     // our run to file.
-    std::fs::write(
-        &af_quant_info_file,
-        serde_json::to_string_pretty(&af_quant_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", af_quant_info_file.display()))?;
-    Ok(())
+    let mut run_record = RunRecord::new(RunDetails::Quant {
+        mapper: (!sc_mapper.is_empty()).then(|| sc_mapper.clone()),
+        map_dir: map_output.clone(),
+        quant_dir: gpl_output.clone(),
+        resumed_steps: checkpoints.skipped_stages().to_vec(),
+        stages: checkpoints.to_json(),
+    });
+    run_record.options = opts.effective_options.clone();
+    run_record.steps = steps.clone();
+    run_record.write(&af_quant_info_file)?;
+
+    let mut provenance = Provenance::new("quant", rp);
+    provenance.set_chemistry(chem, af_home_path)?;
+    if let Some(reads1) = &opts.reads1 {
+        provenance.add_inputs("reads1", reads1)?;
+    }
+    if let Some(reads2) = &opts.reads2 {
+        provenance.add_inputs("reads2", reads2)?;
+    }
+    if opts.index.is_none() {
+        provenance.add_input("rad", &map_output.join("map.rad"))?;
+    }
+    if !pl_info.init_file.as_os_str().is_empty() {
+        provenance.add_input("permit_list", &pl_info.init_file)?;
+    }
+    provenance.add_input("t2g", &quant_res.t2g_map_file)?;
+    for (role, fname) in [
+        ("count_matrix", "quants_mat.mtx"),
+        ("count_matrix_rows", "quants_mat_rows.txt"),
+        ("count_matrix_cols", "quants_mat_cols.txt"),
+        ("anndata", "quants.h5ad"),
+    ] {
+        let p = gpl_output.join("alevin").join(fname);
+        if p.exists() {
+            provenance.add_output(role, &p)?;
+        }
+    }
+    provenance.write(&opts.output)?;
+
+    if opts.call_cells {
+        write_cell_calls(
+            &opts.output,
+            &EmptyDropsParams {
+                threads: threads as usize,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    if opts.report {
+        write_qc_report(&opts.output)?;
+    }
+
+    Ok(QuantResult {
+        output_dir: opts.output.clone(),
+        map_dir: map_output,
+        quant_dir: gpl_output.clone(),
+        mapper: (!sc_mapper.is_empty()).then_some(sc_mapper),
+        count_matrix: gpl_output.join("alevin").join("quants_mat.mtx"),
+        anndata_file: opts
+            .anndata_out
+            .then(|| gpl_output.join("alevin").join("quants.h5ad")),
+        log_file: af_quant_info_file,
+        steps,
+        resumed_steps: checkpoints.skipped_stages().to_vec(),
+    })
+}
+
+/// A single row of a `--sample-sheet` file, as it is read from disk.
+#[derive(Debug, Deserialize)]
+struct SampleSheetRecord {
+    sample: String,
+    reads1: String,
+    reads2: String,
+    #[serde(default)]
+    chemistry: Option<String>,
+    #[serde(default)]
+    permit_list: Option<PathBuf>,
+}
+
+/// A validated sample from a `--sample-sheet` file.
+#[derive(Debug, PartialEq)]
+struct SampleSheetEntry {
+    sample: String,
+    reads1: Vec<PathBuf>,
+    reads2: Vec<PathBuf>,
+    chemistry: Option<String>,
+    permit_list: Option<PathBuf>,
+}
+
+/// Parse the sample sheet at `sample_sheet`. The sheet can be either comma or tab
+/// separated (this is determined from the header line), and must contain the
+/// `sample`, `reads1` and `reads2` columns; `chemistry` and `permit_list` are optional.
+/// Multiple read files for a sample are given as a comma-separated list, just as on
+/// the command line (in a CSV file, such a field must be quoted). Relative paths
+/// are interpreted relative to the directory containing the sample sheet.
+fn parse_sample_sheet(sample_sheet: &Path) -> anyhow::Result<Vec<SampleSheetEntry>> {
+    let sheet_content = std::fs::read_to_string(sample_sheet)
+        .with_context(|| format!("Could not read sample sheet {}", sample_sheet.display()))?;
+
+    let delimiter = match sheet_content.lines().next() {
+        Some(header) if header.contains('\t') => b'\t',
+        Some(_) => b',',
+        None => bail!("The sample sheet {} is empty.", sample_sheet.display()),
+    };
+
+    let sheet_dir = sample_sheet.parent().unwrap_or_else(|| Path::new(""));
+    let resolve = |p: &str| -> PathBuf {
+        let p = PathBuf::from(p.trim());
+        if p.is_relative() {
+            sheet_dir.join(p)
+        } else {
+            p
+        }
+    };
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(sheet_content.as_bytes());
+
+    let mut samples: Vec<SampleSheetEntry> = Vec::new();
+    for (rec_num, rec) in rdr.deserialize().enumerate() {
+        let rec: SampleSheetRecord = rec.with_context(|| {
+            format!(
+                "Could not parse record {} of sample sheet {}",
+                rec_num + 1,
+                sample_sheet.display()
+            )
+        })?;
+
+        if rec.sample.is_empty()
+            || rec.sample == "."
+            || rec.sample == ".."
+            || rec.sample.contains(['/', '\\'])
+        {
+            bail!(
+                "Invalid sample name \"{}\" in record {} of the sample sheet; sample names are used as output directory names.",
+                rec.sample,
+                rec_num + 1
+            );
+        }
+        if samples.iter().any(|s| s.sample == rec.sample) {
+            bail!(
+                "The sample name \"{}\" appears more than once in the sample sheet.",
+                rec.sample
+            );
+        }
+
+        let reads1: Vec<PathBuf> = rec.reads1.split(',').map(resolve).collect();
+        let reads2: Vec<PathBuf> = rec.reads2.split(',').map(resolve).collect();
+        if reads1.len() != reads2.len() {
+            bail!(
+                "Sample \"{}\" lists {} read1 files and {} read2 files; Cannot proceed!",
+                rec.sample,
+                reads1.len(),
+                reads2.len()
+            );
+        }
+
+        samples.push(SampleSheetEntry {
+            sample: rec.sample,
+            reads1,
+            reads2,
+            chemistry: rec.chemistry.filter(|c| !c.is_empty()),
+            permit_list: rec.permit_list.map(|p| resolve(&p.to_string_lossy())),
+        });
+    }
+
+    if samples.is_empty() {
+        bail!(
+            "The sample sheet {} does not contain any samples.",
+            sample_sheet.display()
+        );
+    }
+    Ok(samples)
+}
+
+/// Derive the options used to process a single sample of the sample sheet
+/// from the options passed to `quant`.
+fn sample_opts_from_entry(
+    opts: &MapQuantOpts,
+    entry: &SampleSheetEntry,
+) -> anyhow::Result<MapQuantOpts> {
+    let mut sample_opts = opts.clone();
+    sample_opts.sample_sheet = None;
+    sample_opts.output = opts.output.join(&entry.sample);
+    sample_opts.reads1 = Some(entry.reads1.clone());
+    sample_opts.reads2 = Some(entry.reads2.clone());
+    if let Some(chem) = &entry.chemistry {
+        sample_opts.chemistry = chem.clone();
+    }
+
+    // a per-sample permit list replaces the one passed to
+    // `--unfiltered-pl` or `--explicit-pl`.
+    if let Some(pl) = &entry.permit_list {
+        if opts.unfiltered_pl.is_some() {
+            sample_opts.unfiltered_pl = Some(Some(pl.clone()));
+        } else if opts.explicit_pl.is_some() {
+            sample_opts.explicit_pl = Some(pl.clone());
+        } else {
+            bail!(
+                "Sample \"{}\" provides a permit list, but this can only be used with `--unfiltered-pl` or `--explicit-pl`.",
+                entry.sample
+            );
+        }
+    }
+    Ok(sample_opts)
+}
+
+/// Map and quantify every sample listed in `sample_sheet`. The index, t2g
+/// map and programs are resolved once (in `quant_res`), and each distinct
+/// chemistry is resolved only once (see [`run_samples`] for how failing
+/// samples are handled).
+fn map_and_quant_sample_sheet(
+    af_home_path: &Path,
+    opts: &MapQuantOpts,
+    quant_res: &QuantResources,
+    sample_sheet: &Path,
+) -> anyhow::Result<Vec<QuantResult>> {
+    let samples = parse_sample_sheet(sample_sheet)?;
+    info!(
+        "found {} samples in sample sheet {}",
+        samples.len(),
+        sample_sheet.display()
+    );
+
+    if !opts.dry_run {
+        std::fs::create_dir_all(&opts.output).with_context(|| {
+            format!(
+                "could not create output directory {}",
+                opts.output.display()
+            )
+        })?;
+    }
+
+    let custom_chem_p = af_home_path.join(CHEMISTRIES_PATH);
+    let mut chem_cache: HashMap<String, Chemistry> = HashMap::new();
+    run_samples(opts, sample_sheet, &samples, |entry, sample_chem| {
+        let mut sample_opts = sample_opts_from_entry(opts, entry)?;
+        resolve_auto_chemistry(af_home_path, &mut sample_opts)?;
+        // the detected chemistry is recorded in the summary, even if the
+        // sample then fails
+        sample_chem.clone_from(&sample_opts.chemistry);
+        if !chem_cache.contains_key(&sample_opts.chemistry) {
+            let chem = Chemistry::from_str(
+                &quant_res.index_type,
+                &custom_chem_p,
+                &sample_opts.chemistry,
+            )?;
+            chem_cache.insert(sample_opts.chemistry.clone(), chem);
+        }
+        let chem = &chem_cache[&sample_opts.chemistry];
+        map_and_quant_sample(af_home_path, &sample_opts, quant_res, chem)
+    })
+}
+
+/// Runs `process` on each of the `samples` of `sample_sheet`, along with the
+/// chemistry to record for the sample (which `process` may update once it is
+/// resolved). A failing sample does not stop the remaining ones from being
+/// processed; the outcome of every sample is recorded in
+/// [`SAMPLE_SHEET_SUMMARY_FILE`] in the output directory (unless this is a
+/// dry run).
+fn run_samples<T>(
+    opts: &MapQuantOpts,
+    sample_sheet: &Path,
+    samples: &[SampleSheetEntry],
+    mut process: impl FnMut(&SampleSheetEntry, &mut String) -> anyhow::Result<T>,
+) -> anyhow::Result<Vec<T>> {
+    let mut results = Vec::with_capacity(samples.len());
+    let mut sample_summaries = Vec::with_capacity(samples.len());
+    let mut num_failed = 0_usize;
+    for (sample_num, entry) in samples.iter().enumerate() {
+        info!(
+            "processing sample {} ({} of {})",
+            entry.sample,
+            sample_num + 1,
+            samples.len()
+        );
+
+        let sample_start = Instant::now();
+        let mut sample_chem = entry.chemistry.as_ref().unwrap_or(&opts.chemistry).clone();
+        let sample_res = process(entry, &mut sample_chem);
+        let sample_duration = sample_start.elapsed();
+
+        let mut sample_summary = json!({
+            "sample" : entry.sample,
+            "chemistry" : sample_chem,
+            "output" : opts.output.join(&entry.sample),
+            "elapsed_secs" : sample_duration.as_secs_f64(),
+        });
+        match sample_res {
+            Ok(res) => {
+                sample_summary["status"] = json!("success");
+                results.push(res);
+            }
+            Err(e) => {
+                error!("processing sample {} failed : {:#}", entry.sample, e);
+                sample_summary["status"] = json!("failed");
+                sample_summary["error"] = json!(format!("{:#}", e));
+                num_failed += 1;
+            }
+        }
+        sample_summaries.push(sample_summary);
+    }
+
+    if opts.dry_run {
+        if num_failed > 0 {
+            bail!(
+                "the plans of {} of {} samples have problems; see above for details.",
+                num_failed,
+                samples.len()
+            );
+        }
+        return Ok(results);
+    }
+
+    let summary_file = opts.output.join(SAMPLE_SHEET_SUMMARY_FILE);
+    let summary = json!({
+        "sample_sheet" : sample_sheet,
+        "num_samples" : samples.len(),
+        "num_failed" : num_failed,
+        "samples" : sample_summaries,
+    });
+    write_json_atomic(&summary_file, &summary)?;
+
+    if num_failed > 0 {
+        bail!(
+            "{} of {} samples failed to be processed; see {} for details.",
+            num_failed,
+            samples.len(),
+            summary_file.display()
+        );
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sample_sheet() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let sheet_path = tmp_dir.path().join("samples.tsv");
+        std::fs::write(
+            &sheet_path,
+            "sample\treads1\treads2\tchemistry\tpermit_list\n\
+             s1\ts1_L1_R1.fq.gz,s1_L2_R1.fq.gz\ts1_L1_R2.fq.gz,s1_L2_R2.fq.gz\t10xv3\t\n\
+             # a commented out sample\n\
+             s2\t/data/s2_R1.fq.gz\t/data/s2_R2.fq.gz\t\t/data/pl.txt\n",
+        )
+        .unwrap();
+
+        let samples = parse_sample_sheet(&sheet_path).unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                SampleSheetEntry {
+                    sample: String::from("s1"),
+                    reads1: vec![
+                        tmp_dir.path().join("s1_L1_R1.fq.gz"),
+                        tmp_dir.path().join("s1_L2_R1.fq.gz")
+                    ],
+                    reads2: vec![
+                        tmp_dir.path().join("s1_L1_R2.fq.gz"),
+                        tmp_dir.path().join("s1_L2_R2.fq.gz")
+                    ],
+                    chemistry: Some(String::from("10xv3")),
+                    permit_list: None,
+                },
+                SampleSheetEntry {
+                    sample: String::from("s2"),
+                    reads1: vec![PathBuf::from("/data/s2_R1.fq.gz")],
+                    reads2: vec![PathBuf::from("/data/s2_R2.fq.gz")],
+                    chemistry: None,
+                    permit_list: Some(PathBuf::from("/data/pl.txt")),
+                },
+            ]
+        );
+
+        // duplicated sample names and unbalanced read lists are rejected
+        let bad_sheet_path = tmp_dir.path().join("bad_samples.csv");
+        std::fs::write(
+            &bad_sheet_path,
+            "sample,reads1,reads2\ns1,a_R1.fq,a_R2.fq\ns1,b_R1.fq,b_R2.fq\n",
+        )
+        .unwrap();
+        assert!(parse_sample_sheet(&bad_sheet_path).is_err());
+
+        std::fs::write(
+            &bad_sheet_path,
+            "sample,reads1,reads2\ns1,\"a_R1.fq,b_R1.fq\",a_R2.fq\n",
+        )
+        .unwrap();
+        assert!(parse_sample_sheet(&bad_sheet_path).is_err());
+    }
+
+    #[test]
+    fn test_run_samples_with_a_failing_sample() {
+        use clap::Parser;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let out = tmp_dir.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        let cli = crate::Cli::try_parse_from([
+            "simpleaf",
+            "quant",
+            "-i",
+            "idx",
+            "--sample-sheet",
+            "samples.tsv",
+            "-c",
+            "auto",
+            "-r",
+            "cr-like",
+            "-m",
+            "t2g.tsv",
+            "--knee",
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .unwrap();
+        let crate::simpleaf_commands::Commands::Quant(opts) = cli.command else {
+            panic!("expected a quant command");
+        };
+        let entry = |name: &str| SampleSheetEntry {
+            sample: name.to_string(),
+            reads1: vec![PathBuf::from(format!("{}_R1.fq", name))],
+            reads2: vec![PathBuf::from(format!("{}_R2.fq", name))],
+            chemistry: None,
+            permit_list: None,
+        };
+        let samples = [entry("good"), entry("bad")];
+
+        let sheet = tmp_dir.path().join("samples.tsv");
+        let mut processed = Vec::new();
+        let err = run_samples(&opts, &sheet, &samples, |entry, chem| {
+            processed.push(entry.sample.clone());
+            // the chemistry is detected before the sample fails
+            *chem = String::from("10xv3");
+            if entry.sample == "bad" {
+                bail!("mapping failed");
+            }
+            Ok(entry.sample.clone())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("1 of 2 samples failed"));
+        // the failing sample does not stop the batch
+        assert_eq!(processed, ["good", "bad"]);
+
+        let summary: Value = serde_json::from_str(
+            &std::fs::read_to_string(out.join(SAMPLE_SHEET_SUMMARY_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(summary["num_samples"], 2);
+        assert_eq!(summary["num_failed"], 1);
+        let good = &summary["samples"][0];
+        assert_eq!(good["status"], "success");
+        assert_eq!(good["chemistry"], "10xv3");
+        assert!(good["elapsed_secs"].is_f64());
+        let bad = &summary["samples"][1];
+        assert_eq!(bad["status"], "failed");
+        assert_eq!(bad["chemistry"], "10xv3");
+        assert_eq!(bad["error"], "mapping failed");
+    }
 }
@@ -1,7 +1,7 @@
+use crate::utils::executor::Executor;
 use crate::utils::prog_utils::*;
 
-use anyhow::Context;
-use serde_json::{json, Value};
+use serde_json::Value;
 use std::path::PathBuf;
 
 pub fn refresh_prog_info(af_home_path: PathBuf) -> anyhow::Result<()> {
@@ -14,15 +14,8 @@ pub fn refresh_prog_info(af_home_path: PathBuf) -> anyhow::Result<()> {
         current_rp.piscem.map(|p| p.exe_path),
         current_rp.alevin_fry.map(|p| p.exe_path),
         current_rp.macs.map(|p| p.exe_path),
+        &Executor::from_info(&v)?,
     )?;
 
-    let simpleaf_info_file = af_home_path.join("simpleaf_info.json");
-    let simpleaf_info = json!({ "prog_info": new_rp });
-
-    std::fs::write(
-        &simpleaf_info_file,
-        serde_json::to_string_pretty(&simpleaf_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", simpleaf_info_file.display()))?;
-    Ok(())
+    update_af_home_info(&af_home_path, "prog_info", serde_json::to_value(&new_rp)?)
 }
@@ -0,0 +1,540 @@
+use crate::utils::prog_utils::{check_files_exist, read_json};
+use crate::utils::run_record::{RunDetails, RunRecord, QUANT_RUN_RECORD_FILE};
+
+use anyhow::{bail, Context};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::{info, warn};
+
+use super::ReportOpts;
+
+/// The name of the JSON QC report written next to `simpleaf_quant_log.json`
+pub const QC_REPORT_JSON: &str = "simpleaf_qc_report.json";
+/// The name of the HTML QC report written next to `simpleaf_quant_log.json`
+pub const QC_REPORT_HTML: &str = "simpleaf_qc_report.html";
+
+/// The maximum number of points drawn in the knee plot
+const MAX_KNEE_POINTS: usize = 1000;
+
+pub fn generate_report(opts: ReportOpts) -> anyhow::Result<()> {
+    write_qc_report(&opts.input)
+}
+
+/// The counts observed for a single cell (row) of the count matrix
+#[derive(Clone, Debug, Default)]
+struct CellCounts {
+    umis: f64,
+    genes: usize,
+}
+
+/// Reads the alevin-fry count matrix (in MatrixMarket coordinate format, with
+/// one row per cell) and returns the total UMI count and the number of detected
+/// genes of every cell. In USA mode, the columns hold the spliced, unspliced
+/// and ambiguous counts of each gene one after another, so a gene is detected
+/// if any of its three columns is non-zero.
+fn read_cell_counts(mtx_path: &Path, usa_mode: bool) -> anyhow::Result<Vec<CellCounts>> {
+    let mtx_file = std::fs::File::open(mtx_path)
+        .with_context(|| format!("Could not open count matrix {}", mtx_path.display()))?;
+    let mut lines = BufReader::new(mtx_file).lines();
+
+    // skip the header and comments, then parse the dimensions
+    let (num_rows, num_cols) = loop {
+        let line = match lines.next() {
+            Some(l) => l?,
+            None => bail!("The count matrix {} is truncated.", mtx_path.display()),
+        };
+        if line.starts_with('%') || line.trim().is_empty() {
+            continue;
+        }
+        let dims: Vec<usize> = line
+            .split_whitespace()
+            .map(|x| x.parse::<usize>())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Could not parse the matrix dimensions line \"{}\"", line))?;
+        if dims.len() != 3 {
+            bail!("Could not parse the matrix dimensions line \"{}\"", line);
+        }
+        break (dims[0], dims[1]);
+    };
+
+    let num_genes = if usa_mode { num_cols / 3 } else { num_cols };
+    let mut cells = vec![CellCounts::default(); num_rows];
+    let mut cell_genes: Vec<Vec<usize>> = vec![Vec::new(); num_rows];
+    for l in lines {
+        let line = l?;
+        let mut fields = line.split_whitespace();
+        let (Some(r), Some(c), Some(v)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let r: usize = r.parse()?;
+        let c: usize = c.parse()?;
+        let v: f64 = v.parse()?;
+        if r == 0 || r > num_rows || c == 0 || c > num_cols {
+            bail!(
+                "Found an entry ({}, {}) outside of the {}x{} count matrix.",
+                r,
+                c,
+                num_rows,
+                num_cols
+            );
+        }
+        if v > 0.0 {
+            cells[r - 1].umis += v;
+            cell_genes[r - 1].push((c - 1) % num_genes.max(1));
+        }
+    }
+
+    for (cell, mut genes) in cells.iter_mut().zip(cell_genes) {
+        genes.sort_unstable();
+        genes.dedup();
+        cell.genes = genes.len();
+    }
+    Ok(cells)
+}
+
+/// Reads the `featureDump.txt` file and returns the number of mapped reads
+/// of each cell barcode.
+fn read_mapped_reads(feature_dump: &Path) -> anyhow::Result<HashMap<String, f64>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(feature_dump)
+        .with_context(|| format!("Could not open {}", feature_dump.display()))?;
+
+    let headers = rdr.headers()?.clone();
+    let reads_col = headers
+        .iter()
+        .position(|h| h == "MappedReads")
+        .or_else(|| headers.iter().position(|h| h == "CorrectedReads"))
+        .with_context(|| {
+            format!(
+                "Could not find the MappedReads column in {}",
+                feature_dump.display()
+            )
+        })?;
+
+    let mut mapped_reads = HashMap::new();
+    for rec in rdr.records() {
+        let rec = rec?;
+        if let (Some(cb), Some(reads)) = (rec.get(0), rec.get(reads_col)) {
+            mapped_reads.insert(cb.to_string(), reads.parse::<f64>().unwrap_or(0.0));
+        }
+    }
+    Ok(mapped_reads)
+}
+
+/// Reads the mapping statistics written by piscem (`map_info.json`) or
+/// salmon (`aux_info/meta_info.json`) into the mapping directory.
+fn read_mapping_info(map_dir: &Path) -> Option<Value> {
+    let map_info_p = [
+        map_dir.join("map_info.json"),
+        map_dir.join("aux_info").join("meta_info.json"),
+    ]
+    .into_iter()
+    .find(|p| p.is_file())?;
+
+    let v = match read_json(&map_info_p) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("could not read the mapping information : {:#}", e);
+            return None;
+        }
+    };
+    let num_reads = v["num_reads"]
+        .as_u64()
+        .or_else(|| v["num_processed"].as_u64());
+    let num_mapped = v["num_mapped"].as_u64();
+    let mapping_rate = match (num_reads, num_mapped) {
+        (Some(n), Some(m)) if n > 0 => Some(100.0 * m as f64 / n as f64),
+        _ => v["percent_mapped"].as_f64(),
+    };
+    Some(json!({
+        "num_reads" : num_reads,
+        "num_mapped" : num_mapped,
+        "mapping_rate" : mapping_rate
+    }))
+}
+
+fn median(sorted_vals: &[f64]) -> f64 {
+    let n = sorted_vals.len();
+    match n {
+        0 => 0.0,
+        _ if n % 2 == 1 => sorted_vals[n / 2],
+        _ => (sorted_vals[n / 2 - 1] + sorted_vals[n / 2]) / 2.0,
+    }
+}
+
+fn mean(vals: &[f64]) -> f64 {
+    if vals.is_empty() {
+        0.0
+    } else {
+        vals.iter().sum::<f64>() / vals.len() as f64
+    }
+}
+
+/// Selects the (1-based rank, UMI count) points of the knee plot from the
+/// UMI counts sorted in decreasing order. At most `MAX_KNEE_POINTS` points,
+/// evenly spaced on the log scale of the rank, are kept.
+fn knee_points(sorted_umis_desc: &[f64]) -> Vec<(usize, f64)> {
+    let n = sorted_umis_desc.len();
+    if n <= MAX_KNEE_POINTS {
+        return sorted_umis_desc
+            .iter()
+            .enumerate()
+            .map(|(i, u)| (i + 1, *u))
+            .collect();
+    }
+
+    let log_n = (n as f64).ln();
+    let mut points: Vec<(usize, f64)> = Vec::with_capacity(MAX_KNEE_POINTS);
+    for i in 0..MAX_KNEE_POINTS {
+        let rank = (log_n * i as f64 / (MAX_KNEE_POINTS - 1) as f64)
+            .exp()
+            .round()
+            .clamp(1.0, n as f64) as usize;
+        if points.last().is_none_or(|(r, _)| *r != rank) {
+            points.push((rank, sorted_umis_desc[rank - 1]));
+        }
+    }
+    points
+}
+
+/// Renders the knee plot (the UMI count of each cell against its rank,
+/// both on a log scale) as a standalone SVG element.
+fn knee_plot_svg(points: &[(usize, f64)]) -> String {
+    const WIDTH: f64 = 640.0;
+    const HEIGHT: f64 = 420.0;
+    const MARGIN_LEFT: f64 = 70.0;
+    const MARGIN_RIGHT: f64 = 20.0;
+    const MARGIN_TOP: f64 = 20.0;
+    const MARGIN_BOTTOM: f64 = 50.0;
+
+    let plot_w = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_h = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+
+    let max_rank = points.last().map(|(r, _)| *r).unwrap_or(1).max(10) as f64;
+    let max_umi = points.iter().map(|(_, u)| *u).fold(10.0_f64, f64::max);
+    let x_max = max_rank.log10().ceil();
+    let y_max = max_umi.log10().ceil();
+
+    let x = |rank: f64| MARGIN_LEFT + plot_w * rank.max(1.0).log10() / x_max;
+    let y = |umi: f64| MARGIN_TOP + plot_h * (1.0 - umi.max(1.0).log10() / y_max);
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}" font-family="sans-serif" font-size="12">"#
+    );
+
+    // axes, ticks and grid lines at the powers of 10
+    let _ = writeln!(
+        svg,
+        r#"<rect x="{MARGIN_LEFT}" y="{MARGIN_TOP}" width="{plot_w}" height="{plot_h}" fill="none" stroke="black"/>"#
+    );
+    for e in 0..=(x_max as u32) {
+        let tx = x(10f64.powi(e as i32));
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{tx:.1}" y1="{MARGIN_TOP}" x2="{tx:.1}" y2="{:.1}" stroke="#dddddd"/><text x="{tx:.1}" y="{:.1}" text-anchor="middle">1e{e}</text>"##,
+            MARGIN_TOP + plot_h,
+            MARGIN_TOP + plot_h + 16.0
+        );
+    }
+    for e in 0..=(y_max as u32) {
+        let ty = y(10f64.powi(e as i32));
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{MARGIN_LEFT}" y1="{ty:.1}" x2="{:.1}" y2="{ty:.1}" stroke="#dddddd"/><text x="{:.1}" y="{:.1}" text-anchor="end">1e{e}</text>"##,
+            MARGIN_LEFT + plot_w,
+            MARGIN_LEFT - 6.0,
+            ty + 4.0
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">cell rank</text>"#,
+        MARGIN_LEFT + plot_w / 2.0,
+        HEIGHT - 10.0
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="16" y="{:.1}" text-anchor="middle" transform="rotate(-90 16 {:.1})">UMI count</text>"#,
+        MARGIN_TOP + plot_h / 2.0,
+        MARGIN_TOP + plot_h / 2.0
+    );
+
+    let polyline = points
+        .iter()
+        .map(|(r, u)| format!("{:.1},{:.1}", x(*r as f64), y(*u)))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let _ = writeln!(
+        svg,
+        r##"<polyline points="{polyline}" fill="none" stroke="#1f77b4" stroke-width="2"/>"##
+    );
+    svg.push_str("</svg>");
+    svg
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders the QC summary as a self-contained HTML page.
+fn report_html(summary: &Value, knee_svg: &str) -> String {
+    let fmt_num = |v: &Value| -> String {
+        if let Some(i) = v.as_u64() {
+            format!("{}", i)
+        } else if let Some(f) = v.as_f64() {
+            format!("{:.2}", f)
+        } else {
+            String::from("NA")
+        }
+    };
+
+    let mut rows = String::new();
+    let metrics = [
+        ("Number of cells", &summary["cells"]["num_cells"]),
+        ("Number of genes", &summary["cells"]["num_genes"]),
+        ("Total UMIs", &summary["cells"]["total_umis"]),
+        (
+            "Median UMIs per cell",
+            &summary["cells"]["median_umis_per_cell"],
+        ),
+        (
+            "Mean UMIs per cell",
+            &summary["cells"]["mean_umis_per_cell"],
+        ),
+        (
+            "Median genes per cell",
+            &summary["cells"]["median_genes_per_cell"],
+        ),
+        (
+            "Mean genes per cell",
+            &summary["cells"]["mean_genes_per_cell"],
+        ),
+        (
+            "Median mapped reads per cell",
+            &summary["cells"]["median_reads_per_cell"],
+        ),
+        (
+            "Mean mapped reads per cell",
+            &summary["cells"]["mean_reads_per_cell"],
+        ),
+        ("Number of reads", &summary["mapping"]["num_reads"]),
+        ("Number of mapped reads", &summary["mapping"]["num_mapped"]),
+        ("Mapping rate (%)", &summary["mapping"]["mapping_rate"]),
+    ];
+    for (name, v) in metrics {
+        let _ = writeln!(rows, "<tr><th>{}</th><td>{}</td></tr>", name, fmt_num(v));
+    }
+
+    let permit_list_info = serde_json::to_string_pretty(&summary["permit_list"]).unwrap();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>simpleaf QC report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #cccccc; padding: 4px 12px; text-align: left; }}
+pre {{ background: #f5f5f5; padding: 1em; }}
+</style>
+</head>
+<body>
+<h1>simpleaf QC report</h1>
+<p>Quantification directory: <code>{}</code></p>
+<h2>Summary</h2>
+<table>
+{}</table>
+<h2>Knee plot</h2>
+{}
+<h2>Permit list generation</h2>
+<pre>{}</pre>
+</body>
+</html>
+"#,
+        html_escape(summary["quant_dir"].as_str().unwrap_or_default()),
+        rows,
+        knee_svg,
+        html_escape(&permit_list_info)
+    )
+}
+
+/// Computes the QC summary of the `simpleaf quant` output in `quant_output`, and
+/// writes it as `simpleaf_qc_report.json` and `simpleaf_qc_report.html` into
+/// that same directory.
+pub fn write_qc_report(quant_output: &Path) -> anyhow::Result<()> {
+    let af_quant_dir = quant_output.join("af_quant");
+    let alevin_dir = af_quant_dir.join("alevin");
+    let mtx_path = alevin_dir.join("quants_mat.mtx");
+    let rows_path = alevin_dir.join("quants_mat_rows.txt");
+    check_files_exist(&[mtx_path.clone(), rows_path.clone()])?;
+
+    // the quantification and permit list generation information
+    let quant_info = read_json(&af_quant_dir.join("quant.json")).unwrap_or(Value::Null);
+    let usa_mode = quant_info["usa_mode"].as_bool().unwrap_or(false);
+    let permit_list_info =
+        read_json(&af_quant_dir.join("generate_permit_list.json")).unwrap_or(Value::Null);
+
+    // the mapping directory is recorded in the quant run record, and defaults to `af_map`
+    let map_dir = match RunRecord::read(&quant_output.join(QUANT_RUN_RECORD_FILE)) {
+        Ok(RunRecord {
+            details: RunDetails::Quant { map_dir, .. },
+            ..
+        }) => map_dir,
+        _ => quant_output.join("af_map"),
+    };
+    let mapping_info = read_mapping_info(&map_dir);
+
+    // the cell barcodes; the rows file may hold extra (tab-separated) columns
+    let barcodes: Vec<String> = BufReader::new(std::fs::File::open(&rows_path)?)
+        .lines()
+        .map(|l| l.map(|l| l.split('\t').next().unwrap_or_default().to_string()))
+        .collect::<Result<_, _>>()?;
+
+    info!("reading count matrix {}", mtx_path.display());
+    let cells = read_cell_counts(&mtx_path, usa_mode)?;
+    if cells.len() != barcodes.len() {
+        bail!(
+            "The count matrix has {} rows, but {} contains {} barcodes.",
+            cells.len(),
+            rows_path.display(),
+            barcodes.len()
+        );
+    }
+
+    let mut umis: Vec<f64> = cells.iter().map(|c| c.umis).collect();
+    let mut genes: Vec<f64> = cells.iter().map(|c| c.genes as f64).collect();
+    umis.sort_unstable_by(|a, b| b.total_cmp(a));
+    genes.sort_unstable_by(|a, b| a.total_cmp(b));
+    let umis_asc: Vec<f64> = umis.iter().rev().copied().collect();
+
+    let feature_dump = af_quant_dir.join("featureDump.txt");
+    let mut reads: Vec<f64> = Vec::new();
+    if feature_dump.is_file() {
+        let mapped_reads = read_mapped_reads(&feature_dump)?;
+        reads = barcodes
+            .iter()
+            .filter_map(|cb| mapped_reads.get(cb).copied())
+            .collect();
+        reads.sort_unstable_by(|a, b| a.total_cmp(b));
+    } else {
+        warn!(
+            "could not find {}; skipping the reads per cell statistics.",
+            feature_dump.display()
+        );
+    }
+
+    let points = knee_points(&umis);
+    let summary = json!({
+        "quant_dir" : af_quant_dir,
+        "cells" : {
+            "num_cells" : cells.len(),
+            "num_genes" : quant_info["num_genes"].as_u64().map(|n| if usa_mode { n / 3 } else { n }),
+            "total_umis" : umis.iter().sum::<f64>(),
+            "median_umis_per_cell" : median(&umis_asc),
+            "mean_umis_per_cell" : mean(&umis),
+            "median_genes_per_cell" : median(&genes),
+            "mean_genes_per_cell" : mean(&genes),
+            "median_reads_per_cell" : if reads.is_empty() { None } else { Some(median(&reads)) },
+            "mean_reads_per_cell" : if reads.is_empty() { None } else { Some(mean(&reads)) },
+        },
+        "mapping" : mapping_info,
+        "permit_list" : permit_list_info,
+        "knee_plot" : points.iter().map(|(r, u)| json!([r, u])).collect::<Vec<Value>>()
+    });
+
+    let json_report = quant_output.join(QC_REPORT_JSON);
+    std::fs::write(&json_report, serde_json::to_string_pretty(&summary)?)
+        .with_context(|| format!("could not write {}", json_report.display()))?;
+
+    let html_report = quant_output.join(QC_REPORT_HTML);
+    std::fs::write(&html_report, report_html(&summary, &knee_plot_svg(&points)))
+        .with_context(|| format!("could not write {}", html_report.display()))?;
+
+    info!(
+        "wrote the QC report to {} and {}",
+        json_report.display(),
+        html_report.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&[]), 0.0);
+        assert_eq!(median(&[3.0]), 3.0);
+        assert_eq!(median(&[1.0, 2.0, 7.0]), 2.0);
+        assert_eq!(median(&[1.0, 2.0, 4.0, 7.0]), 3.0);
+    }
+
+    #[test]
+    fn test_knee_points() {
+        assert!(knee_points(&[]).is_empty());
+        assert_eq!(
+            knee_points(&[9.0, 4.0, 1.0]),
+            [(1, 9.0), (2, 4.0), (3, 1.0)]
+        );
+
+        // long curves are subsampled on the log scale of the rank, keeping both ends
+        let umis: Vec<f64> = (0..100_000).rev().map(|u| u as f64).collect();
+        let points = knee_points(&umis);
+        assert!(points.len() <= MAX_KNEE_POINTS);
+        assert_eq!(points[0], (1, 99_999.0));
+        assert_eq!(*points.last().unwrap(), (100_000, 0.0));
+        assert!(points.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(points.iter().all(|(r, u)| umis[r - 1] == *u));
+    }
+
+    #[test]
+    fn test_read_cell_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mtx = dir.path().join("quants_mat.mtx");
+        // 3 cells, and 2 genes with their spliced, unspliced and ambiguous counts
+        std::fs::write(
+            &mtx,
+            "%%MatrixMarket matrix coordinate real general\n\
+            %\n\
+            3 6 5\n\
+            1 1 2\n\
+            1 3 1\n\
+            1 4 1.5\n\
+            2 2 0\n\
+            3 5 4\n",
+        )
+        .unwrap();
+
+        let cells = read_cell_counts(&mtx, false).unwrap();
+        assert_eq!(cells.len(), 3);
+        assert_eq!((cells[0].umis, cells[0].genes), (4.5, 3));
+        assert_eq!((cells[1].umis, cells[1].genes), (0.0, 0));
+        assert_eq!((cells[2].umis, cells[2].genes), (4.0, 1));
+
+        // in USA mode, the counts of the three splicing states of a gene add up
+        let cells = read_cell_counts(&mtx, true).unwrap();
+        assert_eq!((cells[0].umis, cells[0].genes), (4.5, 2));
+        assert_eq!((cells[2].umis, cells[2].genes), (4.0, 1));
+
+        std::fs::write(
+            &mtx,
+            "%%MatrixMarket matrix coordinate real general\n2 2 1\n3 1 1\n",
+        )
+        .unwrap();
+        assert!(read_cell_counts(&mtx, false).is_err());
+        std::fs::write(&mtx, "%%MatrixMarket matrix coordinate real general\n").unwrap();
+        assert!(read_cell_counts(&mtx, false).is_err());
+    }
+}
@@ -1,18 +1,21 @@
 use crate::utils::jrsonnet_main::{parse_jsonnet, ParseAction};
 use crate::utils::prog_utils;
 use crate::utils::prog_utils::ReqProgs;
-use crate::utils::workflow_utils;
+use crate::utils::registry::{Registries, Registry, RegistrySource};
+use crate::utils::workflow_jobs::{self, WorkflowExecutor};
+use crate::utils::workflow_schema;
+use crate::utils::workflow_utils::{self, GraphFormat, SimpleafWorkflow};
 
 use anyhow::{bail, Context};
 use cmd_lib::run_fun;
 use serde_json::json;
 use serde_json::Value;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tabled::{settings::Style, Table, Tabled};
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
-use super::WorkflowCommands;
+use super::{RegistryAddOpts, RegistryRemoveOpts, WorkflowCommands, WorkflowSourceOpts};
 
 #[derive(Tabled)]
 struct WorkflowTemplate {
@@ -21,11 +24,75 @@ struct WorkflowTemplate {
     version: String,
 }
 
+#[derive(Tabled)]
+struct RegistryEntry {
+    name: String,
+    #[tabled(rename = "type")]
+    source_type: String,
+    source: String,
+    revision: String,
+}
+
 pub fn refresh_protocol_estuary<T: AsRef<Path>>(af_home_path: T) -> anyhow::Result<()> {
-    workflow_utils::get_protocol_estuary(
-        af_home_path.as_ref(),
-        workflow_utils::RegistrySourceStrategy::ForceRefresh,
-    )?;
+    Registries::load(af_home_path.as_ref())?.refresh_all()
+}
+
+pub fn add_registry(af_home_path: &Path, add_opts: RegistryAddOpts) -> anyhow::Result<()> {
+    let RegistryAddOpts {
+        name,
+        path,
+        git,
+        rev,
+        archive,
+        checksum,
+    } = add_opts;
+    // exactly one of `--path`, `--git` (with `--rev`) and `--archive` is given, as
+    // the `registry_source` group of the options requires
+    let source = match (path, git.zip(rev), archive) {
+        (Some(path), _, _) => RegistrySource::Local {
+            path: path
+                .canonicalize()
+                .with_context(|| format!("could not find the directory {}", path.display()))?,
+        },
+        (_, Some((url, rev)), _) => RegistrySource::Git { url, rev },
+        (_, _, Some(url)) => RegistrySource::Archive { url, checksum },
+        (None, None, None) => bail!("the registry has no source"),
+    };
+    let mut registries = Registries::load(af_home_path)?;
+    registries.add(Registry {
+        name: name.clone(),
+        source,
+        revision: None,
+    })?;
+    info!(
+        "added the registry {}; its workflows can be fetched with `simpleaf workflow get --name {}/<workflow>`",
+        name, name
+    );
+    Ok(())
+}
+
+pub fn remove_registry(af_home_path: &Path, rem_opts: RegistryRemoveOpts) -> anyhow::Result<()> {
+    let registry = Registries::load(af_home_path)?.remove(&rem_opts.name)?;
+    info!(
+        "removed the registry {} ({})",
+        registry.name, registry.source
+    );
+    Ok(())
+}
+
+pub fn list_registries(af_home_path: &Path) -> anyhow::Result<()> {
+    let registries = Registries::load(af_home_path)?;
+    let entries = registries.iter().map(|r| RegistryEntry {
+        name: r.name.clone(),
+        source_type: match r.source {
+            RegistrySource::Local { .. } => String::from("local"),
+            RegistrySource::Git { .. } => String::from("git"),
+            RegistrySource::Archive { .. } => String::from("archive"),
+        },
+        source: r.source.to_string(),
+        revision: r.revision.clone().unwrap_or_else(|| String::from("N/A")),
+    });
+    println!("{}", Table::new(entries).with(Style::rounded()));
     Ok(())
 }
 
@@ -137,32 +204,41 @@ pub fn list_workflows<T: AsRef<Path>>(af_home_path: T) -> anyhow::Result<()> {
     // TODO: use it somehwere?
     let _rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
 
-    // get protocol library path
-    let protocol_estuary = workflow_utils::get_protocol_estuary(
-        af_home_path.as_ref(),
-        workflow_utils::RegistrySourceStrategy::PreferLocal,
-    )?;
-    // get the corresponding workflow directory path
-    let workflow_path = protocol_estuary.protocols_dir.as_path();
-    let workflows = fs::read_dir(workflow_path)?;
+    let mut registries = Registries::load(af_home_path.as_ref())?;
+    let registry_names: Vec<String> = registries.iter().map(|r| r.name.clone()).collect();
     let mut print_na_cap = false;
     let na_string = String::from("N/A*");
     let mut workflow_entries = vec![];
-    for prot in workflows {
-        if let Ok(prot) = prot {
-            let version =
-                workflow_utils::get_template_version(prot.path(), &protocol_estuary.utils_dir)?;
-            if version == na_string {
-                print_na_cap = true;
+    for registry in registry_names {
+        let pe = match registries.checkout(
+            &registry,
+            workflow_utils::RegistrySourceStrategy::PreferLocal,
+        ) {
+            Ok(pe) => pe,
+            Err(e) => {
+                warn!("Skipping the registry {}: {:#}", registry, e);
+                continue;
+            }
+        };
+        let utils_dir = registries.utils_dir(&pe);
+        // get the corresponding workflow directory path
+        let workflow_path = pe.protocols_dir.as_path();
+        let workflows = fs::read_dir(workflow_path)?;
+        for prot in workflows {
+            if let Ok(prot) = prot {
+                let version = workflow_utils::get_template_version(prot.path(), &utils_dir)?;
+                if version == na_string {
+                    print_na_cap = true;
+                }
+                let n = format!("{:?}", prot.file_name());
+                workflow_entries.push(WorkflowTemplate {
+                    registry: registry.clone(),
+                    name: n,
+                    version,
+                })
+            } else {
+                warn!("Cannot traverse directory {:?}", workflow_path)
             }
-            let n = format!("{:?}", prot.file_name());
-            workflow_entries.push(WorkflowTemplate {
-                registry: String::from("COMBINE-lab/protocol-estuary"),
-                name: n,
-                version,
-            })
-        } else {
-            warn!("Cannot traverse directory {:?}", workflow_path)
         }
     }
     println!("{}", Table::new(workflow_entries).with(Style::rounded()));
@@ -206,18 +282,15 @@ pub fn get_workflow<T: AsRef<Path>>(
             // TODO: use it somehwere?
             let _rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
 
-            // get protocol library path
-            let protocol_estuary = workflow_utils::get_protocol_estuary(
-                af_home_path.as_ref(),
-                workflow_utils::RegistrySourceStrategy::PreferLocal,
-            )?;
+            // find the registry holding the workflow
+            let mut registries = Registries::load(af_home_path.as_ref())?;
+            let (registry, protocol_estuary, name) = registries.resolve_workflow(&name)?;
+            let utils_dir = registries.utils_dir(&protocol_estuary);
             // get the corresponding workflow directory path
             let workflow_path = protocol_estuary.protocols_dir.join(name.as_str());
 
-            let version_str = workflow_utils::get_template_version(
-                workflow_path.clone(),
-                &protocol_estuary.utils_dir,
-            )?;
+            let version_str =
+                workflow_utils::get_template_version(workflow_path.clone(), &utils_dir)?;
 
             /* NOTE: we can't check against this version for very old templates, because we can't
              * even instantaite them any longer because of how the evaluation has changed. Only
@@ -306,8 +379,9 @@ pub fn get_workflow<T: AsRef<Path>>(
 
                     // return with an error
                     bail!(
-                        "Could not find a workflow with name: {}. {}",
+                        "Could not find a workflow with name: {} in the registry {}. {}",
                         name,
+                        registry.name,
                         similar_name_hints
                     );
                 }
@@ -321,6 +395,7 @@ pub fn get_workflow<T: AsRef<Path>>(
             let gwc_info = json!({
                 "command" : "get-workflow-config",
                 "workflow dir": output_path,
+                "registry": registry,
 
                 "args" : {
                     "output" : output,
@@ -348,6 +423,94 @@ pub fn get_workflow<T: AsRef<Path>>(
     Ok(())
 }
 
+/// Loads the manifest to run, either directly from `manifest` or by
+/// instantiating `template`, and returns it along with the path it was read
+/// from and the output directory it specifies.
+fn load_workflow_manifest(
+    af_home_path: &Path,
+    template: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    output: Option<PathBuf>,
+    jpaths: &Option<Vec<PathBuf>>,
+    ext_codes: &Option<Vec<String>>,
+) -> anyhow::Result<(Value, PathBuf, PathBuf)> {
+    if let Some(manifest) = manifest {
+        // If the user passed a fully-instantiated
+        // manifest to execute
+        info!("Loading manifest.");
+        // iterate json files and parse records to commands
+        // convert files into json string vector
+        let instantiated_manifest: Value = workflow_utils::parse_manifest(&manifest)?;
+        let output_path = workflow_utils::get_output_path(&instantiated_manifest)?;
+        Ok((instantiated_manifest, manifest, output_path))
+    } else if let Some(template) = template {
+        //  check the validity of the file
+        if !template.exists() || !template.is_file() {
+            bail!("the path of the given workflow template file doesn't exist; Cannot proceed.")
+        }
+
+        info!("Processing simpleaf template to produce manifest.");
+
+        // iterate json files and parse records to commands
+        // convert files into json string vector
+        let workflow_json_string = workflow_utils::instantiate_workflow_template(
+            af_home_path,
+            template.as_path(),
+            output.clone(),
+            jpaths,
+            ext_codes,
+        )?;
+
+        // write complete workflow (i.e. the manifest) JSON to output folder
+        let instantiated_manifest: Value = serde_json::from_str(workflow_json_string.as_str())?;
+        let output_path = workflow_utils::get_output_path(&instantiated_manifest)?;
+
+        // check if the output path we read from the instantiated template matches
+        // the output path requested by the user (if the user passed one in). If
+        // they do not match, issue an obnoxious warning.
+        // @DongzeHe : We should also probably log this warning to the output
+        // log for subsequent inspection.
+        if let Some(requested_output_path) = output {
+            if requested_output_path != output_path {
+                warn!(
+                    r#"The output path {} was requested via the command line, but 
+                    the output path {} was resolved from the workflow template.
+                    In this case, since the output variable is not used when instantiating 
+                    the template, the value ({}) present in the template must be used.
+                    Please be aware that {} will not be used for output!"#,
+                    requested_output_path.display(),
+                    output_path.display(),
+                    output_path.display(),
+                    requested_output_path.display()
+                );
+            }
+        }
+
+        Ok((instantiated_manifest, template, output_path))
+    } else {
+        bail!(concat!(
+            "You must have one of a manifest or template, ",
+            "but provided neither; this shouldn't happen"
+        ));
+    }
+}
+
+/// Logs every problem found in the command records of `manifest`, and
+/// returns an error if there is any.
+fn ensure_valid_manifest(manifest: &Value) -> anyhow::Result<()> {
+    let issues = workflow_schema::validate_manifest(manifest);
+    if !issues.is_empty() {
+        for issue in &issues {
+            error!("{}", issue);
+        }
+        bail!(
+            "Found {} problem(s) in the workflow manifest; Cannot proceed.",
+            issues.len()
+        );
+    }
+    Ok(())
+}
+
 /// ## simpleaf run-workflow
 /// #### Input
 /// one or more simpleaf workflow JSON file (s) with all required fields
@@ -383,78 +546,29 @@ pub fn run_workflow<T: AsRef<Path>>(
             jpaths,
             skip_step,
             ext_codes,
+            jobs,
+            executor,
+            no_submit,
+            submit_arg,
         } => {
-            // designate that output is optional
-            let output_opt = output;
-
-            let instantiated_manifest: serde_json::Value;
-            let source_path: std::path::PathBuf;
-            let output_path: std::path::PathBuf;
-            if let Some(manifest) = manifest {
-                // If the user passed a fully-instantiated
-                // manifest to execute
-                info!("Loading and executing manifest.");
-                // iterate json files and parse records to commands
-                // convert files into json string vector
-                instantiated_manifest = workflow_utils::parse_manifest(&manifest)?;
-                output_path = workflow_utils::get_output_path(&instantiated_manifest)?;
-                source_path = manifest.clone();
-            } else if let Some(template) = template {
-                //  check the validity of the file
-                if !template.exists() || !template.is_file() {
-                    bail!("the path of the given workflow template file doesn't exist; Cannot proceed.")
-                }
-
-                info!("Processing simpleaf template to produce and execute manifest.");
-
-                // iterate json files and parse records to commands
-                // convert files into json string vector
-                let workflow_json_string = workflow_utils::instantiate_workflow_template(
-                    af_home_path.as_ref(),
-                    template.as_path(),
-                    output_opt.clone(),
-                    &jpaths,
-                    &ext_codes,
-                )?;
-
-                // write complete workflow (i.e. the manifest) JSON to output folder
-                instantiated_manifest = serde_json::from_str(workflow_json_string.as_str())?;
-                output_path = workflow_utils::get_output_path(&instantiated_manifest)?;
-
-                // check if the output path we read from the instantiated template matches
-                // the output path requested by the user (if the user passed one in). If
-                // they do not match, issue an obnoxious warning.
-                // @DongzeHe : We should also probably log this warning to the output
-                // log for subsequent inspection.
-                if let Some(requested_output_path) = output_opt {
-                    if requested_output_path != output_path {
-                        warn!(
-                            r#"The output path {} was requested via the command line, but 
-                            the output path {} was resolved from the workflow template.
-                            In this case, since the output variable is not used when instantiating 
-                            the template, the value ({}) present in the template must be used.
-                            Please be aware that {} will not be used for output!"#,
-                            requested_output_path.display(),
-                            output_path.display(),
-                            output_path.display(),
-                            requested_output_path.display()
-                        );
-                    }
-                }
-
-                source_path = template.clone();
-            } else {
-                bail!(concat!(
-                    "You must have one of a manifest or template, ",
-                    "but provided neither; this shouldn't happen"
-                ));
-            }
+            let (instantiated_manifest, source_path, output_path) = load_workflow_manifest(
+                af_home_path.as_ref(),
+                template,
+                manifest,
+                output,
+                &jpaths,
+                &ext_codes,
+            )?;
 
             // recursively make the output directory, which at this point
             // has been resolved as the one used in the template or manifest
             // (possibly as provided by the user in the former case).
             run_fun!(mkdir -p $output_path)?;
 
+            // check the whole manifest before running anything, so that all
+            // the problems are reported at once
+            ensure_valid_manifest(&instantiated_manifest)?;
+
             // we need to convert the optional to a vector
             let final_skip_step = skip_step.unwrap_or(Vec::new());
 
@@ -470,11 +584,40 @@ pub fn run_workflow<T: AsRef<Path>>(
                 resume,
             )?;
 
-            if !no_execution {
+            // record the registry (and its revision) that the workflow was
+            // fetched from, as written by `simpleaf workflow get`
+            let get_info_path = source_path.with_file_name("get_workflow.json");
+            if get_info_path.is_file() {
+                let get_info = prog_utils::read_json(&get_info_path)?;
+                if let Some(registry) = get_info.get("registry") {
+                    workflow_log.set_workflow_registry(registry.clone());
+                }
+            }
+
+            if !no_execution && executor != WorkflowExecutor::Local {
+                workflow_jobs::submit_workflow_jobs(
+                    simpleaf_workflow,
+                    af_home_path,
+                    output_path.as_path(),
+                    &mut workflow_log,
+                    executor,
+                    !no_submit,
+                    &submit_arg,
+                )?;
+                // the jobs have not run yet, so the run is not (yet) a success;
+                // `--resume` picks up from the first job that did not complete.
+                workflow_log.write(false)?;
+                if no_submit {
+                    info!("the job scripts were written successfully.");
+                } else {
+                    info!("all jobs were submitted successfully.");
+                }
+            } else if !no_execution {
                 workflow_utils::execute_commands_in_workflow(
                     simpleaf_workflow,
                     af_home_path,
                     &mut workflow_log,
+                    jobs as usize,
                 )?;
                 // write log
                 workflow_log.write(true)?;
@@ -491,3 +634,110 @@ pub fn run_workflow<T: AsRef<Path>>(
     } // match Commands::Workflow
     Ok(())
 }
+
+/// Instantiates the workflow in `source` and parses its commands, without
+/// running any of them. The manifest is returned along with the workflow.
+fn parse_workflow(
+    af_home_path: &Path,
+    source: WorkflowSourceOpts,
+) -> anyhow::Result<(Value, SimpleafWorkflow)> {
+    let WorkflowSourceOpts {
+        template,
+        output,
+        manifest,
+        jpaths,
+        ext_codes,
+    } = source;
+    let (instantiated_manifest, source_path, output_path) = load_workflow_manifest(
+        af_home_path,
+        template,
+        manifest,
+        output,
+        &jpaths,
+        &ext_codes,
+    )?;
+
+    ensure_valid_manifest(&instantiated_manifest)?;
+
+    // the log is never written, so the output directory is not created
+    let (simpleaf_workflow, _) = workflow_utils::initialize_workflow(
+        af_home_path,
+        source_path.as_path(),
+        output_path.as_path(),
+        instantiated_manifest.clone(),
+        1,
+        vec![],
+        false,
+    )?;
+    Ok((instantiated_manifest, simpleaf_workflow))
+}
+
+/// Checks a workflow template or manifest without running it. Besides the
+/// flags and dependencies of its commands, this checks that the external
+/// programs can be found and that the input files of the simpleaf commands
+/// exist or are written by an earlier step.
+pub fn validate_workflow(af_home_path: &Path, source: WorkflowSourceOpts) -> anyhow::Result<()> {
+    let (instantiated_manifest, simpleaf_workflow) = parse_workflow(af_home_path, source)?;
+
+    for issue in workflow_schema::shared_steps(&simpleaf_workflow) {
+        warn!("{}", issue);
+    }
+    let issues = workflow_schema::check_workflow(&simpleaf_workflow, &instantiated_manifest);
+    for issue in &issues {
+        error!("{}", issue);
+    }
+    if !issues.is_empty() {
+        bail!("Found {} problem(s) in the workflow.", issues.len());
+    }
+    info!(
+        "The workflow is valid; it runs {} command(s).",
+        simpleaf_workflow.cmd_queue.len()
+    );
+    Ok(())
+}
+
+/// Prints the commands of a workflow and their dependencies as a diagram in
+/// `format`, or writes it to `out_file`.
+pub fn graph_workflow(
+    af_home_path: &Path,
+    source: WorkflowSourceOpts,
+    format: GraphFormat,
+    out_file: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let (_, simpleaf_workflow) = parse_workflow(af_home_path, source)?;
+    let graph = simpleaf_workflow.graph(format);
+    match out_file {
+        Some(p) => {
+            fs::write(&p, graph + "\n")
+                .with_context(|| format!("could not write {}", p.display()))?;
+            info!("wrote the workflow graph to {}", p.display());
+        }
+        None => println!("{}", graph),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    #[test]
+    fn test_registry_add_needs_exactly_one_source() {
+        let parse = |args: &str| {
+            crate::Cli::try_parse_from(
+                ["simpleaf", "workflow", "registry", "add", "-n", "lab"]
+                    .into_iter()
+                    .chain(args.split_whitespace()),
+            )
+        };
+        assert!(parse("--path reg").is_ok());
+        assert!(parse("--git https://example.org/reg.git --rev v1").is_ok());
+        assert!(parse("--archive https://example.org/reg.zip --checksum abc").is_ok());
+
+        assert!(parse("").is_err());
+        assert!(parse("--path reg --archive https://example.org/reg.zip").is_err());
+        assert!(parse("--git https://example.org/reg.git").is_err());
+        assert!(parse("--path reg --rev v1").is_err());
+        assert!(parse("--git https://example.org/reg.git --rev v1 --checksum abc").is_err());
+    }
+}
@@ -1,7 +1,22 @@
 pub mod af_utils;
+pub mod archive;
+pub mod checkpoint;
+pub mod chem_detect;
 pub mod chem_utils;
+pub mod config;
 pub mod constants;
+pub mod empty_drops;
+pub mod executor;
+pub mod home;
 pub mod jrsonnet_main;
+pub mod network;
+pub mod permit_list;
 pub mod prog_utils;
+pub mod provenance;
+pub mod read_checks;
+pub mod registry;
 pub mod remote;
+pub mod run_record;
+pub mod workflow_jobs;
+pub mod workflow_schema;
 pub mod workflow_utils;
@@ -10,7 +10,7 @@ use std::io::BufReader;
 use std::path::{Path, PathBuf};
 
 use strum_macros::EnumIter;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info};
 
 use crate::atac::commands::AtacChemistry;
 use crate::utils::chem_utils::{get_single_custom_chem_from_file, CustomChemistry, ExpectedOri};
@@ -145,6 +145,20 @@ pub enum CellFilterMethod {
     KneeFinding,
 }
 
+impl std::fmt::Display for CellFilterMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CellFilterMethod::ForceCells(nc) => write!(f, "force {} cells", nc),
+            CellFilterMethod::ExpectCells(nc) => write!(f, "expect {} cells", nc),
+            CellFilterMethod::ExplicitList(l) => write!(f, "explicit permit list {}", l),
+            CellFilterMethod::UnfilteredExternalList(l, m) => {
+                write!(f, "unfiltered permit list {} (min. {} reads)", l, m)
+            }
+            CellFilterMethod::KneeFinding => write!(f, "knee finding"),
+        }
+    }
+}
+
 impl CellFilterMethod {
     /// How a [CellFilterMethod] should add itself to an
     /// `alevin-fry` command.
@@ -561,15 +575,29 @@ Please consider removing and re-adding this chemistry with a valid permit list."
                     bail!("Expected permit list was absent, and no remote source was provided.");
                 }
                 Some(Value::String(rpath)) => {
-                    // download the file
-                    let hash =
-                        prog_utils::download_to_file_compute_hash(rpath, &expected_file_path)?;
+                    prog_utils::ensure_online(
+                        &format!(
+                            "The permit list of the chemistry {} ({})",
+                            chem.registry_key(),
+                            expected_file_path.display()
+                        ),
+                        rpath,
+                    )?;
+                    // download the file, which is only kept if it has the expected hash
                     let expected_hash = expected_file_name
                         .to_str()
                         .ok_or(anyhow!("cannot convert expected filename to proper string"))?;
-                    if hash.to_string() != expected_hash {
-                        warn!("The permit list file for {}, obtained from the provided remote url {}, does not match the expected hash {} (the observed hash was {})", chem.registry_key(), rpath, expected_hash, hash.to_string());
-                    }
+                    prog_utils::download_to_file_verify_hash(
+                        rpath,
+                        &expected_file_path,
+                        expected_hash,
+                    )
+                    .with_context(|| {
+                        format!(
+                            "could not obtain the permit list file for {}",
+                            chem.registry_key()
+                        )
+                    })?;
                     Ok(PermitListResult::DownloadSuccessful(expected_file_path))
                 }
                 _ => {
@@ -614,27 +642,13 @@ Please consider removing and re-adding this chemistry with a valid permit list."
 pub fn add_or_transform_fragment_library(
     mapper_type: MapperType,
     fragment_geometry_str: &str,
-    reads1: &Vec<PathBuf>,
-    reads2: &Vec<PathBuf>,
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
     quant_cmd: &mut std::process::Command,
 ) -> Result<FragmentTransformationType> {
-    let known_chem = match mapper_type {
-        MapperType::MappedRadFile => {
-            bail!("Cannot add_or_transform_fragment library when dealing with an already-mapped RAD file.");
-        }
-        MapperType::Piscem => KNOWN_CHEM_MAP_PISCEM.contains_key(fragment_geometry_str),
-        MapperType::Salmon => KNOWN_CHEM_MAP_SALMON.contains_key(fragment_geometry_str),
-    };
-
-    let frag_geom_opt = if !known_chem {
-        Some(FragmentGeomDesc::try_from(fragment_geometry_str)?)
-    } else {
-        None
-    };
-
     // We have a "complex" geometry, so transform the reads through a fifo
-    match frag_geom_opt {
-        Some(frag_geom) if frag_geom.is_complex_geometry() => {
+    match complex_fragment_geometry(&mapper_type, fragment_geometry_str)? {
+        Some(frag_geom) => {
             // parse into a "regex" description
             let regex_geo = frag_geom.as_regex()?;
             // the simplified geometry corresponding to this regex geo
@@ -644,8 +658,8 @@ pub fn add_or_transform_fragment_library(
             // simplified geometry
             let fifo_xform_data = seq_geom_xform::xform_read_pairs_to_fifo(
                 regex_geo,
-                reads1.clone(),
-                reads2.clone(),
+                reads1.to_vec(),
+                reads2.to_vec(),
             )?;
 
             let r1_path = std::path::Path::new(&fifo_xform_data.r1_fifo);
@@ -675,7 +689,7 @@ pub fn add_or_transform_fragment_library(
                 fifo_xform_data,
             ))
         }
-        _ => {
+        None => {
             // just feed the reads directly to the mapper
             match mapper_type {
                 MapperType::Piscem => {
@@ -722,6 +736,70 @@ pub fn add_or_transform_fragment_library(
     }
 }
 
+/// Returns the parsed `fragment_geometry_str` if it describes a "complex" geometry
+/// (see [add_or_transform_fragment_library]) for `mapper_type`, and `None` otherwise.
+fn complex_fragment_geometry(
+    mapper_type: &MapperType,
+    fragment_geometry_str: &str,
+) -> Result<Option<FragmentGeomDesc>> {
+    let known_chem = match mapper_type {
+        MapperType::MappedRadFile => {
+            bail!("Cannot add_or_transform_fragment library when dealing with an already-mapped RAD file.");
+        }
+        MapperType::Piscem => KNOWN_CHEM_MAP_PISCEM.contains_key(fragment_geometry_str),
+        MapperType::Salmon => KNOWN_CHEM_MAP_SALMON.contains_key(fragment_geometry_str),
+    };
+
+    if known_chem {
+        return Ok(None);
+    }
+    let frag_geom = FragmentGeomDesc::try_from(fragment_geometry_str)?;
+    Ok(frag_geom.is_complex_geometry().then_some(frag_geom))
+}
+
+/// Adds the reads and geometry to `quant_cmd` just as [add_or_transform_fragment_library]
+/// would, but without transforming the reads of a "complex" geometry; instead, the fifos
+/// that the transformed reads would be written to are shown as placeholders. This is used
+/// to show the mapping command of a `--dry-run`.
+pub fn plan_fragment_library(
+    mapper_type: MapperType,
+    fragment_geometry_str: &str,
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+    quant_cmd: &mut std::process::Command,
+) -> Result<()> {
+    match complex_fragment_geometry(&mapper_type, fragment_geometry_str)? {
+        Some(frag_geom) => {
+            let simp_geo_string = frag_geom.as_regex()?.get_simplified_description_string();
+            quant_cmd.arg("-1").arg("<fifo of transformed reads1>");
+            quant_cmd.arg("-2").arg("<fifo of transformed reads2>");
+            match mapper_type {
+                MapperType::Piscem => {
+                    add_chemistry_to_args_piscem(simp_geo_string.as_str(), quant_cmd)?;
+                }
+                MapperType::Salmon => {
+                    add_chemistry_to_args_salmon(simp_geo_string.as_str(), quant_cmd)?;
+                }
+                MapperType::MappedRadFile => {
+                    bail!("An existing RAD file is not mapped, so it has no fragment library to plan.");
+                }
+            }
+        }
+        None => {
+            // a simple geometry is passed straight to the mapper, so
+            // nothing is started here.
+            add_or_transform_fragment_library(
+                mapper_type,
+                fragment_geometry_str,
+                reads1,
+                reads2,
+                quant_cmd,
+            )?;
+        }
+    }
+    Ok(())
+}
+
 /// Reads the JSON file at the provided path `p`, parses the file and returns the result as an
 /// `Ok(serde_json::Value)` if the parse is successful.  If the file exists and the parsing is not
 /// successful, returns an `anyhow::Error` describing the failure.
@@ -736,6 +814,7 @@ pub fn parse_resource_json_file(p: &Path, url: Option<&str>) -> Result<Value> {
     // get the file
     if !resource_exists {
         if let Some(dl_url) = url {
+            prog_utils::ensure_online(&format!("The resource file {}", p.display()), dl_url)?;
             // download the custom_chemistries.json file if needed
             prog_utils::download_to_file(dl_url, p)?;
         } else {
@@ -0,0 +1,175 @@
+use anyhow::{bail, Context};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The archive formats that can be extracted by [`extract_archive`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Detects the format of the archive at `path` from its first bytes,
+    /// as the URL it was downloaded from does not always have an extension.
+    pub fn detect(path: &Path) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 4];
+        let n = File::open(path)
+            .and_then(|mut f| f.read(&mut magic))
+            .with_context(|| format!("could not read {}", path.display()))?;
+        match &magic[..n] {
+            [b'P', b'K', 3, 4] | [b'P', b'K', 5, 6] => Ok(ArchiveFormat::Zip),
+            [0x1f, 0x8b, ..] => Ok(ArchiveFormat::TarGz),
+            _ => bail!("{} is neither a zip nor a tar.gz archive.", path.display()),
+        }
+    }
+}
+
+/// Extracts the zip or tar.gz archive at `archive` into the directory
+/// `dest`, which is created if needed. Entries that would be written outside
+/// of `dest` (e.g. `../foo`) are rejected.
+pub fn extract_archive(archive: &Path, dest: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dest)
+        .with_context(|| format!("could not create {}", dest.display()))?;
+    let file =
+        File::open(archive).with_context(|| format!("could not open {}", archive.display()))?;
+    match ArchiveFormat::detect(archive)? {
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))
+                .with_context(|| format!("could not read the zip archive {}", archive.display()))?;
+            // `extract` rejects the entries with unsafe paths
+            zip.extract(dest).with_context(|| {
+                format!(
+                    "could not extract {} into {}",
+                    archive.display(),
+                    dest.display()
+                )
+            })?;
+        }
+        ArchiveFormat::TarGz => {
+            let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(BufReader::new(file)));
+            for entry in tar
+                .entries()
+                .with_context(|| format!("could not read the tar archive {}", archive.display()))?
+            {
+                let mut entry = entry?;
+                // `unpack_in` skips the entries with unsafe paths, and
+                // returns false for them
+                let path = entry.path()?.into_owned();
+                if !entry.unpack_in(dest).with_context(|| {
+                    format!(
+                        "could not extract {} from {}",
+                        path.display(),
+                        archive.display()
+                    )
+                })? {
+                    bail!(
+                        "{} holds the entry {}, which is outside of the archive.",
+                        archive.display(),
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a tar.gz archive to `dest` holding each path (a file or a
+/// directory, recursively) of `entries`, under the name it is paired with.
+/// The archive is only moved to `dest` once it is complete.
+pub fn write_tar_gz(entries: &[(PathBuf, PathBuf)], dest: &Path) -> anyhow::Result<()> {
+    let parent = match dest.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let tmp = tempfile::NamedTempFile::new_in(parent)
+        .with_context(|| format!("could not create a file in {}", parent.display()))?;
+    let enc = flate2::write::GzEncoder::new(
+        BufWriter::new(tmp.as_file()),
+        flate2::Compression::default(),
+    );
+    let mut tar = tar::Builder::new(enc);
+    for (name, path) in entries {
+        if path.is_dir() {
+            tar.append_dir_all(name, path)
+        } else {
+            tar.append_path_with_name(path, name)
+        }
+        .with_context(|| format!("could not add {} to {}", path.display(), dest.display()))?;
+    }
+    tar.into_inner()?
+        .finish()?
+        .flush()
+        .with_context(|| format!("could not write {}", dest.display()))?;
+    tmp.persist(dest)
+        .with_context(|| format!("could not write {}", dest.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_archive() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let zip_path = tmp.path().join("reg.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let opts = zip::write::SimpleFileOptions::default();
+        zip.add_directory("reg-main/protocols/", opts).unwrap();
+        zip.start_file("reg-main/protocols/foo.jsonnet", opts)
+            .unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap();
+
+        let tgz_path = tmp.path().join("reg.tar.gz");
+        let enc = flate2::write::GzEncoder::new(
+            File::create(&tgz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(enc);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "reg-main/protocols/foo.jsonnet", &b"{}"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        for (archive, format) in [
+            (&zip_path, ArchiveFormat::Zip),
+            (&tgz_path, ArchiveFormat::TarGz),
+        ] {
+            assert_eq!(ArchiveFormat::detect(archive).unwrap(), format);
+            let dest = tmp.path().join(format!("{:?}", format));
+            extract_archive(archive, &dest).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(dest.join("reg-main/protocols/foo.jsonnet")).unwrap(),
+                "{}"
+            );
+        }
+
+        // the extracted tree can be packed again
+        let repacked = tmp.path().join("repacked.tar.gz");
+        write_tar_gz(
+            &[(
+                PathBuf::from("reg"),
+                tmp.path().join("Zip").join("reg-main"),
+            )],
+            &repacked,
+        )
+        .unwrap();
+        extract_archive(&repacked, &tmp.path().join("repacked")).unwrap();
+        assert!(tmp
+            .path()
+            .join("repacked/reg/protocols/foo.jsonnet")
+            .is_file());
+
+        let not_archive = tmp.path().join("reg.txt");
+        std::fs::write(&not_archive, "not an archive").unwrap();
+        assert!(extract_archive(&not_archive, &tmp.path().join("txt")).is_err());
+    }
+}
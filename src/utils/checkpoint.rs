@@ -0,0 +1,259 @@
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::info;
+
+use crate::utils::run_record::write_json_atomic;
+
+/// Computes the fingerprint of a pipeline stage. The fingerprint is the
+/// blake3 hash of the `settings` that determine the output of the stage
+/// (e.g. its command line), the size and modification time of each of its
+/// `inputs`, and the fingerprint of the `upstream` stage it depends on (if any).
+/// Input files are not hashed in full, since they may be hundreds of gigabytes.
+pub fn stage_fingerprint(
+    settings: &Value,
+    inputs: &[PathBuf],
+    upstream: Option<&str>,
+) -> anyhow::Result<String> {
+    let mut input_info = Vec::with_capacity(inputs.len());
+    for p in inputs {
+        let md = std::fs::metadata(p)
+            .with_context(|| format!("could not read the metadata of {}", p.display()))?;
+        let mtime = md
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        input_info.push(json!({
+            "path" : p,
+            "size" : md.len(),
+            "mtime" : mtime
+        }));
+    }
+
+    let desc = json!({
+        "settings" : settings,
+        "inputs" : input_info,
+        "upstream" : upstream
+    });
+    Ok(blake3::hash(desc.to_string().as_bytes())
+        .to_hex()
+        .to_string())
+}
+
+/// Tracks the completion of the stages of a pipeline in the `stages`
+/// field of a log file. The log is rewritten whenever a stage starts or
+/// completes, so that a run that fails part way through still leaves a
+/// record of the stages that did complete.
+///
+/// When `resume` is set, the stages recorded by a previous run in the same
+/// log file can be skipped if they completed, their fingerprint is unchanged
+/// and their outputs still exist. Once any stage has been (re-)run, all the
+/// stages that follow it are run as well.
+pub struct StageCheckpoints {
+    log_file: PathBuf,
+    resume: bool,
+    prev_stages: Map<String, Value>,
+    stages: Map<String, Value>,
+    skipped: Vec<String>,
+    stage_rerun: bool,
+}
+
+impl StageCheckpoints {
+    pub fn new(log_file: &Path, resume: bool) -> anyhow::Result<Self> {
+        let mut prev_stages = Map::new();
+        if resume && log_file.exists() {
+            let log_content = std::fs::read_to_string(log_file)
+                .with_context(|| format!("could not read {}", log_file.display()))?;
+            let v: Value = serde_json::from_str(&log_content)
+                .with_context(|| format!("could not parse {}", log_file.display()))?;
+            if let Some(Value::Object(stages)) = v.get("stages") {
+                prev_stages = stages.clone();
+            }
+        }
+
+        Ok(Self {
+            log_file: log_file.to_path_buf(),
+            resume,
+            prev_stages,
+            stages: Map::new(),
+            skipped: Vec::new(),
+            stage_rerun: false,
+        })
+    }
+
+    /// Returns the record of `stage` from the previous run if the stage
+    /// can be skipped, and carries that record over to the current run.
+    pub fn try_skip(
+        &mut self,
+        stage: &str,
+        fingerprint: &str,
+        outputs: &[PathBuf],
+    ) -> Option<Value> {
+        if !self.resume || self.stage_rerun {
+            return None;
+        }
+
+        let prev = self.prev_stages.get(stage)?;
+        let completed = prev["completed"].as_bool().unwrap_or(false);
+        if !completed || prev["fingerprint"].as_str() != Some(fingerprint) {
+            return None;
+        }
+        if !outputs.iter().all(|p| p.exists()) {
+            return None;
+        }
+
+        info!(
+            "the {} stage completed in a previous run and its inputs are unchanged; skipping it.",
+            stage
+        );
+        self.stages.insert(stage.to_string(), prev.clone());
+        self.skipped.push(stage.to_string());
+        Some(prev.clone())
+    }
+
+    /// Records that `stage`, with the given fingerprint, is about to run.
+    pub fn start(&mut self, stage: &str, fingerprint: &str) -> anyhow::Result<()> {
+        self.stage_rerun = true;
+        self.stages.insert(
+            stage.to_string(),
+            json!({
+                "completed" : false,
+                "fingerprint" : fingerprint
+            }),
+        );
+        self.write()
+    }
+
+    /// Records that `stage` has completed, along with any extra `info`
+    /// (e.g. the command and its running time) about the stage.
+    pub fn complete(&mut self, stage: &str, info: Value) -> anyhow::Result<()> {
+        let rec = self
+            .stages
+            .entry(stage.to_string())
+            .or_insert_with(|| json!({}));
+        rec["completed"] = json!(true);
+        if let Value::Object(info) = info {
+            for (k, v) in info {
+                rec[k.as_str()] = v;
+            }
+        }
+        self.write()
+    }
+
+    /// The fingerprint recorded for `stage` in the current run.
+    pub fn fingerprint(&self, stage: &str) -> Option<&str> {
+        self.stages
+            .get(stage)
+            .and_then(|v| v["fingerprint"].as_str())
+    }
+
+    /// The stages that were skipped in the current run.
+    pub fn skipped_stages(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// The `stages` record of the current run, to be embedded in the
+    /// final log.
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.stages.clone())
+    }
+
+    fn write(&self) -> anyhow::Result<()> {
+        let v = json!({ "stages" : self.stages });
+        write_json_atomic(&self.log_file, &v)
+    }
+}
+
+/// The running time recorded for a stage in a previous run (as `time_secs`,
+/// like the steps of a run record).
+pub fn stage_time(rec: &Value) -> Duration {
+    rec["time_secs"]
+        .as_f64()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("r1.fq");
+        std::fs::write(&input, "@r\nACGT\n+\nIIII\n").unwrap();
+        let inputs = [input.clone()];
+        let settings = json!({ "min_reads" : 10 });
+        let fp = stage_fingerprint(&settings, &inputs, None).unwrap();
+        assert_eq!(fp, stage_fingerprint(&settings, &inputs, None).unwrap());
+
+        // a setting, the upstream stage, or the size or modification time of an input changes it
+        let other_settings = json!({ "min_reads" : 5 });
+        assert_ne!(
+            fp,
+            stage_fingerprint(&other_settings, &inputs, None).unwrap()
+        );
+        assert_ne!(
+            fp,
+            stage_fingerprint(&settings, &inputs, Some("x")).unwrap()
+        );
+        std::fs::write(&input, "@r\nACGTA\n+\nIIIII\n").unwrap();
+        let resized = stage_fingerprint(&settings, &inputs, None).unwrap();
+        assert_ne!(fp, resized);
+        let f = std::fs::File::options().write(true).open(&input).unwrap();
+        f.set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000))
+            .unwrap();
+        assert_ne!(
+            resized,
+            stage_fingerprint(&settings, &inputs, None).unwrap()
+        );
+
+        // the inputs must exist
+        assert!(stage_fingerprint(&settings, &[dir.path().join("none")], None).is_err());
+    }
+
+    #[test]
+    fn test_try_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log.json");
+        let output = dir.path().join("map.rad");
+        std::fs::write(&output, "").unwrap();
+        let outputs = [output.clone()];
+
+        let mut checkpoints = StageCheckpoints::new(&log, false).unwrap();
+        checkpoints.start("map", "fp1").unwrap();
+        checkpoints
+            .complete("map", json!({ "time_secs" : 1.5 }))
+            .unwrap();
+        checkpoints.start("gpl", "fp2").unwrap();
+
+        // without resume, nothing is skipped
+        let mut checkpoints = StageCheckpoints::new(&log, false).unwrap();
+        assert!(checkpoints.try_skip("map", "fp1", &outputs).is_none());
+
+        // a stage is skipped if it completed with the same fingerprint
+        let mut checkpoints = StageCheckpoints::new(&log, true).unwrap();
+        let prev = checkpoints.try_skip("map", "fp1", &outputs).unwrap();
+        assert_eq!(stage_time(&prev), Duration::from_secs_f64(1.5));
+        assert_eq!(checkpoints.skipped_stages(), ["map"]);
+        assert_eq!(checkpoints.fingerprint("map"), Some("fp1"));
+        // but not if it did not complete
+        assert!(checkpoints.try_skip("gpl", "fp2", &[]).is_none());
+
+        // nor if its fingerprint differs
+        let mut checkpoints = StageCheckpoints::new(&log, true).unwrap();
+        assert!(checkpoints.try_skip("map", "fp0", &outputs).is_none());
+
+        // nor if one of its outputs is missing
+        std::fs::remove_file(&output).unwrap();
+        let mut checkpoints = StageCheckpoints::new(&log, true).unwrap();
+        assert!(checkpoints.try_skip("map", "fp1", &outputs).is_none());
+
+        // once a stage is run, the following ones are run as well
+        std::fs::write(&output, "").unwrap();
+        let mut checkpoints = StageCheckpoints::new(&log, true).unwrap();
+        checkpoints.start("index", "fp").unwrap();
+        assert!(checkpoints.try_skip("map", "fp1", &outputs).is_none());
+    }
+}
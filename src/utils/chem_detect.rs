@@ -0,0 +1,357 @@
+use crate::utils::chem_utils::get_custom_chem_hm;
+use crate::utils::constants::CHEMISTRIES_PATH;
+use crate::utils::prog_utils::get_generic_buf_reader;
+use crate::utils::read_checks::{read_layouts, read_records};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+use tracing::{debug, info};
+
+/// The value of `--chemistry` asking `simpleaf quant` to detect the
+/// chemistry from the reads.
+pub static AUTO_CHEMISTRY: &str = "auto";
+
+/// The number of read pairs sampled from the start of the read files.
+pub const DEFAULT_NUM_SAMPLED_READS: usize = 10_000;
+
+/// The smallest fraction of the sampled barcodes that must be in the permit
+/// list of the chemistry chosen by `--chemistry auto`.
+pub const MIN_AUTO_BARCODE_RATE: f64 = 0.5;
+
+/// The smallest confidence of the chemistry chosen by `--chemistry auto`.
+pub const MIN_AUTO_CONFIDENCE: f64 = 0.6;
+
+/// The geometries of the chemistries that simpleaf knows natively, some of
+/// which are not spelled out in the registry; only their barcode and UMI
+/// layout matters here.
+static BUILTIN_GEOMETRIES: &[(&str, &str)] = &[
+    ("10xv2", "1{b[16]u[10]x:}2{r:}"),
+    ("10xv2-5p", "1{b[16]u[10]x:}2{r:}"),
+    ("10xv3", "1{b[16]u[12]x:}2{r:}"),
+    ("10xv3-5p", "1{b[16]u[12]x:}2{r:}"),
+    ("10xv4-3p", "1{b[16]u[12]x:}2{r:}"),
+];
+
+/// The geometry of the chemistry `name` if simpleaf knows it natively.
+pub fn builtin_geometry(name: &str) -> Option<&'static str> {
+    BUILTIN_GEOMETRIES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, g)| *g)
+}
+
+/// How well the sampled reads fit a registered chemistry.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChemistryScore {
+    pub name: String,
+    pub geometry: String,
+    /// The name of the permit list of the chemistry
+    pub permit_list: Option<String>,
+    /// The fraction of the sampled read pairs whose lengths fit the geometry
+    pub length_rate: f64,
+    /// The fraction of the read pairs fitting the geometry whose barcode is
+    /// in the permit list of the chemistry, if it could be checked
+    pub barcode_rate: Option<f64>,
+    /// Why the barcodes could not be checked, if they were not
+    pub note: Option<String>,
+    /// The share of the score of this chemistry in the total score of all
+    /// the distinct candidates (see [`ChemistryScore::same_candidate`])
+    pub confidence: f64,
+}
+
+impl ChemistryScore {
+    /// The fraction of the sampled reads that fit the geometry and whose
+    /// barcode is in the permit list.
+    pub fn score(&self) -> f64 {
+        self.length_rate * self.barcode_rate.unwrap_or(0.0)
+    }
+
+    /// Whether the reads cannot tell this chemistry and `other` apart, as
+    /// they share their geometry and permit list (e.g. `10xv2` and
+    /// `10xv2-5p`, which only differ in the orientation of the reads).
+    pub fn same_candidate(&self, other: &ChemistryScore) -> bool {
+        self.permit_list.is_some()
+            && self.permit_list == other.permit_list
+            && self.geometry == other.geometry
+    }
+}
+
+/// The registered chemistries, ranked by how well the sampled reads fit
+/// them.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChemistryDetection {
+    pub num_reads: usize,
+    pub ranking: Vec<ChemistryScore>,
+}
+
+impl ChemistryDetection {
+    /// The chemistry to use for `--chemistry auto`: the best ranked one, if
+    /// it is clearly the best and enough of the barcodes are in its permit
+    /// list.
+    pub fn choose(&self) -> anyhow::Result<&ChemistryScore> {
+        let best = match self.ranking.first() {
+            Some(best) if best.score() > 0.0 => best,
+            _ => bail!(
+                "None of the permit lists available in the simpleaf home matches the barcodes of \
+                the reads. The permit lists of the candidate chemistries can be downloaded with \
+                `simpleaf chemistry fetch`; otherwise, please pass the chemistry explicitly."
+            ),
+        };
+        // the chemistries that only differ from the best one in the
+        // orientation of the reads are one candidate, of which the first
+        // ranked (by name) is chosen
+        let (same, tied): (Vec<&ChemistryScore>, Vec<&ChemistryScore>) = self
+            .ranking
+            .iter()
+            .filter(|c| c.score() == best.score())
+            .partition(|c| best.same_candidate(c));
+        if !tied.is_empty() {
+            bail!(
+                "The reads fit the chemistries {} equally well; please pass the chemistry \
+                explicitly.",
+                same.iter()
+                    .chain(tied.iter())
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if same.len() > 1 {
+            info!(
+                "the reads fit the chemistries {} equally well, as they share their geometry and \
+                permit list; choosing {}. If the orientation of the reads matters, please pass \
+                the chemistry explicitly.",
+                same.iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                best.name
+            );
+        }
+        if best.barcode_rate.unwrap_or(0.0) < MIN_AUTO_BARCODE_RATE
+            || best.confidence < MIN_AUTO_CONFIDENCE
+        {
+            bail!(
+                "The chemistry could not be detected confidently: the best candidate, {}, has {:.1}% \
+                of the barcodes in its permit list and a confidence of {:.2}. Run `simpleaf \
+                chemistry detect` to see the ranking, and please pass the chemistry explicitly.",
+                best.name,
+                100.0 * best.barcode_rate.unwrap_or(0.0),
+                best.confidence
+            );
+        }
+        Ok(best)
+    }
+}
+
+/// Counts the barcodes in `barcodes` (with their multiplicity) that are
+/// listed in the permit list file `plist`.
+fn count_listed(plist: &Path, barcodes: &HashMap<&[u8], usize>) -> anyhow::Result<usize> {
+    let mut seen = HashMap::new();
+    for line in get_generic_buf_reader(plist)?.split(b'\n') {
+        let line = line.with_context(|| format!("could not read {}", plist.display()))?;
+        let bc = line.split(|c| *c == b'\t').next().unwrap_or_default();
+        let bc = bc.strip_suffix(b"\r").unwrap_or(bc);
+        if let Some((k, n)) = barcodes.get_key_value(bc) {
+            seen.insert(*k, *n);
+        }
+    }
+    Ok(seen.values().sum())
+}
+
+/// Samples the first `num_reads` read pairs of `reads1` and `reads2`, and
+/// ranks the chemistries of the registry of `af_home` by how well they fit
+/// them: the lengths of the reads must fit the geometry of the chemistry,
+/// and their barcodes should be in its permit list (if it is in
+/// `af_home/plist`; it is not downloaded).
+pub fn detect_chemistry(
+    af_home: &Path,
+    reads1: &Path,
+    reads2: &Path,
+    num_reads: usize,
+) -> anyhow::Result<ChemistryDetection> {
+    let r1: Vec<Vec<u8>> = read_records(reads1, num_reads)?
+        .into_iter()
+        .map(|r| r.seq)
+        .collect();
+    let r2: Vec<Vec<u8>> = read_records(reads2, num_reads)?
+        .into_iter()
+        .map(|r| r.seq)
+        .collect();
+    if r1.len() != r2.len() {
+        bail!(
+            "{} and {} do not have the same number of reads.",
+            reads1.display(),
+            reads2.display()
+        );
+    }
+    if r1.is_empty() {
+        bail!("{} contains no reads.", reads1.display());
+    }
+    let n = r1.len();
+
+    let mut chems: Vec<_> = get_custom_chem_hm(&af_home.join(CHEMISTRIES_PATH))?
+        .into_iter()
+        .collect();
+    chems.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let plist_dir = af_home.join("plist");
+    // chemistries often share their barcode layout and permit list
+    type BarcodeLayout = (Vec<(usize, usize)>, Vec<(usize, usize)>);
+    let mut hits_cache: HashMap<(String, BarcodeLayout), usize> = HashMap::new();
+    let mut ranking = Vec::new();
+    for (name, chem) in chems {
+        let geometry = builtin_geometry(&name)
+            .unwrap_or(chem.geometry())
+            .to_string();
+        let Some([l1, l2]) = read_layouts(&geometry) else {
+            debug!("skipping chemistry {} with geometry {}", name, geometry);
+            continue;
+        };
+        let fitting: Vec<usize> = (0..n)
+            .filter(|&i| l1.fits(r1[i].len()) && l2.fits(r2[i].len()))
+            .collect();
+
+        let plist = chem.plist_name().as_ref().map(|p| plist_dir.join(p));
+        let (barcode_rate, note) = match (&l1.barcode, &l2.barcode, plist) {
+            _ if fitting.is_empty() => (None, None),
+            (_, _, None) => (None, Some("no registered permit list".to_string())),
+            (None, _, _) | (_, None, _) => (
+                None,
+                Some("the barcode is not at a fixed position".to_string()),
+            ),
+            (_, _, Some(p)) if !p.is_file() => {
+                (None, Some("the permit list is not downloaded".to_string()))
+            }
+            (Some(b1), Some(b2), Some(p)) if b1.is_empty() && b2.is_empty() => {
+                debug!("chemistry {} has no barcode ({})", name, p.display());
+                (None, Some("the geometry has no barcode".to_string()))
+            }
+            (Some(b1), Some(b2), Some(p)) => {
+                let key = (
+                    chem.plist_name().clone().unwrap_or_default(),
+                    (b1.clone(), b2.clone()),
+                );
+                let hits = match hits_cache.get(&key) {
+                    Some(h) => *h,
+                    None => {
+                        let bcs: Vec<Vec<u8>> = fitting
+                            .iter()
+                            .map(|&i| {
+                                let pieces1 = b1.iter().map(|&(o, l)| &r1[i][o..o + l]);
+                                let pieces2 = b2.iter().map(|&(o, l)| &r2[i][o..o + l]);
+                                pieces1.chain(pieces2).flatten().copied().collect()
+                            })
+                            .collect();
+                        let mut counts: HashMap<&[u8], usize> = HashMap::new();
+                        for bc in &bcs {
+                            *counts.entry(bc.as_slice()).or_default() += 1;
+                        }
+                        let h = count_listed(&p, &counts)?;
+                        hits_cache.insert(key, h);
+                        h
+                    }
+                };
+                (Some(hits as f64 / fitting.len() as f64), None)
+            }
+        };
+        ranking.push(ChemistryScore {
+            name,
+            geometry,
+            permit_list: chem.plist_name().clone(),
+            length_rate: fitting.len() as f64 / n as f64,
+            barcode_rate,
+            note,
+            confidence: 0.0,
+        });
+    }
+
+    // the chemistries that the reads cannot tell apart count once
+    let total: f64 = ranking
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| !ranking[..*i].iter().any(|o| o.same_candidate(c)))
+        .map(|(_, c)| c.score())
+        .sum();
+    for c in ranking.iter_mut() {
+        c.confidence = if total > 0.0 { c.score() / total } else { 0.0 };
+    }
+    ranking.sort_by(|a, b| {
+        b.score()
+            .total_cmp(&a.score())
+            .then(b.length_rate.total_cmp(&a.length_rate))
+            .then(a.name.cmp(&b.name))
+    });
+    Ok(ChemistryDetection {
+        num_reads: n,
+        ranking,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_chemistry() {
+        let dir = tempfile::tempdir().unwrap();
+        let af_home = dir.path();
+        std::fs::create_dir(af_home.join("plist")).unwrap();
+        std::fs::write(
+            af_home.join(CHEMISTRIES_PATH),
+            r#"{
+                "short": { "geometry": "1{b[4]u[2]x:}2{r:}", "plist_name": "short_pl" },
+                "long": { "geometry": "1{b[4]u[4]x:}2{r:}", "plist_name": "long_pl" },
+                "long-5p": { "geometry": "1{b[4]u[4]x:}2{r:}", "plist_name": "long_pl" },
+                "other": { "geometry": "1{b[4]u[4]x:}2{r:}", "plist_name": "other_pl" },
+                "missing": { "geometry": "1{b[4]u[4]x:}2{r:}", "plist_name": "missing_pl" },
+                "atac": { "geometry": "__builtin" }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(af_home.join("plist/short_pl"), "AAAA\n").unwrap();
+        std::fs::write(af_home.join("plist/long_pl"), "AAAA\tx\nCCCC\tx\nGGGG\tx\n").unwrap();
+        std::fs::write(af_home.join("plist/other_pl"), "TTTT\n").unwrap();
+
+        // 8bp read 1s, 3 of 4 barcodes being in the permit list of `long`
+        let fastq = |seqs: &[&str]| {
+            seqs.iter()
+                .enumerate()
+                .map(|(i, s)| format!("@r{}\n{}\n+\n{}\n", i, s, "I".repeat(s.len())))
+                .collect::<String>()
+        };
+        let (r1, r2) = (af_home.join("r1.fq"), af_home.join("r2.fq"));
+        std::fs::write(
+            &r1,
+            fastq(&["AAAATTGG", "CCCCTTGG", "GGGGTTGG", "TTAGTTGG"]),
+        )
+        .unwrap();
+        std::fs::write(&r2, fastq(&["ACGTACGTAC"; 4])).unwrap();
+
+        let detection = detect_chemistry(af_home, &r1, &r2, 100).unwrap();
+        assert_eq!(detection.num_reads, 4);
+        let names: Vec<&str> = detection.ranking.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["long", "long-5p", "short", "missing", "other"]);
+        let best = &detection.ranking[0];
+        assert_eq!(best.barcode_rate, Some(0.75));
+        assert!((best.confidence - 0.75).abs() < 1e-9);
+        // `long-5p` shares the geometry and permit list of `long`, so the
+        // two are one candidate
+        assert!(best.same_candidate(&detection.ranking[1]));
+        assert!((detection.ranking[1].confidence - 0.75).abs() < 1e-9);
+        assert_eq!(detection.choose().unwrap().name, "long");
+        assert_eq!(
+            detection.ranking[3].note.as_deref(),
+            Some("the permit list is not downloaded")
+        );
+
+        // reads too short for any of the geometries
+        std::fs::write(&r1, fastq(&["AAAA"; 4])).unwrap();
+        let detection = detect_chemistry(af_home, &r1, &r2, 100).unwrap();
+        assert!(detection.ranking.iter().all(|c| c.length_rate == 0.0));
+        assert!(detection.choose().is_err());
+    }
+}
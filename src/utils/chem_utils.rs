@@ -43,6 +43,7 @@ impl std::fmt::Display for ExpectedOri {
 }
 
 impl ExpectedOri {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> ExpectedOri {
         ExpectedOri::Both
     }
@@ -57,6 +58,7 @@ impl ExpectedOri {
     }
 
     // construct the `ExpectedOri` from a str
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<ExpectedOri> {
         match s {
             "fw" => Ok(ExpectedOri::Forward),
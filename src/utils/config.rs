@@ -0,0 +1,798 @@
+use crate::atac::commands::AtacCommand;
+use crate::simpleaf_commands::Commands;
+use crate::utils::home::SimpleafHome;
+use crate::utils::run_record::{EffectiveOptions, OptionSource, OptionValue};
+use crate::Cli;
+
+use anyhow::{anyhow, bail, Context};
+use clap::error::{ContextKind, ContextValue};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use toml_edit::{DocumentMut, Item, TableLike};
+use tracing::warn;
+
+static SIMPLEAF_CONFIG: OnceLock<SimpleafConfig> = OnceLock::new();
+
+/// The sections of `simpleaf.toml`, and the (sub)command whose options each
+/// of them sets.
+pub static SECTIONS: &[(&str, &[&str])] = &[
+    ("index", &["index"]),
+    ("quant", &["quant"]),
+    ("atac.index", &["atac", "index"]),
+    ("atac.process", &["atac", "process"]),
+];
+
+/// An option set in `simpleaf.toml`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigEntry {
+    /// The section of the option, e.g. `atac.process`
+    pub section: &'static str,
+    /// The long name of the option, e.g. `min-reads`
+    pub option: String,
+    /// The (raw) values the option defaults to
+    pub values: Vec<String>,
+    /// The value as written in the file
+    pub repr: String,
+}
+
+impl ConfigEntry {
+    /// The key of the option, as used by `simpleaf config`, e.g. `quant.min-reads`.
+    pub fn key(&self) -> String {
+        format!("{}.{}", self.section, self.option)
+    }
+}
+
+/// The `simpleaf.toml` file of the simpleaf home, whose `[index]`, `[quant]`,
+/// `[atac.index]` and `[atac.process]` sections replace the defaults of the options of the
+/// corresponding commands. Options given on the command line (or in a
+/// workflow) still take precedence over it.
+#[derive(Clone, Debug, Default)]
+pub struct SimpleafConfig {
+    /// The file the configuration is read from and written to, if any
+    path: Option<PathBuf>,
+    doc: DocumentMut,
+    entries: Vec<ConfigEntry>,
+}
+
+/// The simpleaf command line, with the global options propagated to the
+/// subcommands.
+fn cli_command() -> Command {
+    let mut cmd = Cli::command();
+    cmd.build();
+    cmd
+}
+
+/// The (sub)command whose options are set by `section`.
+fn section_command<'a>(cmd: &'a Command, section: &str) -> Option<&'a Command> {
+    let (_, path) = SECTIONS.iter().find(|(name, _)| *name == section)?;
+    path.iter()
+        .try_fold(cmd, |cmd, name| cmd.find_subcommand(name))
+}
+
+/// Replaces the subcommand at `path` of `cmd` with `f` applied to it.
+fn mut_section(cmd: Command, path: &[&str], f: &mut dyn FnMut(Command) -> Command) -> Command {
+    match path.split_first() {
+        None => f(cmd),
+        Some((name, rest)) => cmd.mut_subcommand(name, |sub| mut_section(sub, rest, f)),
+    }
+}
+
+fn is_flag(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse)
+}
+
+/// Converts between the values a flag is set to in the configuration (whether
+/// it is given) and the values it is stored as (which are the opposite for
+/// flags such as `--no-piscem`).
+fn flag_values(arg: &Arg, values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .map(|v| match (arg.get_action(), v.as_str()) {
+            (ArgAction::SetFalse, "true") => "false".to_string(),
+            (ArgAction::SetFalse, "false") => "true".to_string(),
+            _ => v.clone(),
+        })
+        .collect()
+}
+
+/// The built-in default values of `arg`, as they are set in the configuration.
+fn arg_defaults(arg: &Arg) -> Vec<String> {
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|v| v.to_string_lossy().into_owned())
+        .collect();
+    flag_values(arg, &defaults)
+}
+
+fn takes_many(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+        || arg.get_num_args().is_some_and(|r| r.max_values() > 1)
+}
+
+/// Whether `arg` is a flag that belongs to an exclusive group of `cmd` or conflicts with
+/// other options. Such a flag cannot be set in `simpleaf.toml`: the conflicts
+/// are not checked against the defaults, and the flag could not be cleared
+/// on the command line to give one of the other options instead.
+fn is_exclusive_flag(cmd: &Command, arg: &Arg) -> bool {
+    let id = arg.get_id();
+    is_flag(arg)
+        && (cmd
+            .get_groups()
+            // the derived groups of the option structs take many of their options
+            .any(|g| !g.clone().is_multiple() && g.get_args().any(|a| a == id))
+            || cmd.get_arguments().any(|other| {
+                cmd.get_arg_conflicts_with(other)
+                    .iter()
+                    .any(|a| a.get_id() == id)
+            })
+            || !cmd.get_arg_conflicts_with(arg).is_empty())
+}
+
+/// The options of `cmd` that can be set in `simpleaf.toml`: those with a
+/// long name and a default value (including the flags, but those that
+/// exclude other options).
+fn configurable_args(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments().filter(|arg| {
+        arg.get_long().is_some()
+            && !arg.is_global_set()
+            && !arg.get_default_values().is_empty()
+            && !is_exclusive_flag(cmd, arg)
+    })
+}
+
+/// The option `name` (a long name, in which `_` may replace `-`) of the
+/// command of `section`.
+fn find_arg<'a>(cmd: &'a Command, section: &str, name: &str) -> anyhow::Result<&'a Arg> {
+    let long = name.replace('_', "-");
+    if let Some(arg) = configurable_args(cmd).find(|arg| arg.get_long() == Some(long.as_str())) {
+        return Ok(arg);
+    }
+    if cmd
+        .get_arguments()
+        .any(|arg| arg.get_long() == Some(long.as_str()) && is_exclusive_flag(cmd, arg))
+    {
+        bail!(
+            "`{}` is a flag of `simpleaf {}` that excludes other options, so it cannot be \
+            set in simpleaf.toml; give it on the command line instead",
+            name,
+            section.replace('.', " ")
+        );
+    }
+    bail!(
+        "`{}` is not an option of `simpleaf {}` with a default value; run \
+        `simpleaf config list --all` to see the options that can be set",
+        name,
+        section.replace('.', " ")
+    )
+}
+
+/// Checks that `values` are valid values of `arg`.
+fn check_values(arg: &Arg, values: &[String]) -> anyhow::Result<()> {
+    let long = arg.get_long().unwrap_or_default();
+    if values.len() != 1 && !takes_many(arg) {
+        bail!("`{}` takes a single value", long);
+    }
+    // the values are parsed as those of a lone argument with the same parser
+    let checker = Command::new("simpleaf").no_binary_name(true).arg(
+        Arg::new(arg.get_id().clone())
+            .value_parser(arg.get_value_parser().clone())
+            .allow_hyphen_values(true),
+    );
+    for v in values {
+        if let Err(e) = checker.clone().try_get_matches_from([v]) {
+            let detail = match (e.source(), e.get(ContextKind::ValidValue)) {
+                (Some(source), _) => source.to_string(),
+                (None, Some(ContextValue::Strings(valid))) => {
+                    format!("the possible values are {}", valid.join(", "))
+                }
+                _ => e.kind().to_string(),
+            };
+            bail!("invalid value `{}` for `{}`: {}", v, long, detail);
+        }
+    }
+    Ok(())
+}
+
+/// The raw values of a scalar, or of an array of scalars.
+fn raw_values(item: &Item) -> Option<Vec<String>> {
+    let scalar = |v: &toml_edit::Value| match v {
+        toml_edit::Value::String(s) => Some(s.value().clone()),
+        toml_edit::Value::Integer(i) => Some(i.value().to_string()),
+        toml_edit::Value::Float(f) => Some(f.value().to_string()),
+        toml_edit::Value::Boolean(b) => Some(b.value().to_string()),
+        _ => None,
+    };
+    match item.as_value()? {
+        toml_edit::Value::Array(a) => a.iter().map(scalar).collect(),
+        v => scalar(v).map(|s| vec![s]),
+    }
+}
+
+/// The TOML value of the raw `values` of `arg`, typed after them.
+fn toml_value(arg: &Arg, values: &[String]) -> toml_edit::Value {
+    let scalar = |v: &String| -> toml_edit::Value {
+        if let Ok(b) = v.parse::<bool>() {
+            b.into()
+        } else if let Ok(i) = v.parse::<i64>() {
+            i.into()
+        } else if let Some(f) = v.parse::<f64>().ok().filter(|f| f.is_finite()) {
+            f.into()
+        } else {
+            v.as_str().into()
+        }
+    };
+    if takes_many(arg) {
+        toml_edit::Value::Array(values.iter().map(scalar).collect())
+    } else {
+        scalar(&values[0])
+    }
+}
+
+/// The effective options of a run of `cmd`, given its `matches`; the
+/// options set in `section` come from the configuration.
+fn effective_options(
+    cmd: &Command,
+    matches: &ArgMatches,
+    section: &str,
+    config: &[ConfigEntry],
+) -> EffectiveOptions {
+    let mut options = EffectiveOptions::new();
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        if arg.is_global_set() || matches!(id, "help" | "version") {
+            continue;
+        }
+        let name = arg.get_long().unwrap_or(id);
+        let source = match matches.value_source(id) {
+            Some(ValueSource::DefaultValue)
+                if config
+                    .iter()
+                    .any(|e| e.section == section && e.option == name) =>
+            {
+                OptionSource::Config
+            }
+            Some(ValueSource::DefaultValue) => OptionSource::Default,
+            Some(_) => OptionSource::CommandLine,
+            None => continue,
+        };
+        let raw: Vec<String> = matches
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect();
+        let value = if is_flag(arg) {
+            json!(flag_values(arg, &raw).first().is_some_and(|v| v == "true"))
+        } else if takes_many(arg) {
+            json!(raw)
+        } else {
+            raw.into_iter().next().map_or(Value::Null, Value::String)
+        };
+        options.insert(name.to_string(), OptionValue { value, source });
+    }
+    options
+}
+
+impl SimpleafConfig {
+    /// Reads the `simpleaf.toml` file of `home`; the configuration is empty
+    /// if the file does not exist. It fails if any of the options in the
+    /// file is invalid.
+    pub fn load(home: &SimpleafHome) -> anyhow::Result<Self> {
+        let (config, problems) = Self::read(home)?;
+        match problems.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(config),
+        }
+    }
+
+    /// Reads the `simpleaf.toml` file of `home` as [`load`](Self::load)
+    /// does, but skips (with a warning) the invalid sections and options, so
+    /// that they can still be fixed with `simpleaf config`. It only fails if
+    /// the file cannot be read or is not valid TOML.
+    pub fn load_lossy(home: &SimpleafHome) -> anyhow::Result<Self> {
+        let (config, problems) = Self::read(home)?;
+        for e in problems {
+            warn!("{:#}; it is ignored.", e);
+        }
+        Ok(config)
+    }
+
+    /// Reads the `simpleaf.toml` file of `home`, along with the problems
+    /// with its sections and options.
+    fn read(home: &SimpleafHome) -> anyhow::Result<(Self, Vec<anyhow::Error>)> {
+        let path = home.config_path();
+        let doc = if path.exists() {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("could not read {}", path.display()))?
+                .parse::<DocumentMut>()
+                .with_context(|| format!("could not parse {}", path.display()))?
+        } else {
+            DocumentMut::new()
+        };
+        let mut config = SimpleafConfig {
+            path: Some(path),
+            doc,
+            entries: Vec::new(),
+        };
+        let (entries, problems) = config.read_entries();
+        config.entries = entries;
+        Ok((config, problems))
+    }
+
+    /// The path of the file of this configuration, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Sets the configuration of this run. It can only be set once.
+    pub fn set_global(config: SimpleafConfig) {
+        if SIMPLEAF_CONFIG.set(config).is_err() {
+            tracing::warn!("the simpleaf configuration was already set; ignoring the new one.");
+        }
+    }
+
+    /// The configuration of this run, which is empty unless it was set (e.g.
+    /// when simpleaf is used as a library).
+    pub fn global() -> &'static SimpleafConfig {
+        SIMPLEAF_CONFIG.get_or_init(SimpleafConfig::default)
+    }
+
+    /// The options set in the configuration, in the order of the file.
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// Reads and checks the options set in the file; the invalid sections
+    /// and options are left out of the entries, and returned as problems.
+    fn read_entries(&self) -> (Vec<ConfigEntry>, Vec<anyhow::Error>) {
+        let context = || {
+            format!(
+                "invalid configuration in {}",
+                self.path
+                    .as_deref()
+                    .unwrap_or(Path::new("simpleaf.toml"))
+                    .display()
+            )
+        };
+        let mut problems = Vec::new();
+        // every table must be one of the sections, or hold some of them
+        for (key, item) in self.doc.iter() {
+            if SECTIONS.iter().any(|(name, _)| *name == key) {
+                continue;
+            }
+            let nested: Vec<&str> = SECTIONS
+                .iter()
+                .filter_map(|(name, _)| name.strip_prefix(key)?.strip_prefix('.'))
+                .collect();
+            match item.as_table_like() {
+                Some(table) if !nested.is_empty() => {
+                    for (sub, _) in table.iter().filter(|(sub, _)| !nested.contains(sub)) {
+                        problems.push(anyhow!("{}: unknown section [{}.{}]", context(), key, sub));
+                    }
+                }
+                _ => problems.push(anyhow!(
+                    "{}: unknown section [{}]; the sections are {}",
+                    context(),
+                    key,
+                    SECTIONS
+                        .iter()
+                        .map(|(name, _)| format!("[{}]", name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            }
+        }
+
+        let cli = cli_command();
+        let mut entries = Vec::new();
+        for (section, _) in SECTIONS {
+            let Some(item) = section
+                .split('.')
+                .try_fold(self.doc.as_item(), |item, name| {
+                    item.as_table_like()?.get(name)
+                })
+            else {
+                continue;
+            };
+            let Some(table) = item.as_table_like() else {
+                problems.push(anyhow!("{}: [{}] is not a table", context(), section));
+                continue;
+            };
+            let cmd = section_command(&cli, section).expect("every section has a command");
+            for (key, item) in table.iter() {
+                let entry = find_arg(cmd, section, key).and_then(|arg| {
+                    let values = raw_values(item).with_context(|| {
+                        format!(
+                            "`{}.{}` should be a string, number or boolean, or an array of them",
+                            section, key
+                        )
+                    })?;
+                    check_values(arg, &values)
+                        .with_context(|| format!("invalid `{}.{}`", section, key))?;
+                    Ok(ConfigEntry {
+                        section,
+                        option: arg.get_long().unwrap_or_default().to_string(),
+                        values,
+                        repr: item.to_string().trim().to_string(),
+                    })
+                });
+                match entry.with_context(context) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => problems.push(e),
+                }
+            }
+        }
+        (entries, problems)
+    }
+
+    /// Splits `key` (e.g. `quant.min-reads`) into its section and option.
+    fn split_key(key: &str) -> anyhow::Result<(&'static str, &str)> {
+        if let Some((section, option)) = key.rsplit_once('.') {
+            if let Some((name, _)) = SECTIONS.iter().find(|(name, _)| *name == section) {
+                return Ok((name, option));
+            }
+        }
+        bail!(
+            "`{}` should be given as <SECTION>.<OPTION>, where <SECTION> is one of {} (e.g. quant.threads)",
+            key,
+            SECTIONS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    /// The entry of `key`, if it is set.
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<&ConfigEntry>> {
+        let (section, option) = Self::split_key(key)?;
+        let cli = cli_command();
+        let cmd = section_command(&cli, section).expect("every section has a command");
+        let long = find_arg(cmd, section, option)?
+            .get_long()
+            .unwrap_or_default();
+        Ok(self
+            .entries
+            .iter()
+            .find(|e| e.section == section && e.option == long))
+    }
+
+    /// The built-in default values of `key`.
+    pub fn default_values(key: &str) -> anyhow::Result<Vec<String>> {
+        let (section, option) = Self::split_key(key)?;
+        let cli = cli_command();
+        let cmd = section_command(&cli, section).expect("every section has a command");
+        Ok(arg_defaults(find_arg(cmd, section, option)?))
+    }
+
+    /// Sets `key` to `values`, after checking that they are valid.
+    pub fn set(&mut self, key: &str, values: &[String]) -> anyhow::Result<()> {
+        let (section, option) = Self::split_key(key)?;
+        let cli = cli_command();
+        let cmd = section_command(&cli, section).expect("every section has a command");
+        let arg = find_arg(cmd, section, option)?;
+        check_values(arg, values)?;
+        let long = arg.get_long().unwrap_or_default();
+
+        let mut table: &mut dyn TableLike = self.doc.as_table_mut();
+        for name in section.split('.') {
+            if table.get(name).is_none() {
+                let mut t = toml_edit::Table::new();
+                t.set_implicit(true);
+                table.insert(name, Item::Table(t));
+            }
+            table = table
+                .get_mut(name)
+                .and_then(Item::as_table_like_mut)
+                .with_context(|| format!("[{}] is not a table", section))?;
+        }
+        // the option may have been written with `_` instead of `-`
+        table.remove(&long.replace('-', "_"));
+        table.insert(long, Item::Value(toml_value(arg, values)));
+        self.entries = self.read_entries().0;
+        Ok(())
+    }
+
+    /// Removes `key`, even if its value is invalid; returns whether it was set.
+    pub fn unset(&mut self, key: &str) -> anyhow::Result<bool> {
+        let (section, option) = Self::split_key(key)?;
+        let cli = cli_command();
+        let cmd = section_command(&cli, section).expect("every section has a command");
+        let long = find_arg(cmd, section, option)?
+            .get_long()
+            .unwrap_or_default();
+        let table = section.split('.').try_fold(
+            self.doc.as_table_mut() as &mut dyn TableLike,
+            |table, name| table.get_mut(name)?.as_table_like_mut(),
+        );
+        let Some(table) = table else {
+            return Ok(false);
+        };
+        let removed = table.remove(long).is_some();
+        let removed = table.remove(&long.replace('-', "_")).is_some() || removed;
+        self.entries = self.read_entries().0;
+        Ok(removed)
+    }
+
+    /// Writes the configuration to its file.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            bail!("this configuration has no file to be written to");
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("could not create {}", dir.display()))?;
+        }
+        std::fs::write(path, self.doc.to_string())
+            .with_context(|| format!("could not write {}", path.display()))
+    }
+
+    /// Every option that can be set, along with its value in the
+    /// configuration (if set) or its built-in default.
+    pub fn all_options(&self) -> Vec<(String, String, bool)> {
+        let cli = cli_command();
+        let mut options = Vec::new();
+        for (section, _) in SECTIONS {
+            let cmd = section_command(&cli, section).expect("every section has a command");
+            for arg in configurable_args(cmd) {
+                let long = arg.get_long().unwrap_or_default();
+                let key = format!("{}.{}", section, long);
+                match self
+                    .entries
+                    .iter()
+                    .find(|e| e.section == *section && e.option == long)
+                {
+                    Some(e) => options.push((key, e.repr.clone(), true)),
+                    None => {
+                        let defaults = arg_defaults(arg);
+                        options.push((key, toml_value(arg, &defaults).to_string(), false));
+                    }
+                }
+            }
+        }
+        options
+    }
+
+    /// The simpleaf command line, with the defaults of the options taken
+    /// from this configuration. It is not built, so that it can still be changed.
+    fn configured_command(&self) -> Command {
+        // the arguments are looked up in the built command, but can only be
+        // changed before it is built
+        let built = cli_command();
+        let mut cmd = Cli::command();
+        for entry in &self.entries {
+            let (_, path) = SECTIONS
+                .iter()
+                .find(|(name, _)| *name == entry.section)
+                .expect("every entry has a section");
+            let arg = section_command(&built, entry.section)
+                .and_then(|sub| find_arg(sub, entry.section, &entry.option).ok())
+                .expect("the entries are checked when read");
+            let (id, values) = (arg.get_id().to_string(), flag_values(arg, &entry.values));
+            cmd = mut_section(cmd, path, &mut |sub| {
+                sub.mut_arg(&id, |arg| arg.default_values(values.clone()))
+            });
+        }
+        cmd
+    }
+
+    /// Records the effective options of the commands of `cli` that write a
+    /// run record, given the `matches` of the (built) `cmd` it was parsed from.
+    fn record_effective_options(&self, cmd: &Command, matches: &ArgMatches, cli: &mut Cli) {
+        let options = |section: &str| {
+            let (_, path) = SECTIONS.iter().find(|(name, _)| *name == section)?;
+            let sub_matches = path
+                .iter()
+                .try_fold(matches, |m, name| m.subcommand_matches(name))?;
+            let sub_cmd = section_command(cmd, section)?;
+            Some(effective_options(
+                sub_cmd,
+                sub_matches,
+                section,
+                &self.entries,
+            ))
+        };
+        match &mut cli.command {
+            Commands::Index(opts) => opts.effective_options = options("index").unwrap_or_default(),
+            Commands::Quant(opts) => opts.effective_options = options("quant").unwrap_or_default(),
+            Commands::Atac(AtacCommand::Index(opts)) => {
+                opts.effective_options = options("atac.index").unwrap_or_default()
+            }
+            Commands::Atac(AtacCommand::Process(opts)) => {
+                opts.effective_options = options("atac.process").unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+
+    /// Parses the simpleaf command line `args`, taking the defaults of the
+    /// options from this configuration, and records the effective options
+    /// of the commands writing a run record.
+    pub fn parse_cli<I, T>(&self, args: I) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut cmd = self.configured_command();
+        cmd.build();
+
+        let matches = cmd.try_get_matches_from_mut(args)?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        self.record_effective_options(&cmd, &matches, &mut cli);
+        Ok(cli)
+    }
+
+    /// The options of the command of `section` before any of them is given,
+    /// with the defaults taken from this configuration (e.g. for the builders
+    /// of the [`api`](crate::api), which then set the options directly). The
+    /// options that have no default but must be given are set to a placeholder;
+    /// their long names are returned along with the command, so that the caller
+    /// can check that it replaced them.
+    pub fn section_defaults(&self, section: &str) -> anyhow::Result<(Commands, Vec<String>)> {
+        let Some((_, path)) = SECTIONS.iter().find(|(name, _)| *name == section) else {
+            bail!("there is no section [{}]", section);
+        };
+        let built = cli_command();
+        let sub = section_command(&built, section).expect("every section has a command");
+        let placeholders: Vec<(String, String, String)> = sub
+            .get_arguments()
+            .filter(|arg| arg.is_required_set() && arg.get_default_values().is_empty())
+            .map(|arg| {
+                let value = arg
+                    .get_possible_values()
+                    .first()
+                    .map_or("-".to_string(), |v| v.get_name().to_string());
+                let id = arg.get_id().to_string();
+                (id.clone(), arg.get_long().unwrap_or(&id).to_string(), value)
+            })
+            .collect();
+
+        // the constraints between the options are not checked, since none of them is given
+        let mut cmd = mut_section(self.configured_command(), path, &mut |mut sub| {
+            for (id, _, value) in &placeholders {
+                sub = sub.mut_arg(id, |arg| arg.required(false).default_value(value.clone()));
+            }
+            sub.ignore_errors(true)
+        })
+        .ignore_errors(true);
+        cmd.build();
+
+        let argv = std::iter::once("simpleaf").chain(path.iter().copied());
+        let matches = cmd
+            .try_get_matches_from_mut(argv)
+            .with_context(|| format!("could not get the defaults of [{}]", section))?;
+        let mut cli = Cli::from_arg_matches(&matches)
+            .with_context(|| format!("could not get the defaults of [{}]", section))?;
+        self.record_effective_options(&cmd, &matches, &mut cli);
+        Ok((
+            cli.command,
+            placeholders.into_iter().map(|(_, long, _)| long).collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = SimpleafHome::single_dir(
+            dir.path().to_path_buf(),
+            crate::utils::home::HomeSource::Cli,
+        );
+        let mut config = SimpleafConfig::load(&home).unwrap();
+        assert!(config.entries().is_empty());
+
+        config.set("quant.threads", &["4".to_string()]).unwrap();
+        config
+            .set("atac.process.min_reads", &["25".to_string()])
+            .unwrap();
+        assert!(config.set("quant.threads", &["four".to_string()]).is_err());
+        assert!(config
+            .set("quant.no-such-option", &["1".to_string()])
+            .is_err());
+        assert!(config.set("report.threads", &["1".to_string()]).is_err());
+        config.save().unwrap();
+
+        let text = std::fs::read_to_string(home.config_path()).unwrap();
+        assert!(text.contains("[quant]\nthreads = 4"));
+        assert!(text.contains("[atac.process]\nmin-reads = 25"));
+
+        let mut config = SimpleafConfig::load(&home).unwrap();
+        assert_eq!(config.get("quant.threads").unwrap().unwrap().values, ["4"]);
+
+        // the configuration replaces the defaults, but not the options given on the command line
+        let args = "simpleaf quant -c 10xv3 -o out -i idx -1 r1.fq -2 r2.fq -r cr-like --knee \
+            -m t2g.tsv --min-reads 5";
+        let quant_opts = |config: &SimpleafConfig| match config
+            .parse_cli(args.split_whitespace())
+            .unwrap()
+            .command
+        {
+            Commands::Quant(opts) => opts,
+            _ => panic!("not a quant command"),
+        };
+        let opts = quant_opts(&config);
+        assert_eq!(opts.threads, 4);
+        assert_eq!(opts.min_reads, 5);
+        assert!(opts.use_piscem);
+        let options = &opts.effective_options;
+        assert_eq!(options["threads"].source, OptionSource::Config);
+        assert_eq!(options["min-reads"].source, OptionSource::CommandLine);
+        assert_eq!(options["min-reads"].value, json!("5"));
+        assert_eq!(options["knee"].value, json!(true));
+        assert_eq!(options["resume"].source, OptionSource::Default);
+
+        // a flag set to `true` is given
+        config
+            .set("quant.anndata-out", &["true".to_string()])
+            .unwrap();
+        let opts = quant_opts(&config);
+        assert!(opts.anndata_out);
+        assert_eq!(
+            opts.effective_options["anndata-out"].source,
+            OptionSource::Config
+        );
+
+        // but not the flags excluding other options, which could not be cleared
+        for key in ["quant.knee", "quant.no-piscem", "quant.ignore-ambig-hits"] {
+            let err = config.set(key, &["true".to_string()]).unwrap_err();
+            assert!(
+                err.to_string().contains("excludes other options"),
+                "{}",
+                key
+            );
+        }
+        std::fs::write(home.config_path(), "[quant]\nknee = true\n").unwrap();
+        assert!(SimpleafConfig::load(&home).is_err());
+
+        std::fs::write(home.config_path(), "[quant]\nthreads = \"many\"\n").unwrap();
+        assert!(SimpleafConfig::load(&home).is_err());
+        std::fs::write(home.config_path(), "[report]\nthreads = 1\n").unwrap();
+        assert!(SimpleafConfig::load(&home).is_err());
+    }
+
+    #[test]
+    fn test_unset_invalid_config() {
+        use crate::simpleaf_commands::config::unset_config;
+        use crate::simpleaf_commands::ConfigUnsetOpts;
+
+        let dir = tempfile::tempdir().unwrap();
+        let home = SimpleafHome::single_dir(
+            dir.path().to_path_buf(),
+            crate::utils::home::HomeSource::Cli,
+        );
+        std::fs::write(
+            home.config_path(),
+            "[quant]\nthreads = \"many\"\nmin_reads = 5\n",
+        )
+        .unwrap();
+        assert!(SimpleafConfig::load(&home).is_err());
+        // the valid options are still read
+        let config = SimpleafConfig::load_lossy(&home).unwrap();
+        assert_eq!(config.entries().len(), 1);
+        assert_eq!(config.entries()[0].option, "min-reads");
+
+        unset_config(
+            &home,
+            ConfigUnsetOpts {
+                key: "quant.threads".to_string(),
+            },
+        )
+        .unwrap();
+        let config = SimpleafConfig::load(&home).unwrap();
+        assert!(config.get("quant.threads").unwrap().is_none());
+        assert_eq!(
+            config.get("quant.min-reads").unwrap().unwrap().values,
+            ["5"]
+        );
+    }
+}
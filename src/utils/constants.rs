@@ -9,4 +9,15 @@ pub(crate) static CHEMISTRIES_PATH: &str = "chemistries.json";
 pub(crate) static CHEMISTRIES_URL: &str =
     "https://raw.githubusercontent.com/COMBINE-lab/simpleaf/dev/resources/chemistries.json";
 
+/// The file, in the configuration directory of the simpleaf home, recording
+/// the programs, executor, network settings and workflow registries to use.
+pub(crate) static SIMPLEAF_INFO_PATH: &str = "simpleaf_info.json";
+/// The file, in the configuration directory of the simpleaf home, giving the
+/// defaults of the options of `simpleaf index`, `quant` and `atac process`.
+pub(crate) static SIMPLEAF_CONFIG_PATH: &str = "simpleaf.toml";
+
 pub(crate) static NUM_SAMPLE_LINES: usize = 100;
+
+/// The directory, under the output directory of a command, holding the
+/// stdout and stderr of each of the external programs it runs.
+pub(crate) static LOGS_DIR: &str = "logs";
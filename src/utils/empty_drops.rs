@@ -0,0 +1,581 @@
+//! EmptyDrops-style cell calling (Lun et al., 2019, Genome Biology 20:63):
+//! the barcodes with few UMIs are taken to be empty droplets, whose counts
+//! make up the ambient RNA profile, and every other barcode is tested for a
+//! significant deviation from that profile with a Monte-Carlo multinomial
+//! test.
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The parameters of the cell calling.
+#[derive(Clone, Debug)]
+pub struct EmptyDropsParams {
+    /// Barcodes with at most this many UMIs are taken to be empty droplets
+    pub lower: u64,
+    /// The number of Monte-Carlo iterations
+    pub niters: usize,
+    /// The false discovery rate below which a barcode is called a cell
+    pub fdr: f64,
+    /// Barcodes with at least this many UMIs are called cells without being
+    /// tested
+    pub retain: Option<u64>,
+    /// The seed of the random number generator
+    pub seed: u64,
+    pub threads: usize,
+}
+
+impl Default for EmptyDropsParams {
+    fn default() -> Self {
+        EmptyDropsParams {
+            lower: 100,
+            niters: 10_000,
+            fdr: 0.001,
+            retain: None,
+            seed: 1,
+            threads: 1,
+        }
+    }
+}
+
+/// A small, fast random number generator (xoshiro256**, seeded with
+/// splitmix64), so that the Monte-Carlo iterations are reproducible from
+/// their seed.
+pub(crate) struct Rng {
+    s: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed;
+        let mut splitmix = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Rng {
+            s: [splitmix(), splitmix(), splitmix(), splitmix()],
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        result
+    }
+
+    /// A uniform draw in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// A uniform draw in [0, n).
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+/// Walker's alias table, to draw from a discrete distribution in constant
+/// time.
+struct AliasTable {
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+}
+
+impl AliasTable {
+    fn new(probs: &[f64]) -> Self {
+        let n = probs.len();
+        let total: f64 = probs.iter().sum();
+        let mut scaled: Vec<f64> = probs.iter().map(|p| p * n as f64 / total).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|&i| scaled[i] < 1.0);
+        let mut prob = vec![1.0; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        AliasTable { prob, alias }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> usize {
+        let i = rng.below(self.prob.len());
+        if rng.next_f64() < self.prob[i] {
+            i
+        } else {
+            self.alias[i]
+        }
+    }
+}
+
+/// The UMI counts of the barcodes of a count matrix.
+pub struct CountMatrix {
+    pub barcodes: Vec<String>,
+    pub num_genes: usize,
+    /// The (gene, count) pairs of each barcode, with counts rounded to the
+    /// nearest integer
+    pub counts: Vec<Vec<(u32, u64)>>,
+}
+
+impl CountMatrix {
+    /// Reads the alevin-fry count matrix `mtx_path` (in MatrixMarket
+    /// coordinate format, with one row per barcode) and its barcodes
+    /// `rows_path`. In USA mode, the spliced, unspliced and ambiguous counts
+    /// of each gene are added up.
+    pub fn read(mtx_path: &Path, rows_path: &Path, usa_mode: bool) -> anyhow::Result<Self> {
+        let barcodes: Vec<String> = BufReader::new(
+            std::fs::File::open(rows_path)
+                .with_context(|| format!("Could not open {}", rows_path.display()))?,
+        )
+        .lines()
+        .map(|l| l.map(|l| l.split('\t').next().unwrap_or_default().to_string()))
+        .collect::<Result<_, _>>()?;
+
+        let mtx_file = std::fs::File::open(mtx_path)
+            .with_context(|| format!("Could not open count matrix {}", mtx_path.display()))?;
+        let mut lines = BufReader::new(mtx_file).lines();
+        let (num_rows, num_cols) = loop {
+            let line = match lines.next() {
+                Some(l) => l?,
+                None => bail!("The count matrix {} is truncated.", mtx_path.display()),
+            };
+            if line.starts_with('%') || line.trim().is_empty() {
+                continue;
+            }
+            let dims: Vec<usize> = line
+                .split_whitespace()
+                .map(|x| x.parse::<usize>())
+                .collect::<Result<_, _>>()
+                .with_context(|| {
+                    format!("Could not parse the matrix dimensions line \"{}\"", line)
+                })?;
+            if dims.len() != 3 {
+                bail!("Could not parse the matrix dimensions line \"{}\"", line);
+            }
+            break (dims[0], dims[1]);
+        };
+        if num_rows != barcodes.len() {
+            bail!(
+                "The count matrix has {} rows, but {} contains {} barcodes.",
+                num_rows,
+                rows_path.display(),
+                barcodes.len()
+            );
+        }
+
+        let num_genes = if usa_mode { num_cols / 3 } else { num_cols }.max(1);
+        let mut counts: Vec<Vec<(u32, u64)>> = vec![Vec::new(); num_rows];
+        for l in lines {
+            let line = l?;
+            let mut fields = line.split_whitespace();
+            let (Some(r), Some(c), Some(v)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let r: usize = r.parse()?;
+            let c: usize = c.parse()?;
+            let v: f64 = v.parse()?;
+            if r == 0 || r > num_rows || c == 0 || c > num_cols {
+                bail!(
+                    "Found an entry ({}, {}) outside of the {}x{} count matrix.",
+                    r,
+                    c,
+                    num_rows,
+                    num_cols
+                );
+            }
+            let v = v.round();
+            if v > 0.0 {
+                counts[r - 1].push((((c - 1) % num_genes) as u32, v as u64));
+            }
+        }
+        for row in counts.iter_mut() {
+            row.sort_unstable();
+            row.dedup_by(|next, first| {
+                if next.0 == first.0 {
+                    first.1 += next.1;
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+        Ok(CountMatrix {
+            barcodes,
+            num_genes,
+            counts,
+        })
+    }
+}
+
+/// The outcome of testing (or retaining) a barcode.
+#[derive(Clone, Debug, Serialize)]
+pub struct BarcodeCall {
+    pub barcode: String,
+    pub total_umis: u64,
+    /// The log-probability of the counts of the barcode under the ambient
+    /// profile, if it was tested
+    pub log_prob: Option<f64>,
+    pub p_value: Option<f64>,
+    /// The p-value adjusted with the Benjamini-Hochberg procedure
+    pub fdr: Option<f64>,
+    /// Whether the p-value is the smallest one the iterations can give, i.e.
+    /// more iterations could make it smaller
+    pub limited: bool,
+    pub is_cell: bool,
+}
+
+/// The outcome of the cell calling.
+#[derive(Clone, Debug, Serialize)]
+pub struct CellCalls {
+    pub num_ambient_barcodes: usize,
+    pub ambient_umis: u64,
+    pub num_tested: usize,
+    pub num_retained: usize,
+    pub num_cells: usize,
+    /// The tested and retained barcodes, in the order of the count matrix
+    pub calls: Vec<BarcodeCall>,
+}
+
+/// The ambient profile, estimated from the summed counts of the empty
+/// droplets: the genes seen in them get their observed proportions, scaled
+/// down to leave the genes never seen the share of the counts seen only once
+/// (a simple Good-Turing estimate), so that no gene has a zero probability.
+fn ambient_profile(ambient: &[u64]) -> Vec<f64> {
+    let total: u64 = ambient.iter().sum();
+    let num_unseen = ambient.iter().filter(|a| **a == 0).count();
+    if num_unseen == 0 {
+        return ambient.iter().map(|a| *a as f64 / total as f64).collect();
+    }
+    let num_singletons = ambient.iter().filter(|a| **a == 1).count().max(1);
+    let unseen_mass = (num_singletons as f64 / total as f64).min(0.5);
+    ambient
+        .iter()
+        .map(|&a| match a {
+            0 => unseen_mass / num_unseen as f64,
+            a => (1.0 - unseen_mass) * a as f64 / total as f64,
+        })
+        .collect()
+}
+
+/// The barcodes tested against the ambient profile that have the same total
+/// number of UMIs.
+struct TotalGroup {
+    total: u64,
+    /// The log-probabilities of the barcodes, in increasing order
+    log_probs: Vec<f64>,
+    /// The indices of the barcodes, in the same order
+    barcodes: Vec<usize>,
+}
+
+/// Runs the Monte-Carlo iterations `first..first + n`: each one draws the
+/// counts of a barcode from the ambient profile, one UMI at a time, and,
+/// when the total number of UMIs reaches that of a group, the barcodes of the
+/// group that are at least as likely as the draw are counted. Returns the
+/// counts of each group, as differences (i.e. a count added to index `i`
+/// applies to all the barcodes from `i` on).
+fn run_iterations(
+    groups: &[TotalGroup],
+    log_p: &[f64],
+    table: &AliasTable,
+    seed: u64,
+    iters: &AtomicUsize,
+    niters: usize,
+) -> Vec<Vec<u64>> {
+    let max_total = groups.last().map_or(0, |g| g.total) as usize;
+    let ln: Vec<f64> = (0..=max_total + 1).map(|k| (k as f64).ln()).collect();
+    let mut diffs: Vec<Vec<u64>> = groups
+        .iter()
+        .map(|g| vec![0; g.log_probs.len() + 1])
+        .collect();
+    let mut counts = vec![0_u32; log_p.len()];
+    let mut touched = Vec::new();
+    loop {
+        let it = iters.fetch_add(1, Ordering::Relaxed);
+        if it >= niters {
+            break;
+        }
+        let mut rng = Rng::new(seed.wrapping_add(it as u64));
+        let (mut n, mut lp) = (0_usize, 0.0_f64);
+        for (g, diff) in groups.iter().zip(diffs.iter_mut()) {
+            while n < g.total as usize {
+                let gene = table.sample(&mut rng);
+                let c = counts[gene] as usize;
+                if c == 0 {
+                    touched.push(gene);
+                }
+                // ln(n+1) - ln(c+1) + log(p)
+                lp += ln[n + 1] - ln[c + 1] + log_p[gene];
+                counts[gene] += 1;
+                n += 1;
+            }
+            // the barcodes at least as likely as the draw (log-prob >= lp) are from here on
+            diff[g.log_probs.partition_point(|x| *x < lp)] += 1;
+        }
+        for gene in touched.drain(..) {
+            counts[gene] = 0;
+        }
+    }
+    diffs
+}
+
+/// Tests every barcode of `matrix` with more than `params.lower` UMIs
+/// against the ambient profile, and calls cells at `params.fdr`.
+pub fn call_cells(matrix: &CountMatrix, params: &EmptyDropsParams) -> anyhow::Result<CellCalls> {
+    let totals: Vec<u64> = matrix
+        .counts
+        .iter()
+        .map(|row| row.iter().map(|(_, c)| c).sum())
+        .collect();
+
+    let mut ambient = vec![0_u64; matrix.num_genes];
+    let mut num_ambient_barcodes = 0;
+    for (row, t) in matrix.counts.iter().zip(totals.iter()) {
+        if *t > 0 && *t <= params.lower {
+            num_ambient_barcodes += 1;
+            for (g, c) in row {
+                ambient[*g as usize] += c;
+            }
+        }
+    }
+    let ambient_umis: u64 = ambient.iter().sum();
+    if ambient_umis == 0 {
+        bail!(
+            "No barcode has between 1 and {} UMIs, so the ambient RNA profile cannot be estimated. \
+            The cells can only be called on a count matrix quantified with an unfiltered permit \
+            list (`--unfiltered-pl`), which keeps the barcodes of empty droplets.",
+            params.lower
+        );
+    }
+    let profile = ambient_profile(&ambient);
+    let log_p: Vec<f64> = profile.iter().map(|p| p.ln()).collect();
+
+    // the log-probability of the counts of each tested barcode, under a
+    // multinomial distribution following the ambient profile
+    let is_retained = |t: u64| params.retain.is_some_and(|r| t >= r);
+    let ln_fact = |n: u64| (1..=n).map(|k| (k as f64).ln()).sum::<f64>();
+    let mut tested: Vec<(usize, u64, f64)> = Vec::new();
+    for (i, (row, &t)) in matrix.counts.iter().zip(totals.iter()).enumerate() {
+        if t <= params.lower || is_retained(t) {
+            continue;
+        }
+        let lp = ln_fact(t)
+            + row
+                .iter()
+                .map(|&(g, c)| c as f64 * log_p[g as usize] - ln_fact(c))
+                .sum::<f64>();
+        tested.push((i, t, lp));
+    }
+
+    let mut sorted = tested.clone();
+    sorted.sort_by(|a, b| a.1.cmp(&b.1).then(a.2.total_cmp(&b.2)));
+    let mut groups: Vec<TotalGroup> = Vec::new();
+    for (i, t, lp) in sorted {
+        match groups.last_mut() {
+            Some(g) if g.total == t => {
+                g.log_probs.push(lp);
+                g.barcodes.push(i);
+            }
+            _ => groups.push(TotalGroup {
+                total: t,
+                log_probs: vec![lp],
+                barcodes: vec![i],
+            }),
+        }
+    }
+
+    let table = AliasTable::new(&profile);
+    let iters = AtomicUsize::new(0);
+    let all_diffs: Mutex<Vec<Vec<Vec<u64>>>> = Mutex::new(Vec::new());
+    std::thread::scope(|s| {
+        for _ in 0..params.threads.clamp(1, params.niters.max(1)) {
+            s.spawn(|| {
+                let diffs =
+                    run_iterations(&groups, &log_p, &table, params.seed, &iters, params.niters);
+                all_diffs.lock().unwrap().push(diffs);
+            });
+        }
+    });
+
+    // the number of draws at most as likely as each barcode
+    let mut num_below = vec![0_u64; matrix.counts.len()];
+    for diffs in all_diffs.into_inner().unwrap() {
+        for (g, diff) in groups.iter().zip(diffs) {
+            let mut acc = 0;
+            for (j, b) in g.barcodes.iter().enumerate() {
+                acc += diff[j];
+                num_below[*b] += acc;
+            }
+        }
+    }
+
+    let p_values: Vec<f64> = tested
+        .iter()
+        .map(|(i, _, _)| (num_below[*i] + 1) as f64 / (params.niters + 1) as f64)
+        .collect();
+    let fdrs = benjamini_hochberg(&p_values);
+
+    let mut calls = Vec::new();
+    let mut tested_iter = tested
+        .iter()
+        .zip(p_values.iter().zip(fdrs.iter()))
+        .peekable();
+    for (i, &t) in totals.iter().enumerate() {
+        if is_retained(t) {
+            calls.push(BarcodeCall {
+                barcode: matrix.barcodes[i].clone(),
+                total_umis: t,
+                log_prob: None,
+                p_value: None,
+                fdr: None,
+                limited: false,
+                is_cell: true,
+            });
+        } else if let Some(((_, _, lp), (p, q))) = tested_iter.next_if(|((j, _, _), _)| *j == i) {
+            calls.push(BarcodeCall {
+                barcode: matrix.barcodes[i].clone(),
+                total_umis: t,
+                log_prob: Some(*lp),
+                p_value: Some(*p),
+                fdr: Some(*q),
+                limited: num_below[i] == 0,
+                is_cell: *q <= params.fdr,
+            });
+        }
+    }
+    Ok(CellCalls {
+        num_ambient_barcodes,
+        ambient_umis,
+        num_tested: tested.len(),
+        num_retained: calls.iter().filter(|c| c.p_value.is_none()).count(),
+        num_cells: calls.iter().filter(|c| c.is_cell).count(),
+        calls,
+    })
+}
+
+/// Adjusts `p_values` for multiple testing with the Benjamini-Hochberg
+/// procedure.
+fn benjamini_hochberg(p_values: &[f64]) -> Vec<f64> {
+    let m = p_values.len();
+    let mut order: Vec<usize> = (0..m).collect();
+    order.sort_by(|a, b| p_values[*a].total_cmp(&p_values[*b]));
+    let mut adjusted = vec![0.0; m];
+    let mut running_min = 1.0_f64;
+    for (rank, &i) in order.iter().enumerate().rev() {
+        running_min = running_min.min(p_values[i] * m as f64 / (rank + 1) as f64);
+        adjusted[i] = running_min;
+    }
+    adjusted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_and_alias_table() {
+        let mut rng = Rng::new(7);
+        let first: Vec<u64> = (0..4).map(|_| rng.next_u64()).collect();
+        let mut again = Rng::new(7);
+        assert_eq!(first, (0..4).map(|_| again.next_u64()).collect::<Vec<_>>());
+
+        let table = AliasTable::new(&[0.5, 0.25, 0.25, 0.0]);
+        let mut hist = [0; 4];
+        for _ in 0..40_000 {
+            hist[table.sample(&mut rng)] += 1;
+        }
+        assert_eq!(hist[3], 0);
+        assert!((hist[0] as f64 / 40_000.0 - 0.5).abs() < 0.02);
+        assert!((hist[1] as f64 / 40_000.0 - 0.25).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_benjamini_hochberg() {
+        let q = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.2]);
+        let expected = [0.04, 0.04 * 4.0 / 3.0, 0.04 * 4.0 / 3.0, 0.2];
+        for (a, b) in q.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_call_cells() {
+        // 10 genes; the empty droplets draw from genes 0-4, the cells from
+        // genes 5-9, and a droplet with as many UMIs as the cells follows the
+        // ambient profile
+        let mut counts = Vec::new();
+        let mut barcodes = Vec::new();
+        for i in 0..50 {
+            counts.push((0..5).map(|g| (g, 2 + (i + g as u64) % 3)).collect());
+            barcodes.push(format!("empty{}", i));
+        }
+        for i in 0..5 {
+            counts.push((5..10).map(|g| (g, 30 + i)).collect());
+            barcodes.push(format!("cell{}", i));
+        }
+        counts.push((0..5).map(|g| (g, 30)).collect());
+        barcodes.push("ambient".to_string());
+        let matrix = CountMatrix {
+            barcodes,
+            num_genes: 10,
+            counts,
+        };
+        let params = EmptyDropsParams {
+            lower: 20,
+            niters: 500,
+            fdr: 0.01,
+            threads: 2,
+            ..Default::default()
+        };
+        let calls = call_cells(&matrix, &params).unwrap();
+        assert_eq!(calls.num_ambient_barcodes, 50);
+        assert_eq!(calls.num_tested, 6);
+        let cells: Vec<&str> = calls
+            .calls
+            .iter()
+            .filter(|c| c.is_cell)
+            .map(|c| c.barcode.as_str())
+            .collect();
+        assert_eq!(cells, ["cell0", "cell1", "cell2", "cell3", "cell4"]);
+        assert!(calls.calls[0].limited);
+        assert!(calls.calls[5].p_value.unwrap() > 0.01);
+
+        // the p-values do not depend on the number of threads
+        let serial = call_cells(
+            &matrix,
+            &EmptyDropsParams {
+                threads: 1,
+                ..params.clone()
+            },
+        )
+        .unwrap();
+        let p = |c: &CellCalls| c.calls.iter().map(|c| c.p_value).collect::<Vec<_>>();
+        assert_eq!(p(&calls), p(&serial));
+
+        let retained = call_cells(
+            &matrix,
+            &EmptyDropsParams {
+                retain: Some(160),
+                ..params
+            },
+        )
+        .unwrap();
+        assert_eq!(retained.num_retained, 3);
+        assert_eq!(retained.num_tested, 3);
+    }
+}
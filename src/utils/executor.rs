@@ -0,0 +1,226 @@
+use crate::utils::home::simpleaf_info_path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// The container runtimes that an [`Executor::Container`] can use.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntime {
+    Apptainer,
+    Singularity,
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    fn program(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Apptainer => "apptainer",
+            ContainerRuntime::Singularity => "singularity",
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+/// How the external programs used by simpleaf (piscem, salmon, alevin-fry
+/// and macs3) are run. It is read from the `executor` field of
+/// `simpleaf_info.json`; when that field is absent, the programs are run
+/// directly.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Executor {
+    /// Run the programs directly.
+    #[default]
+    Local,
+    /// Run the programs inside a container image. The paths recorded in
+    /// `prog_info` are then the paths of the programs inside the image.
+    Container {
+        runtime: ContainerRuntime,
+        image: String,
+        /// Bind mounts, as `<host path>[:<container path>[:<options>]]`
+        #[serde(default)]
+        binds: Vec<String>,
+        /// Extra arguments for the runtime, placed right before the image
+        #[serde(default)]
+        runtime_args: Vec<String>,
+    },
+    /// Run the programs through a wrapper command, which is given the
+    /// program and its arguments (e.g. `["srun", "--cpus-per-task=16"]`).
+    Wrapper { prefix: Vec<String> },
+}
+
+impl Executor {
+    /// Reads the executor from the contents of a `simpleaf_info.json` file.
+    pub fn from_info(simpleaf_info: &Value) -> anyhow::Result<Self> {
+        match simpleaf_info.get("executor") {
+            None | Some(Value::Null) => Ok(Executor::Local),
+            Some(v) => serde_json::from_value(v.clone())
+                .context("invalid `executor` entry in simpleaf_info.json"),
+        }
+    }
+
+    /// Reads the executor from the `simpleaf_info.json` in `af_home_path`,
+    /// if there is one.
+    pub fn from_af_home(af_home_path: &Path) -> anyhow::Result<Self> {
+        let p = simpleaf_info_path(af_home_path);
+        if !p.is_file() {
+            return Ok(Executor::Local);
+        }
+        let content = std::fs::read_to_string(&p)
+            .with_context(|| format!("could not read {}", p.display()))?;
+        let v: Value = serde_json::from_str(&content)
+            .with_context(|| format!("could not parse {}", p.display()))?;
+        Self::from_info(&v)
+    }
+
+    /// Returns a `Command` that runs `program` through this executor. The
+    /// arguments subsequently added to the `Command` are passed to `program`.
+    pub fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        match self {
+            Executor::Local => Command::new(program),
+            Executor::Container {
+                runtime,
+                image,
+                binds,
+                runtime_args,
+            } => {
+                let mut cmd = Command::new(runtime.program());
+                match runtime {
+                    ContainerRuntime::Apptainer | ContainerRuntime::Singularity => {
+                        // the current directory is bound by default
+                        cmd.arg("exec");
+                        for b in binds {
+                            cmd.arg("--bind").arg(b);
+                        }
+                    }
+                    ContainerRuntime::Docker | ContainerRuntime::Podman => {
+                        cmd.args(["run", "--rm", "-i"]);
+                        // relative paths must resolve to the same files
+                        // inside the container
+                        if let Ok(cwd) = std::env::current_dir() {
+                            let cwd = cwd.display().to_string();
+                            cmd.arg("-v")
+                                .arg(format!("{}:{}", cwd, cwd))
+                                .arg("-w")
+                                .arg(cwd);
+                        }
+                        for b in binds {
+                            cmd.arg("-v").arg(b);
+                        }
+                    }
+                }
+                cmd.args(runtime_args).arg(image).arg(program);
+                cmd
+            }
+            Executor::Wrapper { prefix } => match prefix.split_first() {
+                Some((wrapper, wrapper_args)) => {
+                    let mut cmd = Command::new(wrapper);
+                    cmd.args(wrapper_args).arg(program);
+                    cmd
+                }
+                None => Command::new(program),
+            },
+        }
+    }
+
+    /// Runs `program --version` through this executor and returns what it
+    /// printed to stdout.
+    pub fn version_output(&self, program: &Path) -> std::io::Result<String> {
+        let out = self.command(program).arg("--version").output()?;
+        if !out.status.success() {
+            return Err(std::io::Error::other(format!(
+                "`{} --version` exited with {}",
+                program.display(),
+                out.status
+            )));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim_end().to_string())
+    }
+}
+
+impl std::fmt::Display for Executor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Executor::Local => write!(f, "local"),
+            Executor::Container { runtime, image, .. } => {
+                write!(f, "{} container {}", runtime.program(), image)
+            }
+            Executor::Wrapper { prefix } => write!(f, "wrapper `{}`", prefix.join(" ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args_of(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_executor_from_info() {
+        let v = serde_json::json!({ "prog_info" : {} });
+        assert_eq!(Executor::from_info(&v).unwrap(), Executor::Local);
+
+        let v = serde_json::json!({
+            "executor" : {
+                "type" : "container",
+                "runtime" : "apptainer",
+                "image" : "simpleaf.sif",
+                "binds" : ["/data"]
+            }
+        });
+        let exec = Executor::from_info(&v).unwrap();
+        let mut cmd = exec.command("/usr/local/bin/piscem");
+        cmd.arg("--version");
+        assert_eq!(
+            args_of(&cmd),
+            [
+                "apptainer",
+                "exec",
+                "--bind",
+                "/data",
+                "simpleaf.sif",
+                "/usr/local/bin/piscem",
+                "--version"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wrapper_executor_runs_program() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("wrapper.log");
+        let wrapper = dir.path().join("wrapper.sh");
+        std::fs::write(
+            &wrapper,
+            format!("#!/bin/sh\necho \"$@\" > {}\nexec \"$@\"\n", log.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&wrapper, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let exec = Executor::Wrapper {
+            prefix: vec![wrapper.display().to_string()],
+        };
+        let out = exec.command("echo").arg("fry 0.11.2").output().unwrap();
+        assert!(out.status.success());
+        assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "fry 0.11.2");
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap().trim(),
+            "echo fry 0.11.2"
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::utils::constants::{SIMPLEAF_CONFIG_PATH, SIMPLEAF_INFO_PATH};
+
+use anyhow::bail;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// The environment variable pointing to the simpleaf home directory.
+pub static AF_HOME_VAR: &str = "ALEVIN_FRY_HOME";
+
+static SIMPLEAF_HOME: OnceLock<SimpleafHome> = OnceLock::new();
+
+/// How the simpleaf home of a run was chosen.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HomeSource {
+    /// The global `--af-home` option
+    Cli,
+    /// The `ALEVIN_FRY_HOME` environment variable
+    Env,
+    /// The XDG base directories
+    Xdg,
+}
+
+/// The directories where simpleaf keeps its configuration (the
+/// `simpleaf_info.json` and `simpleaf.toml` files) and its data (the chemistry registry, the
+/// permit lists and the workflow registries). When the home is given by
+/// `--af-home` or `ALEVIN_FRY_HOME`, both are that directory; otherwise they
+/// follow the [XDG base directory specification](https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html),
+/// and are `$XDG_CONFIG_HOME/simpleaf` and `$XDG_DATA_HOME/simpleaf`.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct SimpleafHome {
+    pub config_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub source: HomeSource,
+}
+
+impl SimpleafHome {
+    /// A home keeping both its configuration and its data in `dir`.
+    pub fn single_dir(dir: PathBuf, source: HomeSource) -> Self {
+        SimpleafHome {
+            config_dir: dir.clone(),
+            data_dir: dir,
+            source,
+        }
+    }
+
+    /// Chooses the home from, in order, `cli_home` (the `--af-home`
+    /// option), `ALEVIN_FRY_HOME` and the XDG base directories.
+    pub fn resolve(cli_home: Option<PathBuf>) -> anyhow::Result<Self> {
+        Self::resolve_with(cli_home, |k| std::env::var_os(k))
+    }
+
+    /// The home following the XDG base directories, whatever `--af-home`
+    /// and `ALEVIN_FRY_HOME` are.
+    pub fn xdg() -> anyhow::Result<Self> {
+        Self::xdg_with(|k| std::env::var_os(k))
+    }
+
+    fn resolve_with(
+        cli_home: Option<PathBuf>,
+        var: impl Fn(&str) -> Option<OsString>,
+    ) -> anyhow::Result<Self> {
+        if let Some(dir) = cli_home {
+            return Ok(Self::single_dir(dir, HomeSource::Cli));
+        }
+        match var(AF_HOME_VAR) {
+            Some(dir) if !dir.is_empty() => Ok(Self::single_dir(dir.into(), HomeSource::Env)),
+            _ => Self::xdg_with(var),
+        }
+    }
+
+    fn xdg_with(var: impl Fn(&str) -> Option<OsString>) -> anyhow::Result<Self> {
+        let base_dir = |xdg_var: &str, default: &str| -> anyhow::Result<PathBuf> {
+            // relative paths are invalid, and should be ignored
+            if let Some(p) = var(xdg_var).map(PathBuf::from) {
+                if p.is_absolute() {
+                    return Ok(p);
+                }
+            }
+            match var("HOME") {
+                Some(h) if !h.is_empty() => Ok(PathBuf::from(h).join(default)),
+                _ => bail!(
+                    "Could not choose the simpleaf home directory: none of `--af-home`, ${}, \
+                    ${} and $HOME is set. Please set one of them to continue.",
+                    AF_HOME_VAR,
+                    xdg_var
+                ),
+            }
+        };
+        Ok(SimpleafHome {
+            config_dir: base_dir("XDG_CONFIG_HOME", ".config")?.join("simpleaf"),
+            data_dir: base_dir("XDG_DATA_HOME", ".local/share")?.join("simpleaf"),
+            source: HomeSource::Xdg,
+        })
+    }
+
+    /// The path of the `simpleaf_info.json` file of this home.
+    pub fn info_path(&self) -> PathBuf {
+        self.config_dir.join(SIMPLEAF_INFO_PATH)
+    }
+
+    /// The path of the `simpleaf.toml` file of this home.
+    pub fn config_path(&self) -> PathBuf {
+        self.config_dir.join(SIMPLEAF_CONFIG_PATH)
+    }
+
+    /// Sets the home of this run. It can only be set once.
+    pub fn set_global(home: SimpleafHome) {
+        if SIMPLEAF_HOME.set(home).is_err() {
+            warn!("the simpleaf home was already set; ignoring the new one.");
+        }
+    }
+
+    /// The home whose data directory is `af_home_path`: the home of this run
+    /// if it is that one, and otherwise a home keeping everything in
+    /// `af_home_path` (e.g. for a directory passed to the library functions).
+    pub fn of(af_home_path: &Path) -> SimpleafHome {
+        match SIMPLEAF_HOME.get() {
+            Some(home) if home.data_dir == af_home_path => home.clone(),
+            _ => Self::single_dir(af_home_path.to_path_buf(), HomeSource::Cli),
+        }
+    }
+}
+
+/// The value of the `--af-home` option in the command line `args`, which
+/// is needed to read `simpleaf.toml` before they are parsed.
+pub fn af_home_arg(args: &[OsString]) -> Option<PathBuf> {
+    let mut home = None;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--af-home" {
+            home = args.next().map(PathBuf::from);
+        } else if let Some(dir) = arg.to_str().and_then(|a| a.strip_prefix("--af-home=")) {
+            home = Some(PathBuf::from(dir));
+        }
+    }
+    home
+}
+
+/// The path of the `simpleaf_info.json` file of the home whose data
+/// directory is `af_home_path`.
+pub fn simpleaf_info_path(af_home_path: &Path) -> PathBuf {
+    SimpleafHome::of(af_home_path).info_path()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_home() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |k: &str| {
+                vars.iter()
+                    .find(|(n, _)| *n == k)
+                    .map(|(_, v)| OsString::from(v))
+            }
+        };
+
+        let home = SimpleafHome::resolve_with(
+            Some(PathBuf::from("/cli")),
+            env(&[("ALEVIN_FRY_HOME", "/env")]),
+        )
+        .unwrap();
+        assert_eq!(
+            home,
+            SimpleafHome::single_dir("/cli".into(), HomeSource::Cli)
+        );
+
+        let home = SimpleafHome::resolve_with(
+            None,
+            env(&[("ALEVIN_FRY_HOME", "/env"), ("HOME", "/home/u")]),
+        )
+        .unwrap();
+        assert_eq!(home.info_path(), PathBuf::from("/env/simpleaf_info.json"));
+        assert_eq!(home.source, HomeSource::Env);
+
+        // the XDG variables default to directories under $HOME, and must be absolute
+        let home = SimpleafHome::resolve_with(
+            None,
+            env(&[
+                ("ALEVIN_FRY_HOME", ""),
+                ("HOME", "/home/u"),
+                ("XDG_DATA_HOME", "/data"),
+                ("XDG_CONFIG_HOME", "relative"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(home.config_dir, PathBuf::from("/home/u/.config/simpleaf"));
+        assert_eq!(home.data_dir, PathBuf::from("/data/simpleaf"));
+        assert_eq!(home.source, HomeSource::Xdg);
+
+        assert!(SimpleafHome::resolve_with(None, env(&[])).is_err());
+
+        let args = |a: &[&str]| a.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            af_home_arg(&args(&["simpleaf", "--af-home", "/a", "inspect"])),
+            Some(PathBuf::from("/a"))
+        );
+        assert_eq!(
+            af_home_arg(&args(&["simpleaf", "inspect", "--af-home=/b"])),
+            Some(PathBuf::from("/b"))
+        );
+        assert_eq!(af_home_arg(&args(&["simpleaf", "inspect"])), None);
+    }
+}
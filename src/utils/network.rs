@@ -0,0 +1,681 @@
+use crate::utils::home::simpleaf_info_path;
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// The longest delay between two attempts at a request.
+const MAX_BACKOFF_SECS: f64 = 60.0;
+
+static NETWORK_SETTINGS: OnceLock<NetworkSettings> = OnceLock::new();
+
+/// How simpleaf downloads its resources (the chemistry registry, the permit
+/// lists and the workflow registries). It is read from the `network` field
+/// of `simpleaf_info.json`, and set by `simpleaf set-network`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// The mirrors of remote locations, as URL prefixes (e.g.
+    /// `https://raw.githubusercontent.com`) mapped to the prefix replacing
+    /// them. The mirror is tried first, and then the original URL.
+    pub mirrors: BTreeMap<String, String>,
+    /// The HTTP(S) proxy to go through. When it is unset, the proxy is taken
+    /// from the `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` environment
+    /// variables, if any.
+    pub proxy: Option<String>,
+    /// The number of times a request is retried after a transient failure
+    pub retries: u32,
+    /// The delay before the first retry, doubled for each following one
+    pub retry_backoff_secs: f64,
+    /// How long to wait for the connection to the server
+    pub connect_timeout_secs: u64,
+    /// How long to wait for the server to answer, once connected
+    pub timeout_secs: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            mirrors: BTreeMap::new(),
+            proxy: None,
+            retries: 3,
+            retry_backoff_secs: 1.0,
+            connect_timeout_secs: 30,
+            timeout_secs: 120,
+        }
+    }
+}
+
+/// The failure of one attempt at a request, and whether it is worth
+/// retrying.
+struct AttemptError {
+    error: anyhow::Error,
+    transient: bool,
+}
+
+impl AttemptError {
+    fn transient(error: anyhow::Error) -> Self {
+        AttemptError {
+            error,
+            transient: true,
+        }
+    }
+
+    fn fatal(error: anyhow::Error) -> Self {
+        AttemptError {
+            error,
+            transient: false,
+        }
+    }
+}
+
+impl From<ureq::Error> for AttemptError {
+    fn from(e: ureq::Error) -> Self {
+        let transient = matches!(
+            e,
+            ureq::Error::Io(_)
+                | ureq::Error::Timeout(_)
+                | ureq::Error::HostNotFound
+                | ureq::Error::ConnectionFailed
+                | ureq::Error::Protocol(_)
+                | ureq::Error::BodyStalled
+        );
+        AttemptError {
+            error: e.into(),
+            transient,
+        }
+    }
+}
+
+/// Returns an error for a response with the status `status`, which is
+/// transient for the statuses that a server returns when it is temporarily
+/// unable to answer.
+fn status_error(status: ureq::http::StatusCode) -> AttemptError {
+    let error = anyhow!(
+        "the server answered {} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("UNKNOWN FAILURE")
+    );
+    if status.is_server_error() || matches!(status.as_u16(), 408 | 429) {
+        AttemptError::transient(error)
+    } else {
+        AttemptError::fatal(error)
+    }
+}
+
+impl NetworkSettings {
+    /// Reads the network settings from the contents of a
+    /// `simpleaf_info.json` file.
+    pub fn from_info(simpleaf_info: &Value) -> anyhow::Result<Self> {
+        let settings: NetworkSettings = match simpleaf_info.get("network") {
+            None | Some(Value::Null) => NetworkSettings::default(),
+            Some(v) => serde_json::from_value(v.clone())
+                .context("invalid `network` entry in simpleaf_info.json")?,
+        };
+        settings
+            .check()
+            .context("invalid `network` entry in simpleaf_info.json")?;
+        Ok(settings)
+    }
+
+    /// Reads the network settings from the `simpleaf_info.json` in
+    /// `af_home_path`, if there is one.
+    pub fn from_af_home(af_home_path: &Path) -> anyhow::Result<Self> {
+        let p = simpleaf_info_path(af_home_path);
+        if !p.is_file() {
+            return Ok(NetworkSettings::default());
+        }
+        let content = std::fs::read_to_string(&p)
+            .with_context(|| format!("could not read {}", p.display()))?;
+        let v: Value = serde_json::from_str(&content)
+            .with_context(|| format!("could not parse {}", p.display()))?;
+        Self::from_info(&v)
+    }
+
+    /// Sets the network settings used by all the downloads of this run.
+    /// They can only be set once, before the first download.
+    pub fn set_global(settings: NetworkSettings) {
+        if NETWORK_SETTINGS.set(settings).is_err() {
+            warn!("the network settings were already set; ignoring the new ones.");
+        }
+    }
+
+    /// The network settings of this run, which are the default ones unless
+    /// [`NetworkSettings::set_global`] was called.
+    pub fn global() -> &'static NetworkSettings {
+        NETWORK_SETTINGS.get_or_init(NetworkSettings::default)
+    }
+
+    /// Checks that the settings are usable.
+    pub fn check(&self) -> anyhow::Result<()> {
+        for (from, to) in &self.mirrors {
+            for u in [from, to] {
+                if !(u.starts_with("http://") || u.starts_with("https://")) {
+                    bail!(
+                        "The mirror {} -> {} should map an http(s) URL to another one.",
+                        from,
+                        to
+                    );
+                }
+            }
+        }
+        if let Some(p) = &self.proxy {
+            ureq::Proxy::new(p).with_context(|| format!("invalid proxy {}", p))?;
+        }
+        if !self.retry_backoff_secs.is_finite() || self.retry_backoff_secs < 0.0 {
+            bail!(
+                "The retry backoff should be a non-negative number of seconds, not {}.",
+                self.retry_backoff_secs
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the URLs to try, in order, to obtain `url`: the URL on the
+    /// mirror with the longest matching prefix, if any, and `url` itself.
+    pub fn candidate_urls(&self, url: &str) -> Vec<String> {
+        let mirrored = self
+            .mirrors
+            .iter()
+            .filter(|(from, _)| {
+                // the prefix must end on a path boundary
+                url.strip_prefix(from.as_str()).is_some_and(|rest| {
+                    rest.is_empty() || rest.starts_with('/') || from.ends_with('/')
+                })
+            })
+            .max_by_key(|(from, _)| from.len())
+            .map(|(from, to)| {
+                format!(
+                    "{}/{}",
+                    to.trim_end_matches('/'),
+                    url[from.len()..].trim_start_matches('/')
+                )
+            });
+        mirrored.into_iter().chain([url.to_string()]).collect()
+    }
+
+    fn agent(&self) -> anyhow::Result<ureq::Agent> {
+        let mut config = ureq::Agent::config_builder()
+            .timeout_connect(Some(Duration::from_secs(self.connect_timeout_secs)))
+            .timeout_recv_response(Some(Duration::from_secs(self.timeout_secs)))
+            .max_redirects(10)
+            // the statuses are handled by the callers, so that the transient
+            // ones can be retried
+            .http_status_as_error(false);
+        if let Some(p) = &self.proxy {
+            let proxy = ureq::Proxy::new(p).with_context(|| format!("invalid proxy {}", p))?;
+            config = config.proxy(Some(proxy));
+        }
+        Ok(ureq::Agent::new_with_config(config.build()))
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        let secs = self.retry_backoff_secs * 2f64.powi(retry.min(30) as i32);
+        Duration::from_secs_f64(secs.min(MAX_BACKOFF_SECS))
+    }
+
+    /// Runs `attempt` until it succeeds, it fails with an error that is not
+    /// transient, or the retries are exhausted.
+    fn with_retries<T>(
+        &self,
+        url: &str,
+        mut attempt: impl FnMut() -> Result<T, AttemptError>,
+    ) -> anyhow::Result<T> {
+        let mut retry = 0;
+        loop {
+            match attempt() {
+                Ok(v) => return Ok(v),
+                Err(e) if e.transient && retry < self.retries => {
+                    let delay = self.backoff(retry);
+                    retry += 1;
+                    warn!(
+                        "could not obtain {}: {:#}; retrying in {:.1}s ({} of {})",
+                        url,
+                        e.error,
+                        delay.as_secs_f64(),
+                        retry,
+                        self.retries
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(e) => return Err(e.error),
+            }
+        }
+    }
+
+    /// Tries each of the candidate URLs of `url` in turn, returning the
+    /// first success or the last failure.
+    fn with_candidates<T>(
+        &self,
+        url: &str,
+        mut fetch: impl FnMut(&str) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let candidates = self.candidate_urls(url);
+        let mut last_error = None;
+        for (i, candidate) in candidates.iter().enumerate() {
+            match fetch(candidate) {
+                Ok(v) => return Ok(v),
+                Err(e) if i + 1 < candidates.len() => {
+                    warn!(
+                        "could not obtain {} from the mirror {}: {:#}; trying {}",
+                        url,
+                        candidate,
+                        e,
+                        candidates[i + 1]
+                    );
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("no URL to obtain {} from", url)))
+    }
+
+    /// Fetches the JSON document at `url`.
+    pub fn read_json(&self, url: &str) -> anyhow::Result<Value> {
+        let agent = self.agent()?;
+        self.with_candidates(url, |candidate| {
+            self.with_retries(candidate, || {
+                let mut response = agent.get(candidate).call()?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(status_error(status));
+                }
+                response.body_mut().read_json().map_err(AttemptError::from)
+            })
+            .with_context(|| format!("could not obtain content from {}", candidate))
+        })
+    }
+
+    /// Downloads `url` to `file_path`. The content is first written to a
+    /// `.part` file next to `file_path`, which is only moved to `file_path`
+    /// once it is complete and accepted by `check`. When a download is
+    /// interrupted, the next attempt (including one by a later run) asks the
+    /// server for the rest only, provided that the remote file is unchanged
+    /// (see [`download_part`]). A download rejected by `check` is deleted,
+    /// and the next candidate URL (if any) is tried.
+    pub fn download(
+        &self,
+        url: &str,
+        file_path: &Path,
+        mut check: impl FnMut(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let agent = self.agent()?;
+        let part = part_path(file_path);
+        let mut first = true;
+        self.with_candidates(url, |candidate| {
+            // a partial download can only be resumed from the same source
+            if !first {
+                remove_part(&part);
+            }
+            first = false;
+            self.with_retries(candidate, || download_part(&agent, candidate, &part))
+                .with_context(|| format!("could not obtain content from {}", candidate))?;
+            check(&part).inspect_err(|_| remove_part(&part))
+        })?;
+        std::fs::rename(&part, file_path)
+            .with_context(|| format!("could not write {}", file_path.display()))?;
+        let _ = std::fs::remove_file(validator_path(&part));
+        Ok(())
+    }
+}
+
+fn part_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    file_path.with_file_name(name)
+}
+
+/// The file holding the validator (the `ETag` or `Last-Modified` header) of
+/// the remote file whose beginning is in `part`.
+fn validator_path(part: &Path) -> PathBuf {
+    let mut name = part.file_name().unwrap_or_default().to_os_string();
+    name.push(".validator");
+    part.with_file_name(name)
+}
+
+/// Removes a partial download, along with its validator.
+fn remove_part(part: &Path) {
+    let _ = std::fs::remove_file(part);
+    let _ = std::fs::remove_file(validator_path(part));
+}
+
+/// The validator of the remote file sent in `response`, which identifies
+/// its version: its strong `ETag` or, failing that, its `Last-Modified` date.
+fn response_validator(response: &ureq::http::Response<ureq::Body>) -> Option<String> {
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    // weak entity tags cannot be used in `If-Range`
+    header("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header("last-modified"))
+}
+
+/// Downloads `url` into `part`, asking only for the bytes that are missing
+/// from `part` if it already exists. The rest is asked for with the
+/// validator of the remote file recorded when `part` was started (in
+/// `If-Range`), so that the server sends the whole file again if it changed
+/// since; a partial download without a validator is restarted.
+fn download_part(agent: &ureq::Agent, url: &str, part: &Path) -> Result<(), AttemptError> {
+    let validator_file = validator_path(part);
+    let mut offset = std::fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    let validator = std::fs::read_to_string(&validator_file).ok();
+    let mut request = agent.get(url);
+    match &validator {
+        Some(validator) if offset > 0 => {
+            debug!(
+                "resuming the download of {} after {} bytes, into {}",
+                url,
+                offset,
+                part.display()
+            );
+            request = request
+                .header("Range", format!("bytes={}-", offset))
+                .header("If-Range", validator.as_str());
+        }
+        _ => {
+            if offset > 0 {
+                info!(
+                    "the version of the partial download {} is unknown; restarting it",
+                    part.display()
+                );
+            }
+            offset = 0;
+        }
+    }
+    let mut response = request.call()?;
+    let status = response.status();
+
+    let resumed = match status.as_u16() {
+        206 if offset > 0 => {
+            let expected = format!("bytes {}-", offset);
+            let matches = response
+                .headers()
+                .get("content-range")
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with(&expected));
+            if !matches {
+                remove_part(part);
+                return Err(AttemptError::transient(anyhow!(
+                    "the server sent another range than the one asked for; restarting the download"
+                )));
+            }
+            true
+        }
+        416 if offset > 0 => {
+            // the partial file does not match the remote one
+            remove_part(part);
+            return Err(AttemptError::transient(anyhow!(
+                "the server could not resume the download; restarting it"
+            )));
+        }
+        _ if status.is_success() => {
+            if offset > 0 {
+                info!(
+                    "the server of {} cannot resume the download, or the file changed; restarting",
+                    url
+                );
+            }
+            false
+        }
+        _ => return Err(status_error(status)),
+    };
+
+    if !resumed {
+        // the validator is written before the content, so that the content
+        // never outlives the version it belongs to
+        let written = match response_validator(&response) {
+            Some(v) => std::fs::write(&validator_file, v),
+            None => match std::fs::remove_file(&validator_file) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                r => r,
+            },
+        };
+        written
+            .with_context(|| format!("could not write {}", validator_file.display()))
+            .map_err(AttemptError::fatal)?;
+    }
+
+    let f = if resumed {
+        OpenOptions::new().append(true).open(part)
+    } else {
+        File::create(part)
+    }
+    .with_context(|| format!("could not create file {}", part.display()))
+    .map_err(AttemptError::fatal)?;
+    let mut ofile = BufWriter::new(f);
+    let mut body = response.body_mut().as_reader();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        // an interrupted transfer is retried, and resumed from what was
+        // written so far
+        let n = body
+            .read(&mut buf)
+            .map_err(|e| AttemptError::transient(e.into()))?;
+        if n == 0 {
+            break;
+        }
+        ofile
+            .write_all(&buf[..n])
+            .with_context(|| format!("could not write {}", part.display()))
+            .map_err(AttemptError::fatal)?;
+    }
+    ofile
+        .flush()
+        .with_context(|| format!("could not write {}", part.display()))
+        .map_err(AttemptError::fatal)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// A stand-in for an HTTP server, which answers each connection with the
+    /// next of `responses` (the raw bytes sent back, before the connection is
+    /// closed), and returns the heads of the requests it received.
+    fn serve(responses: Vec<Vec<u8>>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line.to_lowercase());
+                }
+                requests.push(head);
+                stream.write_all(&response).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn response(status: &str, headers: &[&str], body: &[u8], length: usize) -> Vec<u8> {
+        let mut r = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status, length
+        );
+        for h in headers {
+            r.push_str(h);
+            r.push_str("\r\n");
+        }
+        r.push_str("\r\n");
+        let mut r = r.into_bytes();
+        r.extend_from_slice(body);
+        r
+    }
+
+    fn quick_settings() -> NetworkSettings {
+        NetworkSettings {
+            retry_backoff_secs: 0.0,
+            connect_timeout_secs: 5,
+            timeout_secs: 5,
+            ..NetworkSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_download_retries_and_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pl.txt");
+        // the transfer is cut after 5 bytes, and then a transient failure is retried
+        let (url, server) = serve(vec![
+            response("200 OK", &["ETag: \"v1\""], b"AAAAA", 10),
+            response("503 Service Unavailable", &[], b"", 0),
+            response(
+                "206 Partial Content",
+                &["ETag: \"v1\"", "Content-Range: bytes 5-9/10"],
+                b"BBBBB",
+                5,
+            ),
+        ]);
+        let url = format!("{}/pl.txt", url);
+        quick_settings().download(&url, &file, |_| Ok(())).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"AAAAABBBBB");
+        assert!(!part_path(&file).exists());
+        assert!(!validator_path(&part_path(&file)).exists());
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(!requests[0].contains("range:"));
+        assert!(requests[2].contains("range: bytes=5-"));
+        assert!(requests[2].contains("if-range: \"v1\""));
+    }
+
+    #[test]
+    fn test_download_restarts_unvalidated_or_changed_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pl.txt");
+        let part = part_path(&file);
+
+        // a partial download of an unknown version is not resumed
+        std::fs::write(&part, b"XXXXX").unwrap();
+        let (url, server) = serve(vec![response("200 OK", &[], b"AAAAABBBBB", 10)]);
+        quick_settings()
+            .download(&format!("{}/pl.txt", url), &file, |_| Ok(()))
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"AAAAABBBBB");
+        assert!(!server.join().unwrap()[0].contains("range:"));
+
+        // the server sends the whole file when it changed since the partial download
+        std::fs::write(&part, b"XXXXX").unwrap();
+        std::fs::write(validator_path(&part), "\"v1\"").unwrap();
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            &["ETag: \"v2\""],
+            b"CCCCCDDDDD",
+            10,
+        )]);
+        quick_settings()
+            .download(&format!("{}/pl.txt", url), &file, |_| Ok(()))
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"CCCCCDDDDD");
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("range: bytes=5-"));
+        assert!(requests[0].contains("if-range: \"v1\""));
+    }
+
+    #[test]
+    fn test_download_falls_back_from_the_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pl.txt");
+        let (origin, origin_server) = serve(vec![response("200 OK", &[], b"origin", 6)]);
+        let (mirror, mirror_server) = serve(vec![response("404 Not Found", &[], b"", 0)]);
+        let mut settings = quick_settings();
+        settings.mirrors.insert(origin.clone(), mirror);
+
+        settings
+            .download(&format!("{}/pl.txt", origin), &file, |_| Ok(()))
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"origin");
+        assert!(mirror_server.join().unwrap()[0].starts_with("get /pl.txt"));
+        assert!(origin_server.join().unwrap()[0].starts_with("get /pl.txt"));
+
+        // a download rejected by the check is deleted
+        let (origin, origin_server) = serve(vec![response("200 OK", &[], b"corrupt", 7)]);
+        let other = dir.path().join("other.txt");
+        assert!(quick_settings()
+            .download(&format!("{}/other.txt", origin), &other, |_| bail!(
+                "bad hash"
+            ))
+            .is_err());
+        origin_server.join().unwrap();
+        assert!(!other.exists() && !part_path(&other).exists());
+    }
+
+    #[test]
+    fn test_network_settings() {
+        let settings = NetworkSettings::from_info(&json!({})).unwrap();
+        assert_eq!(settings, NetworkSettings::default());
+
+        let settings = NetworkSettings::from_info(&json!({
+            "network": {
+                "mirrors": {
+                    "https://raw.githubusercontent.com": "https://mirror.example.org/gh/",
+                    "https://raw.githubusercontent.com/COMBINE-lab/simpleaf":
+                        "http://localhost:8000/simpleaf"
+                },
+                "retries": 5
+            }
+        }))
+        .unwrap();
+        assert_eq!(settings.retries, 5);
+        assert_eq!(settings.timeout_secs, 120);
+        assert_eq!(
+            settings.candidate_urls("https://raw.githubusercontent.com/a/b/c.json"),
+            [
+                "https://mirror.example.org/gh/a/b/c.json",
+                "https://raw.githubusercontent.com/a/b/c.json"
+            ]
+        );
+        // the longest prefix wins
+        assert_eq!(
+            settings.candidate_urls(
+                "https://raw.githubusercontent.com/COMBINE-lab/simpleaf/dev/chemistries.json"
+            )[0],
+            "http://localhost:8000/simpleaf/dev/chemistries.json"
+        );
+        assert_eq!(
+            settings.candidate_urls(
+                "https://raw.githubusercontent.com/COMBINE-lab/simpleaf-other/x.json"
+            )[0],
+            "https://mirror.example.org/gh/COMBINE-lab/simpleaf-other/x.json"
+        );
+        assert_eq!(
+            settings.candidate_urls("https://umd.box.com/shared/static/x.txt"),
+            ["https://umd.box.com/shared/static/x.txt"]
+        );
+
+        assert!(NetworkSettings::from_info(&json!({
+            "network": { "mirrors": { "github": "https://mirror.example.org" } }
+        }))
+        .is_err());
+        assert!(
+            NetworkSettings::from_info(&json!({ "network": { "retry_backoff_secs": -1 } }))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use crate::utils::prog_utils::get_generic_buf_reader;
+use crate::utils::read_checks::read_layouts;
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// The directory of the output of `simpleaf quant --infer-pl` holding the
+/// inferred permit list.
+pub static INFERRED_PL_DIR: &str = "inferred_pl";
+
+/// The name of the inferred permit list file.
+pub static INFERRED_PL_FILE: &str = "permit_list.txt";
+
+/// The name of the file holding the statistics of an inferred permit list.
+pub static INFERRED_PL_STATS_FILE: &str = "permit_list_stats.json";
+
+/// The name of the file recording the fingerprint of the inference of a
+/// permit list, so that `simpleaf quant --resume` only reuses it when its
+/// settings and reads are unchanged.
+pub static INFERRED_PL_LOG_FILE: &str = "infer_pl_log.json";
+
+/// A barcode one substitution away from a barcode with at least this many
+/// times as many reads is taken to be a sequencing error of the latter.
+const NEIGHBOR_COUNT_RATIO: u64 = 10;
+
+/// The statistics of an inferred permit list.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PermitListStats {
+    pub geometry: String,
+    /// The number of reads (or read pairs) read
+    pub num_reads: u64,
+    /// The number of reads whose barcode could not be extracted, as the read
+    /// was too short or the barcode held a base other than A, C, G or T
+    pub num_reads_without_barcode: u64,
+    /// The number of distinct barcodes seen
+    pub num_distinct_barcodes: usize,
+    /// The number of barcodes taken to be sequencing errors of a barcode
+    /// one substitution away, and merged into it
+    pub num_corrected_barcodes: usize,
+    /// The number of reads a barcode must have to be kept, not counting the
+    /// reads of the barcodes merged into it
+    pub min_reads: u64,
+    /// The number of barcodes in the permit list
+    pub num_barcodes: usize,
+    /// The number of reads whose barcode (possibly after correction) is in
+    /// the permit list
+    pub num_reads_in_list: u64,
+}
+
+/// Counts the barcodes of the reads in `reads1` and `reads2` (pairs of files
+/// of the same sample), which follow `geometry`. The barcodes must be at a
+/// fixed position in the reads.
+fn count_barcodes(
+    geometry: &str,
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+    stats: &mut PermitListStats,
+) -> anyhow::Result<HashMap<Vec<u8>, u64>> {
+    let Some([l1, l2]) = read_layouts(geometry) else {
+        bail!(
+            "Cannot infer a permit list for the geometry {}, which does not spell out the layout \
+            of the reads.",
+            geometry
+        );
+    };
+    let (Some(b1), Some(b2)) = (l1.barcode, l2.barcode) else {
+        bail!(
+            "Cannot infer a permit list for the geometry {}, as its barcode does not start at a \
+            fixed position in the reads.",
+            geometry
+        );
+    };
+    if b1.is_empty() && b2.is_empty() {
+        bail!(
+            "Cannot infer a permit list for the geometry {}, which has no barcode.",
+            geometry
+        );
+    }
+
+    // only the files holding a part of the barcode are read
+    let mut sources = Vec::new();
+    if !b1.is_empty() {
+        sources.push((reads1, b1));
+    }
+    if !b2.is_empty() {
+        sources.push((reads2, b2));
+    }
+    let mut counts: HashMap<Vec<u8>, u64> = HashMap::new();
+    for i in 0..reads1.len().min(reads2.len()) {
+        let mut readers = Vec::with_capacity(sources.len());
+        for (files, pieces) in sources.iter() {
+            info!("counting the barcodes in {}", files[i].display());
+            let lines = get_generic_buf_reader(&files[i])?.lines();
+            readers.push((&files[i], lines, pieces));
+        }
+        let mut bc = Vec::new();
+        'records: loop {
+            bc.clear();
+            let mut valid = true;
+            for (path, lines, pieces) in readers.iter_mut() {
+                let mut record = Vec::with_capacity(4);
+                for _ in 0..4 {
+                    match lines.next() {
+                        Some(l) => record
+                            .push(l.with_context(|| format!("could not read {}", path.display()))?),
+                        None if record.is_empty() => break 'records,
+                        None => {
+                            bail!("{} ends in the middle of a record.", path.display())
+                        }
+                    }
+                }
+                let seq = record[1].as_bytes();
+                for &(o, l) in pieces.iter() {
+                    match seq.get(o..o + l) {
+                        Some(piece) if piece.iter().all(|c| b"ACGT".contains(c)) => {
+                            bc.extend_from_slice(piece)
+                        }
+                        _ => valid = false,
+                    }
+                }
+            }
+            stats.num_reads += 1;
+            if valid {
+                *counts.entry(bc.clone()).or_default() += 1;
+            } else {
+                stats.num_reads_without_barcode += 1;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+/// Builds a permit list out of the barcode counts in `counts`: going from the
+/// most to the least frequent barcode, a barcode one substitution away from
+/// a kept barcode with at least [`NEIGHBOR_COUNT_RATIO`] times as many reads
+/// is merged into it, and the other barcodes are kept if they have at least
+/// `min_reads` reads. Returns the kept barcodes with their counts (including
+/// the merged barcodes), from the most to the least frequent.
+fn cluster_barcodes(
+    counts: HashMap<Vec<u8>, u64>,
+    min_reads: u64,
+    stats: &mut PermitListStats,
+) -> Vec<(Vec<u8>, u64)> {
+    let mut sorted: Vec<(Vec<u8>, u64)> = counts.into_iter().collect();
+    sorted.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    stats.num_distinct_barcodes = sorted.len();
+
+    let mut kept: Vec<(Vec<u8>, u64)> = Vec::new();
+    // the index in `kept` of each kept barcode, and the count it had before
+    // the merging
+    let mut kept_idx: HashMap<Vec<u8>, (usize, u64)> = HashMap::new();
+    for (bc, count) in sorted {
+        let mut parent: Option<(usize, u64)> = None;
+        let mut neighbor = bc.clone();
+        for pos in 0..bc.len() {
+            for base in *b"ACGT" {
+                if base == bc[pos] {
+                    continue;
+                }
+                neighbor[pos] = base;
+                if let Some(&(i, c)) = kept_idx.get(&neighbor) {
+                    if c >= NEIGHBOR_COUNT_RATIO * count && parent.is_none_or(|(_, pc)| c > pc) {
+                        parent = Some((i, c));
+                    }
+                }
+            }
+            neighbor[pos] = bc[pos];
+        }
+        match parent {
+            Some((i, _)) => {
+                kept[i].1 += count;
+                stats.num_corrected_barcodes += 1;
+            }
+            None if count >= min_reads => {
+                kept_idx.insert(bc.clone(), (kept.len(), count));
+                kept.push((bc, count));
+            }
+            None => {}
+        }
+    }
+    stats.min_reads = min_reads;
+    stats.num_barcodes = kept.len();
+    stats.num_reads_in_list = kept.iter().map(|(_, c)| c).sum();
+    kept
+}
+
+/// Infers an unfiltered permit list from the barcodes of the reads in
+/// `reads1` and `reads2`, which follow `geometry` (see [`cluster_barcodes`]),
+/// and writes it, with its statistics, to `output_dir`. Returns the path of
+/// the permit list file.
+pub fn infer_permit_list(
+    geometry: &str,
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+    min_reads: u64,
+    output_dir: &Path,
+) -> anyhow::Result<(PathBuf, PermitListStats)> {
+    let mut stats = PermitListStats {
+        geometry: geometry.to_string(),
+        ..Default::default()
+    };
+    let counts = count_barcodes(geometry, reads1, reads2, &mut stats)?;
+    let kept = cluster_barcodes(counts, min_reads, &mut stats);
+    if kept.is_empty() {
+        bail!(
+            "No barcode has at least {} reads in the {} reads read, so no permit list could be \
+            inferred.",
+            min_reads,
+            stats.num_reads
+        );
+    }
+
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("could not create the directory {}", output_dir.display()))?;
+    let pl_path = output_dir.join(INFERRED_PL_FILE);
+    let mut w = BufWriter::new(
+        std::fs::File::create(&pl_path)
+            .with_context(|| format!("could not create {}", pl_path.display()))?,
+    );
+    for (bc, _) in kept.iter() {
+        w.write_all(bc)?;
+        w.write_all(b"\n")?;
+    }
+    w.flush()?;
+    let stats_path = output_dir.join(INFERRED_PL_STATS_FILE);
+    std::fs::write(&stats_path, serde_json::to_string_pretty(&stats)?)
+        .with_context(|| format!("could not write {}", stats_path.display()))?;
+
+    info!(
+        "inferred a permit list of {} barcodes (holding {:.1}% of the {} reads, after merging {} \
+        barcodes into a neighbor) in {}",
+        stats.num_barcodes,
+        100.0 * stats.num_reads_in_list as f64 / stats.num_reads.max(1) as f64,
+        stats.num_reads,
+        stats.num_corrected_barcodes,
+        pl_path.display()
+    );
+    Ok((pl_path, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_permit_list() {
+        let dir = tempfile::tempdir().unwrap();
+        // AAAA: 30 reads, AAAT (a sequencing error of AAAA): 2 reads,
+        // CCCC: 12 reads, GGGG: 3 reads, a read with an N
+        let mut bcs = vec!["AAAA"; 30];
+        bcs.extend(["AAAT"; 2]);
+        bcs.extend(["CCCC"; 12]);
+        bcs.extend(["GGGG"; 3]);
+        bcs.push("NAAA");
+        let r1 = dir.path().join("r1.fq");
+        let content = bcs
+            .iter()
+            .enumerate()
+            .map(|(i, bc)| format!("@r{}\n{}TT\n+\nIIIIII\n", i, bc))
+            .collect::<String>();
+        std::fs::write(&r1, content).unwrap();
+        // read 2 holds no barcode, and is not read
+        let r2 = dir.path().join("missing.fq");
+
+        let out = dir.path().join("out");
+        let (pl, stats) =
+            infer_permit_list("1{b[4]u[2]}2{r:}", &[r1.clone()], &[r2.clone()], 10, &out).unwrap();
+        assert_eq!(std::fs::read_to_string(pl).unwrap(), "AAAA\nCCCC\n");
+        assert_eq!(stats.num_reads, 48);
+        assert_eq!(stats.num_reads_without_barcode, 1);
+        assert_eq!(stats.num_distinct_barcodes, 4);
+        assert_eq!(stats.num_corrected_barcodes, 1);
+        assert_eq!(stats.num_reads_in_list, 44);
+        assert!(out.join(INFERRED_PL_STATS_FILE).is_file());
+
+        assert!(
+            infer_permit_list("1{b[4]u[2]}2{r:}", &[r1.clone()], &[r2.clone()], 100, &out).is_err()
+        );
+        assert!(infer_permit_list("1{b[2-4]u[2]}2{r:}", &[r1], &[r2], 10, &out).is_err());
+    }
+}
@@ -1,14 +1,19 @@
+use crate::utils::executor::Executor;
+use crate::utils::home::simpleaf_info_path;
+use crate::utils::network::NetworkSettings;
 use anyhow::{anyhow, bail, Context, Result};
-use cmd_lib::run_fun;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::env;
 use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::LazyLock;
 use tracing::{debug, error, info, warn};
-use ureq::ResponseExt;
 use which::which;
 
 // The below functions are taken from the [`execute`](https://crates.io/crates/execute)
@@ -42,39 +47,44 @@ pub fn shell<S: AsRef<OsStr>>(cmd: S) -> Command {
     command
 }
 
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Makes every subsequent attempt to download a resource fail right away, for
+/// machines without internet access. It is set by the global `--offline` flag
+/// or the `SIMPLEAF_OFFLINE` environment variable.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Returns an error saying that `resource` is missing, if simpleaf runs
+/// offline and it would have to be downloaded from `url`.
+pub fn ensure_online(resource: &str, url: &str) -> Result<()> {
+    if is_offline() {
+        bail!(
+            "{} is missing, and cannot be downloaded from {} because simpleaf is running \
+            offline (`--offline` or `SIMPLEAF_OFFLINE`). Please import it with \
+            `simpleaf bundle import` (using a bundle made by `simpleaf bundle export` on a \
+            machine with internet access), or run without `--offline`.",
+            resource,
+            url
+        );
+    }
+    Ok(())
+}
+
+/// Fetches the JSON document at `url`, following the [`NetworkSettings`] of
+/// this run (mirrors, proxy, retries and timeouts).
+///
 /// NOTE: the body of the JSON object we fetch cannot exceed 10MB
 /// this is a limitation put in place by `ureq` (see : https://docs.rs/ureq/3.0.0-rc4/ureq/struct.Body.html#method.read_json)
 pub fn read_json_from_remote_url<T: AsRef<str>>(url: T) -> Result<serde_json::Value> {
     let url = url.as_ref();
-
-    let config = ureq::Agent::config_builder()
-        .timeout_recv_response(Some(std::time::Duration::from_secs(120)))
-        .max_redirects(10)
-        .build();
-    let agent = ureq::Agent::new_with_config(config);
-    let response = agent.get(url).call();
-
-    match response {
-        Ok(mut response) => {
-            if response.status().is_success() {
-                return Ok(response.body_mut().read_json()?);
-            } else {
-                let c = response
-                    .status()
-                    .canonical_reason()
-                    .unwrap_or("UNKNOWN FAILURE");
-                warn!(
-                    "Obtained status code {} from final url {}",
-                    c,
-                    response.get_uri()
-                );
-            }
-        }
-        Err(e) => {
-            bail!("could not obtain content from {} because {:#?}", url, e);
-        }
-    }
-    bail!("control should not reach here");
+    ensure_online("The remote JSON file", url)?;
+    NetworkSettings::global().read_json(url)
 }
 
 pub fn download_to_file_compute_hash<T: AsRef<str>>(
@@ -84,55 +94,64 @@ pub fn download_to_file_compute_hash<T: AsRef<str>>(
     let url = url.as_ref();
     download_to_file(url, file_path)
         .with_context(|| format!("failed to download the file from {}", url))?;
-    let new_file = std::fs::File::open(file_path)?;
-    let reader = std::io::BufReader::new(new_file);
-    let mut hasher = blake3::Hasher::new();
-    hasher.update_reader(reader)?;
-    Ok(hasher.finalize())
+    hash_file(file_path)
 }
 
-pub fn download_to_file<T: AsRef<str>>(url: T, file_path: &Path) -> Result<()> {
+/// Downloads `url` to `file_path`, which is only written if the blake3 hash
+/// of the content is `expected_hash`. Otherwise, the download is deleted and
+/// an error is returned.
+pub fn download_to_file_verify_hash<T: AsRef<str>>(
+    url: T,
+    file_path: &Path,
+    expected_hash: &str,
+) -> Result<()> {
     let url = url.as_ref();
+    ensure_online(&format!("The file {}", file_path.display()), url)?;
 
     debug!(
         "Downloading file from {} and writing to file {}",
         url,
         file_path.display()
     );
-
-    let config = ureq::Agent::config_builder()
-        .timeout_recv_response(Some(std::time::Duration::from_secs(120)))
-        .max_redirects(10)
-        .build();
-    let agent = ureq::Agent::new_with_config(config);
-    let response = agent.get(url).call();
-
-    match response {
-        Ok(mut response) => {
-            if response.status().is_success() {
-                let mut req = response.body_mut().as_reader();
-                let f = std::fs::File::create(file_path)
-                    .with_context(|| format!("could not create file {}", file_path.display()))?;
-                let mut ofile = std::io::BufWriter::new(f);
-                std::io::copy(&mut req, &mut ofile)?;
-            } else {
-                let c = response
-                    .status()
-                    .canonical_reason()
-                    .unwrap_or("UNKNOWN FAILURE");
-                warn!(
-                    "Obtained status code {} from final url {}",
-                    c,
-                    response.get_uri()
+    NetworkSettings::global()
+        .download(url, file_path, |part| {
+            let hash = hash_file(part)?.to_string();
+            if hash != expected_hash {
+                bail!(
+                    "the downloaded file does not match the expected hash {} (the observed hash \
+                    was {}); it was deleted",
+                    expected_hash,
+                    hash
                 );
             }
-        }
-        Err(e) => {
-            bail!("could not obtain content from {} because {:#?}", url, e);
-        }
-    }
+            Ok(())
+        })
+        .with_context(|| format!("failed to download the file from {}", url))
+}
 
-    Ok(())
+/// Downloads `url` to `file_path`, following the [`NetworkSettings`] of this
+/// run. `file_path` is only written once the download is complete.
+pub fn download_to_file<T: AsRef<str>>(url: T, file_path: &Path) -> Result<()> {
+    let url = url.as_ref();
+    ensure_online(&format!("The file {}", file_path.display()), url)?;
+
+    debug!(
+        "Downloading file from {} and writing to file {}",
+        url,
+        file_path.display()
+    );
+    NetworkSettings::global().download(url, file_path, |_| Ok(()))
+}
+
+/// Computes the blake3 hash of the content of `file_path`. The file is
+/// memory-mapped and hashed on multiple threads, as it may be large (e.g. a
+/// read file or an index).
+pub fn hash_file(file_path: &Path) -> Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_mmap_rayon(file_path)
+        .with_context(|| format!("could not hash {}", file_path.display()))?;
+    Ok(hasher.finalize())
 }
 
 pub fn get_cmd_line_string(prog: &std::process::Command) -> String {
@@ -145,40 +164,339 @@ pub fn get_cmd_line_string(prog: &std::process::Command) -> String {
     prog_vec.join(" ")
 }
 
+/// A record of one step (usually the invocation of an external
+/// program) performed while running a simpleaf command.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StepInfo {
+    /// The name of the step, e.g. `map` or `quant`
+    pub name: String,
+    /// The command line that was run, if the step ran an external program
+    pub cmd: Option<String>,
+    /// The time it took to run the step
+    #[serde(rename = "time_secs", with = "crate::utils::run_record::duration_secs")]
+    pub duration: std::time::Duration,
+}
+
+impl StepInfo {
+    pub fn new(name: &str, cmd: Option<String>, duration: std::time::Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            cmd,
+            duration,
+        }
+    }
+}
+
+/// The state of an input of a planned step (see [`DryRunPlan`]).
+#[derive(Clone, Debug, PartialEq)]
+enum PlannedInput {
+    Present,
+    /// The input will be produced by the named earlier step
+    ProducedBy(String),
+    Missing,
+}
+
+#[derive(Clone, Debug)]
+struct PlannedStep {
+    name: String,
+    cmd: Option<String>,
+    inputs: Vec<(PathBuf, PlannedInput)>,
+    outputs: Vec<PathBuf>,
+}
+
+/// The plan of a run made with `--dry-run`: the settings that were resolved
+/// and the steps (usually invocations of external programs) that would be
+/// performed, along with the state of the inputs of each step.
+#[derive(Clone, Debug, Default)]
+pub struct DryRunPlan {
+    command: String,
+    resolved: Vec<(String, String)>,
+    steps: Vec<PlannedStep>,
+}
+
+impl DryRunPlan {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Records the value that `what` (e.g. the chemistry) resolved to.
+    pub fn resolve<T: std::fmt::Display>(&mut self, what: &str, value: T) {
+        self.resolved.push((what.to_string(), value.to_string()));
+    }
+
+    /// Adds the step `name`, which runs `cmd` (or is performed by simpleaf
+    /// itself if `cmd` is `None`). Each of the `inputs` must either exist
+    /// or be one of the `outputs` of an earlier step (or lie within one).
+    pub fn add_step(
+        &mut self,
+        name: &str,
+        cmd: Option<&Command>,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+    ) {
+        let inputs = inputs
+            .iter()
+            .map(|p| {
+                let producer = self
+                    .steps
+                    .iter()
+                    .rev()
+                    .find(|s| s.outputs.iter().any(|o| p.starts_with(o)));
+                let state = match producer {
+                    Some(s) => PlannedInput::ProducedBy(s.name.clone()),
+                    None if p.exists() => PlannedInput::Present,
+                    None => PlannedInput::Missing,
+                };
+                (p.clone(), state)
+            })
+            .collect();
+        self.steps.push(PlannedStep {
+            name: name.to_string(),
+            cmd: cmd.map(get_cmd_line_string),
+            inputs,
+            outputs: outputs.to_vec(),
+        });
+    }
+
+    /// The planned steps, with a running time of zero.
+    pub fn steps(&self) -> Vec<StepInfo> {
+        self.steps
+            .iter()
+            .map(|s| StepInfo::new(&s.name, s.cmd.clone(), std::time::Duration::ZERO))
+            .collect()
+    }
+
+    /// Prints the plan to stdout, and returns an error if any of the inputs
+    /// of the planned steps are missing.
+    pub fn finish(&self) -> Result<()> {
+        println!("{} (dry run; nothing will be executed)", self.command);
+        let width = self
+            .resolved
+            .iter()
+            .map(|(k, _)| k.len())
+            .max()
+            .unwrap_or(0);
+        for (k, v) in &self.resolved {
+            println!("  {:<width$} : {}", k, v, width = width);
+        }
+
+        let mut n_missing = 0;
+        for (i, step) in self.steps.iter().enumerate() {
+            println!("[{}/{}] {}", i + 1, self.steps.len(), step.name);
+            match &step.cmd {
+                Some(cmd) => println!("  $ {}", cmd),
+                None => println!("  (performed by simpleaf)"),
+            }
+            for (p, state) in &step.inputs {
+                let state = match state {
+                    PlannedInput::Present => String::from("ok"),
+                    PlannedInput::ProducedBy(s) => format!("from {}", s),
+                    PlannedInput::Missing => {
+                        n_missing += 1;
+                        String::from("MISSING")
+                    }
+                };
+                println!("  input  [{}] {}", state, p.display());
+            }
+            for p in &step.outputs {
+                println!("  output {}", p.display());
+            }
+        }
+
+        if n_missing > 0 {
+            bail!(
+                "{} input(s) of the planned steps are missing; the run would fail",
+                n_missing
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Controls whether the output of the programs we run is forwarded to
+/// the log as it is produced (`Verbose`) or only reported if the program
+/// fails (`Quiet`). In both cases, the output is written to the log files
+/// of the step, if any (see [`execute_logged_command`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CommandVerbosityLevel {
-    #[allow(dead_code)]
     Verbose,
     Quiet,
 }
 
+static VERBOSE_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+impl CommandVerbosityLevel {
+    /// The verbosity level selected with the global `--verbose` flag.
+    pub fn global() -> Self {
+        if VERBOSE_COMMANDS.load(Ordering::Relaxed) {
+            CommandVerbosityLevel::Verbose
+        } else {
+            CommandVerbosityLevel::Quiet
+        }
+    }
+
+    /// Sets the verbosity level returned by [`CommandVerbosityLevel::global`].
+    pub fn set_global(level: CommandVerbosityLevel) {
+        VERBOSE_COMMANDS.store(level == CommandVerbosityLevel::Verbose, Ordering::Relaxed);
+    }
+}
+
+/// The number of trailing lines of each output stream of a command that
+/// are kept in memory, to be reported if the command fails.
+const OUTPUT_TAIL_LINES: usize = 200;
+
+/// Reads `stream` line by line until it is closed, writing each line to
+/// `log_file` (if any) and, if `verbose` is set, to the log. Returns the
+/// last [`OUTPUT_TAIL_LINES`] lines that were read.
+fn tee_stream<R: Read>(
+    stream: R,
+    label: &str,
+    stream_name: &str,
+    mut log_file: Option<(PathBuf, File)>,
+    verbose: bool,
+) -> Vec<u8> {
+    let mut reader = BufReader::new(stream);
+    let mut tail: VecDeque<Vec<u8>> = VecDeque::with_capacity(OUTPUT_TAIL_LINES);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("could not read the {} of {}: {}", stream_name, label, e);
+                break;
+            }
+        }
+
+        if let Some((p, f)) = log_file.as_mut() {
+            if let Err(e) = f.write_all(&line) {
+                warn!(
+                    "could not write to {}: {}; the log will be incomplete",
+                    p.display(),
+                    e
+                );
+                log_file = None;
+            }
+        }
+
+        if verbose {
+            // progress bars redraw the line with carriage returns; only show
+            // the final state of the line
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end().rsplit('\r').next().unwrap_or_default();
+            if !text.is_empty() {
+                info!("[{} {}] {}", label, stream_name, text);
+            }
+        }
+
+        if tail.len() == OUTPUT_TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line.clone());
+    }
+    tail.into_iter().flatten().collect()
+}
+
+/// Creates the file `<log_dir>/<step>.<stream_name>`, returning `None`
+/// (and warning) if this is not possible.
+fn create_log_file(log_dir: &Path, step: &str, stream_name: &str) -> Option<(PathBuf, File)> {
+    let p = log_dir.join(format!("{}.{}", step, stream_name));
+    match std::fs::create_dir_all(log_dir).and_then(|_| File::create(&p)) {
+        Ok(f) => Some((p, f)),
+        Err(e) => {
+            warn!("could not create the log file {}: {}", p.display(), e);
+            None
+        }
+    }
+}
+
+/// Runs `cmd` to completion, streaming its stdout and stderr as described
+/// by `verbosity_level`. The returned `Output` holds only the trailing lines
+/// of each stream.
 pub fn execute_command(
     cmd: &mut std::process::Command,
     verbosity_level: CommandVerbosityLevel,
 ) -> Result<std::process::Output, std::io::Error> {
-    match cmd.output() {
-        Ok(output) if output.status.success() => {
-            info!("command returned successfully ({})", output.status);
-            match verbosity_level {
-                CommandVerbosityLevel::Verbose => {
-                    if !&output.stdout.is_empty() {
-                        info!(
-                            "stdout :\n====\n{}====",
-                            String::from_utf8_lossy(&output.stdout)
-                        );
-                    }
-                    if !&output.stderr.is_empty() {
-                        info!(
-                            "stderr :\n====\n{}====",
-                            String::from_utf8_lossy(&output.stderr)
-                        );
-                    }
-                }
-                CommandVerbosityLevel::Quiet => {}
-            }
-            Ok(output)
+    run_command(cmd, verbosity_level, None)
+}
+
+/// Like [`execute_command`], but also writes the stdout and stderr of `cmd`,
+/// as they are produced, to `<log_dir>/<step>.stdout` and `<log_dir>/<step>.stderr`.
+pub fn execute_logged_command(
+    cmd: &mut std::process::Command,
+    verbosity_level: CommandVerbosityLevel,
+    log_dir: &Path,
+    step: &str,
+) -> Result<std::process::Output, std::io::Error> {
+    run_command(cmd, verbosity_level, Some((log_dir, step)))
+}
+
+fn run_command(
+    cmd: &mut std::process::Command,
+    verbosity_level: CommandVerbosityLevel,
+    log: Option<(&Path, &str)>,
+) -> Result<std::process::Output, std::io::Error> {
+    let verbose = verbosity_level == CommandVerbosityLevel::Verbose;
+    let label = match log {
+        Some((_, step)) => step.to_string(),
+        None => Path::new(cmd.get_program())
+            .file_name()
+            .unwrap_or(cmd.get_program())
+            .to_string_lossy()
+            .to_string(),
+    };
+    let (stdout_log, stderr_log) = match log {
+        Some((log_dir, step)) => (
+            create_log_file(log_dir, step, "stdout"),
+            create_log_file(log_dir, step, "stderr"),
+        ),
+        None => (None, None),
+    };
+
+    let mut child = match cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!("command unsuccessful; error : {}", e);
+            return Err(e);
         }
-        Ok(output) => {
-            error!("command unsuccessful ({}): {:?}", output.status, cmd);
+    };
+    let child_stdout = child.stdout.take().expect("stdout should be piped");
+    let child_stderr = child.stderr.take().expect("stderr should be piped");
+
+    let (stdout, stderr, status) = std::thread::scope(|s| {
+        let label = &label;
+        let out_handle =
+            s.spawn(move || tee_stream(child_stdout, label, "stdout", stdout_log, verbose));
+        let err_handle =
+            s.spawn(move || tee_stream(child_stderr, label, "stderr", stderr_log, verbose));
+        let status = child.wait();
+        (
+            out_handle.join().unwrap_or_default(),
+            err_handle.join().unwrap_or_default(),
+            status,
+        )
+    });
+    let output = std::process::Output {
+        status: status?,
+        stdout,
+        stderr,
+    };
+
+    if output.status.success() {
+        info!("command returned successfully ({})", output.status);
+    } else {
+        error!("command unsuccessful ({}): {:?}", output.status, cmd);
+        // in verbose mode, the output has already been logged
+        if !verbose {
             if !&output.stdout.is_empty() {
                 error!(
                     "stdout :\n====\n{}====",
@@ -191,13 +509,17 @@ pub fn execute_command(
                     String::from_utf8_lossy(&output.stderr)
                 );
             }
-            Ok(output)
         }
-        Err(e) => {
-            error!("command unsuccessful; error : {}", e);
-            Err(e)
+        if let Some((log_dir, step)) = log {
+            error!(
+                "the full output of the command is in {}",
+                log_dir
+                    .join(format!("{}.{{stdout,stderr}}", step))
+                    .display()
+            );
         }
     }
+    Ok(output)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -345,6 +667,7 @@ pub fn get_required_progs_from_paths(
     piscem_exe: Option<PathBuf>,
     alevin_fry_exe: Option<PathBuf>,
     macs_exe: Option<PathBuf>,
+    executor: &Executor,
 ) -> Result<ReqProgs> {
     let mut rp = ReqProgs {
         salmon: None,
@@ -420,8 +743,7 @@ pub fn get_required_progs_from_paths(
     };
 
     if let Some(piscem) = opt_piscem {
-        let st = piscem.display().to_string();
-        let sr = run_fun!($st --version);
+        let sr = executor.version_output(&piscem);
         let v = check_version_constraints_from_output("piscem", ">=0.5.1, <1.0.0", sr)?;
         rp.piscem = Some(ProgInfo {
             exe_path: piscem,
@@ -430,8 +752,7 @@ pub fn get_required_progs_from_paths(
     }
 
     if let Some(salmon) = opt_salmon {
-        let st = salmon.display().to_string();
-        let sr = run_fun!($st --version);
+        let sr = executor.version_output(&salmon);
         let v = check_version_constraints_from_output("salmon", ">=1.10.0, <2.0.0", sr)?;
         rp.salmon = Some(ProgInfo {
             exe_path: salmon,
@@ -440,8 +761,7 @@ pub fn get_required_progs_from_paths(
     }
 
     if let Some(macs) = opt_macs {
-        let st = macs.display().to_string();
-        let sr = run_fun!($st --version);
+        let sr = executor.version_output(&macs);
         let v = check_version_constraints_from_output("macs3", ">=3.0.2, <4.0.0", sr)?;
         rp.macs = Some(ProgInfo {
             exe_path: macs,
@@ -449,8 +769,7 @@ pub fn get_required_progs_from_paths(
         });
     }
 
-    let st = alevin_fry.display().to_string();
-    let sr = run_fun!($st --version);
+    let sr = executor.version_output(&alevin_fry);
     let v = check_version_constraints_from_output("alevin-fry", ">=0.8.1, <1.0.0", sr)?;
     rp.alevin_fry = Some(ProgInfo {
         exe_path: alevin_fry,
@@ -469,7 +788,24 @@ pub fn get_required_progs() -> Result<ReqProgs> {
     let alevin_fry_exe = Some(search_for_executable("ALEVIN_FRY", "alevin-fry")?);
     let macs_exe = Some(search_for_executable("ALEVIN_FRY", "macs3")?);
 
-    get_required_progs_from_paths(salmon_exe, piscem_exe, alevin_fry_exe, macs_exe)
+    get_required_progs_from_paths(
+        salmon_exe,
+        piscem_exe,
+        alevin_fry_exe,
+        macs_exe,
+        &Executor::Local,
+    )
+}
+
+/// Opens `ipath` for reading, decompressing it if it is compressed.
+pub fn get_generic_buf_reader(ipath: &Path) -> anyhow::Result<impl BufRead> {
+    let (reader, compression) = niffler::from_path(ipath)
+        .with_context(|| format!("Could not open requsted file {}", ipath.display()))?;
+    match compression {
+        niffler::compression::Format::No => info!("found uncompressed file"),
+        f => info!("found file compressed using {:?}", f),
+    }
+    Ok(BufReader::new(reader))
 }
 
 pub fn check_files_exist(file_vec: &[PathBuf]) -> Result<()> {
@@ -511,7 +847,7 @@ pub fn read_json(json_path: &Path) -> anyhow::Result<serde_json::Value> {
 
 pub fn inspect_af_home(af_home_path: &Path) -> anyhow::Result<serde_json::Value> {
     // Open the file in read-only mode with buffer.
-    let af_info_p = af_home_path.join("simpleaf_info.json");
+    let af_info_p = simpleaf_info_path(af_home_path);
 
     // try read af info
     let v = read_json(af_info_p.as_path());
@@ -525,3 +861,31 @@ pub fn inspect_af_home(af_home_path: &Path) -> anyhow::Result<serde_json::Value>
         )),
     }
 }
+
+/// Sets `key` to `value` in the `simpleaf_info.json` of `af_home_path`,
+/// keeping its other entries (e.g. the `executor`) as they are. The file
+/// (and the configuration directory holding it) is created if it does not
+/// exist.
+pub fn update_af_home_info(
+    af_home_path: &Path,
+    key: &str,
+    value: serde_json::Value,
+) -> anyhow::Result<()> {
+    let af_info_p = simpleaf_info_path(af_home_path);
+    let mut v = if af_info_p.is_file() {
+        read_json(af_info_p.as_path())?
+    } else {
+        if let Some(config_dir) = af_info_p.parent() {
+            std::fs::create_dir_all(config_dir)
+                .with_context(|| format!("could not create {}", config_dir.display()))?;
+        }
+        serde_json::json!({})
+    };
+    match v.as_object_mut() {
+        Some(obj) => {
+            obj.insert(key.to_string(), value);
+        }
+        None => bail!("{} does not hold a JSON object", af_info_p.display()),
+    }
+    crate::utils::run_record::write_json_atomic(&af_info_p, &v)
+}
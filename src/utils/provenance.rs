@@ -0,0 +1,210 @@
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::info;
+
+use crate::utils::af_utils::Chemistry;
+use crate::utils::chem_utils::{get_single_custom_chem_from_file, QueryInRegistry};
+use crate::utils::constants::CHEMISTRIES_PATH;
+use crate::utils::prog_utils::{hash_file, ReqProgs};
+use crate::utils::run_record::write_json_atomic;
+
+/// The name of the provenance file written to the output directory of a run.
+pub static PROVENANCE_FILE: &str = "provenance.json";
+
+/// The size and blake3 hash of a file consumed or produced by a run.
+#[derive(Clone, Debug, Serialize)]
+pub struct FileDigest {
+    /// What the file is used for, e.g. `reads1` or `count_matrix`
+    pub role: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub blake3: String,
+}
+
+impl FileDigest {
+    pub fn new(role: &str, path: &Path) -> anyhow::Result<Self> {
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("could not read the metadata of {}", path.display()))?
+            .len();
+        Ok(Self {
+            role: role.to_string(),
+            path: std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            size,
+            blake3: hash_file(path)?.to_hex().to_string(),
+        })
+    }
+}
+
+/// A record of what went into and came out of a run of a simpleaf command:
+/// the versions of simpleaf and of the programs it ran, the resolved
+/// chemistry (if any) and the hashes of the input and output files.
+/// It is written to [`PROVENANCE_FILE`] in the output directory.
+#[derive(Clone, Debug, Serialize)]
+pub struct Provenance {
+    simpleaf_version: String,
+    command: String,
+    created_at: u64,
+    tool_versions: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chemistry: Option<Value>,
+    inputs: Vec<FileDigest>,
+    outputs: Vec<FileDigest>,
+}
+
+impl Provenance {
+    pub fn new(command: &str, rp: &ReqProgs) -> Self {
+        Self {
+            simpleaf_version: env!("CARGO_PKG_VERSION").to_string(),
+            command: command.to_string(),
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            tool_versions: serde_json::to_value(rp).unwrap_or_default(),
+            chemistry: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Records the chemistry the run used, along with its definition in the
+    /// chemistry registry of `af_home_path`, if it has one. The registry is
+    /// not downloaded if it is absent.
+    pub fn set_chemistry(&mut self, chem: &Chemistry, af_home_path: &Path) -> anyhow::Result<()> {
+        let chem_p = af_home_path.join(CHEMISTRIES_PATH);
+        let definition = match chem {
+            Chemistry::Custom(cc) => Some(cc.clone()),
+            _ if chem_p.is_file() => {
+                get_single_custom_chem_from_file(&chem_p, chem.registry_key())?
+            }
+            _ => None,
+        };
+        self.chemistry = Some(json!({
+            "name" : chem.as_str(),
+            "geometry" : chem.fragment_geometry_str(),
+            "expected_ori" : chem.expected_ori().as_str(),
+            "definition" : definition
+        }));
+        Ok(())
+    }
+
+    /// Hashes the input file `path`.
+    pub fn add_input(&mut self, role: &str, path: &Path) -> anyhow::Result<()> {
+        self.inputs.push(FileDigest::new(role, path)?);
+        Ok(())
+    }
+
+    /// Hashes each of the input files in `paths`.
+    pub fn add_inputs(&mut self, role: &str, paths: &[PathBuf]) -> anyhow::Result<()> {
+        for p in paths {
+            self.add_input(role, p)?;
+        }
+        Ok(())
+    }
+
+    /// Hashes the output file `path`.
+    pub fn add_output(&mut self, role: &str, path: &Path) -> anyhow::Result<()> {
+        self.outputs.push(FileDigest::new(role, path)?);
+        Ok(())
+    }
+
+    /// Hashes all of the files under the output directory `dir`, in order
+    /// of their paths.
+    pub fn add_output_dir(&mut self, role: &str, dir: &Path) -> anyhow::Result<()> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(d) = dirs.pop() {
+            for entry in std::fs::read_dir(&d)
+                .with_context(|| format!("could not read directory {}", d.display()))?
+            {
+                let p = entry?.path();
+                if p.is_dir() {
+                    dirs.push(p);
+                } else {
+                    files.push(p);
+                }
+            }
+        }
+        files.sort();
+        for p in files {
+            self.add_output(role, &p)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the provenance record to [`PROVENANCE_FILE`] in `dir` (see
+    /// [`write_json_atomic`]).
+    pub fn write(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let p = dir.join(PROVENANCE_FILE);
+        write_json_atomic(&p, self)?;
+        info!("wrote the provenance of the run to {}", p.display());
+        Ok(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_progs() -> ReqProgs {
+        ReqProgs {
+            salmon: None,
+            piscem: None,
+            alevin_fry: None,
+            macs: None,
+        }
+    }
+
+    #[test]
+    fn test_file_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let p = dir.path().join("reads.fq");
+        std::fs::write(&p, "@r1\nACGT\n+\nIIII\n").unwrap();
+
+        let digest = FileDigest::new("reads1", &p).unwrap();
+        assert_eq!(digest.role, "reads1");
+        assert_eq!(digest.path, std::fs::canonicalize(&p).unwrap());
+        assert_eq!(digest.size, 16);
+        assert_eq!(
+            digest.blake3,
+            blake3::hash(b"@r1\nACGT\n+\nIIII\n").to_hex().to_string()
+        );
+        assert!(FileDigest::new("reads1", &dir.path().join("missing.fq")).is_err());
+    }
+
+    #[test]
+    fn test_provenance_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("t2g.tsv");
+        std::fs::write(&input, "tx1\tg1\n").unwrap();
+        let out_dir = dir.path().join("out");
+        std::fs::create_dir_all(out_dir.join("sub")).unwrap();
+        std::fs::write(out_dir.join("b.txt"), "b").unwrap();
+        std::fs::write(out_dir.join("sub/a.txt"), "a").unwrap();
+
+        let mut provenance = Provenance::new("quant", &no_progs());
+        provenance.add_input("t2g_map", &input).unwrap();
+        provenance.add_output_dir("quant", &out_dir).unwrap();
+        let p = provenance.write(dir.path()).unwrap();
+        assert_eq!(p, dir.path().join(PROVENANCE_FILE));
+
+        let v: Value = serde_json::from_str(&std::fs::read_to_string(&p).unwrap()).unwrap();
+        assert_eq!(v["command"], "quant");
+        assert_eq!(v["simpleaf_version"], env!("CARGO_PKG_VERSION"));
+        assert!(v.get("chemistry").is_none());
+        assert_eq!(v["inputs"][0]["role"], "t2g_map");
+        assert_eq!(v["inputs"][0]["size"], 7);
+        // the files of an output directory are listed in order of their paths
+        let outputs: Vec<&str> = v["outputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|o| o["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(outputs.len(), 2);
+        assert!(outputs[0].ends_with("b.txt") && outputs[1].ends_with("a.txt"));
+    }
+}
@@ -0,0 +1,500 @@
+use crate::utils::prog_utils::get_generic_buf_reader;
+
+use anyhow::{bail, Context};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// The number of read pairs sampled from the start of each pair of read
+/// files to check their names and lengths.
+pub const NUM_CHECKED_READS: usize = 10_000;
+
+/// The smallest fraction of the sampled read pairs whose lengths must fit the
+/// geometry; below it, the files are rejected, and above it, the reads that
+/// do not fit are only reported.
+const MIN_FITTING_RATE: f64 = 0.9;
+
+/// The bounds on the length of a read following a geometry, and the
+/// position of its barcode.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ReadLayout {
+    pub min_len: usize,
+    /// `None` if the read can be arbitrarily long
+    pub max_len: Option<usize>,
+    /// The offset and length of each barcode piece, or `None` if one of them
+    /// does not start at a fixed offset
+    pub barcode: Option<Vec<(usize, usize)>>,
+}
+
+impl ReadLayout {
+    /// Parses the description of a read (e.g. `b[16]u[12]x:`), of which only
+    /// the lengths of the pieces and the barcode pieces (`b`) matter.
+    fn parse(desc: &str) -> Option<Self> {
+        let mut layout = ReadLayout {
+            min_len: 0,
+            max_len: Some(0),
+            barcode: Some(Vec::new()),
+        };
+        let mut offset = Some(0);
+        let mut rest = desc;
+        while let Some(kind) = rest.chars().next() {
+            rest = &rest[1..];
+            let (min, max) = if let Some(r) = rest.strip_prefix(':') {
+                rest = r;
+                (0, None)
+            } else {
+                let (len, r) = rest.strip_prefix('[')?.split_once(']')?;
+                rest = r;
+                if kind == 'f' {
+                    (len.len(), Some(len.len()))
+                } else if let Some((lo, hi)) = len.split_once('-') {
+                    (lo.parse().ok()?, Some(hi.parse().ok()?))
+                } else {
+                    let n = len.parse().ok()?;
+                    (n, Some(n))
+                }
+            };
+            match kind {
+                'b' => match (offset, max) {
+                    (Some(o), Some(m)) if m == min => {
+                        if let Some(b) = layout.barcode.as_mut() {
+                            b.push((o, min));
+                        }
+                    }
+                    _ => layout.barcode = None,
+                },
+                'u' | 'r' | 'x' | 'f' => {}
+                _ => return None,
+            }
+            layout.min_len += min;
+            layout.max_len = layout.max_len.zip(max).map(|(a, b)| a + b);
+            offset = offset.zip(max.filter(|m| *m == min)).map(|(o, l)| o + l);
+        }
+        Some(layout)
+    }
+
+    pub fn fits(&self, len: usize) -> bool {
+        len >= self.min_len && self.max_len.is_none_or(|m| len <= m)
+    }
+
+    /// The lengths allowed by the layout, e.g. "at least 28 bases".
+    fn describe(&self) -> String {
+        match self.max_len {
+            None => format!("at least {} bases", self.min_len),
+            Some(m) if m == self.min_len => format!("{} bases", m),
+            Some(m) => format!("{} to {} bases", self.min_len, m),
+        }
+    }
+}
+
+/// Parses a geometry (e.g. `1{b[16]u[12]x:}2{r:}`, the syntax parsed into a
+/// `FragmentGeomDesc`) into the layouts of its two reads.
+pub(crate) fn read_layouts(geometry: &str) -> Option<[ReadLayout; 2]> {
+    let (r1, r2) = geometry
+        .trim()
+        .strip_prefix("1{")?
+        .strip_suffix('}')?
+        .split_once("}2{")?;
+    Some([ReadLayout::parse(r1)?, ReadLayout::parse(r2)?])
+}
+
+/// A record of a FASTQ file.
+pub(crate) struct FastqRecord {
+    /// The header line, without the leading `@`
+    pub header: String,
+    pub seq: Vec<u8>,
+}
+
+impl FastqRecord {
+    /// The name of the read, without the `/1` or `/2` suffix marking the
+    /// mate, which is shared by both reads of a pair.
+    fn pair_name(&self) -> &str {
+        let name = self.header.split_whitespace().next().unwrap_or_default();
+        name.strip_suffix("/1")
+            .or_else(|| name.strip_suffix("/2"))
+            .unwrap_or(name)
+    }
+
+    /// The mate of the read (1 or 2), from the `/1` or `/2` suffix of its
+    /// name or the Illumina comment (e.g. `2:N:0:ATCACG`), if there is one.
+    fn mate(&self) -> Option<u8> {
+        let mut fields = self.header.split_whitespace();
+        let name = fields.next()?;
+        if let Some(c) = fields.next() {
+            if let [m @ (b'1' | b'2'), b':', ..] = c.as_bytes() {
+                return Some(m - b'0');
+            }
+        }
+        match name.as_bytes() {
+            [.., b'/', m @ (b'1' | b'2')] => Some(m - b'0'),
+            _ => None,
+        }
+    }
+}
+
+/// Reads (at most) the first `n` records of the FASTQ file `path`, checking
+/// that they are well-formed.
+pub(crate) fn read_records(path: &Path, n: usize) -> anyhow::Result<Vec<FastqRecord>> {
+    let mut lines = get_generic_buf_reader(path)?.lines();
+    let mut records = Vec::with_capacity(n);
+    while records.len() < n {
+        let Some(header) = lines.next() else {
+            break;
+        };
+        let header = header.with_context(|| format!("could not read {}", path.display()))?;
+        let Some(header) = header.strip_prefix('@') else {
+            bail!(
+                "{} is not a FASTQ file: record {} does not start with `@`.",
+                path.display(),
+                records.len() + 1
+            );
+        };
+        let mut rest = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let line = lines
+                .next()
+                .transpose()
+                .with_context(|| format!("could not read {}", path.display()))?
+                .with_context(|| format!("{} ends in the middle of a record.", path.display()))?;
+            rest.push(line);
+        }
+        if !rest[1].starts_with('+') || rest[0].len() != rest[2].len() {
+            bail!(
+                "{} is not a valid FASTQ file: record {} ({}) does not have a `+` line followed by \
+                a quality string as long as its sequence.",
+                path.display(),
+                records.len() + 1,
+                header
+            );
+        }
+        records.push(FastqRecord {
+            header: header.to_string(),
+            seq: rest.swap_remove(0).into_bytes(),
+        });
+    }
+    Ok(records)
+}
+
+/// Checks the first `num_reads` read pairs of `reads1` and `reads2`: the two
+/// files must hold the same reads, in the same order, as read 1 and read 2
+/// respectively, and the lengths of the reads should fit `layouts`.
+fn check_sampled_pairs(
+    reads1: &Path,
+    reads2: &Path,
+    geometry: &str,
+    layouts: Option<&[ReadLayout; 2]>,
+    num_reads: usize,
+) -> anyhow::Result<()> {
+    let r1 = read_records(reads1, num_reads)?;
+    let r2 = read_records(reads2, num_reads)?;
+    if r1.is_empty() && r2.is_empty() {
+        bail!(
+            "{} and {} contain no reads.",
+            reads1.display(),
+            reads2.display()
+        );
+    }
+    if r1.len() != r2.len() {
+        let (short, long, n) = if r1.len() < r2.len() {
+            (reads1, reads2, r1.len())
+        } else {
+            (reads2, reads1, r2.len())
+        };
+        bail!(
+            "{} ends after {} reads, while {} has more: the read 1 and read 2 files do not have the \
+            same number of reads. One of them may be truncated, or they may not belong together.",
+            short.display(),
+            n,
+            long.display()
+        );
+    }
+
+    if let (Some(2), Some(1)) = (r1[0].mate(), r2[0].mate()) {
+        bail!(
+            "The reads of {} are marked as read 2 and those of {} as read 1; the read 1 and read 2 \
+            files seem to be swapped. Please pass the read 1 files to `--reads1` and the read 2 \
+            files to `--reads2`.",
+            reads1.display(),
+            reads2.display()
+        );
+    }
+    if let Some((i, (a, b))) = r1
+        .iter()
+        .zip(r2.iter())
+        .enumerate()
+        .find(|(_, (a, b))| a.pair_name() != b.pair_name())
+    {
+        bail!(
+            "The reads of {} and {} are not paired: read {} is named `{}` in the first file and \
+            `{}` in the second one. Please check that they are the read 1 and read 2 files of the \
+            same sample, and that they are given in the same order.",
+            reads1.display(),
+            reads2.display(),
+            i + 1,
+            a.pair_name(),
+            b.pair_name()
+        );
+    }
+
+    let Some([l1, l2]) = layouts else {
+        return Ok(());
+    };
+    let n = r1.len();
+    let fitting = r1
+        .iter()
+        .zip(r2.iter())
+        .filter(|(x, y)| l1.fits(x.seq.len()) && l2.fits(y.seq.len()))
+        .count();
+    if fitting == n {
+        return Ok(());
+    }
+    let fitting_rate = fitting as f64 / n as f64;
+    let expected = format!(
+        "the geometry {} expects reads 1 of {} and reads 2 of {}",
+        geometry,
+        l1.describe(),
+        l2.describe()
+    );
+    if fitting_rate >= MIN_FITTING_RATE {
+        warn!(
+            "{} of the first {} read pairs of {} and {} do not fit the chemistry ({}); they will \
+            not be quantified.",
+            n - fitting,
+            n,
+            reads1.display(),
+            reads2.display(),
+            expected
+        );
+        return Ok(());
+    }
+    let short = r1
+        .iter()
+        .zip(r2.iter())
+        .filter(|(x, y)| x.seq.len() < l1.min_len || y.seq.len() < l2.min_len)
+        .count();
+    bail!(
+        "Only {:.1}% of the first {} read pairs of {} and {} fit the chemistry, {} of them being \
+        too short: {}. Please check the chemistry, and that the reads were not trimmed.",
+        100.0 * fitting_rate,
+        n,
+        reads1.display(),
+        reads2.display(),
+        short,
+        expected
+    )
+}
+
+/// Reads the whole of the (possibly gzip-compressed) FASTQ file `path`, and
+/// returns its number of records. This fails if a gzip file does not end
+/// cleanly, or if the file ends in the middle of a record.
+fn count_records(path: &Path) -> anyhow::Result<u64> {
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let is_gzip = reader
+        .fill_buf()
+        .with_context(|| format!("could not read {}", path.display()))?
+        .starts_with(&[0x1f, 0x8b]);
+    let mut reader: Box<dyn Read> = if is_gzip {
+        Box::new(flate2::read::MultiGzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut buf = vec![0_u8; 1 << 16];
+    let (mut num_lines, mut last) = (0_u64, b'\n');
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) if is_gzip => bail!(
+                "{} is truncated or corrupt ({}); it may not have been completely downloaded or \
+                copied.",
+                path.display(),
+                e
+            ),
+            Err(e) => return Err(e).with_context(|| format!("could not read {}", path.display())),
+        };
+        num_lines += buf[..n].iter().filter(|c| **c == b'\n').count() as u64;
+        last = buf[n - 1];
+    }
+    if last != b'\n' {
+        num_lines += 1;
+    }
+    if num_lines % 4 != 0 {
+        bail!(
+            "{} ends in the middle of a record; it may be truncated.",
+            path.display()
+        );
+    }
+    Ok(num_lines / 4)
+}
+
+/// Checks the read files of a sample before they are mapped, so that swapped,
+/// mismatched or too short read files are reported before the mapper is run:
+/// the first `num_reads` reads of each pair of files must be paired (i.e.
+/// have the same names), and their lengths must fit `geometry`. Only these
+/// reads are read; see [`check_read_integrity`] for a check of the whole
+/// files.
+pub fn check_read_files(
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+    geometry: &str,
+    num_reads: usize,
+) -> anyhow::Result<()> {
+    let layouts = read_layouts(geometry);
+    if layouts.is_none() {
+        info!(
+            "The lengths of the reads are not checked, as the geometry {} does not spell out the \
+            layout of the reads.",
+            geometry
+        );
+    }
+    for (r1, r2) in reads1.iter().zip(reads2.iter()) {
+        check_sampled_pairs(r1, r2, geometry, layouts.as_ref(), num_reads)?;
+    }
+    Ok(())
+}
+
+/// Reads the read files of a sample completely, by up to `threads` threads,
+/// to check that they are not truncated (gzip files must end cleanly) and
+/// that the files of each pair have the same number of reads. As this reads
+/// (and decompresses) all of the input, it is only run on request.
+pub fn check_read_integrity(
+    reads1: &[PathBuf],
+    reads2: &[PathBuf],
+    threads: usize,
+) -> anyhow::Result<()> {
+    info!(
+        "Reading the {} read files completely to check their integrity.",
+        reads1.len() + reads2.len()
+    );
+    let files: Vec<&PathBuf> = reads1.iter().chain(reads2.iter()).collect();
+    let counts: Mutex<Vec<Option<anyhow::Result<u64>>>> =
+        Mutex::new(files.iter().map(|_| None).collect());
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..threads.clamp(1, files.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(f) = files.get(i) else {
+                    break;
+                };
+                let count = count_records(f);
+                counts.lock().unwrap()[i] = Some(count);
+            });
+        }
+    });
+    let counts = counts
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|c| c.expect("every read file is counted"))
+        .collect::<anyhow::Result<Vec<u64>>>()?;
+    let (counts1, counts2) = counts.split_at(reads1.len());
+    for ((r1, r2), (n1, n2)) in reads1
+        .iter()
+        .zip(reads2.iter())
+        .zip(counts1.iter().zip(counts2.iter()))
+    {
+        if n1 != n2 {
+            bail!(
+                "{} has {} reads, but {} has {}: the read 1 and read 2 files do not have the same \
+                number of reads. One of them may be truncated, or they may not belong together.",
+                r1.display(),
+                n1,
+                r2.display(),
+                n2
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read_layouts() {
+        let [r1, r2] = read_layouts("1{b[16]u[12]x:}2{r:}").unwrap();
+        assert_eq!((r1.min_len, r1.max_len), (28, None));
+        assert_eq!(r1.barcode, Some(vec![(0, 16)]));
+        assert!(r1.fits(28) && r1.fits(150) && !r1.fits(26));
+        assert_eq!(r2.barcode, Some(vec![]));
+
+        let [r1, _] = read_layouts("1{b[9-10]f[CAGAGC]u[8]b[10]}2{r:}").unwrap();
+        assert_eq!((r1.min_len, r1.max_len), (33, Some(34)));
+        assert_eq!(r1.barcode, None);
+
+        let [_, r2] = read_layouts("1{r:}2{x[10]b[8]x[30]b[8]}").unwrap();
+        assert_eq!(r2.barcode, Some(vec![(10, 8), (48, 8)]));
+        assert_eq!(r2.max_len, Some(56));
+
+        assert!(read_layouts("__builtin").is_none());
+        assert!(read_layouts("1{b[16}2{r:}").is_none());
+    }
+
+    #[test]
+    fn test_check_read_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let fastq = |name: &str, mate: u8, lens: &[usize]| {
+            let p = dir.path().join(name);
+            let content = lens
+                .iter()
+                .enumerate()
+                .map(|(i, l)| {
+                    format!(
+                        "@r{} {}:N:0\n{}\n+\n{}\n",
+                        i,
+                        mate,
+                        "A".repeat(*l),
+                        "I".repeat(*l)
+                    )
+                })
+                .collect::<String>();
+            std::fs::write(&p, content).unwrap();
+            vec![p]
+        };
+        let geometry = "1{b[16]u[12]x:}2{r:}";
+        let check = |r1: &[PathBuf], r2: &[PathBuf]| {
+            check_read_files(r1, r2, geometry, 100).map_err(|e| e.to_string())
+        };
+
+        let r1 = fastq("r1.fq", 1, &[28; 4]);
+        let r2 = fastq("r2.fq", 2, &[90; 4]);
+        assert!(check(&r1, &r2).is_ok());
+        assert!(check(&r2, &r1).unwrap_err().contains("swapped"));
+
+        let short = fastq("short.fq", 1, &[20; 4]);
+        assert!(check(&short, &r2).unwrap_err().contains("too short"));
+
+        let fewer = fastq("fewer.fq", 2, &[90; 3]);
+        assert!(check_read_integrity(&r1, &r2, 2).is_ok());
+        assert!(check_read_integrity(&r1, &fewer, 2)
+            .unwrap_err()
+            .to_string()
+            .contains("do not have the same number of reads"));
+
+        let other = dir.path().join("other.fq");
+        std::fs::write(&other, "@x 2:N:0\nAAAA\n+\nIIII\n".repeat(4)).unwrap();
+        assert!(check(&r1, &[other]).unwrap_err().contains("are not paired"));
+
+        // a gzip file cut short
+        let gz = dir.path().join("r2.fq.gz");
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(&std::fs::read(&r2[0]).unwrap()).unwrap();
+        let bytes = enc.finish().unwrap();
+        std::fs::write(&gz, &bytes).unwrap();
+        assert_eq!(count_records(&gz).unwrap(), 4);
+        std::fs::write(&gz, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(count_records(&gz)
+            .unwrap_err()
+            .to_string()
+            .contains("truncated"));
+    }
+}
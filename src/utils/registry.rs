@@ -0,0 +1,612 @@
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{error, info, warn};
+
+use crate::utils::archive;
+use crate::utils::home::simpleaf_info_path;
+use crate::utils::prog_utils::{self, update_af_home_info};
+use crate::utils::workflow_utils::{ProtocolEstuary, RegistrySourceStrategy};
+
+/// The name of the registry holding the published simpleaf workflows.
+pub static DEFAULT_REGISTRY: &str = "protocol-estuary";
+static DEFAULT_REGISTRY_URL: &str =
+    "https://github.com/COMBINE-lab/protocol-estuary/archive/refs/heads/main.zip";
+/// The key of the configured registries in `simpleaf_info.json`.
+static REGISTRIES_KEY: &str = "registries";
+
+/// Where the workflow templates of a registry come from. A registry holds
+/// a `protocols` directory, with one sub-directory per workflow, and
+/// optionally a `utils` directory with the Jsonnet libraries they import.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RegistrySource {
+    /// A directory on the local file system, which is used in place.
+    Local { path: PathBuf },
+    /// A git repository, checked out at `rev` (a commit, a tag or a branch).
+    Git { url: String, rev: String },
+    /// A zip or tar.gz archive of the registry, e.g. the GitHub archive of a
+    /// tag, optionally verified against its blake3 `checksum`.
+    #[serde(alias = "zip")]
+    Archive {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<String>,
+    },
+}
+
+impl fmt::Display for RegistrySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistrySource::Local { path } => write!(f, "{}", path.display()),
+            RegistrySource::Git { url, rev } => write!(f, "{}@{}", url, rev),
+            RegistrySource::Archive { url, .. } => write!(f, "{}", url),
+        }
+    }
+}
+
+/// A named source of workflow templates, as recorded in the `registries`
+/// field of `simpleaf_info.json`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Registry {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: RegistrySource,
+    /// The revision of the local copy of the registry: the commit checked
+    /// out for git registries (and local directories that are git
+    /// repositories), and the blake3 hash of the archive for archive registries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+impl Registry {
+    fn default_registry() -> Self {
+        Registry {
+            name: DEFAULT_REGISTRY.to_string(),
+            source: RegistrySource::Archive {
+                url: DEFAULT_REGISTRY_URL.to_string(),
+                checksum: None,
+            },
+            revision: None,
+        }
+    }
+
+    /// Checks that the name of the registry can name the directory of its
+    /// local copy.
+    fn check_name(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() || self.name.contains(['/', '\\']) || self.name.starts_with('.') {
+            bail!(
+                "`{}` is not a valid registry name: it must be non-empty, must not start with `.` \
+                and must not contain `/`.",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that a local copy of the registry, made on another machine,
+    /// can be imported: its name must be valid, and it must not be a local
+    /// directory (which has no copy).
+    pub fn check_importable(&self) -> anyhow::Result<()> {
+        self.check_name()?;
+        if let RegistrySource::Local { path } = &self.source {
+            bail!(
+                "The registry {} is the local directory {}, which cannot be imported.",
+                self.name,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// The directory holding the local copy of the registry.
+    fn dir(&self, af_home_path: &Path) -> PathBuf {
+        match &self.source {
+            RegistrySource::Local { path } => path.clone(),
+            // kept where it was before other registries could be added
+            _ if self.name == DEFAULT_REGISTRY => af_home_path.join(DEFAULT_REGISTRY),
+            _ => af_home_path.join("registries").join(&self.name),
+        }
+    }
+
+    /// Finds the root of the local copy of the registry.
+    fn root(&self, af_home_path: &Path) -> Option<PathBuf> {
+        find_root(&self.dir(af_home_path))
+    }
+
+    /// Returns the local copy of the registry, fetching it first if there is
+    /// none or if `rss` asks for a refresh. The revision of the copy is
+    /// updated when it is fetched.
+    fn checkout(
+        &mut self,
+        af_home_path: &Path,
+        rss: &RegistrySourceStrategy,
+    ) -> anyhow::Result<ProtocolEstuary> {
+        let present = self.root(af_home_path);
+        let root = match present {
+            Some(root) if !rss.is_force_refresh() => root,
+            _ => {
+                self.fetch(af_home_path)?;
+                self.root(af_home_path).with_context(|| {
+                    format!(
+                        "The registry {} ({}) does not hold a `protocols` directory.",
+                        self.name, self.source
+                    )
+                })?
+            }
+        };
+        Ok(ProtocolEstuary {
+            protocols_dir: root.join("protocols"),
+            utils_dir: root.join("utils"),
+        })
+    }
+
+    /// Fetches the registry from its source and records its revision.
+    fn fetch(&mut self, af_home_path: &Path) -> anyhow::Result<()> {
+        let dir = self.dir(af_home_path);
+        match &self.source {
+            RegistrySource::Local { path } => {
+                if !path.is_dir() {
+                    bail!(
+                        "The directory {} of the registry {} does not exist.",
+                        path.display(),
+                        self.name
+                    );
+                }
+                // local directories are used as they are, only their
+                // revision is recorded if they are under version control
+                self.revision = git(path, &["rev-parse", "HEAD"]).ok();
+            }
+            RegistrySource::Git { url, rev } => {
+                if !url.starts_with("file://") && !Path::new(url).exists() {
+                    prog_utils::ensure_online(
+                        &format!("The workflow registry {}", self.name),
+                        url,
+                    )?;
+                }
+                info!("fetching {} of {} for the registry {}", rev, url, self.name);
+                std::fs::create_dir_all(&dir)?;
+                if !dir.join(".git").exists() {
+                    git(&dir, &["init", "--quiet"])?;
+                    git(&dir, &["remote", "add", "origin", url])?;
+                }
+                git(&dir, &["fetch", "--quiet", "--depth", "1", "origin", rev])?;
+                git(
+                    &dir,
+                    &["checkout", "--quiet", "--force", "--detach", "FETCH_HEAD"],
+                )?;
+                self.revision = Some(git(&dir, &["rev-parse", "HEAD"])?);
+            }
+            RegistrySource::Archive { url, checksum } => {
+                prog_utils::ensure_online(&format!("The workflow registry {}", self.name), url)?;
+                info!("downloading the registry {} from {}", self.name, url);
+                let parent = dir.parent().unwrap_or(af_home_path);
+                std::fs::create_dir_all(parent)?;
+                // the archive is downloaded and extracted next to the local
+                // copy, which is only replaced once all of it succeeded
+                let staging = tempfile::Builder::new()
+                    .prefix(&format!(".{}-", self.name))
+                    .tempdir_in(parent)
+                    .with_context(|| {
+                        format!("could not create a directory in {}", parent.display())
+                    })?;
+                let archive_file = staging.path().join("archive");
+                let hash = prog_utils::download_to_file_compute_hash(url, &archive_file)?
+                    .to_hex()
+                    .to_string();
+                if let Some(expected) = checksum {
+                    if !hash.eq_ignore_ascii_case(expected) {
+                        bail!(
+                            "The archive of the registry {} downloaded from {} has the blake3 \
+                            hash {}, but {} was expected.",
+                            self.name,
+                            url,
+                            hash,
+                            expected
+                        );
+                    }
+                }
+                let extracted = staging.path().join("extracted");
+                archive::extract_archive(&archive_file, &extracted).with_context(|| {
+                    format!(
+                        "could not extract the archive of the registry {}",
+                        self.name
+                    )
+                })?;
+                if find_root(&extracted).is_none() {
+                    bail!(
+                        "The archive of the registry {} does not hold a `protocols` directory.",
+                        self.name
+                    );
+                }
+                replace_dir(&extracted, &dir, staging.path())?;
+                self.revision = Some(hash);
+            }
+        }
+        info!(
+            "The registry {} is at revision {}.",
+            self.name,
+            self.revision.as_deref().unwrap_or("N/A")
+        );
+        Ok(())
+    }
+}
+
+/// Finds the root of a registry in `dir`, i.e. the directory holding
+/// `protocols`. Archives usually hold a single top-level directory, so that
+/// is searched too.
+fn find_root(dir: &Path) -> Option<PathBuf> {
+    if dir.join("protocols").is_dir() {
+        return Some(dir.to_path_buf());
+    }
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .find(|p| p.join("protocols").is_dir())
+}
+
+/// Moves `new` to `dir`, replacing what was there. The previous `dir` is
+/// moved into `trash` first, and is put back if `new` cannot be moved; both
+/// must be on the same file system as `dir`.
+fn replace_dir(new: &Path, dir: &Path, trash: &Path) -> anyhow::Result<()> {
+    let previous = trash.join("previous");
+    let had_previous = dir.exists();
+    if had_previous {
+        std::fs::rename(dir, &previous)
+            .with_context(|| format!("could not move {} out of the way", dir.display()))?;
+    }
+    if let Err(e) = std::fs::rename(new, dir) {
+        if had_previous {
+            std::fs::rename(&previous, dir).with_context(|| {
+                format!(
+                    "could not restore the previous copy of {}, which is now at {}",
+                    dir.display(),
+                    previous.display()
+                )
+            })?;
+        }
+        return Err(e).with_context(|| format!("could not move {} into place", dir.display()));
+    }
+    Ok(())
+}
+
+/// Runs git with `args` in `dir`, and returns its trimmed standard output.
+fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("could not run git; please make sure it is installed and on the PATH")?;
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed in {}: {}",
+            args.join(" "),
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The registries configured in an `ALEVIN_FRY_HOME` directory. The default
+/// registry always comes first.
+pub struct Registries {
+    af_home_path: PathBuf,
+    registries: Vec<Registry>,
+}
+
+impl Registries {
+    /// Reads the registries recorded in the `simpleaf_info.json` of
+    /// `af_home_path`, if any.
+    pub fn load(af_home_path: &Path) -> anyhow::Result<Self> {
+        let info_path = simpleaf_info_path(af_home_path);
+        let mut registries: Vec<Registry> = if info_path.is_file() {
+            let v = prog_utils::read_json(&info_path)?;
+            match v.get(REGISTRIES_KEY) {
+                None | Some(Value::Null) => Vec::new(),
+                Some(r) => serde_json::from_value(r.clone())
+                    .context("invalid `registries` entry in simpleaf_info.json")?,
+            }
+        } else {
+            Vec::new()
+        };
+        if !registries.iter().any(|r| r.name == DEFAULT_REGISTRY) {
+            registries.insert(0, Registry::default_registry());
+        }
+        Ok(Registries {
+            af_home_path: af_home_path.to_path_buf(),
+            registries,
+        })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        update_af_home_info(
+            &self.af_home_path,
+            REGISTRIES_KEY,
+            serde_json::to_value(&self.registries)?,
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Registry> {
+        self.registries.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Registry> {
+        self.registries.iter().find(|r| r.name == name)
+    }
+
+    /// Returns the local copy of the registry `name`, fetching it if needed
+    /// (see [`RegistrySourceStrategy`]).
+    pub fn checkout(
+        &mut self,
+        name: &str,
+        rss: RegistrySourceStrategy,
+    ) -> anyhow::Result<ProtocolEstuary> {
+        let af_home_path = self.af_home_path.clone();
+        let registry = self
+            .registries
+            .iter_mut()
+            .find(|r| r.name == name)
+            .with_context(|| format!("There is no registry named {}.", name))?;
+        let before = registry.revision.clone();
+        let pe = registry.checkout(&af_home_path, &rss)?;
+        if registry.revision != before {
+            self.save()?;
+        }
+        Ok(pe)
+    }
+
+    /// The Jsonnet utility library to evaluate the templates of `pe` with:
+    /// its own `utils` directory if it has one, and otherwise that of the
+    /// default registry.
+    pub fn utils_dir(&mut self, pe: &ProtocolEstuary) -> PathBuf {
+        if pe.utils_dir.exists() {
+            return pe.utils_dir.clone();
+        }
+        match self.checkout(DEFAULT_REGISTRY, RegistrySourceStrategy::PreferLocal) {
+            Ok(default) => default.utils_dir,
+            Err(e) => {
+                warn!(
+                    "could not get the utility library of the registry {}: {:#}",
+                    DEFAULT_REGISTRY, e
+                );
+                pe.utils_dir.clone()
+            }
+        }
+    }
+
+    /// Fetches the registry and adds it. Nothing is recorded if it cannot be
+    /// fetched.
+    pub fn add(&mut self, mut registry: Registry) -> anyhow::Result<()> {
+        registry.check_name()?;
+        if self.get(&registry.name).is_some() {
+            bail!(
+                "A registry named {} already exists; please remove it first.",
+                registry.name
+            );
+        }
+        let fetched = registry.fetch(&self.af_home_path).and_then(|_| {
+            registry.root(&self.af_home_path).with_context(|| {
+                format!(
+                    "The registry {} ({}) does not hold a `protocols` directory.",
+                    registry.name, registry.source
+                )
+            })
+        });
+        if let Err(e) = fetched {
+            // do not leave a partial copy behind
+            let dir = registry.dir(&self.af_home_path);
+            if !matches!(registry.source, RegistrySource::Local { .. }) && dir.exists() {
+                std::fs::remove_dir_all(&dir)
+                    .with_context(|| format!("could not remove {}", dir.display()))?;
+            }
+            return Err(e);
+        }
+        self.registries.push(registry);
+        self.save()
+    }
+
+    /// Removes the registry `name`, along with the local copy that simpleaf
+    /// made of it.
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<Registry> {
+        if name == DEFAULT_REGISTRY {
+            bail!("The default registry {} cannot be removed.", name);
+        }
+        let pos = self
+            .registries
+            .iter()
+            .position(|r| r.name == name)
+            .with_context(|| format!("There is no registry named {}.", name))?;
+        let registry = self.registries.remove(pos);
+        if !matches!(registry.source, RegistrySource::Local { .. }) {
+            let dir = registry.dir(&self.af_home_path);
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)
+                    .with_context(|| format!("could not remove {}", dir.display()))?;
+            }
+        }
+        self.save()?;
+        Ok(registry)
+    }
+
+    /// Returns the registries that simpleaf keeps a local copy of (i.e. all
+    /// but the local directories), along with the directory of their copy,
+    /// fetching the copies that are missing.
+    pub fn local_copies(&mut self) -> anyhow::Result<Vec<(Registry, PathBuf)>> {
+        let mut copies = Vec::new();
+        for name in self.names() {
+            let registry = self.get(&name).unwrap();
+            if matches!(registry.source, RegistrySource::Local { .. }) {
+                continue;
+            }
+            self.checkout(&name, RegistrySourceStrategy::PreferLocal)?;
+            let registry = self.get(&name).unwrap().clone();
+            let dir = registry.dir(&self.af_home_path);
+            copies.push((registry, dir));
+        }
+        Ok(copies)
+    }
+
+    /// Installs `copy` as the local copy of `registry`, as exported by
+    /// [`Registries::local_copies`] on another machine, replacing the
+    /// current copy and configuration of a registry with the same name.
+    /// `copy` must be on the same file system as `ALEVIN_FRY_HOME`.
+    pub fn import(&mut self, registry: Registry, copy: &Path) -> anyhow::Result<()> {
+        registry.check_importable()?;
+        if let Some(RegistrySource::Local { path }) = self.get(&registry.name).map(|r| &r.source) {
+            bail!(
+                "The registry {} is the local directory {} here; please remove it first.",
+                registry.name,
+                path.display()
+            );
+        }
+        if find_root(copy).is_none() {
+            bail!(
+                "The copy of the registry {} does not hold a `protocols` directory.",
+                registry.name
+            );
+        }
+        let dir = registry.dir(&self.af_home_path);
+        let parent = dir.parent().unwrap_or(&self.af_home_path);
+        std::fs::create_dir_all(parent)?;
+        let trash = tempfile::Builder::new()
+            .prefix(&format!(".{}-", registry.name))
+            .tempdir_in(parent)?;
+        replace_dir(copy, &dir, trash.path())?;
+        match self.registries.iter_mut().find(|r| r.name == registry.name) {
+            Some(r) => *r = registry,
+            None => self.registries.push(registry),
+        }
+        self.save()
+    }
+
+    /// Refreshes the local copies of all the registries. Git registries
+    /// checked out at a branch move to its latest commit.
+    pub fn refresh_all(&mut self) -> anyhow::Result<()> {
+        let mut failed = Vec::new();
+        for name in self.names() {
+            if let Err(e) = self.checkout(&name, RegistrySourceStrategy::ForceRefresh) {
+                error!("could not refresh the registry {}: {:#}", name, e);
+                failed.push(name);
+            }
+        }
+        if !failed.is_empty() {
+            bail!("Could not refresh the registries {}.", failed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Finds the workflow `name`, either qualified by its registry as
+    /// `<registry>/<workflow>`, or searched in all the registries. Returns
+    /// the registry and the local copy holding it.
+    pub fn resolve_workflow(
+        &mut self,
+        name: &str,
+    ) -> anyhow::Result<(Registry, ProtocolEstuary, String)> {
+        if let Some((reg, wf)) = name.split_once('/') {
+            if self.get(reg).is_none() {
+                bail!(
+                    "There is no registry named {}; the registries are: {}.",
+                    reg,
+                    self.names().join(", ")
+                );
+            }
+            let pe = self.checkout(reg, RegistrySourceStrategy::PreferLocal)?;
+            return Ok((self.get(reg).unwrap().clone(), pe, wf.to_string()));
+        }
+
+        let mut found = Vec::new();
+        for reg in self.names() {
+            match self.checkout(&reg, RegistrySourceStrategy::PreferLocal) {
+                Ok(pe) if pe.protocols_dir.join(name).is_dir() => found.push((reg, pe)),
+                Ok(_) => {}
+                Err(e) => warn!("skipping the registry {}: {:#}", reg, e),
+            }
+        }
+        match found.len() {
+            // let the caller report the similar names of the default registry
+            0 => {
+                let pe = self.checkout(DEFAULT_REGISTRY, RegistrySourceStrategy::PreferLocal)?;
+                Ok((
+                    self.get(DEFAULT_REGISTRY).unwrap().clone(),
+                    pe,
+                    name.to_string(),
+                ))
+            }
+            1 => {
+                let (reg, pe) = found.pop().unwrap();
+                Ok((self.get(&reg).unwrap().clone(), pe, name.to_string()))
+            }
+            _ => Err(anyhow!(
+                "The workflow {} exists in several registries; please use one of {}.",
+                name,
+                found
+                    .iter()
+                    .map(|(reg, _)| format!("{}/{}", reg, name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.registries.iter().map(|r| r.name.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registries() {
+        let af_home = tempfile::tempdir().unwrap();
+        let lab = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(lab.path().join("protocols").join("foo")).unwrap();
+
+        let mut registries = Registries::load(af_home.path()).unwrap();
+        assert_eq!(registries.names(), vec![DEFAULT_REGISTRY]);
+
+        // invalid names and sources are not recorded
+        for (name, path) in [("a/b", lab.path()), ("lab", af_home.path())] {
+            assert!(registries
+                .add(Registry {
+                    name: name.to_string(),
+                    source: RegistrySource::Local {
+                        path: path.to_path_buf()
+                    },
+                    revision: None,
+                })
+                .is_err());
+        }
+
+        registries
+            .add(Registry {
+                name: "lab".to_string(),
+                source: RegistrySource::Local {
+                    path: lab.path().to_path_buf(),
+                },
+                revision: None,
+            })
+            .unwrap();
+        let mut registries = Registries::load(af_home.path()).unwrap();
+        assert_eq!(registries.names(), vec![DEFAULT_REGISTRY, "lab"]);
+
+        let (reg, pe, wf) = registries.resolve_workflow("lab/foo").unwrap();
+        assert_eq!(reg.name, "lab");
+        assert_eq!(wf, "foo");
+        assert_eq!(pe.protocols_dir, lab.path().join("protocols"));
+        assert!(registries.resolve_workflow("nope/foo").is_err());
+
+        assert!(registries.remove(DEFAULT_REGISTRY).is_err());
+        registries.remove("lab").unwrap();
+        // the directory of a local registry is left in place
+        assert!(lab.path().join("protocols").is_dir());
+        let registries = Registries::load(af_home.path()).unwrap();
+        assert!(registries.get("lab").is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::utils::prog_utils::StepInfo;
+
+/// The version of the run record format. It should be incremented whenever
+/// a field is removed or changes meaning; adding optional fields does not
+/// require a new version.
+pub const RUN_RECORD_SCHEMA_VERSION: u32 = 1;
+
+/// The names of the run record files written by the different commands.
+pub static INDEX_RUN_RECORD_FILE: &str = "simpleaf_index_log.json";
+pub static QUANT_RUN_RECORD_FILE: &str = "simpleaf_quant_log.json";
+pub static PROCESS_RUN_RECORD_FILE: &str = "simpleaf_process_log.json";
+
+/// (De)serializes a `Duration` as a (fractional) number of seconds.
+pub mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(d.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(d)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The details of a run that are specific to the command that was run.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RunDetails {
+    Index {
+        /// `piscem` or `salmon`
+        index_type: String,
+        index_dir: PathBuf,
+        #[serde(default)]
+        ref_dir: Option<PathBuf>,
+        /// The options used to build the expanded reference, if one was built
+        #[serde(default)]
+        roers_options: Option<Value>,
+    },
+    Quant {
+        /// The mapper used, or `None` if an existing mapping directory was quantified
+        #[serde(default)]
+        mapper: Option<String>,
+        map_dir: PathBuf,
+        quant_dir: PathBuf,
+        /// The steps skipped because they completed in a previous run
+        #[serde(default)]
+        resumed_steps: Vec<String>,
+        /// The checkpoints of the pipeline stages (see `--resume`)
+        #[serde(default)]
+        stages: Value,
+    },
+    AtacProcess {
+        mapper: String,
+        map_dir: PathBuf,
+        process_dir: PathBuf,
+    },
+}
+
+/// Where the value of an option of a run came from.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OptionSource {
+    /// Given on the command line (or in the workflow manifest, or to a builder of the api)
+    CommandLine,
+    /// The default set in the `simpleaf.toml` file of the simpleaf home
+    Config,
+    /// The built-in default of simpleaf
+    Default,
+}
+
+/// The value of an option of a run, and where it came from.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OptionValue {
+    pub value: Value,
+    pub source: OptionSource,
+}
+
+/// The effective options of a run, keyed by their (long) name.
+pub type EffectiveOptions = BTreeMap<String, OptionValue>;
+
+/// The record of a run of `simpleaf index`, `simpleaf quant` or
+/// `simpleaf atac process`, written to the output directory of the run.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RunRecord {
+    pub schema_version: u32,
+    pub simpleaf_version: String,
+    #[serde(flatten)]
+    pub details: RunDetails,
+    /// The value of each option of the command, and where it came from
+    #[serde(default)]
+    pub options: EffectiveOptions,
+    /// The steps of the run, in the order in which they were run
+    pub steps: Vec<StepInfo>,
+}
+
+impl RunRecord {
+    pub fn new(details: RunDetails) -> Self {
+        Self {
+            schema_version: RUN_RECORD_SCHEMA_VERSION,
+            simpleaf_version: env!("CARGO_PKG_VERSION").to_string(),
+            details,
+            options: EffectiveOptions::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// The name of the command that produced the record.
+    pub fn command(&self) -> &'static str {
+        match self.details {
+            RunDetails::Index { .. } => "index",
+            RunDetails::Quant { .. } => "quant",
+            RunDetails::AtacProcess { .. } => "atac process",
+        }
+    }
+
+    /// Reads the record in `path`, which may be either a run record file
+    /// or the output directory of a run.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let p = find_run_record(path)?;
+        let content = std::fs::read_to_string(&p)
+            .with_context(|| format!("could not read {}", p.display()))?;
+        let v: Value = serde_json::from_str(&content)
+            .with_context(|| format!("could not parse {}", p.display()))?;
+        match v.get("schema_version").and_then(Value::as_u64) {
+            None => bail!(
+                "{} is not a versioned run record; it was either written by an older version of simpleaf or by a run that did not complete",
+                p.display()
+            ),
+            Some(ver) if ver > RUN_RECORD_SCHEMA_VERSION as u64 => bail!(
+                "{} has schema version {}, but this version of simpleaf only supports versions up to {}",
+                p.display(),
+                ver,
+                RUN_RECORD_SCHEMA_VERSION
+            ),
+            Some(_) => {}
+        }
+        serde_json::from_value(v).with_context(|| format!("invalid run record {}", p.display()))
+    }
+
+    /// Writes the record to `path` (see [`write_json_atomic`]).
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        write_json_atomic(path, self)
+    }
+}
+
+/// Writes `value` as JSON to `path`. The JSON is first written to a
+/// temporary file in the same directory, which then replaces `path`, so
+/// that readers never see a partially-written file.
+pub fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("could not create a temporary file in {}", dir.display()))?;
+    serde_json::to_writer_pretty(&mut tmp, value)?;
+    tmp.write_all(b"\n")?;
+    tmp.persist(path)
+        .with_context(|| format!("could not write {}", path.display()))?;
+    Ok(())
+}
+
+/// If `path` is a directory, returns the run record file in it; otherwise
+/// returns `path` itself.
+fn find_run_record(path: &Path) -> anyhow::Result<PathBuf> {
+    if !path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    let found: Vec<PathBuf> = [
+        INDEX_RUN_RECORD_FILE,
+        QUANT_RUN_RECORD_FILE,
+        PROCESS_RUN_RECORD_FILE,
+    ]
+    .iter()
+    .map(|f| path.join(f))
+    .filter(|p| p.is_file())
+    .collect();
+    match found.as_slice() {
+        [p] => Ok(p.clone()),
+        [] => bail!("found no simpleaf run record in {}", path.display()),
+        _ => bail!(
+            "found several simpleaf run records in {}; please pass the one to use",
+            path.display()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_run_record_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut record = RunRecord::new(RunDetails::Quant {
+            mapper: Some(String::from("piscem")),
+            map_dir: PathBuf::from("out/af_map"),
+            quant_dir: PathBuf::from("out/af_quant"),
+            resumed_steps: vec![String::from("map")],
+            stages: serde_json::json!({ "map" : { "completed" : true } }),
+        });
+        record.steps.push(StepInfo::new(
+            "map",
+            Some(String::from("piscem map-sc")),
+            Duration::from_millis(1500),
+        ));
+
+        let p = dir.path().join(QUANT_RUN_RECORD_FILE);
+        record.write(&p).unwrap();
+
+        let v: Value = serde_json::from_str(&std::fs::read_to_string(&p).unwrap()).unwrap();
+        assert_eq!(v["schema_version"], RUN_RECORD_SCHEMA_VERSION);
+        assert_eq!(v["command"], "quant");
+        assert_eq!(v["steps"][0]["time_secs"], 1.5);
+        // the checkpoints must stay at the top level, where `--resume` looks for them
+        assert_eq!(v["stages"]["map"]["completed"], true);
+
+        assert_eq!(RunRecord::read(dir.path()).unwrap(), record);
+    }
+
+    #[test]
+    fn test_unversioned_record_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let p = dir.path().join(QUANT_RUN_RECORD_FILE);
+        std::fs::write(&p, r#"{ "time_info" : {}, "cmd_info" : {} }"#).unwrap();
+        assert!(RunRecord::read(&p).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::utils::home::{HomeSource, SimpleafHome, AF_HOME_VAR};
+use crate::utils::prog_utils::{self, CommandVerbosityLevel};
+use crate::utils::workflow_utils::{SimpleafWorkflow, WorkflowLog};
+
+/// The directory, under the output directory of a workflow, holding the
+/// job scripts, their logs and their completion markers.
+pub static WORKFLOW_JOBS_DIR: &str = "workflow_jobs";
+
+/// How the steps of a workflow are run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowExecutor {
+    /// one after the other, by the running simpleaf process
+    Local,
+    /// as jobs submitted to SLURM with `sbatch`
+    Slurm,
+    /// as jobs submitted to PBS (or Torque) with `qsub`
+    Pbs,
+}
+
+impl WorkflowExecutor {
+    fn submit_program(&self) -> &'static str {
+        match self {
+            WorkflowExecutor::Local => "sh",
+            WorkflowExecutor::Slurm => "sbatch",
+            WorkflowExecutor::Pbs => "qsub",
+        }
+    }
+
+    fn script_header(&self, job_name: &str, log_path: &Path) -> String {
+        match self {
+            WorkflowExecutor::Local => String::new(),
+            WorkflowExecutor::Slurm => format!(
+                "#SBATCH --job-name={}\n#SBATCH --output={}\n",
+                job_name,
+                log_path.display()
+            ),
+            WorkflowExecutor::Pbs => format!(
+                "#PBS -N {}\n#PBS -j oe\n#PBS -o {}\n",
+                job_name,
+                log_path.display()
+            ),
+        }
+    }
+
+    /// The arguments making a job wait for the successful completion of
+    /// the jobs with ids `dep_ids`.
+    fn dependency_args(&self, dep_ids: &[String]) -> Vec<String> {
+        if dep_ids.is_empty() {
+            return Vec::new();
+        }
+        let ids = dep_ids.join(":");
+        match self {
+            WorkflowExecutor::Local => Vec::new(),
+            WorkflowExecutor::Slurm => vec![format!("--dependency=afterok:{}", ids)],
+            WorkflowExecutor::Pbs => vec![String::from("-W"), format!("depend=afterok:{}", ids)],
+        }
+    }
+
+    /// Extracts the job id from the output of the submission command.
+    fn parse_job_id(&self, submit_output: &str) -> Option<String> {
+        let out = submit_output.trim();
+        let id = match self {
+            // `sbatch --parsable` prints `<id>` or `<id>;<cluster>`
+            WorkflowExecutor::Slurm => out.split(';').next().unwrap_or_default(),
+            _ => out,
+        };
+        (!id.is_empty()).then(|| id.to_string())
+    }
+}
+
+impl std::fmt::Display for WorkflowExecutor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkflowExecutor::Local => write!(f, "local"),
+            WorkflowExecutor::Slurm => write!(f, "slurm"),
+            WorkflowExecutor::Pbs => write!(f, "pbs"),
+        }
+    }
+}
+
+/// A job running one command of a workflow, as recorded under
+/// `Cluster Jobs` in `simpleaf_workflow_log.json`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ClusterJob {
+    pub step: u64,
+    pub name: String,
+    /// The JSON pointer to the command record in the workflow
+    #[serde(default)]
+    pub path: String,
+    pub script: PathBuf,
+    /// The file the job creates once its command has succeeded
+    pub done_marker: PathBuf,
+    /// The id assigned by the scheduler, or `None` if the job was not submitted
+    pub job_id: Option<String>,
+    /// The names of the jobs that must succeed before this job starts
+    pub depends_on: Vec<String>,
+}
+
+impl ClusterJob {
+    pub fn is_done(&self) -> bool {
+        self.done_marker.is_file()
+    }
+}
+
+/// Quotes `s` for use as a single word in a POSIX shell command line.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let is_plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c));
+    if is_plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// Returns the first step of the jobs recorded in `cluster_jobs` (the
+/// `Cluster Jobs` entry of a workflow log) that did not complete, or
+/// `None` if they all did.
+pub fn first_incomplete_step(cluster_jobs: &Value) -> anyhow::Result<Option<u64>> {
+    let jobs: Vec<ClusterJob> = serde_json::from_value(cluster_jobs.clone())
+        .context("Could not parse the `Cluster Jobs` recorded in the workflow log")?;
+    Ok(jobs.iter().filter(|j| !j.is_done()).map(|j| j.step).min())
+}
+
+/// Returns the JSON pointers of the commands of the jobs recorded in
+/// `cluster_jobs` that completed, which are not submitted again on resume.
+pub fn completed_commands(cluster_jobs: &Value) -> anyhow::Result<Vec<String>> {
+    let jobs: Vec<ClusterJob> = serde_json::from_value(cluster_jobs.clone())
+        .context("Could not parse the `Cluster Jobs` recorded in the workflow log")?;
+    Ok(jobs
+        .into_iter()
+        .filter(|j| j.is_done() && !j.path.is_empty())
+        .map(|j| j.path)
+        .collect())
+}
+
+/// The name of the job running the command at `path` (a JSON pointer, e.g.
+/// `/external-commands/b`) of `step`, which is also the name of the shell
+/// variable holding its id in `submit_jobs.sh`. It depends only on the
+/// command, so that the job of a command keeps its name (and its files) on
+/// `--resume`.
+fn job_name(step: u64, path: &str) -> String {
+    let words: Vec<&str> = path
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    format!("step{}_{}", step, words.join("_"))
+}
+
+/// The lines of a job script making the simpleaf commands it runs use
+/// `home`, whatever the environment of the job.
+fn home_env_lines(home: &SimpleafHome) -> String {
+    let abs = |p: &Path| std::fs::canonicalize(p).unwrap_or(p.into());
+    match home.source {
+        HomeSource::Xdg => {
+            let base = |dir: &Path| {
+                let dir = abs(dir);
+                dir.parent().map(Path::to_path_buf).unwrap_or(dir)
+            };
+            format!(
+                "unset {}\nexport XDG_CONFIG_HOME={}\nexport XDG_DATA_HOME={}",
+                AF_HOME_VAR,
+                shell_quote(&base(&home.config_dir).to_string_lossy()),
+                shell_quote(&base(&home.data_dir).to_string_lossy())
+            )
+        }
+        HomeSource::Cli | HomeSource::Env => format!(
+            "export {}={}",
+            AF_HOME_VAR,
+            shell_quote(&abs(&home.data_dir).to_string_lossy())
+        ),
+    }
+}
+
+/// Writes a job script for each command of `simpleaf_workflow` to the
+/// [`WORKFLOW_JOBS_DIR`] directory under `output`, where each job depends on
+/// the jobs of the commands its command depends on. If `submit` is true, the
+/// jobs are submitted (with `submit_args` as extra arguments); otherwise a
+/// `submit_jobs.sh` script submitting them is written instead. The jobs are
+/// recorded in `workflow_log`.
+pub fn submit_workflow_jobs<T: AsRef<Path>>(
+    simpleaf_workflow: SimpleafWorkflow,
+    af_home_path: T,
+    output: &Path,
+    workflow_log: &mut WorkflowLog,
+    executor: WorkflowExecutor,
+    submit: bool,
+    submit_args: &[String],
+) -> anyhow::Result<()> {
+    let submitter = submit.then(|| Path::new(executor.submit_program()));
+    write_workflow_jobs(
+        simpleaf_workflow,
+        af_home_path.as_ref(),
+        output,
+        workflow_log,
+        executor,
+        submitter,
+        submit_args,
+    )
+}
+
+/// Writes (and submits with `submitter`, if given) the jobs of
+/// `simpleaf_workflow`, as [`submit_workflow_jobs`] does.
+fn write_workflow_jobs(
+    simpleaf_workflow: SimpleafWorkflow,
+    af_home_path: &Path,
+    output: &Path,
+    workflow_log: &mut WorkflowLog,
+    executor: WorkflowExecutor,
+    submitter: Option<&Path>,
+    submit_args: &[String],
+) -> anyhow::Result<()> {
+    if executor == WorkflowExecutor::Local {
+        bail!("Local workflows are not run through job scripts; this shouldn't happen.");
+    }
+
+    let jobs_dir = output.join(WORKFLOW_JOBS_DIR);
+    std::fs::create_dir_all(&jobs_dir)
+        .with_context(|| format!("could not create {}", jobs_dir.display()))?;
+    let jobs_dir = std::fs::canonicalize(&jobs_dir)?;
+    let cwd = std::env::current_dir()?;
+    let home_env = home_env_lines(&SimpleafHome::of(af_home_path));
+
+    // the jobs are created in queue order, so the i-th job runs the i-th command
+    let mut jobs: Vec<ClusterJob> = Vec::new();
+    let mut submit_lines = vec![String::from("#!/bin/sh"), String::from("set -e")];
+
+    for (i, cr) in simpleaf_workflow.cmd_queue.iter().enumerate() {
+        if i == 0 {
+            workflow_log.timeit(cr.step);
+        }
+
+        let name = job_name(cr.step, &cr.path);
+        if let Some(other) = jobs.iter().find(|j| j.name == name) {
+            bail!(
+                "The commands at {} and {} would both be run by the job {}; please rename one of them.",
+                other.path,
+                cr.path,
+                name
+            );
+        }
+        let job_name = format!("simpleaf_{}", name);
+        let script = jobs_dir.join(format!("{}.sh", name));
+        let done_marker = jobs_dir.join(format!("{}.done", name));
+        let log_path = jobs_dir.join(format!("{}.log", name));
+
+        let mut cmd_line = cr.cmd_line.clone();
+        if !cr.is_external() && CommandVerbosityLevel::global() == CommandVerbosityLevel::Verbose {
+            cmd_line.push_str(" --verbose");
+        }
+        let content = format!(
+            "#!/bin/sh\n{}\n# {} command for step {}\nset -e\nrm -f {}\ncd {}\n{}\n{}\ntouch {}\n",
+            executor.script_header(&job_name, &log_path),
+            cr.program_name,
+            cr.step,
+            shell_quote(&done_marker.to_string_lossy()),
+            shell_quote(&cwd.to_string_lossy()),
+            home_env,
+            cmd_line,
+            shell_quote(&done_marker.to_string_lossy()),
+        );
+        write_script(&script, &content)?;
+        // the command may have completed in a previous run
+        match std::fs::remove_file(&done_marker) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e)
+                    .with_context(|| format!("could not remove {}", done_marker.display()))
+            }
+            _ => {}
+        }
+
+        let depends_on: Vec<String> = cr
+            .depends_on
+            .iter()
+            .map(|j| jobs[*j].name.clone())
+            .collect();
+
+        // the line of `submit_jobs.sh` submitting this job, which keeps its id
+        // in the `<name>` shell variable
+        let dep_vars: Vec<String> = depends_on.iter().map(|d| format!("${}", d)).collect();
+        let mut submit_words = vec![executor.submit_program().to_string()];
+        if executor == WorkflowExecutor::Slurm {
+            submit_words.push(String::from("--parsable"));
+        }
+        submit_words.extend(executor.dependency_args(&dep_vars));
+        submit_words.extend(submit_args.iter().map(|a| shell_quote(a)));
+        submit_words.push(shell_quote(&script.to_string_lossy()));
+        submit_lines.push(format!("{}=$({})", name, submit_words.join(" ")));
+
+        let mut job = ClusterJob {
+            step: cr.step,
+            name,
+            path: cr.path.clone(),
+            script,
+            done_marker,
+            job_id: None,
+            depends_on,
+        };
+
+        if let Some(submitter) = submitter {
+            let dep_ids: Vec<String> = cr
+                .depends_on
+                .iter()
+                .filter_map(|j| jobs[*j].job_id.clone())
+                .collect();
+            match submit_job(executor, submitter, &job.script, &dep_ids, submit_args) {
+                Ok(id) => {
+                    info!(
+                        "submitted {} command for step {} as job {}",
+                        cr.program_name, cr.step, id
+                    );
+                    job.job_id = Some(id);
+                }
+                Err(e) => {
+                    workflow_log.set_cluster_jobs(executor, jobs);
+                    workflow_log.write(false)?;
+                    return Err(e).with_context(|| {
+                        format!(
+                            "Could not submit the {} command for step {}",
+                            cr.program_name, cr.step
+                        )
+                    });
+                }
+            }
+        }
+        jobs.push(job);
+    }
+
+    if submitter.is_none() {
+        let submit_script = jobs_dir.join("submit_jobs.sh");
+        submit_lines.push(String::new());
+        write_script(&submit_script, &submit_lines.join("\n"))?;
+        info!(
+            "wrote {} job scripts to {}; run {} to submit them",
+            jobs.len(),
+            jobs_dir.display(),
+            submit_script.display()
+        );
+    }
+    workflow_log.set_cluster_jobs(executor, jobs);
+    Ok(())
+}
+
+fn write_script(path: &Path, content: &str) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(path)
+        .with_context(|| format!("could not create {}", path.display()))?;
+    f.write_all(content.as_bytes())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        f.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Submits the job script `script` with the `submitter` program of
+/// `executor`, to start once the jobs with ids `dep_ids` have succeeded, and
+/// returns the id of the new job.
+fn submit_job(
+    executor: WorkflowExecutor,
+    submitter: &Path,
+    script: &Path,
+    dep_ids: &[String],
+    submit_args: &[String],
+) -> anyhow::Result<String> {
+    let mut cmd = std::process::Command::new(submitter);
+    if executor == WorkflowExecutor::Slurm {
+        cmd.arg("--parsable");
+    }
+    cmd.args(executor.dependency_args(dep_ids))
+        .args(submit_args)
+        .arg(script);
+
+    let cmd_string = prog_utils::get_cmd_line_string(&cmd);
+    let out = cmd
+        .output()
+        .with_context(|| format!("could not run `{}`", cmd_string))?;
+    if !out.status.success() {
+        bail!(
+            "`{}` exited with {}. The stderr of the invocation was: {}",
+            cmd_string,
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    executor
+        .parse_job_id(&String::from_utf8_lossy(&out.stdout))
+        .with_context(|| format!("`{}` did not print a job id", cmd_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::workflow_utils::initialize_workflow;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("--fasta=genome.fa"), "--fasta=genome.fa");
+        assert_eq!(shell_quote("my reads.fq"), "'my reads.fq'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_job_scripts_and_resume_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = serde_json::json!({
+            "meta_info": { "output": dir.path() },
+            "external-commands": {
+                "a": { "step": 1, "program_name": "echo", "arguments": ["a"] },
+                "b": { "step": 1, "program_name": "echo", "arguments": ["b"] },
+                "c": { "step": 2, "program_name": "echo", "arguments": ["c", ">", "c.txt"] }
+            }
+        });
+        let template = dir.path().join("wf.json");
+        let (sw, mut wl) = initialize_workflow(
+            dir.path(),
+            template.as_path(),
+            dir.path(),
+            manifest,
+            1,
+            vec![],
+            false,
+        )
+        .unwrap();
+
+        submit_workflow_jobs(
+            sw,
+            dir.path(),
+            dir.path(),
+            &mut wl,
+            WorkflowExecutor::Slurm,
+            false,
+            &[String::from("--partition=short")],
+        )
+        .unwrap();
+        wl.write(false).unwrap();
+
+        let jobs_dir = std::fs::canonicalize(dir.path().join(WORKFLOW_JOBS_DIR)).unwrap();
+        let script =
+            std::fs::read_to_string(jobs_dir.join("step2_external_commands_c.sh")).unwrap();
+        assert!(script.contains("#SBATCH --job-name=simpleaf_step2_external_commands_c"));
+        assert!(script.contains("\necho c > c.txt\n"));
+        let submit = std::fs::read_to_string(jobs_dir.join("submit_jobs.sh")).unwrap();
+        assert!(submit.contains(
+            "step2_external_commands_c=$(sbatch --parsable \
+            --dependency=afterok:$step1_external_commands_a:$step1_external_commands_b \
+            --partition=short"
+        ));
+
+        let log: Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join("simpleaf_workflow_log.json")).unwrap(),
+        )
+        .unwrap();
+        let cluster_jobs = &log["Latest Run"]["Cluster Jobs"];
+        assert_eq!(first_incomplete_step(cluster_jobs).unwrap(), Some(1));
+        for name in ["step1_external_commands_a", "step1_external_commands_b"] {
+            std::fs::write(jobs_dir.join(format!("{}.done", name)), "").unwrap();
+        }
+        assert_eq!(first_incomplete_step(cluster_jobs).unwrap(), Some(2));
+        std::fs::write(jobs_dir.join("step2_external_commands_c.done"), "").unwrap();
+        assert_eq!(first_incomplete_step(cluster_jobs).unwrap(), None);
+    }
+
+    /// Writes a stand-in for the submission program `name` to `bin_dir`, which
+    /// records its arguments in `<name>.calls` and prints the id of the n-th
+    /// job submitted as `output` with `N` replaced by n.
+    #[cfg(unix)]
+    fn stub_submitter(bin_dir: &Path, name: &str, output: &str) -> PathBuf {
+        let calls = bin_dir.join(format!("{}.calls", name));
+        let content = format!(
+            "#!/bin/sh\necho \"$@\" >> {calls}\nn=$(wc -l < {calls})\necho \"{}\" | sed \"s/N/$n/\"\n",
+            output,
+            calls = shell_quote(&calls.to_string_lossy())
+        );
+        write_script(&bin_dir.join(name), &content).unwrap();
+        calls
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_submit_and_resume_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin_dir = dir.path().join("bin");
+        std::fs::create_dir(&bin_dir).unwrap();
+        let sbatch_calls = stub_submitter(&bin_dir, "sbatch", "10N;cluster");
+        let qsub_calls = stub_submitter(&bin_dir, "qsub", "20N.server");
+
+        let manifest = serde_json::json!({
+            "meta_info": { "output": dir.path() },
+            "external-commands": {
+                "a": { "step": 1, "program_name": "echo", "arguments": ["a"] },
+                "b": { "step": 1, "program_name": "echo", "arguments": ["b"] },
+                "c": { "step": 2, "program_name": "echo", "arguments": ["c"] }
+            }
+        });
+        let template = dir.path().join("wf.json");
+        let workflow = |resume: bool| {
+            initialize_workflow(
+                dir.path(),
+                template.as_path(),
+                dir.path(),
+                manifest.clone(),
+                1,
+                vec![],
+                resume,
+            )
+            .unwrap()
+        };
+        let cluster_jobs = || -> Vec<ClusterJob> {
+            let log: Value = serde_json::from_str(
+                &std::fs::read_to_string(dir.path().join("simpleaf_workflow_log.json")).unwrap(),
+            )
+            .unwrap();
+            serde_json::from_value(log["Latest Run"]["Cluster Jobs"].clone()).unwrap()
+        };
+
+        let (sw, mut wl) = workflow(false);
+        write_workflow_jobs(
+            sw,
+            dir.path(),
+            dir.path(),
+            &mut wl,
+            WorkflowExecutor::Slurm,
+            Some(&bin_dir.join("sbatch")),
+            &[String::from("--partition=short")],
+        )
+        .unwrap();
+        wl.write(false).unwrap();
+
+        let jobs = cluster_jobs();
+        let ids: Vec<_> = jobs.iter().map(|j| j.job_id.clone().unwrap()).collect();
+        assert_eq!(ids, ["101", "102", "103"]);
+        let calls = std::fs::read_to_string(&sbatch_calls).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        assert!(calls[0].starts_with("--parsable --partition=short "));
+        assert!(calls[2].starts_with("--parsable --dependency=afterok:101:102 --partition=short "));
+        assert!(calls[2].ends_with("step2_external_commands_c.sh"));
+
+        // only the jobs that did not complete are submitted again
+        let done_marker = jobs[0].done_marker.clone();
+        std::fs::write(&done_marker, "").unwrap();
+        let (sw, mut wl) = workflow(true);
+        assert_eq!(sw.cmd_queue.len(), 2);
+        write_workflow_jobs(
+            sw,
+            dir.path(),
+            dir.path(),
+            &mut wl,
+            WorkflowExecutor::Pbs,
+            Some(&bin_dir.join("qsub")),
+            &[],
+        )
+        .unwrap();
+        wl.write(false).unwrap();
+
+        let jobs = cluster_jobs();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].path, "/external-commands/b");
+        assert_eq!(jobs[0].name, "step1_external_commands_b");
+        assert_eq!(jobs[1].job_id.as_deref(), Some("202.server"));
+        // the jobs keep their names, so the completed job keeps its marker
+        assert!(!jobs[0].is_done());
+        assert!(done_marker.is_file());
+        let calls = std::fs::read_to_string(&qsub_calls).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[1].starts_with("-W depend=afterok:201.server "));
+    }
+}
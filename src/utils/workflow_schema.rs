@@ -0,0 +1,604 @@
+use clap::builder::ValueParser;
+use clap::{Arg, CommandFactory};
+use serde_json::Value;
+use std::any::TypeId;
+use std::path::{Path, PathBuf};
+
+use crate::utils::prog_utils::shell;
+use crate::utils::workflow_jobs::shell_quote;
+use crate::utils::workflow_utils::{to_quoted_string, ProgramName, SimpleafWorkflow, SystemFields};
+use crate::Cli;
+
+// the path-valued flags of the simpleaf commands that name files or
+// directories written by the command; the other path-valued flags are read
+const OUTPUT_FLAGS: &[&str] = &["output", "work-dir"];
+
+/// A problem found in a workflow manifest, located by the JSON pointer of
+/// the offending field (e.g. `/quant/10x_run/--chemistry`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestIssue {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ManifestIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.pointer, self.message)
+    }
+}
+
+/// Escapes a field name for use as a token of a JSON pointer.
+pub(crate) fn json_pointer_token(field_name: &str) -> String {
+    field_name.replace('~', "~0").replace('/', "~1")
+}
+
+/// A flag of a simpleaf command, as defined by its clap argument.
+struct FlagSchema {
+    // e.g. `--fasta` and `-f`
+    names: Vec<String>,
+    // `None` for switches, which take no value
+    value_parser: Option<ValueParser>,
+    // whether the flag can be given without a value
+    value_optional: bool,
+    value_delimiter: Option<char>,
+    // the description of the expected value
+    expected: String,
+    // whether the value is a path, which the command reads unless the flag
+    // is one of `OUTPUT_FLAGS`
+    is_path: bool,
+    is_output: bool,
+}
+
+impl FlagSchema {
+    fn from_arg(arg: &Arg) -> Option<FlagSchema> {
+        let mut names: Vec<String> = arg
+            .get_long_and_visible_aliases()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|l| format!("--{}", l))
+            .collect();
+        names.extend(
+            arg.get_short_and_visible_aliases()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|s| format!("-{}", s)),
+        );
+        // positional arguments cannot be given in a manifest
+        if names.is_empty() {
+            return None;
+        }
+
+        if !arg.get_action().takes_values() {
+            return Some(FlagSchema {
+                names,
+                value_parser: None,
+                value_optional: true,
+                value_delimiter: None,
+                expected: String::from("a boolean"),
+                is_path: false,
+                is_output: false,
+            });
+        }
+
+        let parser = arg.get_value_parser();
+        let is_path = parser.type_id() == TypeId::of::<PathBuf>();
+        let possible_values: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|pv| !pv.is_hide_set())
+            .map(|pv| pv.get_name().to_string())
+            .collect();
+        let mut expected = if !possible_values.is_empty() {
+            format!("one of {}", possible_values.join(", "))
+        } else {
+            let t = parser.type_id();
+            if [
+                TypeId::of::<u8>(),
+                TypeId::of::<u16>(),
+                TypeId::of::<u32>(),
+                TypeId::of::<u64>(),
+                TypeId::of::<usize>(),
+            ]
+            .iter()
+            .any(|id| t == *id)
+            {
+                String::from("a non-negative integer")
+            } else if [TypeId::of::<i32>(), TypeId::of::<i64>()]
+                .iter()
+                .any(|id| t == *id)
+            {
+                String::from("an integer")
+            } else if [TypeId::of::<f32>(), TypeId::of::<f64>()]
+                .iter()
+                .any(|id| t == *id)
+            {
+                String::from("a number")
+            } else if is_path {
+                String::from("a path")
+            } else {
+                String::from("a string")
+            }
+        };
+        let value_delimiter = arg.get_value_delimiter();
+        if let Some(d) = value_delimiter {
+            expected.push_str(&format!(" (or a '{}' separated list of them)", d));
+        }
+        let value_optional = arg.get_num_args().is_some_and(|r| r.min_values() == 0);
+        if value_optional {
+            expected.push_str(", or an empty string");
+        }
+
+        Some(FlagSchema {
+            names,
+            value_parser: Some(parser.clone()),
+            value_optional,
+            value_delimiter,
+            expected,
+            is_path,
+            is_output: arg.get_long().is_some_and(|l| OUTPUT_FLAGS.contains(&l)),
+        })
+    }
+
+    /// Checks a value given to this flag in a manifest, returning what was
+    /// expected if it is invalid.
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let parser = match &self.value_parser {
+            // switches are set by `true` (or an empty string) and unset by `false`
+            None => {
+                return match value {
+                    Value::Bool(_) => Ok(()),
+                    Value::String(s) if s.is_empty() => Ok(()),
+                    v => Err(format!("expected {}, but found {}", self.expected, v)),
+                };
+            }
+            Some(parser) => parser,
+        };
+        let s = match value {
+            // `false` leaves the flag out
+            Value::Bool(false) => return Ok(()),
+            Value::Bool(true) if self.value_optional => return Ok(()),
+            Value::String(s) if s.is_empty() && self.value_optional => return Ok(()),
+            Value::String(_) | Value::Number(_) => to_quoted_string(value),
+            v => return Err(format!("expected {}, but found {}", self.expected, v)),
+        };
+
+        // run the value through the parser of the flag
+        let mut arg = Arg::new("value")
+            .long("value")
+            .value_parser(parser.clone())
+            .allow_hyphen_values(true);
+        if let Some(d) = self.value_delimiter {
+            arg = arg.value_delimiter(d);
+        }
+        clap::Command::new("simpleaf")
+            .arg(arg)
+            .try_get_matches_from(["simpleaf", "--value", s.as_str()])
+            .map(|_| ())
+            .map_err(|_| format!("expected {}, but found {}", self.expected, value))
+    }
+}
+
+/// The flags of a simpleaf command, generated from its clap definition.
+struct CommandSchema {
+    // e.g. `simpleaf atac process`
+    name: String,
+    flags: Vec<FlagSchema>,
+}
+
+impl CommandSchema {
+    /// Returns the schema of the simpleaf command named by `subcommand`
+    /// (e.g. `["atac", "process"]`), if there is such a command.
+    fn for_subcommand(subcommand: &[String]) -> Option<CommandSchema> {
+        let mut cmd = Cli::command();
+        // propagate the global flags to the subcommands
+        cmd.build();
+        let mut curr = &cmd;
+        for word in subcommand {
+            curr = curr.find_subcommand(word)?;
+        }
+        // a command grouping other commands (e.g. `simpleaf chemistry`)
+        // cannot be run by itself
+        if curr.has_subcommands() || subcommand.is_empty() {
+            return None;
+        }
+        Some(CommandSchema {
+            name: format!("simpleaf {}", subcommand.join(" ")),
+            flags: curr
+                .get_arguments()
+                .filter_map(FlagSchema::from_arg)
+                .collect(),
+        })
+    }
+
+    /// Returns the schema of the simpleaf command run by `pn`, if any.
+    fn for_program(pn: &ProgramName) -> Option<CommandSchema> {
+        match pn {
+            ProgramName::Index => Self::for_subcommand(&[String::from("index")]),
+            ProgramName::Quant => Self::for_subcommand(&[String::from("quant")]),
+            ProgramName::Simpleaf(subcommand) => Self::for_subcommand(subcommand),
+            ProgramName::External(_) => None,
+        }
+    }
+
+    fn flag(&self, name: &str) -> Option<&FlagSchema> {
+        self.flags
+            .iter()
+            .find(|f| f.names.iter().any(|n| n == name))
+    }
+}
+
+/// Checks every command record in an instantiated workflow manifest:
+/// the types of the `step`, `program_name`, `active` and `depends_on`
+/// fields, the `arguments` of external commands, and, for simpleaf commands,
+/// that each field is a flag of the command with a valid value and that the
+/// flags form a valid command line. The commands that are not active are
+/// only checked for the types of their system fields. All the problems
+/// found are returned.
+pub fn validate_manifest(manifest: &Value) -> Vec<ManifestIssue> {
+    let mut issues = Vec::new();
+    validate_layer(manifest, "", &mut issues);
+    issues
+}
+
+fn validate_layer(value: &Value, json_path: &str, issues: &mut Vec<ManifestIssue>) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+    for (field_name, field) in fields {
+        let path = format!("{}/{}", json_path, json_pointer_token(field_name));
+        if field.get(SystemFields::Step.as_str()).is_some() {
+            validate_command_record(field, &path, issues);
+        } else if field.get(SystemFields::ProgramName.as_str()).is_some() {
+            issues.push(ManifestIssue {
+                pointer: path,
+                message: String::from(
+                    "this command record has a `program_name` but no `step`, so it would never be run",
+                ),
+            });
+        } else {
+            validate_layer(field, &path, issues);
+        }
+    }
+}
+
+fn validate_command_record(record: &Value, path: &str, issues: &mut Vec<ManifestIssue>) {
+    // `rel_pointer` is relative to the record, e.g. `arguments/1`
+    let mut issue = |rel_pointer: &str, message: String| {
+        issues.push(ManifestIssue {
+            pointer: format!("{}/{}", path, rel_pointer),
+            message,
+        })
+    };
+
+    let step = &record[SystemFields::Step.as_str()];
+    if step.as_u64().is_none() {
+        issue(
+            SystemFields::Step.as_str(),
+            format!("expected a non-negative integer, but found {}", step),
+        );
+    }
+
+    let active = match record.get(SystemFields::Active.as_str()) {
+        None => true,
+        Some(Value::Bool(b)) => *b,
+        Some(v) => {
+            issue(
+                SystemFields::Active.as_str(),
+                format!("expected a boolean, but found {}", v),
+            );
+            true
+        }
+    };
+
+    if let Some(deps) = record.get(SystemFields::DependsOn.as_str()) {
+        let valid = |d: &Value| d.is_u64() || d.is_string();
+        match deps {
+            Value::Array(a) => {
+                for (i, d) in a.iter().enumerate() {
+                    if !valid(d) {
+                        issue(
+                            &format!("{}/{}", SystemFields::DependsOn.as_str(), i),
+                            format!("expected a step number or a command name, but found {}", d),
+                        );
+                    }
+                }
+            }
+            d if valid(d) => {}
+            d => issue(
+                SystemFields::DependsOn.as_str(),
+                format!(
+                    "expected a step number, a command name or an array of them, but found {}",
+                    d
+                ),
+            ),
+        }
+    }
+
+    let pn = match record.get(SystemFields::ProgramName.as_str()) {
+        Some(Value::String(s)) => ProgramName::from_str(s),
+        Some(v) => {
+            issue(
+                SystemFields::ProgramName.as_str(),
+                format!("expected a string, but found {}", v),
+            );
+            return;
+        }
+        None => {
+            issues.push(ManifestIssue {
+                pointer: path.to_string(),
+                message: String::from("this command record has a `step` but no `program_name`"),
+            });
+            return;
+        }
+    };
+    // inactive commands are neither parsed nor run
+    if !active {
+        return;
+    }
+
+    let subcommand = match &pn {
+        ProgramName::External(_) => {
+            match record.get(SystemFields::ExternalArguments.as_str()) {
+                Some(Value::Array(args)) => {
+                    for (i, a) in args.iter().enumerate() {
+                        if !(a.is_string() || a.is_number()) {
+                            issue(
+                                &format!("{}/{}", SystemFields::ExternalArguments.as_str(), i),
+                                format!("expected a string, but found {}", a),
+                            );
+                        }
+                    }
+                }
+                Some(v) => issue(
+                    SystemFields::ExternalArguments.as_str(),
+                    format!("expected an array of strings, but found {}", v),
+                ),
+                None => issues.push(ManifestIssue {
+                    pointer: path.to_string(),
+                    message: format!("the {} command has no `arguments` field", pn),
+                }),
+            }
+            return;
+        }
+        ProgramName::Index => vec![String::from("index")],
+        ProgramName::Quant => vec![String::from("quant")],
+        ProgramName::Simpleaf(subcommand) => subcommand.clone(),
+    };
+
+    if subcommand.first().map(String::as_str) == Some("workflow") {
+        issue(
+            SystemFields::ProgramName.as_str(),
+            String::from("simpleaf workflow commands cannot be run from within a workflow"),
+        );
+        return;
+    }
+    let Some(schema) = CommandSchema::for_subcommand(&subcommand) else {
+        issue(
+            SystemFields::ProgramName.as_str(),
+            format!("{} is not a simpleaf command", pn),
+        );
+        return;
+    };
+
+    let mut valid_flags = true;
+    if let Value::Object(fields) = record {
+        for (k, v) in fields {
+            if [
+                SystemFields::Step,
+                SystemFields::ProgramName,
+                SystemFields::Active,
+                SystemFields::DependsOn,
+            ]
+            .iter()
+            .any(|f| f.as_str() == k)
+            {
+                continue;
+            }
+            match schema.flag(k) {
+                Some(flag) => {
+                    if let Err(msg) = flag.check(v) {
+                        valid_flags = false;
+                        issue(&json_pointer_token(k), msg);
+                    }
+                }
+                None => {
+                    valid_flags = false;
+                    issue(
+                        &json_pointer_token(k),
+                        format!("{} has no such flag", schema.name),
+                    );
+                }
+            }
+        }
+    }
+
+    // missing required flags, conflicting flags etc. can only be found by
+    // parsing the whole command line
+    if valid_flags {
+        if let Err(e) = pn.create_simpleaf_cmd(record) {
+            issues.push(ManifestIssue {
+                pointer: path.to_string(),
+                message: format!("{:#}", e),
+            });
+        }
+    }
+}
+
+/// Warns about the commands of `simpleaf_workflow` sharing a step with other
+/// commands without declaring their dependencies: their relative order is
+/// unspecified, and they may run at the same time with `--jobs`.
+pub fn shared_steps(simpleaf_workflow: &SimpleafWorkflow) -> Vec<ManifestIssue> {
+    let queue = &simpleaf_workflow.cmd_queue;
+    let mut issues = Vec::new();
+    for cr in queue.iter().filter(|cr| cr.declared_deps.is_none()) {
+        let others: Vec<&str> = queue
+            .iter()
+            .filter(|o| o.step == cr.step && o.path != cr.path)
+            .map(|o| o.path.as_str())
+            .collect();
+        if !others.is_empty() {
+            issues.push(ManifestIssue {
+                pointer: format!("{}/{}", cr.path, SystemFields::Step.as_str()),
+                message: format!(
+                    "step {} is shared with {}; these commands may run in any order, or at the same time",
+                    cr.step,
+                    others.join(", ")
+                ),
+            });
+        }
+    }
+    issues
+}
+
+/// Whether `program` can be run by the shell running the external commands,
+/// either as an executable or as a shell builtin.
+fn is_runnable(program: &str) -> bool {
+    if which::which(program).is_ok() {
+        return true;
+    }
+    shell(format!("type {}", shell_quote(program)))
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Checks the commands that `simpleaf_workflow` would run, which were
+/// parsed from `manifest`: the programs of the external commands must be
+/// runnable, and the files read by the simpleaf commands must either exist
+/// or be written by a command of an earlier step. A file is considered
+/// written by an external command if it appears among its arguments.
+pub fn check_workflow(
+    simpleaf_workflow: &SimpleafWorkflow,
+    manifest: &Value,
+) -> Vec<ManifestIssue> {
+    let mut issues = Vec::new();
+    // the paths written by the commands, with their step
+    let mut written: Vec<(u64, PathBuf)> = Vec::new();
+
+    for cr in &simpleaf_workflow.cmd_queue {
+        let Some(record) = manifest.pointer(&cr.path) else {
+            continue;
+        };
+        let Some(schema) = CommandSchema::for_program(&cr.program_name) else {
+            let program = cr.program_name.to_string();
+            if !is_runnable(&program) {
+                issues.push(ManifestIssue {
+                    pointer: format!("{}/{}", cr.path, SystemFields::ProgramName.as_str()),
+                    message: format!("could not find the program `{}` on the PATH", program),
+                });
+            }
+            if let Some(Value::Array(args)) = record.get(SystemFields::ExternalArguments.as_str()) {
+                written.extend(
+                    args.iter()
+                        .map(to_quoted_string)
+                        .filter(|a| !a.starts_with('-'))
+                        .map(|a| (cr.step, PathBuf::from(a))),
+                );
+            }
+            continue;
+        };
+
+        let Value::Object(fields) = record else {
+            continue;
+        };
+        let mut outputs = Vec::new();
+        for (k, v) in fields {
+            let Some(flag) = schema.flag(k).filter(|f| f.is_path) else {
+                continue;
+            };
+            let v = match v {
+                Value::String(v) if !v.is_empty() => v,
+                _ => continue,
+            };
+            let paths: Vec<&str> = match flag.value_delimiter {
+                Some(d) => v.split(d).collect(),
+                None => vec![v.as_str()],
+            };
+            for p in paths.into_iter().map(Path::new) {
+                if flag.is_output {
+                    outputs.push((cr.step, p.to_path_buf()));
+                } else if !p.exists()
+                    && !written
+                        .iter()
+                        .any(|(step, w)| *step < cr.step && p.starts_with(w))
+                {
+                    issues.push(ManifestIssue {
+                        pointer: format!("{}/{}", cr.path, json_pointer_token(k)),
+                        message: format!(
+                            "the input {} does not exist, and is not written by a command of an earlier step",
+                            p.display()
+                        ),
+                    });
+                }
+            }
+        }
+        written.extend(outputs);
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_manifest() {
+        let manifest = json!({
+            "meta_info": { "output": "out" },
+            "quant": {
+                "10x_run": {
+                    "step": 2,
+                    "program_name": "simpleaf quant",
+                    "--chemistry": "10xv3",
+                    "--threads": "many",
+                    "--resolution": "best",
+                    "--knee": true,
+                    "--expect-cells": 3000,
+                    "--map-dir": "map",
+                    "--output": "quant",
+                    "--t2g-map": "t2g.tsv",
+                    "--colour": "blue"
+                },
+                "missing output": {
+                    "step": 3,
+                    "program_name": "simpleaf atac process",
+                    "--index": "idx",
+                    "--reads1": "r1.fq",
+                    "--reads2": "r2.fq",
+                    "--barcode-reads": "bc.fq"
+                },
+                "inactive": {
+                    "step": 4,
+                    "program_name": "simpleaf quant",
+                    "active": false,
+                    "--threads": "many"
+                }
+            },
+            "external": {
+                "gunzip": { "step": "1", "program_name": "gunzip", "arguments": ["-c", true] }
+            }
+        });
+
+        let issues: Vec<String> = validate_manifest(&manifest)
+            .iter()
+            .map(|i| i.pointer.clone())
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "/external/gunzip/step",
+                "/external/gunzip/arguments/1",
+                "/quant/10x_run/--colour",
+                "/quant/10x_run/--resolution",
+                "/quant/10x_run/--threads",
+                "/quant/missing output",
+            ]
+        );
+
+        let issues = validate_manifest(&manifest);
+        assert!(issues[2].message.contains("no such flag"));
+        assert!(issues[3].message.contains("one of cr-like"));
+        assert!(issues[4].message.contains("non-negative integer"));
+        assert!(issues[5].message.contains("--output"));
+    }
+}
@@ -1,10 +1,8 @@
 // TODO:
-// allow multiple registry, just like conda envs
 // find a way to pull files from github directly instead of using local copy of protocol estuary
 
 use anyhow::{anyhow, bail, Context};
 use chrono::{DateTime, Local};
-use clap::Parser;
 // use cmd_lib::run_cmd;
 use serde_json::{json, Map, Value};
 use std::boxed::Box;
@@ -15,17 +13,20 @@ use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use tracing::{error, info, warn};
 
+use crate::utils::config::SimpleafConfig;
+use crate::utils::home::SimpleafHome;
 use crate::utils::jrsonnet_main::parse_jsonnet;
 use crate::utils::prog_utils;
-use crate::utils::prog_utils::CommandVerbosityLevel;
-use crate::{Cli, Commands};
+use crate::utils::registry::{Registries, DEFAULT_REGISTRY};
+use crate::utils::workflow_jobs::{self, shell_quote, ClusterJob, WorkflowExecutor};
+use crate::utils::workflow_schema::json_pointer_token;
+use crate::Commands;
 
-use super::af_utils::create_dir_if_absent;
 use super::jrsonnet_main::ParseAction;
 use super::prog_utils::shell;
 
 // fields that are not representing any simpleaf flag
-const SKIPARG: &[&str] = &["step", "program_name", "active"];
+const SKIPARG: &[&str] = &["step", "program_name", "active", "depends_on"];
 
 #[derive(Debug)]
 pub enum WFCommand {
@@ -50,7 +51,7 @@ pub struct JsonPatch {
     pub patch: serde_json::Value,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct PatchCollection {
     patches: Vec<JsonPatch>,
 }
@@ -389,6 +390,15 @@ pub fn update_start_at(v: &Value) -> anyhow::Result<u64> {
         .as_bool()
         .with_context(|| "cannot parse `Succeed` as bool; Cannot resume.")?;
 
+    // if the steps were submitted as cluster jobs, resume from the first
+    // step with a job that did not complete
+    if let Some(cluster_jobs) = latest_run.get("Cluster Jobs") {
+        return match workflow_jobs::first_incomplete_step(cluster_jobs)? {
+            Some(step) => Ok(step),
+            None => bail!("All the jobs of the previous run completed. Cannot resume."),
+        };
+    }
+
     let start_at = latest_run
         .get("Execution Terminated Step")
         .with_context(|| {
@@ -436,83 +446,218 @@ pub fn get_previous_log<T: AsRef<Path>>(output: T) -> anyhow::Result<Value> {
     }
 }
 
+/// Runs a single command of a workflow.
+fn run_workflow_command(
+    cmd: WFCommand,
+    af_home_path: &Path,
+    pn: &ProgramName,
+    step: u64,
+) -> anyhow::Result<()> {
+    match cmd {
+        WFCommand::SimpleafCommand(cmd) => run_simpleaf_command(*cmd, af_home_path),
+        // If this is an external command, then run it
+        WFCommand::ExternalCommand(mut ext_cmd) => {
+            // log
+            let cmd_string = prog_utils::get_cmd_line_string(&ext_cmd);
+            info!("Invoking command : {}", cmd_string);
+
+            match ext_cmd.output() {
+                Ok(cres) => {
+                    // check the return status of external command
+                    if cres.status.success() {
+                        Ok(())
+                    } else {
+                        let cmd_stderr = std::str::from_utf8(&cres.stderr[..])?;
+                        let msg = format!(
+                            "{} command at step {} failed to exit with code 0 under the shell.\n\
+                        The exit status was: {}.\n\
+                        The stderr of the invocation was: {}.",
+                            pn, step, cres.status, cmd_stderr
+                        );
+                        warn!(msg);
+                        bail!(msg);
+                    }
+                }
+                Err(e) => {
+                    let msg = format!(
+                        "{} command at step {} failed to execute under the shell.\n\
+                        The returned error was: {:?}.\n",
+                        pn, step, e
+                    );
+                    warn!(msg);
+                    bail!(msg);
+                }
+            }
+        }
+    }
+}
+
+/// Runs a simpleaf command of a workflow, as `simpleaf` itself would.
+fn run_simpleaf_command(cmd: Commands, af_home_path: &Path) -> anyhow::Result<()> {
+    match cmd {
+        Commands::Workflow(_) => {
+            bail!("simpleaf workflow commands cannot be run from within a workflow.")
+        }
+        Commands::Home(_) => {
+            bail!("simpleaf home commands cannot be run from within a workflow.")
+        }
+        Commands::Config(_) => {
+            bail!("simpleaf config commands cannot be run from within a workflow.")
+        }
+        cmd => crate::run_command(cmd, &SimpleafHome::of(af_home_path)),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum CommandState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    // not run because a command it depends on failed
+    Blocked,
+}
+
+/// Runs the commands of the workflow, each once all of the commands it
+/// depends on have succeeded, with up to `jobs` commands running at the same
+/// time. When a command fails, the commands that do not depend on it keep
+/// running; the failures are recorded in the workflow log, and the error of
+/// the first failed command is returned.
 pub fn execute_commands_in_workflow<T: AsRef<Path>>(
     simpleaf_workflow: SimpleafWorkflow,
     af_home_path: T,
     workflow_log: &mut WorkflowLog,
+    jobs: usize,
 ) -> anyhow::Result<()> {
+    let af_home_path = af_home_path.as_ref();
+    let jobs = jobs.max(1);
+
+    let mut cmds = Vec::with_capacity(simpleaf_workflow.cmd_queue.len());
+    let mut records = Vec::with_capacity(simpleaf_workflow.cmd_queue.len());
     for cr in simpleaf_workflow.cmd_queue {
-        let pn = cr.program_name;
-        let step = cr.step;
-        // this if statement is no longer needed as commands with a negative exec order
-        // are ignore when constructing the the cmd queue
-        // say something
-        info!("Running {} command for step {}.", pn, step,);
-
-        // initiliaze a stopwatch
-        workflow_log.timeit(step);
-
-        match cr.cmd {
-            WFCommand::SimpleafCommand(cmd) => {
-                let exec_result = match *cmd {
-                    Commands::Index(index_opts) => {
-                        crate::indexing::build_ref_and_index(af_home_path.as_ref(), index_opts)
-                    }
-                    // if we are running mapping and quantification
-                    Commands::Quant(quant_opts) => {
-                        crate::quant::map_and_quant(af_home_path.as_ref(), quant_opts)
-                    }
-                    _ => todo!(),
-                };
-                if let Err(e) = exec_result {
-                    workflow_log.write(false)?;
-                    info!("Execution terminated at {} command for step {}", pn, step);
-                    return Err(e);
+        let CommandRecord {
+            step,
+            program_name,
+            cmd,
+            path,
+            depends_on,
+            field_trajectory_vec,
+            ..
+        } = cr;
+        cmds.push(Some(cmd));
+        records.push((step, program_name, path, depends_on, field_trajectory_vec));
+    }
+
+    let mut state = vec![CommandState::Pending; records.len()];
+    let mut first_error: Option<anyhow::Error> = None;
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        let mut running = 0;
+        loop {
+            // the dependencies of a command always come before it in the queue,
+            // so a single pass propagates the failures.
+            for i in 0..records.len() {
+                let (step, pn, _, depends_on, _) = &records[i];
+                if state[i] == CommandState::Pending
+                    && depends_on
+                        .iter()
+                        .any(|d| matches!(state[*d], CommandState::Failed | CommandState::Blocked))
+                {
+                    warn!(
+                        "Not running {} command for step {}, since a command it depends on failed.",
+                        pn, step
+                    );
+                    state[i] = CommandState::Blocked;
+                }
+            }
+
+            let mut finished = None;
+            for i in 0..records.len() {
+                if running >= jobs || finished.is_some() {
+                    break;
+                }
+                let (step, pn, _, depends_on, _) = &records[i];
+                if state[i] != CommandState::Pending
+                    || depends_on
+                        .iter()
+                        .any(|d| state[*d] != CommandState::Succeeded)
+                {
+                    continue;
+                }
+                info!("Running {} command for step {}.", pn, step);
+                state[i] = CommandState::Running;
+                workflow_log.timeit(*step);
+                let start_time = Local::now();
+                let cmd = cmds[i].take().expect("each command is run once");
+                if jobs == 1 {
+                    finished = Some((
+                        i,
+                        start_time,
+                        run_workflow_command(cmd, af_home_path, pn, *step),
+                    ));
                 } else {
-                    info!("Successfully ran {} command for step {}", pn, step);
-                    workflow_log.update(&cr.field_trajectory_vec[..])?;
+                    running += 1;
+                    let tx = tx.clone();
+                    let (pn, step) = (pn.to_string(), *step);
+                    let pn_ref = &records[i].1;
+                    scope.spawn(move || {
+                        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                            run_workflow_command(cmd, af_home_path, pn_ref, step)
+                        }))
+                        .unwrap_or_else(|_| {
+                            Err(anyhow!("{} command at step {} panicked.", pn, step))
+                        });
+                        // the receiver outlives the scope, so this cannot fail
+                        let _ = tx.send((i, start_time, res));
+                    });
                 }
             }
-            // If this is an external command, then initialize it and run
-            WFCommand::ExternalCommand(mut ext_cmd) => {
-                // log
-                let cmd_string = prog_utils::get_cmd_line_string(&ext_cmd);
-                info!("Invoking command : {}", cmd_string);
-
-                // initiate a stopwatch
-                workflow_log.timeit(cr.step);
-
-                match ext_cmd.output() {
-                    Ok(cres) => {
-                        // check the return status of external command
-                        if cres.status.success() {
-                            // succeed. update log
-                            workflow_log.update(&cr.field_trajectory_vec[..])?;
-                        } else {
-                            workflow_log.write(false)?;
-                            let cmd_stderr = std::str::from_utf8(&cres.stderr[..])?;
-                            let msg = format!("{} command at step {} failed to exit with code 0 under the shell.\n\
-                            The exit status was: {}.\n\
-                            The stderr of the invocation was: {}.", pn, step, cres.status, cmd_stderr);
-                            warn!(msg);
-                            bail!(msg);
-                        }
-                    }
-                    Err(e) => {
-                        workflow_log.write(false)?;
-                        let msg = format!(
-                            "{} command at step {} failed to execute under the shell.\n\
-                            The returned error was: {:?}.\n",
-                            pn, step, e
-                        );
-                        warn!(msg);
-                        bail!(msg);
-                    } // TODO: use this in the log somewhere.
-                } // invoke external cmd
+
+            let (i, start_time, res) = match finished {
+                Some(f) => f,
+                None if running > 0 => {
+                    running -= 1;
+                    rx.recv()?
+                }
+                None => break,
+            };
+            let (step, pn, path, _, field_trajectory_vec) = &records[i];
+            match res {
+                Ok(()) => {
+                    info!("Successfully ran {} command for step {}", pn, step);
+                    state[i] = CommandState::Succeeded;
+                    workflow_log.record_success(*step, start_time, field_trajectory_vec, path)?;
+                }
+                Err(e) => {
+                    info!("Execution terminated at {} command for step {}", pn, step);
+                    state[i] = CommandState::Failed;
+                    workflow_log.record_failure(path);
+                    first_error.get_or_insert(e);
+                }
             }
         }
-        // info!("successfully ran {} command for step {}.", pn, step);
-    } // for cmd_queue
+        Ok(())
+    })?;
+
+    // resume from the first command that did not succeed
+    if let Some(step) = records
+        .iter()
+        .zip(state.iter())
+        .filter(|(_, st)| **st != CommandState::Succeeded)
+        .map(|(r, _)| r.0)
+        .min()
+    {
+        workflow_log.set_terminated_step(step);
+        workflow_log.write(false)?;
+        return Err(
+            first_error.unwrap_or_else(|| anyhow!("Not all commands of the workflow were run."))
+        );
+    }
+    // the commands may not have finished in the order of their steps
+    if let Some(step) = records.iter().map(|r| r.0).max() {
+        workflow_log.set_terminated_step(step);
+    }
     Ok(())
 }
 
@@ -543,6 +688,15 @@ pub fn initialize_workflow<T: AsRef<Path>>(
     Ok((sw, wl))
 }
 
+/// The formats `simpleaf workflow graph` can write the workflow in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// a Graphviz DOT digraph
+    Dot,
+    /// a Mermaid flowchart
+    Mermaid,
+}
+
 // Each SimpleafWorkflow represents a json
 /// Simpleaf Workflow record
 pub struct SimpleafWorkflow {
@@ -568,23 +722,72 @@ impl SimpleafWorkflow {
         // This is a running vec as fill_cmd_queue is a recursive function
         let field_trajectory_vec: Vec<usize> = Vec::new();
 
+        // all the commands in the workflow, including the inactive ones,
+        // which `depends_on` can refer to
+        let mut manifest_cmds: Vec<ManifestCommand> = Vec::new();
+
         // find and parse simpleaf and external commands recorded in the workflow JSON object.
         SimpleafWorkflow::fill_cmd_queue(
             workflow_json_value,
             &mut cmd_queue,
             field_trajectory_vec,
+            String::new(),
+            &mut manifest_cmds,
             workflow_log,
         )?;
 
         // sort the cmd queue by its `step`.
         cmd_queue.sort_by(|cmd1, cmd2| cmd1.step.cmp(&cmd2.step));
 
+        resolve_dependencies(&mut cmd_queue, &manifest_cmds)?;
+
         Ok(SimpleafWorkflow {
             af_home_path: af_home_path.as_ref().to_owned(),
             cmd_queue,
         })
     }
 
+    /// Renders the commands in the queue, labelled by their step, program
+    /// and location in the manifest, along with their dependencies as edges.
+    pub fn graph(&self, format: GraphFormat) -> String {
+        let mut lines = vec![match format {
+            GraphFormat::Dot => String::from("digraph workflow {"),
+            GraphFormat::Mermaid => String::from("flowchart TD"),
+        }];
+        for (i, cr) in self.cmd_queue.iter().enumerate() {
+            let program = cr.program_name.to_string();
+            let program = program.trim_matches('"');
+            lines.push(match format {
+                GraphFormat::Dot => format!(
+                    "    c{} [label=\"step {}: {}\\n{}\"];",
+                    i,
+                    cr.step,
+                    program.replace('"', "\\\""),
+                    cr.path.replace('"', "\\\"")
+                ),
+                GraphFormat::Mermaid => format!(
+                    "    c{}[\"step {}: {}<br/>{}\"]",
+                    i,
+                    cr.step,
+                    program.replace('"', "#quot;"),
+                    cr.path.replace('"', "#quot;")
+                ),
+            });
+        }
+        for (i, cr) in self.cmd_queue.iter().enumerate() {
+            for d in &cr.depends_on {
+                lines.push(match format {
+                    GraphFormat::Dot => format!("    c{} -> c{};", d, i),
+                    GraphFormat::Mermaid => format!("    c{} --> c{}", d, i),
+                });
+            }
+        }
+        if format == GraphFormat::Dot {
+            lines.push(String::from("}"));
+        }
+        lines.join("\n")
+    }
+
     /// This function collect the command records from a `serde_json::Value` that records a complete simpleaf workflow,
     /// parse them as `CommandRecord` structs and push them into the `cmd_queue` vector.
     /// ### Details
@@ -599,6 +802,8 @@ impl SimpleafWorkflow {
         workflow_json_value: &Value,
         cmd_queue: &mut Vec<CommandRecord>,
         field_trajectory_vec: Vec<usize>,
+        json_path: String,
+        manifest_cmds: &mut Vec<ManifestCommand>,
         workflow_log: &mut WorkflowLog,
     ) -> anyhow::Result<()> {
         // save some allocation
@@ -610,6 +815,7 @@ impl SimpleafWorkflow {
                 let mut curr_field_trajectory_vec = field_trajectory_vec.clone();
 
                 curr_field_trajectory_vec.push(workflow_log.get_field_id(field_name));
+                let curr_json_path = format!("{}/{}", json_path, json_pointer_token(field_name));
 
                 // If "Step" exists, then this field records an external or a simpleaf command
                 if field.get(SystemFields::Step.as_str()).is_some() {
@@ -625,10 +831,19 @@ impl SimpleafWorkflow {
                             )
                         })?;
 
+                    manifest_cmds.push(ManifestCommand {
+                        path: curr_json_path.clone(),
+                        name: field_name.clone(),
+                        step,
+                    });
+
                     // parse "active" if there is one
                     let active =
                         if workflow_log.skip_step.contains(&step) || step < workflow_log.start_at {
                             false
+                        } else if workflow_log.completed_cmds.contains(&curr_json_path) {
+                            // this command succeeded in the run being resumed
+                            false
                         } else if let Some(v) = field.get(SystemFields::Active.as_str()) {
                             v.as_bool().with_context(|| {
                                 format!(
@@ -654,7 +869,19 @@ impl SimpleafWorkflow {
                             info!("Parsing {} command for step {}", pn, step);
                             // The `step` will be used for sorting the cmd_queue vector.
                             // all commands must have a valid `step`.
-                            let cmd = match pn.create_cmd(field) {
+                            let cmd_and_line = pn
+                                .create_cmd(field)
+                                .and_then(|c| Ok((c, pn.create_cmd_line(field)?)));
+                            let declared_deps = match field.get(SystemFields::DependsOn.as_str()) {
+                                Some(v) => Some(DependencyRef::parse_list(v).with_context(|| {
+                                    format!(
+                                        "Could not parse the depends_on field of the {} command for step {}",
+                                        pn, step
+                                    )
+                                })?),
+                                None => None,
+                            };
+                            let (cmd, cmd_line) = match cmd_and_line {
                                 Ok(v) => v,
                                 Err(e) => {
                                     if pn.is_external() {
@@ -669,6 +896,10 @@ impl SimpleafWorkflow {
                                 active,
                                 program_name: pn,
                                 cmd,
+                                cmd_line,
+                                path: curr_json_path,
+                                declared_deps,
+                                depends_on: Vec::new(),
                                 field_trajectory_vec: curr_field_trajectory_vec,
                             });
                         } else {
@@ -682,6 +913,8 @@ impl SimpleafWorkflow {
                         field,
                         cmd_queue,
                         curr_field_trajectory_vec,
+                        curr_json_path,
+                        manifest_cmds,
                         workflow_log,
                     )?;
                 }
@@ -691,6 +924,136 @@ impl SimpleafWorkflow {
     }
 }
 
+/// A command of the workflow, whether or not it will be run.
+struct ManifestCommand {
+    // the JSON pointer to the command record, e.g. `/rna/simpleaf_index`
+    path: String,
+    // the name of the field holding the command record
+    name: String,
+    step: u64,
+}
+
+/// A command that another command depends on, as written in its
+/// `depends_on` field: either a step number or the name of a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DependencyRef {
+    Step(u64),
+    Name(String),
+}
+
+impl DependencyRef {
+    fn parse(v: &Value) -> anyhow::Result<DependencyRef> {
+        match v {
+            Value::Number(n) => n
+                .as_u64()
+                .map(DependencyRef::Step)
+                .with_context(|| format!("Cannot parse {} as a step number", n)),
+            Value::String(s) => Ok(DependencyRef::Name(s.clone())),
+            v => bail!("Expected a step number or a command name, but found {}", v),
+        }
+    }
+
+    /// Parses the value of a `depends_on` field, which is a single
+    /// dependency or an array of them.
+    fn parse_list(v: &Value) -> anyhow::Result<Vec<DependencyRef>> {
+        match v {
+            Value::Array(a) => a.iter().map(DependencyRef::parse).collect(),
+            v => Ok(vec![DependencyRef::parse(v)?]),
+        }
+    }
+}
+
+impl std::fmt::Display for DependencyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DependencyRef::Step(s) => write!(f, "step {}", s),
+            DependencyRef::Name(n) => write!(f, "{:?}", n),
+        }
+    }
+}
+
+/// Fills the `depends_on` field of the commands in `cmd_queue`, which must
+/// be sorted by step, with the positions of the commands they depend on.
+/// A command without a `depends_on` field depends on the queued commands of
+/// the closest preceding step, so the steps run in order. A dependency is
+/// either a step number, standing for all the commands of that step, or the
+/// name of a command, which is either the name of its field or its full JSON
+/// pointer (e.g. `/rna/simpleaf_index`). Dependencies on commands that are
+/// not run (i.e. inactive, skipped or already completed) are satisfied.
+fn resolve_dependencies(
+    cmd_queue: &mut [CommandRecord],
+    manifest_cmds: &[ManifestCommand],
+) -> anyhow::Result<()> {
+    for i in 0..cmd_queue.len() {
+        let step = cmd_queue[i].step;
+        let depends_on: Vec<usize> = match &cmd_queue[i].declared_deps {
+            None => {
+                let prev_step = cmd_queue[..i]
+                    .iter()
+                    .map(|c| c.step)
+                    .filter(|s| *s < step)
+                    .max();
+                (0..i)
+                    .filter(|j| Some(cmd_queue[*j].step) == prev_step)
+                    .collect()
+            }
+            Some(deps) => {
+                let mut depends_on = Vec::new();
+                for dep in deps {
+                    let targets: Vec<&ManifestCommand> = match dep {
+                        DependencyRef::Step(s) => {
+                            manifest_cmds.iter().filter(|c| c.step == *s).collect()
+                        }
+                        DependencyRef::Name(n) => {
+                            let by_path: Vec<&ManifestCommand> =
+                                manifest_cmds.iter().filter(|c| &c.path == n).collect();
+                            if by_path.is_empty() {
+                                manifest_cmds.iter().filter(|c| &c.name == n).collect()
+                            } else {
+                                by_path
+                            }
+                        }
+                    };
+                    if targets.is_empty() {
+                        bail!(
+                            "The {} command at {} depends on {}, but there is no such command in the workflow",
+                            cmd_queue[i].program_name, cmd_queue[i].path, dep
+                        );
+                    }
+                    if let DependencyRef::Name(n) = dep {
+                        if targets.len() > 1 {
+                            bail!(
+                                "The {} command at {} depends on {:?}, which matches the commands at {}; use the full path of one of them",
+                                cmd_queue[i].program_name,
+                                cmd_queue[i].path,
+                                n,
+                                targets.iter().map(|c| c.path.as_str()).collect::<Vec<&str>>().join(", ")
+                            );
+                        }
+                    }
+                    for t in targets {
+                        if t.step >= step {
+                            bail!(
+                                "The {} command at {} (step {}) depends on the command at {} (step {}); a command can only depend on commands of earlier steps",
+                                cmd_queue[i].program_name, cmd_queue[i].path, step, t.path, t.step
+                            );
+                        }
+                        if let Some(j) = cmd_queue[..i].iter().position(|c| c.path == t.path) {
+                            if !depends_on.contains(&j) {
+                                depends_on.push(j);
+                            }
+                        }
+                    }
+                }
+                depends_on.sort_unstable();
+                depends_on
+            }
+        };
+        cmd_queue[i].depends_on = depends_on;
+    }
+    Ok(())
+}
+
 #[derive(Copy, Clone)]
 struct CommandRuntime {
     start_time: DateTime<Local>,
@@ -737,6 +1100,20 @@ pub struct WorkflowLog {
     // this is used for updating the log file <simpleaf_workflow_log.json>
     // this field will be updated after the
     previous_log: Option<Value>,
+
+    // the jobs the steps were submitted as, if they were not run locally
+    workflow_executor: WorkflowExecutor,
+    cluster_jobs: Vec<ClusterJob>,
+
+    // the JSON pointers of the commands that succeeded, including those of
+    // the run being resumed, and of those that failed
+    completed_cmds: Vec<String>,
+    failed_cmds: Vec<String>,
+    // the step to resume from, if some commands failed or were not run
+    terminated_step: Option<u64>,
+
+    // the registry the workflow was fetched from, with its revision
+    workflow_registry: Option<Value>,
 }
 
 impl WorkflowLog {
@@ -764,8 +1141,21 @@ impl WorkflowLog {
 
         // If previous log is Some(), i.e., --resume is set and we can find the file
         // then update start at using the Terminated At field
+        // and carry over the commands that completed in that run
+        let mut completed_cmds = Vec::new();
         if let Some(v) = &previous_log {
             start_at = update_start_at(v)?;
+            if let Some(Value::Array(cmds)) = v["Latest Run"].get("Completed Commands") {
+                completed_cmds.extend(cmds.iter().filter_map(|c| c.as_str().map(String::from)));
+            }
+            // the commands whose cluster jobs completed are not submitted again
+            if let Some(cluster_jobs) = v["Latest Run"].get("Cluster Jobs") {
+                for path in workflow_jobs::completed_commands(cluster_jobs)? {
+                    if !completed_cmds.contains(&path) {
+                        completed_cmds.push(path);
+                    }
+                }
+            }
         }
 
         // get output json path
@@ -806,9 +1196,32 @@ impl WorkflowLog {
             field_id_to_name: Vec::new(),
             // cmds_field_id_trajectory: Vec::new()
             previous_log,
+            workflow_executor: WorkflowExecutor::Local,
+            cluster_jobs: Vec::new(),
+            completed_cmds,
+            failed_cmds: Vec::new(),
+            terminated_step: None,
+            workflow_registry: None,
         })
     }
 
+    /// Records the jobs that the commands were submitted as by `executor`.
+    pub fn set_cluster_jobs(&mut self, executor: WorkflowExecutor, jobs: Vec<ClusterJob>) {
+        self.workflow_executor = executor;
+        self.cluster_jobs = jobs;
+    }
+
+    /// Records the registry that the workflow was fetched from by
+    /// `simpleaf workflow get`.
+    pub fn set_workflow_registry(&mut self, registry: Value) {
+        self.workflow_registry = Some(registry);
+    }
+
+    /// Sets the step to resume the workflow from.
+    pub fn set_terminated_step(&mut self, step: u64) {
+        self.terminated_step = Some(step);
+    }
+
     pub fn timeit(&mut self, step: u64) {
         self.command_runtime = Some(CommandRuntime {
             start_time: Local::now(),
@@ -831,7 +1244,9 @@ impl WorkflowLog {
         };
 
         // will be NA if used --no-execution
-        let execution_terminated_at = if let Some(command_runtime) = &self.command_runtime {
+        let execution_terminated_at = if let Some(step) = self.terminated_step {
+            step
+        } else if let Some(command_runtime) = &self.command_runtime {
             command_runtime.step
         } else {
             // If no record, then terminated at the beginning
@@ -868,7 +1283,7 @@ impl WorkflowLog {
         let d = Local::now().signed_duration_since(self.workflow_start_time);
         let execution_elapsed_time = duration_to_dhms(d);
 
-        let meta_info = json!(
+        let mut meta_info = json!(
             {
                 "Workflow Name": self.workflow_name,
                 "Workflow Meta Info":  workflow_meta_info,
@@ -881,9 +1296,18 @@ impl WorkflowLog {
                     "Execution Terminated Step":  execution_terminated_at,
                     "Number of Succeed Commands": self.num_succ,
                     "Command Runtime by Step": Value::from(self.cmd_runtime_records.clone()),
+                    "Completed Commands": self.completed_cmds,
+                    "Failed Commands": self.failed_cmds,
                 },
                 "Previous Runs": previous_runs
         });
+        if !self.cluster_jobs.is_empty() {
+            meta_info["Latest Run"]["Executor"] = json!(self.workflow_executor);
+            meta_info["Latest Run"]["Cluster Jobs"] = json!(self.cluster_jobs);
+        }
+        if let Some(registry) = &self.workflow_registry {
+            meta_info["Workflow Registry"] = registry.clone();
+        }
 
         // execution log
         std::fs::write(
@@ -966,6 +1390,26 @@ impl WorkflowLog {
         }
     }
 
+    /// Records the successful run of the command at `path` of `step`, started
+    /// at `start_time`, as [`WorkflowLog::update`] does.
+    pub fn record_success(
+        &mut self,
+        step: u64,
+        start_time: DateTime<Local>,
+        field_trajectory_vec: &[usize],
+        path: &str,
+    ) -> anyhow::Result<()> {
+        self.command_runtime = Some(CommandRuntime { start_time, step });
+        self.update(field_trajectory_vec)?;
+        self.completed_cmds.push(path.to_string());
+        Ok(())
+    }
+
+    /// Records the failure of the command at `path`.
+    pub fn record_failure(&mut self, path: &str) {
+        self.failed_cmds.push(path.to_string());
+    }
+
     /// Update WorkflowLog:
     /// 1. the `active` field of the executed commands in execution log
     /// 2. cmd runtime
@@ -1000,6 +1444,15 @@ pub struct CommandRecord {
     pub active: bool,
     pub program_name: ProgramName,
     pub cmd: WFCommand,
+    /// The shell command line running the command, as written to job scripts
+    pub cmd_line: String,
+    /// The JSON pointer to the command record in the workflow
+    pub path: String,
+    /// The dependencies listed in the `depends_on` field, if there is one
+    pub declared_deps: Option<Vec<DependencyRef>>,
+    /// The positions in the command queue of the commands this command
+    /// depends on
+    pub depends_on: Vec<usize>,
     //pub simpleaf_cmd: Option<Commands>,
     //pub external_cmd: Option<Command>,
 
@@ -1027,18 +1480,25 @@ impl CommandRecord {
 pub enum ProgramName {
     Index,
     Quant,
+    /// Any other simpleaf command, as the words naming it after `simpleaf`
+    /// (e.g. `["atac", "process"]` for `simpleaf atac process`)
+    Simpleaf(Vec<String>),
     External(String),
 }
 
 impl ProgramName {
     /// Instantiate a ProgramName enum according to a str
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(field_name: &str) -> ProgramName {
-        if field_name.starts_with("simpleaf") && field_name.ends_with("index") {
-            ProgramName::Index
-        } else if field_name.starts_with("simpleaf") && field_name.ends_with("quant") {
-            ProgramName::Quant
-        } else {
-            ProgramName::External(field_name.to_string())
+        let mut words = field_name.split_whitespace();
+        if words.next() != Some("simpleaf") {
+            return ProgramName::External(field_name.to_string());
+        }
+        let subcommand: Vec<String> = words.map(String::from).collect();
+        match subcommand.as_slice() {
+            [cmd] if cmd == "index" => ProgramName::Index,
+            [cmd] if cmd == "quant" => ProgramName::Quant,
+            _ => ProgramName::Simpleaf(subcommand),
         }
     }
 
@@ -1047,13 +1507,16 @@ impl ProgramName {
         matches!(self, &ProgramName::External(_))
     }
 
-    /// Create a valid simpleaf command object using the arguments recoreded in the field.
-    /// step and program name will be ignored in this procedure
-    pub fn create_simpleaf_cmd(&self, value: &Value) -> anyhow::Result<WFCommand> {
+    /// Collect the command line arguments of a simpleaf command from the arguments
+    /// recorded in the field. step and program name will be ignored in this procedure
+    fn simpleaf_args(&self, value: &Value) -> anyhow::Result<Vec<String>> {
         let mut arg_vec = match self {
             ProgramName::Index => vec![String::from("simpleaf"), String::from("index")],
             ProgramName::Quant => vec![String::from("simpleaf"), String::from("quant")],
-            _ => bail!("creating simpleaf command from external program."),
+            ProgramName::Simpleaf(subcommand) => std::iter::once(String::from("simpleaf"))
+                .chain(subcommand.iter().cloned())
+                .collect(),
+            ProgramName::External(_) => bail!("creating simpleaf command from external program."),
         };
 
         // Iterate over all (arg, value) pairs to
@@ -1083,22 +1546,31 @@ impl ProgramName {
             warn!("Found an invalid root layer; Ignored. All root layers must represent a valid simpleaf command.");
         };
 
-        // check if empty
-        if arg_vec.len() > 2 {
-            let cmd = Cli::parse_from(arg_vec).command;
-            Ok(WFCommand::SimpleafCommand(Box::new(cmd)))
-        } else {
-            bail!(
-                "Found a {} command with no argument. Cannot Proceed.",
-                arg_vec.join(" ")
-            )
+        // some commands (e.g. `simpleaf inspect`) take no argument, so
+        // missing arguments are reported when parsing the command
+        Ok(arg_vec)
+    }
+
+    /// Create a valid simpleaf command object using the arguments recoreded in the field.
+    /// step and program name will be ignored in this procedure
+    pub fn create_simpleaf_cmd(&self, value: &Value) -> anyhow::Result<WFCommand> {
+        let arg_vec = self.simpleaf_args(value)?;
+        let cmd = match SimpleafConfig::global().parse_cli(&arg_vec) {
+            Ok(cli) => cli.command,
+            Err(e) => bail!(
+                "`{}` is not a valid simpleaf command:\n{}",
+                arg_vec.join(" "),
+                e.render()
+            ),
+        };
+        if let Commands::Workflow(_) = cmd {
+            bail!("simpleaf workflow commands cannot be run from within a workflow.");
         }
+        Ok(WFCommand::SimpleafCommand(Box::new(cmd)))
     }
 
-    /// This function instantiates a std::process::Command
-    /// for an external command record according to
-    /// the  "arguments" field.
-    pub fn create_external_cmd(&self, value: &Value) -> anyhow::Result<WFCommand> {
+    /// Collect the shell words of an external command from the "arguments" field.
+    fn external_args(&self, value: &Value) -> anyhow::Result<Vec<String>> {
         // get the argument vector, which is named as "Argument"
         let arg_value_vec = value
             .get(SystemFields::ExternalArguments.as_str())
@@ -1114,6 +1586,14 @@ impl ProgramName {
         for arg_value in arg_value_vec {
             arg_vec.push(to_quoted_string(arg_value));
         }
+        Ok(arg_vec)
+    }
+
+    /// This function instantiates a std::process::Command
+    /// for an external command record according to
+    /// the  "arguments" field.
+    pub fn create_external_cmd(&self, value: &Value) -> anyhow::Result<WFCommand> {
+        let arg_vec = self.external_args(value)?;
 
         if arg_vec.len() == 1 {
             warn!(
@@ -1129,6 +1609,22 @@ impl ProgramName {
         Ok(WFCommand::ExternalCommand(external_cmd))
     }
 
+    /// Create the shell command line running the command recorded in the field.
+    /// simpleaf commands are run by the current simpleaf executable.
+    pub fn create_cmd_line(&self, value: &Value) -> anyhow::Result<String> {
+        if self.is_external() {
+            return Ok(self.external_args(value)?.join(" "));
+        }
+        let simpleaf_exe = std::env::current_exe()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| String::from("simpleaf"));
+        Ok(std::iter::once(simpleaf_exe)
+            .chain(self.simpleaf_args(value)?.into_iter().skip(1))
+            .map(|a| shell_quote(&a))
+            .collect::<Vec<String>>()
+            .join(" "))
+    }
+
     pub fn create_cmd(&self, value: &Value) -> anyhow::Result<WFCommand> {
         if self.is_external() {
             self.create_external_cmd(value)
@@ -1146,6 +1642,8 @@ impl std::fmt::Display for ProgramName {
             match &self {
                 ProgramName::Index => SystemFields::SimpleafIndex.to_string(),
                 ProgramName::Quant => SystemFields::SimpleafQuant.to_string(),
+                ProgramName::Simpleaf(subcommand) =>
+                    format!("{:?}", format!("simpleaf {}", subcommand.join(" "))),
                 ProgramName::External(pn) => pn.to_owned(),
             }
         )
@@ -1166,6 +1664,7 @@ pub(crate) enum SystemFields {
     Step,
     ProgramName,
     Active,
+    DependsOn,
     MetaInfo,
     ExternalArguments,
     SimpleafIndex,
@@ -1184,6 +1683,7 @@ impl SystemFields {
             SystemFields::Step => "step",
             SystemFields::ProgramName => "program_name",
             SystemFields::Active => "active",
+            SystemFields::DependsOn => "depends_on",
             SystemFields::MetaInfo => "meta_info",
             SystemFields::ExternalArguments => "arguments",
             SystemFields::SimpleafIndex => "simpleaf index",
@@ -1263,78 +1763,14 @@ impl RegistrySourceStrategy {
     }
 }
 
+/// Returns the local copy of the default registry of workflows, the
+/// protocol estuary, fetching it first if needed.
 pub fn get_protocol_estuary<T: AsRef<Path>>(
     af_home_path: T,
     rss: RegistrySourceStrategy,
 ) -> anyhow::Result<ProtocolEstuary> {
-    let dl_url = "https://github.com/COMBINE-lab/protocol-estuary/archive/refs/heads/main.zip";
-
-    // define expected dirs and files
-    let pe_dir = af_home_path.as_ref().join("protocol-estuary");
-    let pe_main_dir = pe_dir.join("protocol-estuary-main");
-    let protocols_dir = pe_main_dir.join("protocols");
-    let utils_dir = pe_main_dir.join("utils");
-    let pe_zip_file = pe_dir.join("protocol-estuary.zip");
-
-    let protocol_estuary = ProtocolEstuary {
-        protocols_dir,
-        utils_dir,
-    };
-
-    // if output dir exists, and the user is not
-    // requesting a force refresh of the protocol
-    // estuary, then return
-    if protocol_estuary.exists() && !rss.is_force_refresh() {
-        info!("protocol estuary already exists, and no forced refresh was requested.");
-        Ok(protocol_estuary)
-    } else {
-        // make pe
-        create_dir_if_absent(&pe_dir)?;
-
-        prog_utils::download_to_file(dl_url, &pe_zip_file)?;
-
-        // unzip
-        let mut unzip_cmd = std::process::Command::new("unzip");
-        unzip_cmd
-            .arg("-o")
-            .arg(pe_zip_file.to_string_lossy().to_string())
-            .arg("-d")
-            .arg(pe_dir.to_string_lossy().to_string());
-
-        match prog_utils::execute_command(&mut unzip_cmd, CommandVerbosityLevel::Quiet) {
-            Ok(_output) => {}
-            Err(e) => {
-                // if failed, then remove dir and return with an error
-                std::fs::remove_dir(pe_dir.as_path()).with_context({
-                    || {
-                        format!(
-                            "failed to unzip protocol library zip file, \
-                            then failed to remove the protocol library directory. \n\
-                            Please remove it manually, for example, using `rm -rf {}`",
-                            pe_dir.display()
-                        )
-                    }
-                })?;
-                return Err(anyhow!(
-                    "failed to unzip protocol library zip file at {}. The error was: {:?}.",
-                    pe_zip_file.display(),
-                    e
-                ));
-            }
-        }
-
-        // final check
-        if protocol_estuary.exists() {
-            info!("The protocol estuary was succesfully refreshed.");
-            Ok(protocol_estuary)
-        } else {
-            bail!(
-                "Could not fetch protocol library. \
-                    This should not happen. \
-                    Please submit an issue on the simpleaf GitHub repository."
-            )
-        }
-    }
+    let mut registries = Registries::load(af_home_path.as_ref())?;
+    registries.checkout(DEFAULT_REGISTRY, rss)
 }
 
 /// Copy all files from the src folder to the dst folder.\
@@ -1357,6 +1793,7 @@ pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> anyhow::Res
 mod tests {
     // use clap::Parser;
 
+    use super::GraphFormat;
     use super::ProgramName;
     use super::WFCommand;
     use crate::utils::workflow_utils::SystemFields;
@@ -1408,6 +1845,31 @@ mod tests {
             external.is_external(),
             "ProgramName::External is a simpleaf cmd."
         );
+
+        let atac_process = ProgramName::from_str("simpleaf atac process");
+        assert_eq!(
+            atac_process,
+            ProgramName::Simpleaf(vec!["atac".to_string(), "process".to_string()])
+        );
+        assert!(!atac_process.is_external());
+        // `simpleaf atac index` is not `simpleaf index`
+        assert_ne!(ProgramName::from_str("simpleaf atac index"), index);
+
+        let lookup = ProgramName::from_str("simpleaf chemistry lookup");
+        match lookup
+            .create_cmd(&json!({ "step": 1, "--name": "10xv3" }))
+            .unwrap()
+        {
+            WFCommand::SimpleafCommand(cmd) => assert!(matches!(
+                *cmd,
+                Commands::Chemistry(crate::simpleaf_commands::ChemistryCommand::Lookup(_))
+            )),
+            c => panic!("expected SimpleafCommand, found {:?}", c),
+        }
+        assert!(lookup.create_cmd(&json!({ "--nme": "10xv3" })).is_err());
+        assert!(ProgramName::from_str("simpleaf workflow list")
+            .create_cmd(&json!({}))
+            .is_err());
     }
 
     #[test]
@@ -1497,6 +1959,12 @@ mod tests {
                 field_id_to_name,
                 skip_step,
                 previous_log: _,
+                workflow_executor,
+                cluster_jobs,
+                completed_cmds,
+                failed_cmds,
+                terminated_step,
+                workflow_registry,
             } => {
                 // test wl
                 // check JSON log output json
@@ -1542,6 +2010,11 @@ mod tests {
                 );
 
                 assert!(command_runtime.is_none());
+                assert_eq!(workflow_executor, &super::WorkflowExecutor::Local);
+                assert!(cluster_jobs.is_empty());
+                assert!(completed_cmds.is_empty() && failed_cmds.is_empty());
+                assert!(terminated_step.is_none());
+                assert!(workflow_registry.is_none());
 
                 assert_eq!(num_succ, &0);
             }
@@ -1656,4 +2129,71 @@ mod tests {
             e => panic!("expected SimpleafCommand, found {:?}", e),
         };
     }
+
+    #[test]
+    fn test_workflow_dependencies() {
+        let workflow_json_value = json!({
+            "meta_info": { "output": "output_dir" },
+            "cmds": {
+                "a": { "step": 1, "program_name": "echo", "arguments": ["a"] },
+                "b": { "step": 1, "program_name": "echo", "arguments": ["b"] },
+                "c": { "step": 2, "program_name": "echo", "depends_on": "a", "arguments": ["c"] },
+                "d": { "step": 3, "program_name": "echo", "arguments": ["d"] },
+                "e": { "step": 4, "program_name": "echo", "depends_on": [1, "/cmds/c"], "arguments": ["e"] },
+                "f": { "step": 5, "program_name": "echo", "active": false, "arguments": ["f"] },
+                "g": { "step": 6, "program_name": "echo", "depends_on": "f", "arguments": ["g"] }
+            }
+        });
+        let (sw, _) = initialize_workflow(
+            PathBuf::from("af_home").as_path(),
+            PathBuf::from("fake_config.config").as_path(),
+            PathBuf::from("output_dir").as_path(),
+            workflow_json_value.clone(),
+            1,
+            vec![],
+            false,
+        )
+        .unwrap();
+
+        let deps: Vec<(&str, Vec<usize>)> = sw
+            .cmd_queue
+            .iter()
+            .map(|c| (c.path.as_str(), c.depends_on.clone()))
+            .collect();
+        assert_eq!(
+            deps,
+            vec![
+                ("/cmds/a", vec![]),
+                ("/cmds/b", vec![]),
+                ("/cmds/c", vec![0]),
+                ("/cmds/d", vec![2]),
+                ("/cmds/e", vec![0, 1, 2]),
+                // f is inactive, so g can run right away
+                ("/cmds/g", vec![]),
+            ]
+        );
+
+        let dot = sw.graph(GraphFormat::Dot);
+        assert!(dot.starts_with("digraph workflow {"));
+        assert!(dot.contains("    c2 [label=\"step 2: echo\\n/cmds/c\"];"));
+        assert!(dot.contains("    c0 -> c2;"));
+        let mermaid = sw.graph(GraphFormat::Mermaid);
+        assert!(mermaid.contains("    c1 --> c4"));
+        assert!(!mermaid.contains("--> c5"));
+
+        // a command cannot depend on a command of the same or a later step
+        let mut v = workflow_json_value;
+        v["cmds"]["c"]["depends_on"] = json!("b");
+        v["cmds"]["b"]["step"] = json!(2);
+        assert!(initialize_workflow(
+            PathBuf::from("af_home").as_path(),
+            PathBuf::from("fake_config.config").as_path(),
+            PathBuf::from("output_dir").as_path(),
+            v,
+            1,
+            vec![],
+            false,
+        )
+        .is_err());
+    }
 }